@@ -0,0 +1,188 @@
+//! Serves the files in a directory over HTTP, e.g., `/tasks` or logs on a headless machine.
+//!
+//! To reach the server from the host when running in QEMU with user networking,
+//! forward a host port to it, e.g., `hostfwd=tcp::8080-:80`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use app_io::println;
+use fs_node::{DirRef, FileOrDir, FileRef};
+use getopts::Options;
+use http_server::{HttpServer, Request, Response, Router, HTTP_PORT};
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("httpd: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("p", "port", "listen on PORT (default: 80)", "PORT");
+    opts.optopt("c", "max-connections", "handle at most COUNT connections at once (default: 16)", "COUNT");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    let port = matches
+        .opt_str("p")
+        .map(|port| port.parse::<u16>().map_err(|_| "invalid port"))
+        .transpose()?
+        .unwrap_or(HTTP_PORT);
+
+    let cwd = task::with_current_task(|t| t.get_env().lock().working_dir.clone())
+        .map_err(|_| "failed to get current task")?;
+    let root = match matches.free.first() {
+        Some(dir) => match Path::new(dir).get(&cwd) {
+            Some(FileOrDir::Dir(dir)) => dir,
+            _ => return Err(format!("{} is not a directory", dir)),
+        },
+        None => cwd,
+    };
+
+    // Listen on every interface, including the loopback interface.
+    let interfaces = net::get_interfaces().lock().clone();
+    let (first, others) = interfaces.split_first().ok_or("no network interfaces available")?;
+    let mut server = HttpServer::new(first.clone(), port, Router::new().get("/*", move |request| serve(&root, request)));
+    for interface in others {
+        server = server.interface(interface.clone());
+    }
+    if let Some(max_connections) = matches.opt_str("c") {
+        server = server.max_connections(max_connections.parse().map_err(|_| "invalid connection count")?);
+    }
+
+    for interface in &interfaces {
+        match interface.ipv4_address() {
+            Some(cidr) => println!("httpd: serving on http://{}:{}/", cidr.address(), port),
+            None => println!("httpd: serving on port {}", port),
+        }
+    }
+    server.serve()?;
+    Ok(())
+}
+
+/// Responds with the file or directory listing at the request's path, relative to `root`.
+fn serve(root: &DirRef, request: &Request) -> Response {
+    let path = request.path();
+    let mut relative = String::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            // Don't let clients escape the served directory.
+            ".." => return Response::error(403),
+            name => {
+                relative.push_str(name);
+                relative.push('/');
+            }
+        }
+    }
+
+    let node = if relative.is_empty() {
+        Some(FileOrDir::Dir(root.clone()))
+    } else {
+        Path::new(relative.trim_end_matches('/')).get(root)
+    };
+    match node {
+        Some(FileOrDir::File(file)) => serve_file(&file, path),
+        // Relative links in a listing only work if the directory's URL ends in a slash.
+        Some(FileOrDir::Dir(_)) if !path.ends_with('/') => {
+            Response::new(301).with_header("Location", &format!("{}/", path))
+        }
+        Some(FileOrDir::Dir(dir)) => {
+            let index = dir.lock().get("index.html");
+            match index {
+                Some(FileOrDir::File(file)) => serve_file(&file, "index.html"),
+                _ => list_directory(&dir, path),
+            }
+        }
+        None => Response::error(404),
+    }
+}
+
+fn serve_file(file: &FileRef, name: &str) -> Response {
+    let mut file = file.lock();
+    let mut contents = vec![0; file.len()];
+    match file.read_at(&mut contents, 0) {
+        Ok(len) => {
+            contents.truncate(len);
+            Response::ok(content_type(name), contents)
+        }
+        Err(_) => Response::error(500),
+    }
+}
+
+fn list_directory(dir: &DirRef, path: &str) -> Response {
+    let mut entries = dir.lock().list();
+    entries.sort();
+
+    let title = format!("Index of {}", escape_html(path));
+    let mut html = format!("<!DOCTYPE html>\n<html><head><title>{0}</title></head><body>\n<h1>{0}</h1>\n<ul>\n", title);
+    if path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in entries {
+        let is_dir = matches!(dir.lock().get(&name), Some(FileOrDir::Dir(_)));
+        let name = escape_html(&name);
+        let suffix = if is_dir { "/" } else { "" };
+        html.push_str(&format!("<li><a href=\"{0}{1}\">{0}{1}</a></li>\n", name, suffix));
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    Response::ok("text/html; charset=utf-8", html.into_bytes())
+}
+
+/// Returns the media type of a file based on its extension.
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("svg") => "image/svg+xml",
+        Some("pcap") => "application/vnd.tcpdump.pcap",
+        Some("o" | "bin" | "wasm") => "application/octet-stream",
+        // Most files in Theseus, e.g., those in `/tasks`, are plain text without an extension.
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: httpd [OPTIONS] [DIR]
+Serves the files in DIR, or the current directory, over HTTP.
+Directories without an index.html file are served as a listing of their contents.";
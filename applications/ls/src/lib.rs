@@ -11,8 +11,8 @@ use alloc::{
     string::String,
     vec::Vec,
 };
-use core::fmt::Write;
-use fs_node::{FileOrDir, DirRef};
+use core::{fmt::Write, time::Duration};
+use fs_node::{DateTime, FileOrDir, FsNode, DirRef, Metadata};
 use getopts::Options;
 use path::Path;
 
@@ -20,6 +20,7 @@ pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("s", "size", "print the size of each file in directory");
+    opts.optflag("l", "long", "print the kind, permissions, size, and modification time of each entry");
 
     let matches = match opts.parse(args) {
         Ok(m) => m,
@@ -36,6 +37,7 @@ pub fn main(args: Vec<String>) -> isize {
     }
 
     let size_option = matches.opt_present("s");
+    let long_option = matches.opt_present("l");
 
     let Ok(curr_wd) = task::with_current_task(|t| t.get_env().lock().working_dir.clone()) else {
         println!("failed to get current task");
@@ -44,7 +46,7 @@ pub fn main(args: Vec<String>) -> isize {
 
     // print children of working directory if no child is specified
     if matches.free.is_empty() {
-        print_children(&curr_wd, size_option);
+        print_children(&curr_wd, size_option, long_option);
         return 0;
     }
 
@@ -53,7 +55,7 @@ pub fn main(args: Vec<String>) -> isize {
     // Navigate to the path specified by first argument
     match path.get(&curr_wd) {
         Some(FileOrDir::Dir(dir)) => {
-            print_children(&dir, size_option);
+            print_children(&dir, size_option, long_option);
             0
         }
         Some(FileOrDir::File(file)) => {
@@ -67,13 +69,15 @@ pub fn main(args: Vec<String>) -> isize {
     }
 }
 
-fn print_children(dir: &DirRef, print_size: bool) {
+fn print_children(dir: &DirRef, print_size: bool, print_long: bool) {
     let mut child_string = String::new();
     let mut child_list = dir.lock().list(); 
     child_list.reverse();
     for child in child_list.iter() {
         let child_path = dir.lock().get(child).expect("Failed to get child path");
-        if print_size {
+        if print_long {
+            writeln!(child_string, "{}    {}", format_metadata(&child_path.metadata()), child).expect("Failed to write child_string");
+        } else if print_size {
             match &child_path {
                 FileOrDir::File(file_ref) => {
                     let file = file_ref.lock();
@@ -90,11 +94,32 @@ fn print_children(dir: &DirRef, print_size: bool) {
     println!("{}", child_string);
 }
 
+/// Formats the given metadata as a single line, e.g., `-rw      1024  2023-05-01 12:34:56`.
+fn format_metadata(metadata: &Metadata) -> String {
+    let kind = if metadata.is_dir() { 'd' } else { '-' };
+    let write = if metadata.read_only { '-' } else { 'w' };
+    let modified = metadata.modified.map(format_time).unwrap_or_else(|| String::from("-"));
+    let mut s = String::new();
+    write!(s, "{}r{} {:>10}  {:<19}", kind, write, metadata.size, modified).expect("Failed to write metadata");
+    s
+}
+
+/// Formats a duration since the Unix epoch as `YYYY-MM-DD HH:MM:SS` (UTC).
+fn format_time(since_epoch: Duration) -> String {
+    let t = DateTime::from_unix_time(since_epoch);
+    let mut s = String::new();
+    write!(
+        s, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        t.year, t.month, t.day, t.hour, t.minute, t.second,
+    ).expect("Failed to write time");
+    s
+}
+
 fn print_usage(opts: Options) {
     println!("{}", opts.usage(USAGE));
 }
 
 
-const USAGE: &str = "Usage: ls [DIR | FILE]
+const USAGE: &str = "Usage: ls [OPTION]... [DIR | FILE]
 List the contents of the given directory or info about the given file.
 If no arguments are provided, it lists the contents of the current directory.";
@@ -27,8 +27,13 @@ pub fn main(args: Vec<String>) -> isize {
     let mut ret = 0;
 
     for dir_name in args.iter() {
-        // add child dir to current directory
-        if let Err(err) = VFSDirectory::create(dir_name.to_string(), &curr_wd) {
+        // add child dir to current directory, which may need to create its own kind of directory
+        let native_dir = curr_wd.lock().create_dir(dir_name);
+        let result = match native_dir {
+            Some(result) => result,
+            None => VFSDirectory::create(dir_name.to_string(), &curr_wd),
+        };
+        if let Err(err) = result {
             println!("Error creating {:?}: {}", dir_name, err);
             ret = -1;
         }
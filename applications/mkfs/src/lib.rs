@@ -0,0 +1,84 @@
+//! Formats a storage device with a new, empty native filesystem.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate app_io;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use getopts::Options;
+use nativefs::FormatOptions;
+use storage_manager::StorageDeviceId;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("mkfs: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("j", "journal", "the number of 4KiB blocks to reserve for the journal", "BLOCKS");
+    opts.optopt("i", "bytes-per-inode", "the number of bytes of storage per inode", "BYTES");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    let id = match matches.free.as_slice() {
+        [id] => id,
+        _ => {
+            print_usage(opts);
+            return Err("expected exactly one storage device ID".into());
+        }
+    };
+    let id = StorageDeviceId(id.parse().map_err(|_| format!("invalid storage device ID {id:?}"))?);
+
+    let mut options = FormatOptions::default();
+    if let Some(blocks) = matches.opt_str("j") {
+        options.journal_blocks = blocks.parse()
+            .map_err(|_| format!("invalid number of journal blocks {blocks:?}"))?;
+    }
+    if let Some(bytes) = matches.opt_str("i") {
+        options.bytes_per_inode = bytes.parse()
+            .map_err(|_| format!("invalid number of bytes per inode {bytes:?}"))?;
+    }
+
+    // Formatting a device out from under a mounted filesystem would corrupt it,
+    // and that includes a filesystem mounted from any partition on the device.
+    let mounts = mount_table::mounts();
+    let is_mounted = |device: StorageDeviceId| mounts.iter().any(|m| m.device() == Some(device));
+    if is_mounted(id) {
+        return Err(format!("storage device {} is mounted; unmount it first", id.0));
+    }
+    if let Some(partition) = storage_manager::partitions_of(id).into_iter().find(|p| is_mounted(*p)) {
+        return Err(format!("partition {} on storage device {} is mounted; unmount it first", partition.0, id.0));
+    }
+
+    let device = storage_manager::storage_device(id)
+        .ok_or_else(|| format!("no storage device exists with ID {}", id.0))?;
+    nativefs::format(device, &options)?;
+    println!("Formatted storage device {} with a native filesystem.", id.0);
+    println!("Mount it with `mount -t native {} <DIRECTORY>`.", id.0);
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: mkfs [OPTIONS] DEVICE_ID
+Formats the storage device with the ID DEVICE_ID with a new, empty native filesystem.
+All existing data on that storage device will be lost.";
@@ -0,0 +1,119 @@
+//! Mounts a filesystem over an existing directory.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate app_io;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use fs_node::DirRef;
+use getopts::Options;
+use path::Path;
+use storage_manager::StorageDeviceId;
+use vfs_node::VFSDirectory;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("mount: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("t", "type", "the type of filesystem to mount: \"memfs\", \"fat\", or \"native\"", "TYPE");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    // With no arguments, just list the currently-mounted filesystems.
+    if matches.free.is_empty() {
+        let mounts = mount_table::mounts();
+        if mounts.is_empty() {
+            println!("No filesystems are mounted.");
+            return Ok(());
+        }
+        println!("SOURCE               TARGET");
+        for m in mounts {
+            println!("{:<20} {}", m.source(), m.target());
+        }
+        return Ok(());
+    }
+
+    let fs_type = matches.opt_str("t").ok_or("missing filesystem type, specify one with `-t`")?;
+    let (source, target) = match matches.free.as_slice() {
+        [target] => (None, target),
+        [source, target] => (Some(source), target),
+        _ => {
+            print_usage(opts);
+            return Err("too many arguments".into());
+        }
+    };
+
+    let cwd = task::with_current_task(|t| t.get_env().lock().working_dir.clone())
+        .map_err(|_| "failed to get current task")?;
+    let mount_point = Path::new(target).get_dir(&cwd)
+        .ok_or_else(|| format!("couldn't find directory {target:?}"))?;
+    // The root of the mounted filesystem takes on the name of its mount point.
+    let name = mount_point.lock().get_name();
+
+    let (fs_root, source, device) = match fs_type.as_str() {
+        "memfs" => (VFSDirectory::new_root(name), String::from("memfs"), None),
+        "fat" | "native" => {
+            let id = source
+                .ok_or_else(|| format!("the {fs_type:?} filesystem type requires a storage device ID"))?;
+            open_storage_fs(&fs_type, id, name)?
+        }
+        other => return Err(format!("unsupported filesystem type {other:?}")),
+    };
+
+    mount_table::mount(&mount_point, fs_root, source, device).map_err(Into::into)
+}
+
+/// Opens the FAT or native filesystem (as given by `fs_type`)
+/// on the storage device with the given `id`.
+///
+/// Returns the root directory of that filesystem, its mount source description, and the device's ID.
+fn open_storage_fs(fs_type: &str, id: &str, name: String) -> Result<(DirRef, String, Option<StorageDeviceId>), String> {
+    let id = StorageDeviceId(id.parse().map_err(|_| format!("invalid storage device ID {id:?}"))?);
+
+    // Opening the same filesystem twice would lead to it being corrupted.
+    if mount_table::mounts().iter().any(|m| m.device() == Some(id)) {
+        return Err(format!("storage device {} is already mounted", id.0));
+    }
+
+    let device = storage_manager::storage_device(id)
+        .ok_or_else(|| format!("no storage device exists with ID {}", id.0))?;
+    if fs_type == "native" {
+        let fs = nativefs::NativeFileSystem::new(device)?;
+        Ok((fs.root_dir(name), format!("nativefs:{id}"), Some(id)))
+    } else {
+        let fs = fatfs_node::FatFileSystem::new(device)?;
+        Ok((fs.root_dir(name), format!("fat:{id}"), Some(id)))
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: mount [-t TYPE [SOURCE] TARGET]
+Mounts a filesystem over the existing TARGET directory.
+If no arguments are provided, it lists all mounted filesystems.
+
+Supported filesystem types:
+    memfs    an empty in-memory filesystem; SOURCE is not used.
+    fat      a FAT filesystem; SOURCE is the ID of the storage device it resides on.
+    native   a native filesystem created by `mkfs`; SOURCE is the ID of the storage device it resides on.";
@@ -0,0 +1,76 @@
+//! Renames or moves files and directories.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate app_io;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use getopts::Options;
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("mv: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    let (dest, sources) = match matches.free.split_last() {
+        Some((dest, sources)) if !sources.is_empty() => (Path::new(dest), sources),
+        _ => {
+            print_usage(opts);
+            return Err("missing argument".into());
+        }
+    };
+
+    let cwd = task::with_current_task(|t| t.get_env().lock().working_dir.clone())
+        .map_err(|_| "failed to get current task")?;
+
+    // If the destination is an existing directory, the sources are moved into it.
+    let dest_is_dir = dest.get_dir(&cwd).is_some();
+    if sources.len() > 1 && !dest_is_dir {
+        return Err(format!("target '{dest}' is not a directory"));
+    }
+
+    for source in sources {
+        let source = Path::new(source);
+        let target = if dest_is_dir {
+            let name = source.file_name()
+                .ok_or_else(|| format!("cannot move '{source}': it has no file name"))?;
+            dest.join(name)
+        } else {
+            dest.to_owned()
+        };
+        path::rename(source, &target, &cwd)
+            .map_err(|e| format!("cannot move '{source}' to '{target}': {e}"))?;
+    }
+
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: mv SOURCE DEST
+  or:  mv SOURCE... DIRECTORY
+Renames SOURCE to DEST, or moves each SOURCE into the existing DIRECTORY.";
@@ -0,0 +1,93 @@
+//! Looks up the IP addresses of hostnames and configures the DNS nameservers.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use app_io::println;
+use core::str::FromStr;
+use dns::RecordType;
+use getopts::Options;
+use net::IpAddress;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("nslookup: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt(
+        "s",
+        "servers",
+        "use the given comma-separated nameservers for all DNS lookups",
+        "IP[,IP...]",
+    );
+    opts.optflag("d", "dhcp", "use the nameservers acquired from DHCP for all DNS lookups");
+    opts.optflag("c", "clear-cache", "discard all cached DNS answers");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    if let Some(servers) = matches.opt_str("s") {
+        let servers = servers
+            .split(',')
+            .map(|server| IpAddress::from_str(server.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "invalid nameserver IP address")?;
+        dns::set_nameservers(servers);
+    } else if matches.opt_present("d") {
+        dns::set_nameservers(Vec::new());
+    }
+    if matches.opt_present("c") {
+        dns::clear_cache();
+    }
+
+    let interface = net::get_default_interface().ok_or("no network interfaces available")?;
+
+    // With no hostnames, just list the nameservers in use.
+    if matches.free.is_empty() {
+        for server in dns::nameservers(&interface) {
+            println!("nameserver {}", server);
+        }
+        return Ok(());
+    }
+
+    for host in &matches.free {
+        let mut found = false;
+        for record_type in [RecordType::A, RecordType::Aaaa] {
+            let addresses = dns::lookup(&interface, host, record_type)
+                .map_err(|e| alloc::format!("{host}: {e}"))?;
+            for address in addresses {
+                println!("{host} has address {address}");
+                found = true;
+            }
+        }
+        if !found {
+            println!("{host} has no IP addresses");
+        }
+    }
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: nslookup [OPTIONS] [HOST...]
+Looks up the IPv4 and IPv6 addresses of each HOST using DNS.
+With no HOST, lists the nameservers that DNS lookups are sent to.";
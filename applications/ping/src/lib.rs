@@ -10,7 +10,7 @@ extern crate alloc;
 
 use alloc::{string::String, vec, vec::Vec};
 use app_io::println;
-use core::{str::FromStr, time::Duration};
+use core::time::Duration;
 use getopts::{Matches, Options};
 use net::{
     icmp::{Endpoint, PacketBuffer, PacketMetadata, Socket},
@@ -67,11 +67,16 @@ pub fn main(args: Vec<String>) -> isize {
 }
 
 fn _main(matches: Matches) -> Result<(), &'static str> {
-    let remote = IpAddress::from_str(matches.free.first().ok_or("no arguments_provided")?)
-        .map_err(|_| "invalid argument")?;
-
     let interface = net::get_default_interface().ok_or("no network interfaces available")?;
 
+    let host = matches.free.first().ok_or("no arguments_provided")?;
+    let remote = dns::resolve_one(&interface, host)?;
+    if !matches!(remote, IpAddress::Ipv4(_)) {
+        return Err("only IPv4 destinations are supported");
+    }
+    // E.g., the loopback interface for `127.0.0.1`.
+    let interface = net::get_interface_for(remote).ok_or("no network interfaces available")?;
+
     let count = matches
         .opt_get_default("c", u16::MAX)
         .map_err(|_| "invalid count")?;
@@ -147,9 +152,8 @@ fn _main(matches: Matches) -> Result<(), &'static str> {
             repr.emit(&mut packet, &ChecksumCapabilities::ignored());
             drop(locked);
 
-            // Poll the socket to send the packet. Once we have a custom socket type this
-            // won't be necessary.
-            interface.poll();
+            // Wake the interface's network task to send the packet.
+            interface.wake();
             num_sent += 1;
         }
 
@@ -185,4 +189,4 @@ fn print_usage(opts: &Options) {
 }
 
 const USAGE: &str = "Usage: ping DESTINATION
-Pings an IPv4 address and displays network statistics";
+Pings a hostname or IPv4 address and displays network statistics";
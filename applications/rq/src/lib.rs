@@ -14,6 +14,8 @@ use getopts::Options;
 pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "stats", "print each CPU's load balancing statistics instead");
+    opts.optopt("b", "balance", "enable or disable load balancing between CPUs", "on|off");
 
     let matches = match opts.parse(args) {
         Ok(m) => m,
@@ -26,6 +28,24 @@ pub fn main(args: Vec<String>) -> isize {
     if matches.opt_present("h") {
         return print_usage(opts);
     }
+
+    if let Some(balance) = matches.opt_str("b") {
+        match balance.as_str() {
+            "on" => task::scheduler::set_load_balancing(true),
+            "off" => task::scheduler::set_load_balancing(false),
+            _ => {
+                println!("invalid argument to --balance: {}", balance);
+                return -1;
+            }
+        }
+        return 0;
+    }
+
+    if matches.opt_present("s") {
+        print_load_balance_stats();
+        return 0;
+    }
+
     let bootstrap_cpu = cpu::bootstrap_cpu();
 
     for (cpu, task_list) in task::scheduler::tasks() {
@@ -55,12 +75,36 @@ pub fn main(args: Vec<String>) -> isize {
     0
 }
 
+fn print_load_balance_stats() {
+    println!(
+        "Load balancing is {}",
+        if task::scheduler::is_load_balancing_enabled() { "enabled" } else { "disabled" }
+    );
+    println!("{:>5} {:>10} {:>10} {:>10} {:>10} {:>10}", "CPU", "BUSYNESS", "CHECKS", "OUT", "IN", "REQUESTS");
+    for (cpu, stats) in task::scheduler::load_balance_stats() {
+        let busyness = task::scheduler::busyness(cpu).unwrap_or(0);
+        println!(
+            "{:>5} {:>10} {:>10} {:>10} {:>10} {:>10}{}",
+            cpu.value(),
+            busyness,
+            stats.balance_checks,
+            stats.migrated_out,
+            stats.migrated_in,
+            stats.work_requests,
+            if stats.wants_work { " *" } else { "" }
+        );
+    }
+}
+
 fn print_usage(opts: Options) -> isize {
     let mut brief = "Usage: rq \n \n".to_string();
 
     brief.push_str(
         "Prints each CPU's ID, the tasks on its runqueue ('*' identifies the currently running \
-         task), and whether it is the boot CPU or not",
+         task), and whether it is the boot CPU or not.\n\n\
+         With --stats, prints how many times each CPU checked whether to migrate tasks to other \
+         CPUs, how many tasks it migrated out and in, and how many times it requested work \
+         because it had nothing to run ('*' identifies CPUs currently requesting work).",
     );
 
     println!("{} \n", opts.usage(&brief));
@@ -0,0 +1,237 @@
+//! Captures the frames sent and received by a network interface.
+//!
+//! Captured frames are either summarized on the terminal, written to a pcap file,
+//! or streamed in the pcap format over a serial port, e.g., to be piped into
+//! Wireshark on the host:
+//! ```sh
+//! wireshark -k -i <(socat - /dev/pts/N)
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use app_io::println;
+use core::{convert::TryFrom, str::FromStr};
+use core2::io::Write;
+use fs_node::{FileOrDir, FileRef};
+use getopts::Options;
+use memfs::MemFile;
+use net::{
+    capture::{self, CapturedFrame, Direction, Filter, LinkType, PacketInfo},
+    IpAddress, NetworkInterface,
+};
+use path::Path;
+use serial_port::{SerialPort, SerialPortAddress};
+use sync_irq::IrqSafeMutex;
+
+const DEFAULT_SNAP_LEN: usize = 65535;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("tcpdump: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("D", "list-interfaces", "list the network interfaces that can be captured");
+    opts.optopt("i", "interface", "capture the interface with the given index (default: the default interface)", "INDEX");
+    opts.optopt("c", "count", "exit after capturing COUNT frames", "COUNT");
+    opts.optopt("s", "snapshot-length", "capture at most SNAPLEN bytes of each frame (default: 65535)", "SNAPLEN");
+    opts.optopt("w", "write", "write the captured frames to FILE in the pcap format", "FILE");
+    opts.optopt("", "serial", "stream the captured frames over PORT in the pcap format", "PORT");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    if matches.opt_present("D") {
+        for (index, interface) in net::get_interfaces().lock().iter().enumerate() {
+            println!("{}. {}", index, describe(interface));
+        }
+        return Ok(());
+    }
+
+    let interface = match matches.opt_str("i") {
+        Some(index) => {
+            let index: usize = index.parse().map_err(|_| "invalid interface index")?;
+            net::get_interfaces().lock().get(index).cloned().ok_or("no interface with the given index")?
+        }
+        None => net::get_default_interface().ok_or("no network interfaces available")?,
+    };
+    let count = matches
+        .opt_str("c")
+        .map(|count| count.parse::<usize>().map_err(|_| "invalid count"))
+        .transpose()?;
+    let snap_len = matches
+        .opt_str("s")
+        .map(|snap_len| snap_len.parse::<usize>().map_err(|_| "invalid snapshot length"))
+        .transpose()?
+        .unwrap_or(DEFAULT_SNAP_LEN);
+    let filter = if matches.free.is_empty() {
+        None
+    } else {
+        Some(Filter::from_str(&matches.free.join(" "))?)
+    };
+
+    let capture = interface.start_capture(filter, snap_len)?;
+    let link_type = capture.link_type();
+
+    let mut output = if let Some(file_path) = matches.opt_str("w") {
+        Output::File { file: create_file(&file_path)?, offset: 0 }
+    } else if let Some(port) = matches.opt_str("serial") {
+        let address = SerialPortAddress::try_from(port.as_str()).map_err(|_| "invalid serial port")?;
+        let port = serial_port::get_serial_port(address).ok_or("serial port was not initialized")?;
+        Output::Serial(port.clone())
+    } else {
+        Output::Summary
+    };
+    output.write(&capture::pcap_file_header(link_type, snap_len))?;
+
+    println!("tcpdump: capturing on {}, link type {:?}, snapshot length {} bytes", describe(&interface), link_type, snap_len);
+
+    let mut captured = 0;
+    while count.map_or(true, |count| captured < count) {
+        let frame = capture.recv();
+        match output {
+            Output::Summary => println!("{}", summarize(&frame, link_type)),
+            _ => output.write(&frame.pcap_record())?,
+        }
+        captured += 1;
+    }
+
+    println!("{} frames captured, {} frames dropped", captured, capture.dropped());
+    Ok(())
+}
+
+/// Where captured frames are written to.
+enum Output {
+    /// Summarize each frame on the terminal.
+    Summary,
+    /// Append frames to a pcap file.
+    File { file: FileRef, offset: usize },
+    /// Stream frames over a serial port in the pcap format.
+    Serial(Arc<IrqSafeMutex<SerialPort>>),
+}
+
+impl Output {
+    /// Writes the given pcap-encoded `bytes`, if the output is in the pcap format.
+    fn write(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+        match self {
+            Output::Summary => Ok(()),
+            Output::File { file, offset } => {
+                *offset += file.lock().write_at(bytes, *offset)?;
+                Ok(())
+            }
+            Output::Serial(port) => port.lock().write_all(bytes).map_err(|_| "failed to write to serial port"),
+        }
+    }
+}
+
+/// Creates a new file at the given `path`, relative to the current working directory.
+fn create_file(path: &str) -> Result<FileRef, String> {
+    let cwd = task::with_current_task(|t| t.get_env().lock().working_dir.clone())
+        .map_err(|_| "failed to get current task")?;
+    let path: &Path = path.as_ref();
+    let name = path.file_name().ok_or("invalid file name")?;
+    if path.get(&cwd).is_some() {
+        return Err(format!("{} already exists", path));
+    }
+    let parent = match path.parent() {
+        Some(parent) if !AsRef::<str>::as_ref(parent).is_empty() => match parent.get(&cwd) {
+            Some(FileOrDir::Dir(dir)) => dir,
+            _ => return Err(format!("{} is not a directory", parent)),
+        },
+        _ => cwd,
+    };
+    Ok(MemFile::create(name.to_string(), &parent)?)
+}
+
+/// Returns a description of the given interface, e.g., `10.0.2.15/24 (dhcp)`.
+fn describe(interface: &NetworkInterface) -> String {
+    let address = match interface.ipv4_address() {
+        Some(cidr) => cidr.to_string(),
+        None => "no address".to_string(),
+    };
+    if interface.is_loopback() {
+        format!("{} (loopback)", address)
+    } else if interface.is_dhcp_enabled() {
+        format!("{} (dhcp)", address)
+    } else {
+        address
+    }
+}
+
+/// Returns a one-line summary of the given frame.
+fn summarize(frame: &CapturedFrame, link_type: LinkType) -> String {
+    let info = PacketInfo::parse(link_type, &frame.data);
+    let seconds_today = frame.timestamp.as_secs() % (24 * 60 * 60);
+    let time = format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        seconds_today / 3600,
+        seconds_today / 60 % 60,
+        seconds_today % 60,
+        frame.timestamp.subsec_micros(),
+    );
+    let direction = match frame.direction {
+        Direction::Received => "In ",
+        Direction::Sent => "Out",
+    };
+
+    let (Some(src_addr), Some(dst_addr)) = (info.src_addr, info.dst_addr) else {
+        let protocol = match info.ethertype {
+            Some(0x0806) => "ARP".to_string(),
+            Some(ethertype) => format!("ethertype {:#06x}", ethertype),
+            None => "unknown".to_string(),
+        };
+        return format!("{} {} {}, length {}", time, direction, protocol, frame.original_len);
+    };
+    let protocol = match info.ip_protocol {
+        Some(1) => "ICMP".to_string(),
+        Some(6) => "TCP".to_string(),
+        Some(17) => "UDP".to_string(),
+        Some(58) => "ICMP6".to_string(),
+        Some(protocol) => format!("protocol {}", protocol),
+        None => "unknown".to_string(),
+    };
+    format!(
+        "{} {} {} > {}: {}, length {}",
+        time,
+        direction,
+        endpoint(src_addr, info.src_port),
+        endpoint(dst_addr, info.dst_port),
+        protocol,
+        info.ip_payload_len.unwrap_or(0),
+    )
+}
+
+fn endpoint(address: IpAddress, port: Option<u16>) -> String {
+    match port {
+        Some(port) => format!("{}.{}", address, port),
+        None => address.to_string(),
+    }
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: tcpdump [OPTIONS] [EXPRESSION]
+Captures the frames sent and received by a network interface.
+Only frames that match EXPRESSION are captured, e.g., `tcp and port 80`.
+The capture runs until COUNT frames have been captured or tcpdump is killed.";
@@ -0,0 +1,110 @@
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use app_io::println;
+
+pub fn main(_args: Vec<String>) -> isize {
+    println!("testing deadline admission");
+    test_deadline_admission();
+    println!("testing fifo preemption");
+    test_fifo();
+    println!("done");
+    0
+}
+
+// Spawn deadline tasks on one CPU until its real-time bandwidth is exhausted,
+// and check that the next one is rejected.
+fn test_deadline_admission() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    let cpu = cpu::current_cpu();
+    let runtime = Duration::from_millis(30);
+    let period = Duration::from_millis(100);
+
+    let tasks = (0..3)
+        .map(|id| {
+            spawn::new_task_builder(deadline_worker, &STOP)
+                .name(format!("test-realtime-deadline-{id}"))
+                .pin_on_cpu(cpu)
+                .deadline(runtime, period, period)
+                .spawn()
+                .expect("failed to spawn deadline task")
+        })
+        .collect::<Vec<_>>();
+
+    let rejected = spawn::new_task_builder(deadline_worker, &STOP)
+        .name(String::from("test-realtime-deadline-rejected"))
+        .pin_on_cpu(cpu)
+        .deadline(runtime, period, period)
+        .spawn();
+    assert!(rejected.is_err(), "deadline task exceeding the CPU's bandwidth was admitted");
+
+    let invalid = spawn::new_task_builder(deadline_worker, &STOP)
+        .name(String::from("test-realtime-deadline-invalid"))
+        .deadline(period, runtime, period)
+        .spawn();
+    assert!(invalid.is_err(), "deadline task with runtime > deadline was admitted");
+
+    STOP.store(true, Ordering::Relaxed);
+    for task in tasks {
+        task.join().expect("failed to join deadline task");
+    }
+}
+
+fn deadline_worker(stop: &'static AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+    }
+}
+
+// Spin in a FIFO task and check that a normal task on the same CPU didn't run meanwhile.
+fn test_fifo() {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    let cpu = cpu::current_cpu();
+    let normal = spawn::new_task_builder(
+        |_: ()| {
+            while !STOP.load(Ordering::Relaxed) {
+                COUNTER.fetch_add(1, Ordering::Relaxed);
+            }
+        },
+        (),
+    )
+    .name(String::from("test-realtime-normal"))
+    .pin_on_cpu(cpu)
+    .spawn()
+    .expect("failed to spawn normal task");
+
+    let fifo = spawn::new_task_builder(
+        |_: ()| {
+            let before = COUNTER.load(Ordering::Relaxed);
+            let start = time::Instant::now();
+            while start.elapsed() < Duration::from_millis(100) {
+                core::hint::spin_loop();
+            }
+            COUNTER.load(Ordering::Relaxed) - before
+        },
+        (),
+    )
+    .name(String::from("test-realtime-fifo"))
+    .pin_on_cpu(cpu)
+    .fifo(1)
+    .spawn()
+    .expect("failed to spawn fifo task");
+
+    let preempted = match fifo.join().expect("failed to join fifo task") {
+        task::ExitValue::Completed(value) => *value.downcast::<usize>().expect("unexpected exit value"),
+        other => panic!("fifo task didn't complete: {other:?}"),
+    };
+    STOP.store(true, Ordering::Relaxed);
+    normal.join().expect("failed to join normal task");
+    assert_eq!(preempted, 0, "normal task ran while a fifo task was runnable");
+}
@@ -30,5 +30,10 @@ fn rmain(_args: Vec<String>) -> io::Result<()> {
     let mut out = theseus_std::fs::File::create("test.txt")?;
     out.write(b"yo what's up\nhey there!")?;
     out.write_all(b"take 2: yo what's up, hey there!")?;
+    drop(out);
+
+    theseus_std::fs::rename("test.txt", "test_renamed.txt")?;
+    let len = theseus_std::fs::metadata("test_renamed.txt")?.len();
+    println!("renamed test.txt to test_renamed.txt ({} bytes)", len);
     Ok(())
 }
@@ -0,0 +1,104 @@
+//! Tests the basic features of Theseus's port of the `std::net` module from Rust `std`.
+//!
+//! By default, exchanges data over TCP and UDP between sockets on the loopback interface,
+//! which doesn't depend on any external network.
+//!
+//! If a TCP server address is given, e.g., QEMU's host at `10.0.2.2:80`,
+//! instead connects to it, sends an HTTP request, and prints the response.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+use app_io::println;
+use core2::io::{self, Read, Write};
+use theseus_std::net::{Shutdown, TcpListener, TcpStream, UdpSocket};
+
+const MESSAGE: &[u8] = b"hello over loopback";
+
+pub fn main(args: Vec<String>) -> isize {
+    let result = match args.first() {
+        Some(server) => test_http_get(server),
+        None => test_loopback_tcp().and_then(|_| test_loopback_udp()),
+    };
+    match result {
+        Ok(_) => {
+            println!("test_std_net complete!");
+            0
+        }
+        Err(e) => {
+            println!("test_std_net error: {:?}", e);
+            -1
+        }
+    }
+}
+
+fn test_loopback_tcp() -> io::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let server_addr = listener.local_addr()?;
+    // The listening socket completes the handshake on its own,
+    // so connecting doesn't need to wait for `accept()`.
+    let mut client = TcpStream::connect(server_addr)?;
+    let (mut server, client_addr) = listener.accept()?;
+    println!("TCP: {} accepted connection from {}", server_addr, client_addr);
+
+    client.write_all(MESSAGE)?;
+    client.shutdown(Shutdown::Write)?;
+    let mut received = Vec::new();
+    server.read_to_end(&mut received)?;
+    if received != MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "TCP: received wrong data"));
+    }
+
+    server.write_all(&received)?;
+    server.shutdown(Shutdown::Write)?;
+    let mut echoed = Vec::new();
+    client.read_to_end(&mut echoed)?;
+    if echoed != MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "TCP: received wrong echo"));
+    }
+    println!("TCP: echoed {} bytes", echoed.len());
+    Ok(())
+}
+
+fn test_loopback_udp() -> io::Result<()> {
+    let server = UdpSocket::bind("127.0.0.1:0")?;
+    let client = UdpSocket::bind("127.0.0.1:0")?;
+    client.connect(server.local_addr()?)?;
+
+    client.send(MESSAGE)?;
+    let mut buf = [0; 64];
+    let (len, from) = server.recv_from(&mut buf)?;
+    if &buf[..len] != MESSAGE || from != client.local_addr()? {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "UDP: received wrong datagram"));
+    }
+
+    server.send_to(&buf[..len], from)?;
+    let len = client.recv(&mut buf)?;
+    if &buf[..len] != MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "UDP: received wrong echo"));
+    }
+    println!("UDP: echoed {} bytes", len);
+    Ok(())
+}
+
+fn test_http_get(server: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(server)?;
+    println!("connected to {} from {}", stream.peer_addr()?, stream.local_addr()?);
+
+    stream.write_all(b"GET / HTTP/1.0\r\nConnection: close\r\n\r\n")?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        let len = stream.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..len]);
+    }
+    println!("received {} bytes:\n{}", response.len(), String::from_utf8_lossy(&response));
+    Ok(())
+}
@@ -0,0 +1,273 @@
+//! Shows the tasks that use the most CPU time, refreshing periodically.
+//!
+//! A task's CPU usage is the run time it accumulated since the previous refresh,
+//! as a percentage of the time between refreshes, such that 100% is one whole CPU.
+//!
+//! The terminal is put into raw mode so that keys take effect as soon as they're pressed:
+//! `q` quits, and `c`, `t`, and `i` sort tasks by CPU usage, total run time, and ID.
+//! The display is drawn by a separate task, such that this task can block on reading input.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    collections::BTreeMap,
+    fmt::Write,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use app_io::{println, ImmutableRead, ImmutableWrite};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::Duration,
+};
+use getopts::Options;
+use task::{RunState, TaskRef};
+use time::Instant;
+
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+const DEFAULT_COUNT: usize = 20;
+
+/// How long CPU usage is sampled for before the display is first drawn.
+const FIRST_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the display task checks whether it should redraw or stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Clears the screen and moves the cursor to the top left corner.
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("top: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("d", "delay", "refresh every MS milliseconds (default: 1000)", "MS");
+    opts.optopt("n", "count", "show at most N tasks (default: 20)", "N");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    let interval = match matches.opt_str("d") {
+        Some(delay) => match delay.parse::<u64>() {
+            Ok(ms) if ms > 0 => Duration::from_millis(ms),
+            _ => return Err(format!("invalid delay: {delay}")),
+        },
+        None => Duration::from_millis(DEFAULT_INTERVAL_MS),
+    };
+    let count = match matches.opt_str("n") {
+        Some(count) => count.parse::<usize>().map_err(|_| format!("invalid count: {count}"))?,
+        None => DEFAULT_COUNT,
+    };
+
+    let discipline = app_io::line_discipline()?;
+    let stdin = app_io::stdin()?;
+    let stdout = app_io::stdout()?;
+    let main_task = task::get_my_current_task().ok_or("couldn't get current task")?;
+
+    let shared = Arc::new(Shared {
+        stop: AtomicBool::new(false),
+        redraw: AtomicBool::new(false),
+        sort_by: AtomicU8::new(SORT_BY_USAGE),
+    });
+    let display = Display {
+        stdout,
+        shared: shared.clone(),
+        main_task,
+        interval,
+        count,
+    };
+
+    discipline.set_raw();
+    let display_task = match spawn::new_task_builder(display_loop, display)
+        .name("top_display".to_string())
+        .spawn()
+    {
+        Ok(task) => task,
+        Err(e) => {
+            discipline.set_sane();
+            return Err(e.to_string());
+        }
+    };
+
+    let result = read_keys(&*stdin, &shared);
+    shared.stop.store(true, Ordering::Relaxed);
+    let _ = display_task.join();
+    discipline.set_sane();
+    println!();
+    result
+}
+
+/// The keys that select how tasks are sorted.
+const SORT_BY_USAGE: u8 = b'c';
+const SORT_BY_RUNTIME: u8 = b't';
+const SORT_BY_ID: u8 = b'i';
+
+/// The state shared between this app's main task and its display task.
+struct Shared {
+    /// Whether the display task should stop.
+    stop: AtomicBool,
+    /// Whether the display should be redrawn before the next refresh, e.g., to re-sort it.
+    redraw: AtomicBool,
+    /// The key that selects how tasks are sorted.
+    sort_by: AtomicU8,
+}
+
+/// Handles key presses until the user quits or stdin is closed.
+fn read_keys(stdin: &dyn ImmutableRead, shared: &Shared) -> Result<(), String> {
+    let mut buf = [0u8];
+    loop {
+        let byte_num = stdin.read(&mut buf).map_err(|_| "failed to read from stdin")?;
+        if byte_num == 0 {
+            return Ok(());
+        }
+        match buf[0] {
+            b'q' | b'Q' => return Ok(()),
+            key @ (SORT_BY_USAGE | SORT_BY_RUNTIME | SORT_BY_ID) => {
+                shared.sort_by.store(key, Ordering::Relaxed);
+                shared.redraw.store(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The argument of the display task.
+struct Display {
+    stdout: Arc<dyn ImmutableWrite>,
+    shared: Arc<Shared>,
+    /// This app's main task, which may be killed (e.g., by Ctrl+C) without stopping this one.
+    main_task: TaskRef,
+    interval: Duration,
+    count: usize,
+}
+
+impl Display {
+    fn should_stop(&self) -> bool {
+        self.shared.stop.load(Ordering::Relaxed) || self.main_task.has_exited()
+    }
+}
+
+/// A task's CPU usage over the last refresh interval.
+struct Row {
+    id: usize,
+    name: String,
+    runstate: RunState,
+    last_cpu: Option<cpu::CpuId>,
+    is_idle: bool,
+    /// The percentage of one CPU that the task used.
+    usage: f64,
+    runtime: Duration,
+    context_switches: usize,
+}
+
+/// The entry point of the task that periodically redraws the display.
+fn display_loop(display: Display) {
+    let mut previous = BTreeMap::new();
+    let mut previous_time = Instant::now();
+    let mut rows = sample(&mut previous, &mut previous_time);
+    let mut next_refresh = previous_time + FIRST_INTERVAL;
+
+    while !display.should_stop() {
+        let now = Instant::now();
+        if now >= next_refresh {
+            rows = sample(&mut previous, &mut previous_time);
+            next_refresh = now + display.interval;
+            draw(&display, &mut rows);
+        } else if display.shared.redraw.swap(false, Ordering::Relaxed) {
+            draw(&display, &mut rows);
+        }
+        let _ = sleep::sleep(POLL_INTERVAL.min(next_refresh.duration_since(now)));
+    }
+}
+
+/// Returns the CPU usage of all tasks since the `previous` sample, then replaces it with this one.
+fn sample(previous: &mut BTreeMap<usize, Duration>, previous_time: &mut Instant) -> Vec<Row> {
+    let now = Instant::now();
+    let elapsed = now.duration_since(*previous_time).as_nanos().max(1) as f64;
+    let mut current = BTreeMap::new();
+    let mut rows = Vec::new();
+
+    for (id, task) in task::all_tasks() {
+        let Some(task) = task.upgrade() else { continue };
+        let stats = task.cpu_stats();
+        let previous_runtime = previous.get(&id).copied().unwrap_or_default();
+        let ran = stats.runtime.saturating_sub(previous_runtime);
+        current.insert(id, stats.runtime);
+        rows.push(Row {
+            id,
+            name: task.name.clone(),
+            runstate: task.runstate(),
+            last_cpu: stats.last_cpu,
+            is_idle: task.is_an_idle_task,
+            usage: ran.as_nanos() as f64 * 100.0 / elapsed,
+            runtime: stats.runtime,
+            context_switches: stats.context_switches,
+        });
+    }
+
+    *previous = current;
+    *previous_time = now;
+    rows
+}
+
+fn draw(display: &Display, rows: &mut [Row]) {
+    match display.shared.sort_by.load(Ordering::Relaxed) {
+        SORT_BY_RUNTIME => rows.sort_by(|a, b| b.runtime.cmp(&a.runtime)),
+        SORT_BY_ID => rows.sort_by_key(|row| row.id),
+        _ => rows.sort_by(|a, b| b.usage.total_cmp(&a.usage).then(a.id.cmp(&b.id))),
+    }
+
+    let cpu_count = cpu::cpu_count().max(1);
+    let busy: f64 = rows.iter().filter(|row| !row.is_idle).map(|row| row.usage).sum();
+
+    let mut screen = String::from(CLEAR_SCREEN);
+    let _ = writeln!(
+        screen,
+        "top - {} tasks, {} CPUs, {:.1}% busy",
+        rows.len(),
+        cpu_count,
+        busy / cpu_count as f64,
+    );
+    let _ = writeln!(screen, "q: quit, c: sort by CPU usage, t: sort by total time, i: sort by ID\n");
+    let _ = writeln!(
+        screen,
+        "{0:<5}  {1:<10}  {2:<3}  {3:>6}  {4:>12}  {5:>9}  {6}",
+        "ID", "RUNSTATE", "CPU", "%CPU", "TIME", "SWITCHES", "NAME",
+    );
+    for row in rows.iter().take(display.count) {
+        let runstate = format!("{:?}", row.runstate);
+        let cpu = row.last_cpu.map(|cpu| format!("{cpu}")).unwrap_or_else(|| String::from("-"));
+        let runtime = format!("{}.{:03}", row.runtime.as_secs(), row.runtime.subsec_millis());
+        let name = if row.is_idle { format!("{} (idle)", row.name) } else { row.name.clone() };
+        let _ = writeln!(
+            screen,
+            "{0:<5}  {1:<10}  {2:<3}  {3:>6.1}  {4:>12}  {5:>9}  {6}",
+            row.id, runstate, cpu, row.usage, runtime, row.context_switches, name,
+        );
+    }
+
+    let _ = display.stdout.write_all(screen.as_bytes());
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: top [OPTIONS]
+Shows the tasks that use the most CPU time, refreshing periodically.
+Press q to quit, or c, t, or i to sort tasks by CPU usage, total run time, or ID.";
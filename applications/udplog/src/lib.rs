@@ -0,0 +1,83 @@
+//! Starts, stops, and shows the status of the UDP remote log sink.
+//!
+//! On the receiving machine, the records can be printed with, e.g.,
+//! `socat -u UDP-RECV:5901 STDOUT`, or with `tools/receive_udp_messages`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use app_io::println;
+use core::str::FromStr;
+use getopts::Options;
+use net::{IpAddress, IpEndpoint};
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("udplog: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("p", "port", "send records to PORT (default: 5901)", "PORT");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    match matches.free.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["start", host] => {
+            let port = matches
+                .opt_str("p")
+                .map(|port| port.parse::<u16>().map_err(|_| "invalid port"))
+                .transpose()?
+                .unwrap_or(udp_log_sink::DEFAULT_PORT);
+            let address = match IpAddress::from_str(host) {
+                Ok(address) => address,
+                Err(_) => {
+                    let interface = net::get_default_interface().ok_or("no network interfaces available")?;
+                    dns::resolve_one(&interface, host).map_err(|e| format!("{host}: {e}"))?
+                }
+            };
+            let remote = IpEndpoint::new(address, port);
+            udp_log_sink::start(remote)?;
+            println!("udplog: sending log records to {}", remote);
+        }
+        ["stop"] => udp_log_sink::stop()?,
+        ["status"] | [] => match udp_log_sink::status() {
+            Some(status) => {
+                let state = if status.sending { "sending" } else { "waiting for networking" };
+                println!("sending log records to {} ({})", status.remote, state);
+                println!("{} queued, {} sent, {} dropped", status.queued, status.sent, status.dropped);
+            }
+            None => println!("not sending log records"),
+        },
+        _ => {
+            print_usage(opts);
+            return Err("invalid arguments".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: udplog [OPTIONS] [start HOST | stop | status]
+Sends log records as UDP datagrams to HOST, stops sending them, or shows the status of the sink.
+Records logged before networking is up are queued and sent once it is.";
@@ -0,0 +1,62 @@
+//! Unmounts a filesystem that was mounted over a directory.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate app_io;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use getopts::Options;
+use path::Path;
+
+pub fn main(args: Vec<String>) -> isize {
+    match run(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("umount: {}", e);
+            -1
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = opts.parse(args).map_err(|e| e.to_string())?;
+
+    if matches.opt_present("h") {
+        print_usage(opts);
+        return Ok(());
+    }
+
+    if matches.free.is_empty() {
+        print_usage(opts);
+        return Err("missing argument".into());
+    }
+
+    let cwd = task::with_current_task(|t| t.get_env().lock().working_dir.clone())
+        .map_err(|_| "failed to get current task")?;
+
+    for target in &matches.free {
+        // Resolving the target yields the root directory of the filesystem mounted there.
+        let fs_root = Path::new(target).get_dir(&cwd)
+            .ok_or_else(|| format!("couldn't find directory {target:?}"))?;
+        let mount = mount_table::unmount(&fs_root)
+            .map_err(|e| format!("couldn't unmount {target:?}: {e}"))?;
+        println!("Unmounted {} from {}", mount.source(), mount.target());
+    }
+
+    Ok(())
+}
+
+fn print_usage(opts: Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &str = "Usage: umount TARGET...
+Unmounts the filesystem that is mounted at each TARGET directory.";
@@ -24,7 +24,6 @@ extern crate vfs_node;
 extern crate spin;
 
 
-use core::str::FromStr;
 use alloc::{
     borrow::ToOwned,
     string::{String, ToString},
@@ -62,7 +61,7 @@ pub fn main(args: Vec<String>) -> isize {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("v", "verbose", "enable verbose logging");
-    opts.optopt ("d", "destination", "specify the IP address (and optionally, the port) of the update server", "IP_ADDR[:PORT]");
+    opts.optopt ("d", "destination", "specify the hostname or IP address (and optionally, the port) of the update server", "HOST[:PORT]");
 
     let matches = match opts.parse(args) {
         Ok(m) => m,
@@ -97,29 +96,27 @@ pub fn main(args: Vec<String>) -> isize {
 
 
 fn rmain(matches: Matches) -> Result<(), String> {
-    let mut remote_endpoint = if let Some(ip_str) = matches.opt_str("d") {
-        IpEndpoint::from_str(&ip_str)
-            .map_err(|_e| "couldn't parse destination IP address/port".to_string())?
-    } else {
-        ota_update_client::default_remote_endpoint()
+    let destination = matches.opt_str("d");
+    // Only resolve the update server for commands that need it.
+    let remote_endpoint = || -> Result<IpEndpoint, String> {
+        let iface = get_default_interface().ok_or_else(|| "couldn't get default interface".to_owned())?;
+        ota_update_client::remote_endpoint(&iface, destination.as_deref())
+            .map_err(|e| format!("couldn't resolve update server destination: {e}"))
     };
-    if remote_endpoint.port == 0 {
-        remote_endpoint.port = ota_update_client::default_remote_endpoint().port;
-    }
 
     if verbose!() { println!("MATCHES: {:?}", matches.free); }
 
     match &*matches.free[0] {
         "list" | "ls" => {
-            list(remote_endpoint, matches.free.get(1))
+            list(remote_endpoint()?, matches.free.get(1))
         }
         "list-diff" | "ls-diff" => {
             let update_build = matches.free.get(1).ok_or_else(|| String::from("missing UPDATE_BUILD argument"))?;
-            diff(remote_endpoint, update_build)
+            diff(remote_endpoint()?, update_build)
         }
         "download" | "dl" => {
             let update_build = matches.free.get(1).ok_or_else(|| String::from("missing UPDATE_BUILD argument"))?;
-            download(remote_endpoint, update_build, matches.free.get(2..))
+            download(remote_endpoint()?, update_build, matches.free.get(2..))
         }
         "apply" | "ap" => {
             let base_dir_path = matches.free.get(1).ok_or_else(|| String::from("missing BASE_DIR path argument"))?;
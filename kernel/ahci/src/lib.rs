@@ -0,0 +1,707 @@
+//! Storage device driver for SATA drives attached to an AHCI controller,
+//! e.g., QEMU's `-device ahci` or the SATA controller of most modern chipsets.
+//!
+//! The primary structs of interest are [`AhciController`], which is a [`StorageController`],
+//! and [`AhciDrive`], which is a [`StorageDevice`].
+//!
+//! When an `AhciController` is created, it resets the HBA (Host Bus Adapter),
+//! discovers which of its ports have a SATA drive attached,
+//! and sets up each such port's command list, received FIS area, and command tables.
+//!
+//! Data is transferred using DMA via the drive's command slots.
+//! If both the HBA and the drive support Native Command Queuing (NCQ),
+//! a large transfer is split into several queued commands that are issued at once;
+//! otherwise, one command is issued at a time.
+//! The driver polls the port for the completion of its commands.
+//!
+//! # Limitations
+//! * Only SATA (non-ATAPI) drives are supported, and port multipliers are not.
+//! * Data is transferred through a per-port bounce buffer, so at most
+//!   [`MAX_SECTORS_PER_TRANSFER`] sectors can be read or written at once.
+
+#![no_std]
+
+extern crate alloc;
+
+mod regs;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use core::{cmp::min, hint, time::Duration};
+use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
+use log::{debug, error, info};
+use memory::{create_contiguous_mapping, MappedPages, PhysicalAddress, DMA_FLAGS};
+use pci::PciDevice;
+use spin::Mutex;
+use storage_device::{StorageController, StorageDevice, StorageDeviceRef};
+use regs::*;
+
+/// SATA drives addressed by this driver always use 512-byte logical sectors.
+const SECTOR_SIZE_IN_BYTES: usize = 512;
+
+/// The maximum number of sectors that can be transferred by a single read or write.
+pub const MAX_SECTORS_PER_TRANSFER: usize = 256;
+/// The number of sectors transferred by each queued command when a transfer is split up for NCQ.
+const SECTORS_PER_COMMAND: usize = 16;
+
+/// The maximum number of command slots that a port can have.
+const MAX_COMMAND_SLOTS: usize = 32;
+/// The maximum number of loop iterations to wait for the HBA or a port to change state.
+const MAX_WAIT_ITERATIONS: usize = 10_000_000;
+/// How long a port has after the HBA reset for its device to be detected at all.
+const DEVICE_DETECTION_TIMEOUT: Duration = Duration::from_millis(10);
+/// How long a port has after the HBA reset to establish communication with a detected device.
+const LINK_UP_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a drive has to complete an issued command before the port is restarted and the command fails.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The layout of each port's DMA memory region.
+/// The command list, which has one 32-byte header per command slot and must be 1K-aligned.
+const COMMAND_LIST_OFFSET:   usize = 0x0;
+/// The received FIS area, which must be 256-byte aligned.
+const RECEIVED_FIS_OFFSET:   usize = 0x400;
+/// The command tables, one per command slot, each of which must be 128-byte aligned.
+const COMMAND_TABLES_OFFSET: usize = 0x1000;
+/// The size of each command table: the command FIS area followed by a single PRDT entry, rounded up.
+const COMMAND_TABLE_SIZE:    usize = 0x100;
+/// The bounce buffer through which all data is transferred.
+const DATA_BUFFER_OFFSET:    usize = COMMAND_TABLES_OFFSET + MAX_COMMAND_SLOTS * COMMAND_TABLE_SIZE;
+const DATA_BUFFER_SIZE:      usize = MAX_SECTORS_PER_TRANSFER * SECTOR_SIZE_IN_BYTES;
+const PORT_MEMORY_SIZE:      usize = DATA_BUFFER_OFFSET + DATA_BUFFER_SIZE;
+
+/// The possible ATA commands that this driver issues to a drive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum AtaCommand {
+    /// Read sectors using DMA (28-bit LBA)
+    ReadDma          = 0xC8,
+    /// Write sectors using DMA (28-bit LBA)
+    WriteDma         = 0xCA,
+    /// Read sectors using DMA (48-bit LBA)
+    ReadDmaExt       = 0x25,
+    /// Write sectors using DMA (48-bit LBA)
+    WriteDmaExt      = 0x35,
+    /// Read sectors using native command queuing
+    ReadFpdmaQueued  = 0x60,
+    /// Write sectors using native command queuing
+    WriteFpdmaQueued = 0x61,
+    /// Flush the drive's write cache (28-bit LBA)
+    CacheFlush       = 0xE7,
+    /// Flush the drive's write cache (48-bit LBA)
+    CacheFlushExt    = 0xEA,
+    /// Get identifying details of an ATA drive.
+    IdentifyDevice   = 0xEC,
+}
+
+
+/// The HBA's memory-mapped registers, which are shared by the controller and all of its drives.
+type HbaRef = Arc<Mutex<MappedPages>>;
+
+/// Returns the registers of the given `port` within the HBA's memory-mapped registers.
+fn port_registers(hba: &mut MappedPages, port: usize) -> Result<&mut PortRegisters, &'static str> {
+    hba.as_type_mut(PORT_REGISTERS_OFFSET + port * PORT_REGISTERS_SIZE)
+}
+
+
+/// An AHCI controller, which has up to 32 ports, each of which may have a SATA drive attached.
+#[derive(Debug)]
+pub struct AhciController {
+    /// The drives attached to this controller's ports.
+    drives: Vec<AhciDriveRef>,
+}
+
+impl AhciController {
+    /// Creates a new instance of an AHCI controller based on the given PCI device,
+    /// initializing every SATA drive attached to its ports.
+    pub fn new(pci_device: &PciDevice) -> Result<AhciController, &'static str> {
+        pci_device.pci_set_command_bus_master_bit();
+        // The HBA's registers are mapped through its ABAR, which is BAR5.
+        let mut hba_mp = pci_device.pci_map_bar_mem(5)?;
+
+        let (capabilities, ports_implemented) = {
+            let regs: &mut HbaRegisters = hba_mp.as_type_mut(0)?;
+            // Reset the HBA to bring it into a known state; AHCI mode must be enabled first.
+            regs.ghc.write(regs.ghc.read() | GHC_AE);
+            regs.ghc.write(regs.ghc.read() | GHC_HR);
+            wait_until(|| regs.ghc.read() & GHC_HR == 0)
+                .map_err(|_| "AHCI controller did not complete its reset")?;
+            // The reset clears AHCI mode, so enable it again. We poll for completion, so disable interrupts.
+            regs.ghc.write((regs.ghc.read() | GHC_AE) & !GHC_IE);
+            regs.is.write(regs.is.read());
+            (regs.cap.read(), regs.pi.read())
+        };
+
+        // The ports up to and including the highest implemented port must be within the mapped ABAR.
+        let num_port_slots = (MAX_PORTS as u32 - ports_implemented.leading_zeros()) as usize;
+        if hba_mp.size_in_bytes() < PORT_REGISTERS_OFFSET + num_port_slots * PORT_REGISTERS_SIZE {
+            return Err("AHCI controller's ABAR was too small to cover all of its implemented ports");
+        }
+        let hba: HbaRef = Arc::new(Mutex::new(hba_mp));
+
+        let mut drives = Vec::new();
+        for port in (0 .. MAX_PORTS).filter(|p| ports_implemented & (1 << p) != 0) {
+            let sata_status = wait_for_link(&hba, port)?;
+            let signature = port_registers(&mut hba.lock(), port)?.sig.read();
+            let detection = sata_status & SSTS_DET_MASK;
+            let power_state = (sata_status >> SSTS_IPM_SHIFT) & SSTS_IPM_MASK;
+            if detection != SSTS_DET_PRESENT || power_state != SSTS_IPM_ACTIVE {
+                continue;
+            }
+            match signature {
+                SIG_ATA => match AhciDrive::new(Arc::clone(&hba), port, capabilities) {
+                    Ok(drive) => {
+                        info!("AHCI port {}: SATA drive {:?}, {} sectors, NCQ {}",
+                            port, drive.model.trim(), drive.sector_count,
+                            if drive.ncq_enabled { "enabled" } else { "disabled" },
+                        );
+                        drives.push(Arc::new(Mutex::new(drive)));
+                    }
+                    Err(e) => error!("AHCI port {}: failed to initialize SATA drive: {}", port, e),
+                },
+                SIG_ATAPI           => info!("AHCI port {}: unsupported SATAPI drive", port),
+                SIG_SEMB            => info!("AHCI port {}: unsupported enclosure management bridge", port),
+                SIG_PORT_MULTIPLIER => info!("AHCI port {}: unsupported port multiplier", port),
+                other               => info!("AHCI port {}: unknown device signature {:#X}", port, other),
+            }
+        }
+
+        info!("AHCI controller at {}: {} ports implemented, {} drives initialized",
+            pci_device.location, ports_implemented.count_ones(), drives.len(),
+        );
+        Ok(AhciController { drives })
+    }
+
+    /// Returns an `Iterator` over all of the `AhciDrive`s attached to this controller.
+    pub fn iter(&self) -> impl Iterator<Item = &AhciDriveRef> {
+        self.drives.iter()
+    }
+}
+
+impl StorageController for AhciController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(
+            self.drives.iter().map(|drive| Arc::clone(drive) as StorageDeviceRef)
+        )
+    }
+}
+
+
+/// A SATA drive attached to one port of an AHCI controller.
+#[derive(Debug)]
+pub struct AhciDrive {
+    /// The HBA's memory-mapped registers, shared with all other drives on the same controller.
+    hba: HbaRef,
+    /// The index of the port that this drive is attached to.
+    port: usize,
+    /// The command list, received FIS area, command tables, and data buffer of this drive's port.
+    memory: MappedPages,
+    memory_phys_addr: PhysicalAddress,
+    /// The number of command slots supported by the HBA.
+    num_command_slots: usize,
+    /// Whether the HBA supports Native Command Queuing.
+    hba_supports_ncq: bool,
+    /// The maximum number of queued commands supported by the drive, if it supports NCQ.
+    ncq_queue_depth: Option<usize>,
+    /// Whether transfers are split into several commands that are queued at once.
+    ncq_enabled: bool,
+    /// Whether the drive supports 48-bit LBAs; if not, the 28-bit commands are used instead.
+    supports_lba48: bool,
+    /// The size of the drive in sectors.
+    sector_count: usize,
+    /// The model number reported by the drive.
+    model: String,
+}
+
+impl AhciDrive {
+    /// Sets up the given `port` of the HBA and identifies the drive attached to it.
+    fn new(hba: HbaRef, port: usize, capabilities: u32) -> Result<AhciDrive, &'static str> {
+        let (mut memory, memory_phys_addr) = create_contiguous_mapping(PORT_MEMORY_SIZE, DMA_FLAGS)?;
+        if capabilities & CAP_S64A == 0 && (memory_phys_addr.value() + PORT_MEMORY_SIZE) > (u32::MAX as usize) {
+            return Err("AHCI controller cannot address DMA memory above 4 GiB");
+        }
+        memory.as_slice_mut::<u8>(0, PORT_MEMORY_SIZE)?.fill(0);
+
+        let num_command_slots = (((capabilities >> CAP_NCS_SHIFT) & CAP_NCS_MASK) + 1) as usize;
+        // Point each command header at its command table.
+        for slot in 0 .. num_command_slots {
+            let table_phys_addr = (memory_phys_addr.value() + COMMAND_TABLES_OFFSET + slot * COMMAND_TABLE_SIZE) as u64;
+            let header: &mut CommandHeader = memory.as_type_mut(COMMAND_LIST_OFFSET + slot * core::mem::size_of::<CommandHeader>())?;
+            header.ctba.write(table_phys_addr as u32);
+            header.ctbau.write((table_phys_addr >> 32) as u32);
+        }
+
+        let mut drive = AhciDrive {
+            hba,
+            port,
+            memory,
+            memory_phys_addr,
+            num_command_slots,
+            hba_supports_ncq: capabilities & CAP_SNCQ != 0,
+            ncq_queue_depth: None,
+            ncq_enabled: false,
+            supports_lba48: false,
+            sector_count: 0,
+            model: String::new(),
+        };
+
+        drive.stop_command_engine()?;
+        {
+            let command_list = (memory_phys_addr.value() + COMMAND_LIST_OFFSET) as u64;
+            let received_fis = (memory_phys_addr.value() + RECEIVED_FIS_OFFSET) as u64;
+            let mut hba = drive.hba.lock();
+            let regs = port_registers(&mut hba, port)?;
+            regs.clb.write(command_list as u32);
+            regs.clbu.write((command_list >> 32) as u32);
+            regs.fb.write(received_fis as u32);
+            regs.fbu.write((received_fis >> 32) as u32);
+            // Clear any errors and interrupts left over from the firmware; we poll instead of using interrupts.
+            regs.serr.write(0xFFFF_FFFF);
+            regs.is.write(0xFFFF_FFFF);
+            regs.ie.write(0);
+        }
+        drive.start_command_engine()?;
+
+        let identify_data = drive.identify()?;
+        // Words 100-103 hold the 48-bit sector count if the drive supports 48-bit LBAs (word 83, bit 10);
+        // otherwise, words 60-61 hold the 28-bit sector count.
+        drive.supports_lba48 = identify_data[83] & (1 << 10) != 0;
+        drive.sector_count = if drive.supports_lba48 {
+            (identify_data[100] as usize)
+                | (identify_data[101] as usize) << 16
+                | (identify_data[102] as usize) << 32
+                | (identify_data[103] as usize) << 48
+        } else {
+            (identify_data[60] as usize) | (identify_data[61] as usize) << 16
+        };
+        // Word 76, bit 8 indicates NCQ support, and word 75 holds the maximum queue depth minus one.
+        if identify_data[76] & (1 << 8) != 0 {
+            drive.ncq_queue_depth = Some((identify_data[75] as usize & 0x1F) + 1);
+        }
+        drive.ncq_enabled = drive.supports_ncq();
+        // The model number is in words 27-46, with the bytes of each word swapped.
+        drive.model = identify_data[27 ..= 46].iter()
+            .flat_map(|word| [(word >> 8) as u8 as char, *word as u8 as char])
+            .collect();
+        Ok(drive)
+    }
+
+    /// Returns the index of the HBA port that this drive is attached to.
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    /// Returns the model number reported by this drive.
+    pub fn model(&self) -> &str {
+        self.model.trim()
+    }
+
+    /// Returns the maximum number of queued commands supported by this drive,
+    /// or `None` if it doesn't support Native Command Queuing.
+    pub fn ncq_queue_depth(&self) -> Option<usize> {
+        self.ncq_queue_depth
+    }
+
+    /// Returns `true` if this drive currently uses Native Command Queuing for reads and writes.
+    pub fn is_ncq_enabled(&self) -> bool {
+        self.ncq_enabled
+    }
+
+    /// Sets whether this drive should use Native Command Queuing for reads and writes.
+    ///
+    /// Returns an error if NCQ is requested but isn't supported by the drive or the HBA.
+    pub fn set_ncq_enabled(&mut self, enabled: bool) -> Result<(), &'static str> {
+        if enabled && !self.supports_ncq() {
+            return Err("AhciDrive::set_ncq_enabled(): NCQ is not supported by this drive or its controller");
+        }
+        self.ncq_enabled = enabled;
+        Ok(())
+    }
+
+    /// Returns whether both this drive and its HBA support NCQ,
+    /// and the HBA has enough command slots to queue more than one command at once.
+    fn supports_ncq(&self) -> bool {
+        self.hba_supports_ncq && self.ncq_queue_depth.is_some() && self.num_command_slots >= 2
+    }
+
+    /// Reads data from this drive starting at the given `offset_in_sectors` into the provided `buffer`.
+    ///
+    /// The buffer length must be a multiple of the sector size (512 bytes)
+    /// and cover no more than [`MAX_SECTORS_PER_TRANSFER`] sectors.
+    ///
+    /// Returns the number of sectors (*not bytes*) that were read from the drive.
+    pub fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_transfer(buffer.len(), offset_in_sectors)?;
+        if sector_count == 0 {
+            return Ok(0);
+        }
+        self.transfer(offset_in_sectors, sector_count, false)?;
+        buffer.copy_from_slice(self.memory.as_slice(DATA_BUFFER_OFFSET, buffer.len())?);
+        Ok(sector_count)
+    }
+
+    /// Writes data from the provided `buffer` to this drive, starting at the given `offset_in_sectors` into the drive.
+    ///
+    /// The buffer length must be a multiple of the sector size (512 bytes)
+    /// and cover no more than [`MAX_SECTORS_PER_TRANSFER`] sectors.
+    ///
+    /// Returns the number of sectors (*not bytes*) that were written to the drive.
+    pub fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_transfer(buffer.len(), offset_in_sectors)?;
+        if sector_count == 0 {
+            return Ok(0);
+        }
+        self.memory.as_slice_mut(DATA_BUFFER_OFFSET, buffer.len())?.copy_from_slice(buffer);
+        self.transfer(offset_in_sectors, sector_count, true)?;
+        Ok(sector_count)
+    }
+
+    /// Flushes the drive's write cache, such that all previously-written data is persisted.
+    pub fn flush_cache(&mut self) -> Result<(), &'static str> {
+        let command = if self.supports_lba48 { AtaCommand::CacheFlushExt } else { AtaCommand::CacheFlush };
+        self.prepare_command(0, command, 0, 0)?;
+        self.issue_and_wait(1 << 0, false)
+    }
+
+    /// Checks that a transfer of `length_in_bytes` starting at `offset_in_sectors` is valid for this drive.
+    ///
+    /// Returns the number of sectors to be transferred.
+    fn check_transfer(&self, length_in_bytes: usize, offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if length_in_bytes % SECTOR_SIZE_IN_BYTES != 0 {
+            return Err("AhciDrive: the buffer length must be a multiple of sector size (512) bytes");
+        }
+        let sector_count = length_in_bytes / SECTOR_SIZE_IN_BYTES;
+        if sector_count > MAX_SECTORS_PER_TRANSFER {
+            return Err("AhciDrive: cannot transfer more sectors than the max per transfer");
+        }
+        if offset_in_sectors.checked_add(sector_count).map_or(true, |end| end > self.sector_count) {
+            return Err("AhciDrive: offset_in_sectors was out of bounds");
+        }
+        Ok(sector_count)
+    }
+
+    /// Transfers `sector_count` sectors between the drive, starting at `lba_start`,
+    /// and the beginning of the data buffer.
+    ///
+    /// `sector_count` must not be `0`, which the drive would interpret as 65536 sectors.
+    ///
+    /// If NCQ is enabled, the transfer is split into several commands that are queued at once.
+    fn transfer(&mut self, lba_start: usize, sector_count: usize, is_write: bool) -> Result<(), &'static str> {
+        if !self.ncq_enabled {
+            let command = match (is_write, self.supports_lba48) {
+                (true,  true)  => AtaCommand::WriteDmaExt,
+                (false, true)  => AtaCommand::ReadDmaExt,
+                (true,  false) => AtaCommand::WriteDma,
+                (false, false) => AtaCommand::ReadDma,
+            };
+            self.prepare_command(0, command, lba_start, sector_count)?;
+            return self.issue_and_wait(1 << 0, false);
+        }
+
+        let command = if is_write { AtaCommand::WriteFpdmaQueued } else { AtaCommand::ReadFpdmaQueued };
+        let queue_depth = min(self.num_command_slots, self.ncq_queue_depth.unwrap_or(1));
+        let mut sectors_done = 0;
+        while sectors_done < sector_count {
+            // Prepare one batch of queued commands, each of which uses the command slot matching its tag.
+            let mut slots = 0u32;
+            for slot in 0 .. queue_depth {
+                if sectors_done >= sector_count {
+                    break;
+                }
+                let count = min(SECTORS_PER_COMMAND, sector_count - sectors_done);
+                self.prepare_queued_command(slot, command, lba_start + sectors_done, count, sectors_done * SECTOR_SIZE_IN_BYTES)?;
+                slots |= 1 << slot;
+                sectors_done += count;
+            }
+            self.issue_and_wait(slots, true)?;
+        }
+        Ok(())
+    }
+
+    /// Issues an IDENTIFY DEVICE command and returns the drive's 256 words of identify data.
+    fn identify(&mut self) -> Result<[u16; 256], &'static str> {
+        self.prepare_command(0, AtaCommand::IdentifyDevice, 0, 1)?;
+        self.issue_and_wait(1 << 0, false)?;
+        let bytes: &[u8] = self.memory.as_slice(DATA_BUFFER_OFFSET, SECTOR_SIZE_IN_BYTES)?;
+        let mut words = [0u16; 256];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+            *word = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(words)
+    }
+
+    /// Fills in the command header and command table of the given command `slot` with a non-queued command,
+    /// which transfers data to or from the beginning of the data buffer.
+    /// Commands that don't transfer data should use a `sector_count` of `0`.
+    fn prepare_command(
+        &mut self,
+        slot: usize,
+        command: AtaCommand,
+        lba: usize,
+        sector_count: usize,
+    ) -> Result<(), &'static str> {
+        let mut fis = [0u8; FIS_REG_H2D_LENGTH];
+        fis[0] = FIS_TYPE_REG_H2D;
+        fis[1] = 0x80; // this FIS contains a command
+        fis[2] = command as u8;
+        if self.supports_lba48 {
+            write_lba(&mut fis, lba);
+        } else {
+            write_lba28(&mut fis, lba);
+        }
+        fis[12] = sector_count as u8;
+        fis[13] = (sector_count >> 8) as u8;
+        let is_write = matches!(command, AtaCommand::WriteDmaExt | AtaCommand::WriteDma);
+        self.write_command(slot, &fis, 0, sector_count * SECTOR_SIZE_IN_BYTES, is_write)
+    }
+
+    /// Fills in the command header and command table of the given command `slot` with a queued (NCQ) command,
+    /// whose tag is the same as its slot.
+    fn prepare_queued_command(
+        &mut self,
+        slot: usize,
+        command: AtaCommand,
+        lba: usize,
+        sector_count: usize,
+        data_offset: usize,
+    ) -> Result<(), &'static str> {
+        let mut fis = [0u8; FIS_REG_H2D_LENGTH];
+        fis[0] = FIS_TYPE_REG_H2D;
+        fis[1] = 0x80; // this FIS contains a command
+        fis[2] = command as u8;
+        // For queued commands, the sector count goes in the features registers and the tag in the count register.
+        fis[3] = sector_count as u8;
+        fis[11] = (sector_count >> 8) as u8;
+        write_lba(&mut fis, lba);
+        fis[12] = (slot as u8) << 3;
+        let is_write = command == AtaCommand::WriteFpdmaQueued;
+        self.write_command(slot, &fis, data_offset, sector_count * SECTOR_SIZE_IN_BYTES, is_write)
+    }
+
+    /// Writes the given command `fis` into the command table of `slot`,
+    /// along with a PRDT entry describing `length` bytes at `data_offset` into the data buffer.
+    fn write_command(
+        &mut self,
+        slot: usize,
+        fis: &[u8; FIS_REG_H2D_LENGTH],
+        data_offset: usize,
+        length: usize,
+        is_write: bool,
+    ) -> Result<(), &'static str> {
+        if slot >= self.num_command_slots {
+            return Err("AhciDrive: command slot is not supported by the controller");
+        }
+        if data_offset + length > DATA_BUFFER_SIZE {
+            return Err("AhciDrive: command would overflow the data buffer");
+        }
+        let table_offset = COMMAND_TABLES_OFFSET + slot * COMMAND_TABLE_SIZE;
+        {
+            let table: &mut [u8] = self.memory.as_slice_mut(table_offset, COMMAND_TABLE_HEADER_SIZE)?;
+            table.fill(0);
+            table[.. FIS_REG_H2D_LENGTH].copy_from_slice(fis);
+        }
+        let prdt_length = if length == 0 { 0 } else { 1 };
+        if length != 0 {
+            let data_phys_addr = (self.memory_phys_addr.value() + DATA_BUFFER_OFFSET + data_offset) as u64;
+            let entry: &mut PrdtEntry = self.memory.as_type_mut(table_offset + COMMAND_TABLE_HEADER_SIZE)?;
+            entry.dba.write(data_phys_addr as u32);
+            entry.dbau.write((data_phys_addr >> 32) as u32);
+            entry.dbc.write((length - 1) as u32);
+        }
+
+        let mut flags = CommandHeaderFlags::empty();
+        if is_write {
+            flags |= CommandHeaderFlags::WRITE;
+        }
+        let header: &mut CommandHeader = self.memory.as_type_mut(COMMAND_LIST_OFFSET + slot * core::mem::size_of::<CommandHeader>())?;
+        header.flags.write(flags.bits() | (prdt_length << 16) | (FIS_REG_H2D_LENGTH / 4) as u32);
+        header.prdbc.write(0);
+        Ok(())
+    }
+
+    /// Issues the commands in the given bitmask of command `slots` and waits for all of them to complete.
+    ///
+    /// If `queued` is `true`, the commands are NCQ commands, which must also be marked as active.
+    fn issue_and_wait(&mut self, slots: u32, queued: bool) -> Result<(), &'static str> {
+        let port = self.port;
+        self.wait_for_port(|regs| regs.tfd.read() & (PORT_TFD_BSY | PORT_TFD_DRQ) == 0)
+            .map_err(|_| "AhciDrive: port was busy before issuing a command")?;
+        {
+            let mut hba = self.hba.lock();
+            let regs = port_registers(&mut hba, port)?;
+            if queued {
+                regs.sact.write(slots);
+            }
+            regs.ci.write(slots);
+        }
+
+        let start = time::now::<time::Monotonic>();
+        loop {
+            let (interrupt_status, command_issue, active, task_file) = {
+                let mut hba = self.hba.lock();
+                let regs = port_registers(&mut hba, port)?;
+                (regs.is.read(), regs.ci.read(), regs.sact.read(), regs.tfd.read())
+            };
+            if interrupt_status & PORT_IS_ERROR_MASK != 0 || task_file & PORT_TFD_ERR != 0 {
+                error!("AhciDrive: port {} reported an error: interrupt status {:#X}, task file {:#X}",
+                    port, interrupt_status, task_file,
+                );
+                self.recover()?;
+                return Err("AhciDrive: the drive reported an error");
+            }
+            if (command_issue | active) & slots == 0 {
+                break;
+            }
+            if time::now::<time::Monotonic>().duration_since(start) >= COMMAND_TIMEOUT {
+                error!("AhciDrive: port {} timed out waiting for commands to complete: CI {:#X}, SACT {:#X}, TFD {:#X}",
+                    port, command_issue, active, task_file,
+                );
+                self.recover()?;
+                return Err("AhciDrive: timed out waiting for the drive to complete a command");
+            }
+            hint::spin_loop();
+        }
+
+        let mut hba = self.hba.lock();
+        let regs = port_registers(&mut hba, port)?;
+        regs.is.write(regs.is.read());
+        Ok(())
+    }
+
+    /// Recovers the port after an error by restarting its command engine and clearing its error status.
+    fn recover(&mut self) -> Result<(), &'static str> {
+        self.stop_command_engine()?;
+        {
+            let mut hba = self.hba.lock();
+            let regs = port_registers(&mut hba, self.port)?;
+            regs.serr.write(0xFFFF_FFFF);
+            regs.is.write(0xFFFF_FFFF);
+        }
+        debug!("AhciDrive: restarting port {} after an error", self.port);
+        self.start_command_engine()
+    }
+
+    /// Stops the port from processing its command list and receiving FISes.
+    fn stop_command_engine(&mut self) -> Result<(), &'static str> {
+        self.modify_port(|regs| regs.cmd.write(regs.cmd.read() & !PORT_CMD_ST))?;
+        self.wait_for_port(|regs| regs.cmd.read() & PORT_CMD_CR == 0)
+            .map_err(|_| "AhciDrive: port's command list did not stop running")?;
+        self.modify_port(|regs| regs.cmd.write(regs.cmd.read() & !PORT_CMD_FRE))?;
+        self.wait_for_port(|regs| regs.cmd.read() & PORT_CMD_FR == 0)
+            .map_err(|_| "AhciDrive: port's FIS receive did not stop running")
+    }
+
+    /// Starts the port's processing of its command list and receiving of FISes.
+    fn start_command_engine(&mut self) -> Result<(), &'static str> {
+        self.wait_for_port(|regs| regs.cmd.read() & PORT_CMD_CR == 0)
+            .map_err(|_| "AhciDrive: port's command list was still running")?;
+        self.modify_port(|regs| regs.cmd.write(regs.cmd.read() | PORT_CMD_FRE | PORT_CMD_SUD))?;
+        self.modify_port(|regs| regs.cmd.write(regs.cmd.read() | PORT_CMD_ST))
+    }
+
+    fn modify_port<F: FnOnce(&mut PortRegisters)>(&self, f: F) -> Result<(), &'static str> {
+        let mut hba = self.hba.lock();
+        f(port_registers(&mut hba, self.port)?);
+        Ok(())
+    }
+
+    /// Polls this drive's port registers until the given `condition` is met.
+    fn wait_for_port<F: Fn(&mut PortRegisters) -> bool>(&self, condition: F) -> Result<(), &'static str> {
+        for _ in 0 .. MAX_WAIT_ITERATIONS {
+            let mut hba = self.hba.lock();
+            if condition(port_registers(&mut hba, self.port)?) {
+                return Ok(());
+            }
+            drop(hba);
+            hint::spin_loop();
+        }
+        Err("AhciDrive: timed out waiting for the port")
+    }
+}
+
+impl StorageDevice for AhciDrive {
+    fn size_in_blocks(&self) -> usize {
+        self.sector_count
+    }
+}
+impl BlockIo for AhciDrive {
+    fn block_size(&self) -> usize { SECTOR_SIZE_IN_BYTES }
+}
+impl KnownLength for AhciDrive {
+    fn len(&self) -> usize { self.block_size() * self.size_in_blocks() }
+}
+impl BlockReader for AhciDrive {
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        self.read_sectors(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
+    }
+}
+impl BlockWriter for AhciDrive {
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        self.write_sectors(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.flush_cache().map_err(|_e| IoError::Other("AhciDrive: failed to flush the drive's cache"))
+    }
+}
+
+pub type AhciDriveRef = Arc<Mutex<AhciDrive>>;
+
+
+/// Writes the given 48-bit `lba` into a Register Host-to-Device FIS, selecting LBA addressing mode.
+fn write_lba(fis: &mut [u8; FIS_REG_H2D_LENGTH], lba: usize) {
+    fis[4] = lba as u8;
+    fis[5] = (lba >> 8) as u8;
+    fis[6] = (lba >> 16) as u8;
+    fis[7] = 1 << 6; // LBA mode
+    fis[8] = (lba >> 24) as u8;
+    fis[9] = (lba >> 32) as u8;
+    fis[10] = (lba >> 40) as u8;
+}
+
+/// Writes the given 28-bit `lba` into a Register Host-to-Device FIS, selecting LBA addressing mode.
+///
+/// The top four bits of a 28-bit LBA go in the low nibble of the device register.
+fn write_lba28(fis: &mut [u8; FIS_REG_H2D_LENGTH], lba: usize) {
+    fis[4] = lba as u8;
+    fis[5] = (lba >> 8) as u8;
+    fis[6] = (lba >> 16) as u8;
+    fis[7] = 1 << 6 | ((lba >> 24) as u8 & 0x0F); // LBA mode
+}
+
+/// Polls the SATA status of the given `port` until its link with a device is established,
+/// returning the last value of its `PxSSTS` register.
+///
+/// The HBA reset makes every port renegotiate its link, so `PxSSTS` can't be trusted right away.
+/// Per the AHCI spec, a port with no device detected after 10ms is empty,
+/// whereas a detected device has up to 1s to establish communication.
+fn wait_for_link(hba: &HbaRef, port: usize) -> Result<u32, &'static str> {
+    {
+        // If the HBA supports staggered spin-up, the device is not detected until it has been spun up.
+        let mut hba_locked = hba.lock();
+        let regs = port_registers(&mut hba_locked, port)?;
+        regs.cmd.write(regs.cmd.read() | PORT_CMD_SUD);
+    }
+    let start = time::now::<time::Monotonic>();
+    loop {
+        let sata_status = port_registers(&mut hba.lock(), port)?.ssts.read();
+        let detection = sata_status & SSTS_DET_MASK;
+        let elapsed = time::now::<time::Monotonic>().duration_since(start);
+        if detection == SSTS_DET_PRESENT
+            || (detection == SSTS_DET_NONE && elapsed >= DEVICE_DETECTION_TIMEOUT)
+            || elapsed >= LINK_UP_TIMEOUT
+        {
+            return Ok(sata_status);
+        }
+        hint::spin_loop();
+    }
+}
+
+/// Polls the given `condition` until it is met, or returns an error if it takes too long.
+fn wait_until<F: FnMut() -> bool>(mut condition: F) -> Result<(), ()> {
+    for _ in 0 .. MAX_WAIT_ITERATIONS {
+        if condition() {
+            return Ok(());
+        }
+        hint::spin_loop();
+    }
+    Err(())
+}
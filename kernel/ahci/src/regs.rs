@@ -0,0 +1,194 @@
+//! The memory-mapped registers of an AHCI controller (HBA) and its ports,
+//! as well as the in-memory structures that the HBA reads and writes via DMA.
+//!
+//! The HBA's registers are mapped from its ABAR (BAR5) and consist of:
+//! * `HbaRegisters`: the generic host control registers, at offset `0x0`.
+//! * `PortRegisters`: one set of registers per port, at offset `0x100 + (port * 0x80)`.
+//!
+//! See the AHCI specification, Section 3, for more details:
+//! <https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/serial-ata-ahci-spec-rev1-3-1.pdf>
+
+#![allow(dead_code)] // not every register and bit is used by the driver
+
+use bitflags::bitflags;
+use volatile::{Volatile, ReadOnly};
+use zerocopy::FromBytes;
+
+/// The offset of the first port's registers from the start of the HBA's registers.
+pub const PORT_REGISTERS_OFFSET: usize = 0x100;
+/// The size of each port's registers.
+pub const PORT_REGISTERS_SIZE: usize = 0x80;
+/// The maximum number of ports that an HBA may implement.
+pub const MAX_PORTS: usize = 32;
+
+/// The layout in memory of the generic host control registers.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct HbaRegisters {
+    /// Host capabilities
+    pub cap:                        ReadOnly<u32>,          // 0x0
+    /// Global host control
+    pub ghc:                        Volatile<u32>,          // 0x4
+    /// Interrupt status, one bit per port
+    pub is:                         Volatile<u32>,          // 0x8
+    /// Ports implemented, one bit per port
+    pub pi:                         ReadOnly<u32>,          // 0xC
+    /// AHCI version
+    pub vs:                         ReadOnly<u32>,          // 0x10
+    pub ccc_ctl:                    Volatile<u32>,          // 0x14
+    pub ccc_ports:                  Volatile<u32>,          // 0x18
+    pub em_loc:                     ReadOnly<u32>,          // 0x1C
+    pub em_ctl:                     Volatile<u32>,          // 0x20
+    /// Extended host capabilities
+    pub cap2:                       ReadOnly<u32>,          // 0x24
+    /// BIOS/OS handoff control and status
+    pub bohc:                       Volatile<u32>,          // 0x28
+    _padding0:                      [u8; 212],              // 0x2C - 0xFF, reserved and vendor specific
+}
+
+/// The layout in memory of one port's registers.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct PortRegisters {
+    /// Command list base address, lower 32 bits (1K-aligned)
+    pub clb:                        Volatile<u32>,          // 0x0
+    /// Command list base address, upper 32 bits
+    pub clbu:                       Volatile<u32>,          // 0x4
+    /// FIS base address, lower 32 bits (256-byte aligned)
+    pub fb:                         Volatile<u32>,          // 0x8
+    /// FIS base address, upper 32 bits
+    pub fbu:                        Volatile<u32>,          // 0xC
+    /// Interrupt status
+    pub is:                         Volatile<u32>,          // 0x10
+    /// Interrupt enable
+    pub ie:                         Volatile<u32>,          // 0x14
+    /// Command and status
+    pub cmd:                        Volatile<u32>,          // 0x18
+    _padding0:                      [u8; 4],                // 0x1C
+    /// Task file data, which mirrors the ATA status (bits 7:0) and error (bits 15:8) registers
+    pub tfd:                        ReadOnly<u32>,          // 0x20
+    /// Signature of the attached device
+    pub sig:                        ReadOnly<u32>,          // 0x24
+    /// SATA status (SCR0: SStatus)
+    pub ssts:                       ReadOnly<u32>,          // 0x28
+    /// SATA control (SCR2: SControl)
+    pub sctl:                       Volatile<u32>,          // 0x2C
+    /// SATA error (SCR1: SError)
+    pub serr:                       Volatile<u32>,          // 0x30
+    /// SATA active (SCR3: SActive), one bit per NCQ command slot
+    pub sact:                       Volatile<u32>,          // 0x34
+    /// Command issue, one bit per command slot
+    pub ci:                         Volatile<u32>,          // 0x38
+    pub sntf:                       Volatile<u32>,          // 0x3C
+    pub fbs:                        Volatile<u32>,          // 0x40
+    pub devslp:                     Volatile<u32>,          // 0x44
+    _padding1:                      [u8; 56],               // 0x48 - 0x7F, reserved and vendor specific
+}
+
+/// HBA capabilities bit: supports 64-bit addressing
+pub const CAP_S64A:                 u32 = 1 << 31;
+/// HBA capabilities bit: supports native command queuing
+pub const CAP_SNCQ:                 u32 = 1 << 30;
+/// HBA capabilities: the number of command slots per port, minus one
+pub const CAP_NCS_SHIFT:            u32 = 8;
+pub const CAP_NCS_MASK:             u32 = 0x1F;
+
+/// Global host control bit: AHCI enable
+pub const GHC_AE:                   u32 = 1 << 31;
+/// Global host control bit: interrupt enable
+pub const GHC_IE:                   u32 = 1 << 1;
+/// Global host control bit: HBA reset
+pub const GHC_HR:                   u32 = 1 << 0;
+
+/// Port command bit: command list running
+pub const PORT_CMD_CR:              u32 = 1 << 15;
+/// Port command bit: FIS receive running
+pub const PORT_CMD_FR:              u32 = 1 << 14;
+/// Port command bit: FIS receive enable
+pub const PORT_CMD_FRE:             u32 = 1 << 4;
+/// Port command bit: spin-up device
+pub const PORT_CMD_SUD:             u32 = 1 << 1;
+/// Port command bit: start processing the command list
+pub const PORT_CMD_ST:              u32 = 1 << 0;
+
+/// Port interrupt status bit: task file error
+pub const PORT_IS_TFES:             u32 = 1 << 30;
+/// Port interrupt status bits that indicate a fatal error on the port.
+pub const PORT_IS_ERROR_MASK:       u32 = 0x7D80_0000;
+
+/// Task file data bit: the device is busy
+pub const PORT_TFD_BSY:             u32 = 1 << 7;
+/// Task file data bit: the device is requesting a data transfer
+pub const PORT_TFD_DRQ:             u32 = 1 << 3;
+/// Task file data bit: the device reported an error
+pub const PORT_TFD_ERR:             u32 = 1 << 0;
+
+/// SATA status: device detection (bits 3:0); `3` means a device is present and communicating,
+/// `1` means a device was detected but communication is not yet established, and `0` means no device was detected.
+pub const SSTS_DET_MASK:            u32 = 0xF;
+pub const SSTS_DET_NONE:            u32 = 0x0;
+pub const SSTS_DET_PRESENT:         u32 = 0x3;
+/// SATA status: interface power management (bits 11:8); `1` means the interface is active.
+pub const SSTS_IPM_SHIFT:           u32 = 8;
+pub const SSTS_IPM_MASK:            u32 = 0xF;
+pub const SSTS_IPM_ACTIVE:          u32 = 0x1;
+
+/// The possible signatures of devices attached to a port.
+pub const SIG_ATA:                  u32 = 0x0000_0101;
+pub const SIG_ATAPI:                u32 = 0xEB14_0101;
+pub const SIG_SEMB:                 u32 = 0xC33C_0101;
+pub const SIG_PORT_MULTIPLIER:      u32 = 0x9669_0101;
+
+
+/// An entry in a port's command list, which describes the command in one command slot.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct CommandHeader {
+    /// Bits 4:0: command FIS length in dwords, bit 6: write, bits 31:16: PRDT length in entries
+    pub flags:                      Volatile<u32>,
+    /// The number of bytes transferred so far, updated by the HBA
+    pub prdbc:                      Volatile<u32>,
+    /// Command table base address, lower 32 bits (128-byte aligned)
+    pub ctba:                       Volatile<u32>,
+    /// Command table base address, upper 32 bits
+    pub ctbau:                      Volatile<u32>,
+    _reserved:                      [u32; 4],
+}
+
+bitflags! {
+    /// Flags in the first dword of a command header.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct CommandHeaderFlags: u32 {
+        /// Clear busy upon R_OK
+        const CLEAR_BUSY = 1 << 10;
+        /// Prefetchable
+        const PREFETCH   = 1 << 7;
+        /// The direction of the data transfer is from memory to the device.
+        const WRITE      = 1 << 6;
+        /// The command is for an ATAPI device.
+        const ATAPI      = 1 << 5;
+    }
+}
+
+/// An entry in a command table's Physical Region Descriptor Table (PRDT),
+/// which describes one region of memory that data is transferred to or from.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct PrdtEntry {
+    /// Data base address, lower 32 bits
+    pub dba:                        Volatile<u32>,
+    /// Data base address, upper 32 bits
+    pub dbau:                       Volatile<u32>,
+    _reserved:                      u32,
+    /// Bits 21:0: byte count minus one (must be odd, i.e., an even byte count), bit 31: interrupt on completion
+    pub dbc:                        Volatile<u32>,
+}
+
+/// The size in bytes of the command FIS, ATAPI command, and reserved region
+/// at the start of every command table, which precede the PRDT.
+pub const COMMAND_TABLE_HEADER_SIZE: usize = 0x80;
+
+/// The type of a Register Host-to-Device FIS, which is used to send an ATA command.
+pub const FIS_TYPE_REG_H2D:         u8 = 0x27;
+/// The length of a Register Host-to-Device FIS in bytes.
+pub const FIS_REG_H2D_LENGTH:       usize = 20;
@@ -1,7 +1,8 @@
 #![no_std]
 #![feature(let_chains)]
 
-use core::{fmt, sync::atomic::{AtomicU32, Ordering}, convert::TryFrom};
+use core::{fmt, sync::atomic::{AtomicU32, Ordering}, convert::TryFrom, time::Duration};
+use core::arch::x86_64::{_mm_mfence, _rdtsc};
 use derive_more::*;
 use volatile::{Volatile, ReadOnly, WriteOnly};
 use zerocopy::FromBytes;
@@ -29,9 +30,9 @@ pub const APIC_SPURIOUS_INTERRUPT_IRQ: u8  = 0xFF;
 
 // APIC timer register values.
 const APIC_TIMER_DISABLE:              u32 = 1 << 16;
-const _APIC_TIMER_MODE_ONESHOT:        u32 = 0b00 << 17;
+const APIC_TIMER_MODE_ONESHOT:         u32 = 0b00 << 17;
 const APIC_TIMER_MODE_PERIODIC:        u32 = 0b01 << 17;
-const _APIC_TIMER_MODE_TSC_DEADLINE:   u32 = 0b10 << 17;
+const APIC_TIMER_MODE_TSC_DEADLINE:    u32 = 0b10 << 17;
 /// The IRQ number reserved for Local APIC timer interrupts in the IDT.
 pub const LOCAL_APIC_LVT_IRQ:          u8  = 0x22;
 
@@ -110,6 +111,17 @@ pub fn has_x2apic() -> bool {
     *res // because call_once returns a reference to the cached IS_X2APIC value
 }
 
+/// Returns `true` if the LVT timer of this machine's Local APICs supports TSC-deadline mode.
+pub fn has_tsc_deadline() -> bool {
+    static HAS_TSC_DEADLINE: Once<bool> = Once::new(); // cache the result
+    *HAS_TSC_DEADLINE.call_once(||
+        X86CpuIdInstr::new()
+            .get_feature_info()
+            .expect("Couldn't get CpuId feature info")
+            .has_tsc_deadline()
+    )
+}
+
 /// Returns a reference to the list of LocalApics, one per CPU core.
 pub fn get_lapics() -> &'static AtomicMap<ApicId, IrqSafeRwLock<LocalApic>> {
 	&LOCAL_APICS
@@ -426,6 +438,24 @@ pub struct LocalApic {
     /// The value that should be written to the APIC timer's initial count register
     /// when enabling the LVT timer.
     initial_timer_count: u32,
+    /// The number of TSC ticks per timeslice, which is used to convert one-shot timeouts
+    /// into TSC deadlines. This is `0` if the TSC wasn't calibrated.
+    tsc_ticks_per_timeslice: u64,
+    /// How the LVT timer fires whenever it is enabled.
+    timer_mode: TimerMode,
+    /// Whether the LVT timer is currently enabled (unmasked).
+    timer_enabled: bool,
+}
+
+/// How the LVT timer of a [`LocalApic`] fires whenever it is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TimerMode {
+    /// Once every timeslice.
+    Periodic,
+    /// Once, when the TSC reaches the given value.
+    OneShot { tsc_deadline: u64 },
+    /// Never.
+    Stopped,
 }
 impl fmt::Debug for LocalApic {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -524,6 +554,9 @@ impl LocalApic {
             apic_id: ApicId(u32::MAX), // placeholder, is replaced below.
             is_bootstrap_cpu,
             initial_timer_count: 0, // set in `calibrate_lapic_timer()`
+            tsc_ticks_per_timeslice: 0, // set in `calibrate_lapic_timer()`
+            timer_mode: TimerMode::Periodic,
+            timer_enabled: true,
         };
 
         // Now that the APIC hardware is enabled, we can safely obtain this Local APIC's ID.
@@ -622,39 +655,44 @@ impl LocalApic {
         }
     }
 
-    /// Returns the number of APIC ticks that occurred during the given number of `microseconds`.
+    /// Returns the number of APIC ticks and the number of TSC ticks that occurred
+    /// during the given number of `microseconds`.
     ///
-    /// The number of ticks must be a `u32` due to the size of the APIC timer count register.
-    fn calibrate_lapic_timer(&mut self, microseconds: u32) -> u32 {
+    /// The number of APIC ticks must be a `u32` due to the size of the APIC timer count register.
+    fn calibrate_lapic_timer(&mut self, microseconds: u32) -> (u32, u64) {
         // Start with the max counter value, since we're counting down
         const INITIAL_COUNT: u32 = 0xFFFF_FFFF;
 
-        let end_count = match &mut self.inner {
+        let (end_count, tsc_ticks) = match &mut self.inner {
             LapicType::X2Apic => {
                 unsafe { 
                     wrmsr(IA32_X2APIC_DIV_CONF, LapicTimerDivide::By16.as_register_value() as u64);
                     wrmsr(IA32_X2APIC_INIT_COUNT, INITIAL_COUNT as u64);
                 }
+                let start_tsc = unsafe { _rdtsc() };
 
                 // wait for the given period using the PIT clock
                 pit_wait(microseconds).unwrap();
 
                 unsafe { wrmsr(IA32_X2APIC_LVT_TIMER, APIC_TIMER_DISABLE as u64); } // stop apic timer
-                rdmsr(IA32_X2APIC_CUR_COUNT) as u32
+                let end_tsc = unsafe { _rdtsc() };
+                (rdmsr(IA32_X2APIC_CUR_COUNT) as u32, end_tsc - start_tsc)
             }
             LapicType::XApic(regs) => {
                 regs.timer_divide.write(LapicTimerDivide::By16.as_register_value());
                 regs.timer_initial_count.write(INITIAL_COUNT);
+                let start_tsc = unsafe { _rdtsc() };
 
                 // wait for the given period using the PIT clock
                 pit_wait(microseconds).unwrap();
 
                 regs.lvt_timer.write(APIC_TIMER_DISABLE); // stop apic timer
-                regs.timer_current_count.read()
+                let end_tsc = unsafe { _rdtsc() };
+                (regs.timer_current_count.read(), end_tsc - start_tsc)
             }
         };
         
-        INITIAL_COUNT - end_count
+        (INITIAL_COUNT - end_count, tsc_ticks)
     }
 
     /// After this lapic has been enabled, initialize its LVT timer.
@@ -663,7 +701,9 @@ impl LocalApic {
             info!("apic_timer_fixed config: overriding LocalAPIC LVT timer period to {}", 1000000);
             1000000 // for bochs, which doesn't do apic periods right
         } else {
-            self.calibrate_lapic_timer(CONFIG_TIMESLICE_PERIOD_MICROSECONDS)
+            let (apic_period, tsc_period) = self.calibrate_lapic_timer(CONFIG_TIMESLICE_PERIOD_MICROSECONDS);
+            self.tsc_ticks_per_timeslice = tsc_period;
+            apic_period
         };
         trace!("LocalApic {}, timer period count: {} ({:#X})", self.apic_id, apic_period, apic_period);
         self.initial_timer_count = apic_period;
@@ -698,36 +738,111 @@ impl LocalApic {
     }
 
     /// Enable (unmask) or disable (mask) the LVT timer interrupt on this lapic.
+    ///
+    /// When enabled, the timer fires in the mode last set by [`LocalApic::set_timer_periodic()`]
+    /// or [`LocalApic::set_timer_oneshot()`], which is periodic by default.
     pub fn enable_lvt_timer(&mut self, enable: bool) {
+        self.timer_enabled = enable;
+        self.program_lvt_timer();
+    }
+
+    /// Returns `true` if this lapic's timer can be programmed with [`LocalApic::set_timer_oneshot()`].
+    ///
+    /// This requires the TSC to have been calibrated along with the timer,
+    /// which isn't the case under the `apic_timer_fixed` config.
+    pub fn supports_oneshot_timer(&self) -> bool {
+        self.tsc_ticks_per_timeslice != 0
+    }
+
+    /// Sets the LVT timer to fire once every timeslice, which is the default.
+    pub fn set_timer_periodic(&mut self) {
+        if self.timer_mode != TimerMode::Periodic {
+            self.timer_mode = TimerMode::Periodic;
+            self.program_lvt_timer();
+        }
+    }
+
+    /// Sets the LVT timer to fire once after the given `timeout`, or never if `timeout` is `None`.
+    ///
+    /// The deadline is fixed when this is called, so it isn't extended
+    /// if the timer is disabled and re-enabled in the meantime.
+    /// The TSC-deadline timer mode is used if it is available,
+    /// and the one-shot mode of the timer's count register otherwise.
+    pub fn set_timer_oneshot(&mut self, timeout: Option<Duration>) -> Result<(), &'static str> {
+        if !self.supports_oneshot_timer() {
+            return Err("the Local APIC timer wasn't calibrated against the TSC");
+        }
+        self.timer_mode = match timeout {
+            Some(timeout) => {
+                let timeslice_nanos = CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u128 * 1000;
+                let tsc_ticks = timeout.as_nanos() * self.tsc_ticks_per_timeslice as u128 / timeslice_nanos;
+                let now = unsafe { _rdtsc() };
+                TimerMode::OneShot { tsc_deadline: now.saturating_add(u64::try_from(tsc_ticks).unwrap_or(u64::MAX)) }
+            }
+            None => TimerMode::Stopped,
+        };
+        self.program_lvt_timer();
+        Ok(())
+    }
+
+    /// Writes the current timer mode to the LVT timer registers.
+    fn program_lvt_timer(&mut self) {
         // From section 10.5.4 of Intel SDM:
         //   Changing the mode of the APIC timer (from one-shot to periodic or vice versa)
         //   by writing to the timer LVT entry does not start the timer.
         //   To start the timer, it is necessary to write to the initial-count register.
         //
         // Thus, when enabling the timer, we must immeditely write the initial count again.
-        if enable {
-            let timer_enable = LOCAL_APIC_LVT_IRQ as u32 | APIC_TIMER_MODE_PERIODIC;
-            match &mut self.inner {
-                LapicType::X2Apic => unsafe {
-                    wrmsr(IA32_X2APIC_LVT_TIMER, timer_enable as u64);
-                    wrmsr(IA32_X2APIC_INIT_COUNT, self.initial_timer_count as u64);
-                }
-                LapicType::XApic(regs) => {
-                    regs.lvt_timer.write(timer_enable);
-                    regs.timer_initial_count.write(self.initial_timer_count);
-                }
+        let (timer_lvt, initial_count, tsc_deadline) = match self.timer_mode {
+            _ if !self.timer_enabled => (APIC_TIMER_DISABLE, None, None),
+            TimerMode::Stopped => (APIC_TIMER_DISABLE, None, None),
+            TimerMode::Periodic => (
+                LOCAL_APIC_LVT_IRQ as u32 | APIC_TIMER_MODE_PERIODIC,
+                Some(self.initial_timer_count),
+                None,
+            ),
+            TimerMode::OneShot { tsc_deadline } if has_tsc_deadline() => (
+                LOCAL_APIC_LVT_IRQ as u32 | APIC_TIMER_MODE_TSC_DEADLINE,
+                None,
+                Some(tsc_deadline),
+            ),
+            TimerMode::OneShot { tsc_deadline } => {
+                // Convert the time remaining until the deadline into APIC ticks.
+                // A count of 0 wouldn't start the timer, so a deadline that passed fires right away.
+                let tsc_ticks = tsc_deadline.saturating_sub(unsafe { _rdtsc() });
+                let count = tsc_ticks as u128 * self.initial_timer_count as u128 / self.tsc_ticks_per_timeslice as u128;
+                (
+                    LOCAL_APIC_LVT_IRQ as u32 | APIC_TIMER_MODE_ONESHOT,
+                    Some(count.clamp(1, u32::MAX as u128) as u32),
+                    None,
+                )
             }
-        } else {
-            let timer_disable = APIC_TIMER_DISABLE;
-            match &mut self.inner {
-                LapicType::X2Apic => unsafe {
-                    wrmsr(IA32_X2APIC_LVT_TIMER, timer_disable as u64);
+        };
+
+        match &mut self.inner {
+            LapicType::X2Apic => unsafe {
+                wrmsr(IA32_X2APIC_LVT_TIMER, timer_lvt as u64);
+                if let Some(initial_count) = initial_count {
+                    wrmsr(IA32_X2APIC_INIT_COUNT, initial_count as u64);
                 }
-                LapicType::XApic(regs) => {
-                    regs.lvt_timer.write(timer_disable);
+            }
+            LapicType::XApic(regs) => {
+                regs.lvt_timer.write(timer_lvt);
+                if let Some(initial_count) = initial_count {
+                    regs.timer_initial_count.write(initial_count);
                 }
             }
         }
+
+        // Section 10.5.4.1 of the Intel SDM requires an `MFENCE` between the (memory-mapped)
+        // write that enables TSC-deadline mode and the write of the deadline itself.
+        // A deadline that has already passed fires right away.
+        if let Some(tsc_deadline) = tsc_deadline {
+            unsafe {
+                _mm_mfence();
+                wrmsr(IA32_TSC_DEADLINE, tsc_deadline);
+            }
+        }
     }
 
     /// Returns the ID of this Local APIC (fast).
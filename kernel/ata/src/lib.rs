@@ -2,7 +2,17 @@
 //! 
 //! The primary struct of interest is [`AtaDrive`].
 //! 
-//! Support for DMA is not yet implemented, but the slower port-based I/O is fully supported.
+//! Two transfer modes are supported:
+//! * [`AtaTransferMode::Pio`]: slower port-based I/O, which works on every drive.
+//! * [`AtaTransferMode::Dma`]: PCI IDE bus-master DMA using a Physical Region Descriptor (PRD) table,
+//!   in which completion is signaled by the bus's interrupt.
+//!   This requires the IDE controller to expose a bus master (BAR4) and the drive to support DMA.
+//!
+//! When an [`IdeController`] is initialized, DMA is enabled for each drive whose IDENTIFY data
+//! reports DMA support and whose bus has a bus master; all other drives use PIO.
+//! The mode of an individual drive can be changed afterwards via [`AtaDrive::set_transfer_mode()`].
+//!
+//! Both modes are exposed through the same [`BlockReader`] and [`BlockWriter`] impls.
 
 #![no_std]
 #![feature(abi_x86_interrupt)]
@@ -10,7 +20,7 @@
 extern crate alloc;
 #[macro_use] extern crate log;
 
-use core::fmt;
+use core::{fmt, hint, sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering}};
 use bitflags::bitflags;
 use spin::Mutex;
 use alloc::{
@@ -21,6 +31,7 @@ use alloc::{
 };
 use port_io::{Port, PortReadOnly, PortWriteOnly};
 use pci::PciDevice;
+use memory::{create_contiguous_mapping, MappedPages, PhysicalAddress, DMA_FLAGS};
 use storage_device::{StorageDevice, StorageDeviceRef, StorageController};
 use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
 use x86_64::structures::idt::InterruptStackFrame;
@@ -38,6 +49,20 @@ const MAX_LBA_28_VALUE: usize = (1 << 28) - 1;
 /// To use a BAR as a Port address, you must mask out the lowest 2 bits.
 const PCI_BAR_PORT_MASK: u16 = 0xFFFC;
 
+/// The size of the physically-contiguous buffer that each bus uses for DMA transfers.
+const DMA_BUFFER_SIZE_IN_BYTES: usize = 64 * 1024;
+/// The maximum number of sectors that can be transferred in a single DMA command,
+/// which is limited by the size of each bus's DMA buffer.
+const MAX_DMA_SECTORS_PER_TRANSFER: usize = DMA_BUFFER_SIZE_IN_BYTES / SECTOR_SIZE_IN_BYTES;
+/// A single PRD entry cannot describe a region that crosses a 64 KiB boundary.
+const PRD_BOUNDARY: usize = 64 * 1024;
+/// The size of one PRD entry: a 32-bit physical address, a 16-bit byte count, and 16 bits of flags.
+const PRD_ENTRY_SIZE_IN_BYTES: usize = 8;
+/// The maximum number of PRD entries needed to describe the DMA buffer.
+const MAX_PRD_ENTRIES: usize = DMA_BUFFER_SIZE_IN_BYTES / PRD_BOUNDARY + 1;
+/// Set in the last entry of a PRD table to mark the end of the table.
+const PRD_END_OF_TABLE: u32 = 1 << 31;
+
 
 bitflags! {
 	/// The possible error values found in an ATA drive's error port.
@@ -87,6 +112,34 @@ bitflags! {
     }
 }
 
+bitflags! {
+	/// The possible values used in an ATA bus master's command port.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct BusMasterCommand: u8 {
+		/// The direction of the transfer: set for device-to-memory (reads), cleared for memory-to-device (writes).
+		const READ  = 0x08;
+		/// Set to start the DMA transfer, cleared to stop it.
+		const START = 0x01;
+    }
+}
+
+bitflags! {
+	/// The possible values found in an ATA bus master's status port.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct BusMasterStatus: u8 {
+		/// Set by firmware if the slave drive is capable of DMA.
+		const SLAVE_DMA_CAPABLE  = 0x40;
+		/// Set by firmware if the master drive is capable of DMA.
+		const MASTER_DMA_CAPABLE = 0x20;
+		/// Set when the drive has raised an interrupt. Cleared by writing a `1` to it.
+		const INTERRUPT          = 0x04;
+		/// Set when the DMA transfer failed. Cleared by writing a `1` to it.
+		const ERROR              = 0x02;
+		/// Set while the bus master is actively transferring data.
+		const ACTIVE             = 0x01;
+    }
+}
+
 #[allow(dead_code)]
 /// The possible commands that can be issued to an ATA drive's command port. 
 /// More esoteric commands (nearly a full list) are here: <https://wiki.osdev.org/ATA_Command_Matrix>.
@@ -165,12 +218,27 @@ enum BusDriveSelect {
 }
 
 
-/// TODO: support DMA like so: <https://wiki.osdev.org/ATA/ATAPI_using_DMA#The_Bus_Master_Register>
-/// There is one instance of this struct for each `AtaBus`.
+/// The transfer mode that an `AtaDrive` uses to read and write data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AtaTransferMode {
+	/// Blocking port-based I/O, in which the CPU moves every word of data.
+	Pio,
+	/// PCI IDE bus-master DMA, in which the controller moves the data
+	/// and signals completion with an interrupt.
+	Dma,
+}
+
+
+/// The bus master registers of an ATA bus, used for DMA transfers,
+/// as described here: <https://wiki.osdev.org/ATA/ATAPI_using_DMA#The_Bus_Master_Register>.
+/// There is one instance of this struct for each `AtaBus` that supports DMA.
+/// 
+/// Each bus master owns its own Physical Region Descriptor (PRD) table
+/// and a physically-contiguous buffer that all DMA transfers on its bus go through.
 /// 
 /// Note: TODO: depending on whether BAR4 is a Port I/O address or MMIO address, this could also be mapped into memory.
 ///             We need to have an abstraction either above or beneath `Volatile` that allows reads/writes from port I/O and memory addresses similarly.
-#[allow(unused)]
+#[derive(Debug)]
 struct AtaBusMaster {
 	/// For the primary bus, this exists at BAR4 + 0.
 	/// For the secondary,   this exists at BAR4 + 8.
@@ -181,8 +249,194 @@ struct AtaBusMaster {
 	/// For the primary bus, this exists at BAR4 + 4.
 	/// For the secondary,   this exists at BAR4 + 12.
 	prdt_address: Port<u32>,
+	/// The PRD table, which describes the physical memory regions of `buffer`.
+	prdt: MappedPages,
+	/// The physical address of the PRD table.
+	prdt_phys_addr: PhysicalAddress,
+	/// The buffer that data is transferred to or from by the bus master.
+	buffer: MappedPages,
+	/// The physical address of the DMA buffer.
+	buffer_phys_addr: PhysicalAddress,
+	/// The state shared with this bus's interrupt handler.
+	completion: &'static DmaCompletion,
+}
+
+impl AtaBusMaster {
+	/// Creates a new bus master whose registers start at the given `port_base`,
+	/// allocating its PRD table and DMA buffer.
+	/// 
+	/// The given `completion` state is registered such that this bus's interrupt handler
+	/// can acknowledge interrupts and signal the completion of DMA transfers.
+	fn new(
+		port_base: u16,
+		ata_status_port: u16,
+		completion: &'static DmaCompletion,
+	) -> Result<AtaBusMaster, &'static str> {
+		let (prdt, prdt_phys_addr) = create_contiguous_mapping(MAX_PRD_ENTRIES * PRD_ENTRY_SIZE_IN_BYTES, DMA_FLAGS)?;
+		let (buffer, buffer_phys_addr) = create_contiguous_mapping(DMA_BUFFER_SIZE_IN_BYTES, DMA_FLAGS)?;
+		// The bus master can only address 32-bit physical memory.
+		if (prdt_phys_addr.value() + prdt.size_in_bytes()) > (u32::MAX as usize)
+			|| (buffer_phys_addr.value() + DMA_BUFFER_SIZE_IN_BYTES) > (u32::MAX as usize)
+		{
+			return Err("DMA memory for the ATA bus master was not in the 32-bit physical address space");
+		}
+
+		let bus_master = AtaBusMaster {
+			command: Port::new(port_base),
+			status: Port::new(port_base + 2),
+			prdt_address: Port::new(port_base + 4),
+			prdt,
+			prdt_phys_addr,
+			buffer,
+			buffer_phys_addr,
+			completion,
+		};
+		// Stop any transfer that the firmware may have left running.
+		unsafe { bus_master.command.write(0); }
+		completion.ata_status_port.store(ata_status_port, Ordering::Release);
+		completion.bus_master_status_port.store(port_base + 2, Ordering::Release);
+		Ok(bus_master)
+	}
+
+	/// Fills in the PRD table such that it describes the first `length_in_bytes` of the DMA buffer,
+	/// splitting the buffer into regions that do not cross a 64 KiB boundary.
+	fn prepare_prdt(&mut self, length_in_bytes: usize) -> Result<(), &'static str> {
+		let entries: &mut [u32] = self.prdt.as_slice_mut(0, MAX_PRD_ENTRIES * 2)?;
+		let mut phys_addr = self.buffer_phys_addr.value();
+		let mut remaining = length_in_bytes;
+		let mut index = 0;
+		while remaining > 0 {
+			let bytes_to_boundary = PRD_BOUNDARY - (phys_addr % PRD_BOUNDARY);
+			let region_size = core::cmp::min(remaining, bytes_to_boundary);
+			remaining -= region_size;
+			// A byte count of 0 means 64 KiB.
+			let byte_count = (region_size % PRD_BOUNDARY) as u32;
+			let end_of_table = if remaining == 0 { PRD_END_OF_TABLE } else { 0 };
+			let entry = entries.get_mut(index * 2 .. index * 2 + 2).ok_or("PRD table was too small")?;
+			entry[0] = phys_addr as u32;
+			entry[1] = end_of_table | byte_count;
+			phys_addr += region_size;
+			index += 1;
+		}
+		Ok(())
+	}
+
+	/// Returns whether the firmware has marked the given drive as being DMA capable.
+	fn is_dma_capable(&self, which: BusDriveSelect) -> bool {
+		let status = BusMasterStatus::from_bits_truncate(self.status.read());
+		match which {
+			BusDriveSelect::Master => status.contains(BusMasterStatus::MASTER_DMA_CAPABLE),
+			BusDriveSelect::Slave  => status.contains(BusMasterStatus::SLAVE_DMA_CAPABLE),
+		}
+	}
+
+	/// Marks the given drive as DMA capable in the bus master's status port.
+	fn set_dma_capable(&mut self, which: BusDriveSelect) {
+		let capable = match which {
+			BusDriveSelect::Master => BusMasterStatus::MASTER_DMA_CAPABLE,
+			BusDriveSelect::Slave  => BusMasterStatus::SLAVE_DMA_CAPABLE,
+		};
+		// Only keep the capability bits, as writing a `1` to the interrupt or error bits would clear them.
+		let status = BusMasterStatus::from_bits_truncate(self.status.read())
+			& (BusMasterStatus::MASTER_DMA_CAPABLE | BusMasterStatus::SLAVE_DMA_CAPABLE);
+		unsafe { self.status.write((status | capable).bits()); }
+	}
+}
+
+
+/// The state shared between an ATA bus that performs DMA transfers and its interrupt handler.
+#[derive(Debug)]
+struct DmaCompletion {
+	/// The port of the bus master's status register, or `0` if the bus has no bus master.
+	bus_master_status_port: AtomicU16,
+	/// The port of the ATA bus's status register, which is read to acknowledge the drive's interrupt.
+	ata_status_port: AtomicU16,
+	/// Set while a DMA transfer is in flight and has not yet been acknowledged.
+	/// Only the caller that clears this flag may complete the transfer.
+	in_flight: AtomicBool,
+	/// Set once the drive's interrupt for the in-flight DMA transfer has been acknowledged.
+	completed: AtomicBool,
+	/// The bus master's status observed when the transfer was acknowledged.
+	bus_master_status: AtomicU8,
 }
 
+impl DmaCompletion {
+	const fn new() -> DmaCompletion {
+		DmaCompletion {
+			bus_master_status_port: AtomicU16::new(0),
+			ata_status_port: AtomicU16::new(0),
+			in_flight: AtomicBool::new(false),
+			completed: AtomicBool::new(false),
+			bus_master_status: AtomicU8::new(0),
+		}
+	}
+
+	/// Prepares for a new DMA transfer, which must be done before the command is issued to the drive.
+	fn begin(&self) {
+		self.completed.store(false, Ordering::Release);
+		self.in_flight.store(true, Ordering::Release);
+	}
+
+	/// Cleans up after a DMA transfer, once the bus master has been stopped.
+	fn end(&self) {
+		self.in_flight.store(false, Ordering::Release);
+	}
+
+	/// Waits until the in-flight DMA transfer has been acknowledged.
+	///
+	/// The caller holds the lock on the storage device, which is a spinlock,
+	/// so this spins rather than blocking the current task.
+	/// It also periodically polls the bus master itself, in case interrupts are disabled
+	/// on this CPU, e.g., during early device initialization, or the interrupt was lost.
+	fn wait(&self) {
+		let mut spins: usize = 0;
+		while !self.completed.load(Ordering::Acquire) {
+			spins = spins.wrapping_add(1);
+			if spins % DMA_POLL_INTERVAL == 0 && self.acknowledge() {
+				break;
+			}
+			hint::spin_loop();
+		}
+	}
+
+	/// Acknowledges a pending interrupt from the drive, if the bus master reports one
+	/// and a DMA transfer is in flight, and signals that the transfer has completed.
+	/// 
+	/// Returns `true` if this call completed the in-flight transfer.
+	fn acknowledge(&self) -> bool {
+		let bus_master_status_port = self.bus_master_status_port.load(Ordering::Acquire);
+		if bus_master_status_port == 0 {
+			return false;
+		}
+		let bus_master_status = Port::<u8>::new(bus_master_status_port);
+		let status = BusMasterStatus::from_bits_truncate(bus_master_status.read());
+		if !status.contains(BusMasterStatus::INTERRUPT) {
+			return false;
+		}
+		// The interrupt handler and a polling waiter may both observe the interrupt; only one may complete the transfer.
+		if self.in_flight.compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire).is_err() {
+			return false;
+		}
+		// Reading the ATA status port acknowledges the interrupt on the drive itself.
+		let _ = PortReadOnly::<u8>::new(self.ata_status_port.load(Ordering::Acquire)).read();
+		// Clear only the interrupt bit, leaving the error bit for the waiting transfer to observe.
+		let cleared = (status & (BusMasterStatus::MASTER_DMA_CAPABLE | BusMasterStatus::SLAVE_DMA_CAPABLE))
+			| BusMasterStatus::INTERRUPT;
+		unsafe { bus_master_status.write(cleared.bits()); }
+		self.bus_master_status.store(status.bits(), Ordering::Release);
+		self.completed.store(true, Ordering::Release);
+		true
+	}
+}
+
+/// How many times [`DmaCompletion::wait()`] spins between polling the bus master for completion.
+const DMA_POLL_INTERVAL: usize = 100_000;
+
+/// The DMA completion state of the primary ATA bus.
+static PRIMARY_DMA_COMPLETION: DmaCompletion = DmaCompletion::new();
+/// The DMA completion state of the secondary ATA bus.
+static SECONDARY_DMA_COMPLETION: DmaCompletion = DmaCompletion::new();
+
 
 /// There are two ATA buses on an IDE controller,
 /// and each one can have two drives attached to it:
@@ -240,6 +494,9 @@ struct AtaBus {
 	/// `DEVADDRESS`, located at `BAR1 + 3`. 
 	/// Not sure what this is used for.
 	_drive_address: Port<u8>,
+
+	/// The bus master used for DMA transfers, if the IDE controller supports it.
+	bus_master: Option<AtaBusMaster>,
 }
 
 impl AtaBus {
@@ -262,6 +519,150 @@ impl AtaBus {
 			alternate_status: PortReadOnly::new(control_bar + 2),
 			control: PortWriteOnly::new(control_bar + 2),
 			_drive_address: Port::new(control_bar + 3),
+
+			bus_master: None,
+		}
+	}
+
+	/// Issues the actual read DMA command on the ATA Bus without performing any bounds checks.
+	/// 
+	/// See `AtaDrive::read_dma()` (the caller of this function) for more documentation.
+	fn read_dma(&mut self, 
+		buffer: &mut [u8],
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize
+	) -> Result<usize, &'static str> {
+		if sector_count == 0 {
+			return Ok(0);
+		}
+		let length_in_bytes = sector_count * SECTOR_SIZE_IN_BYTES;
+		self.transfer_dma(which, lba_start, sector_count, true)?;
+
+		let bus_master = self.bus_master.as_ref().ok_or("ATA bus does not support DMA")?;
+		let dma_buffer: &[u8] = bus_master.buffer.as_slice(0, length_in_bytes)?;
+		buffer[.. length_in_bytes].copy_from_slice(dma_buffer);
+		Ok(sector_count)
+	}
+
+	/// Issues the actual write DMA command on the ATA Bus without performing any bounds checks.
+	/// 
+	/// See `AtaDrive::write_dma()` (the caller of this function) for more documentation.
+	fn write_dma(&mut self, 
+		buffer: &[u8],
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize
+	) -> Result<usize, &'static str> {
+		if sector_count == 0 {
+			return Ok(0);
+		}
+		let length_in_bytes = sector_count * SECTOR_SIZE_IN_BYTES;
+		{
+			let bus_master = self.bus_master.as_mut().ok_or("ATA bus does not support DMA")?;
+			let dma_buffer: &mut [u8] = bus_master.buffer.as_slice_mut(0, length_in_bytes)?;
+			dma_buffer.copy_from_slice(&buffer[.. length_in_bytes]);
+		}
+		self.transfer_dma(which, lba_start, sector_count, false)?;
+
+		// Flush the drive's cache after each write command
+		let cache_flush_cmd = if lba_start <= MAX_LBA_28_VALUE { AtaCommand::CacheFlush } else { AtaCommand::CacheFlushExt };
+		unsafe { self.command.write(cache_flush_cmd as u8) };
+
+		self.wait_for_data_done().map_err(|_| "error after cache flush after DMA write")?;
+		Ok(sector_count)
+	}
+
+	/// Performs a single DMA transfer of `sector_count` sectors between the drive and the bus master's DMA buffer,
+	/// blocking until the drive signals its completion with an interrupt.
+	/// 
+	/// If `is_read` is `true`, data is transferred from the drive into the DMA buffer;
+	/// otherwise, data is transferred from the DMA buffer to the drive.
+	fn transfer_dma(&mut self,
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize,
+		is_read: bool,
+	) -> Result<(), &'static str> {
+		// Use 28-bit LBAs, unless the LBA is too large, then we use 48-bit LBAs
+		let using_lba_28 = lba_start <= MAX_LBA_28_VALUE;
+		let command = match (is_read, using_lba_28) {
+			(true,  true)  => AtaCommand::ReadDma,
+			(true,  false) => AtaCommand::ReadDmaExt,
+			(false, true)  => AtaCommand::WriteDma,
+			(false, false) => AtaCommand::WriteDmaExt,
+		};
+		let direction = if is_read { BusMasterCommand::READ } else { BusMasterCommand::empty() };
+
+		self.wait_for_data_done().map_err(|_| "error before issuing DMA command")?;
+
+		let bus_master = self.bus_master.as_mut().ok_or("ATA bus does not support DMA")?;
+		bus_master.prepare_prdt(sector_count * SECTOR_SIZE_IN_BYTES)?;
+		unsafe {
+			// Stop the bus master and set the direction of the transfer.
+			bus_master.command.write(direction.bits());
+			bus_master.prdt_address.write(bus_master.prdt_phys_addr.value() as u32);
+			// Clear any previous interrupt and error status by writing a `1` to those bits.
+			bus_master.status.write(bus_master.status.read() | (BusMasterStatus::INTERRUPT | BusMasterStatus::ERROR).bits());
+		}
+		let completion = bus_master.completion;
+		completion.begin();
+
+		self.issue_command(command, which, lba_start, sector_count);
+
+		let bus_master = self.bus_master.as_ref().ok_or("ATA bus does not support DMA")?;
+		unsafe { bus_master.command.write((direction | BusMasterCommand::START).bits()); }
+
+		// Wait for the interrupt handler to signal that the transfer has completed.
+		completion.wait();
+
+		// Stop the bus master, which must be done even if the transfer failed.
+		unsafe { bus_master.command.write(0); }
+		completion.end();
+		let bus_master_status = BusMasterStatus::from_bits_truncate(completion.bus_master_status.load(Ordering::Acquire));
+		if bus_master_status.contains(BusMasterStatus::ERROR) {
+			return Err("ATA bus master reported an error during DMA transfer");
+		}
+		self.wait_for_data_done().map_err(|_| "error after DMA transfer")
+	}
+
+	/// Selects the given drive and issues the given read or write `command`
+	/// for `sector_count` sectors starting at `lba_start`.
+	///
+	/// The command is issued with either 28-bit or 48-bit LBAs, depending on the value of `lba_start`;
+	/// the caller must choose the matching variant of `command`.
+	fn issue_command(&mut self,
+		command: AtaCommand,
+		which: BusDriveSelect,
+		lba_start: usize,
+		sector_count: usize,
+	) {
+		if lba_start <= MAX_LBA_28_VALUE {
+			unsafe {
+				// bits [24:28] of the LBA need to go into the lower 4 bits of the `drive_select` port.
+				self.drive_select.write(0xE0 | (which as u8) | ((lba_start >> 24) as u8 & 0x0F));
+				self.sector_count.write(sector_count as u8);
+				self.lba_high.write((lba_start >> 16) as u8);
+				self.lba_mid.write( (lba_start >>  8) as u8);
+				self.lba_low.write(  lba_start        as u8);
+				self.command.write(command as u8);
+			}
+		} else {
+			// When using 48-bit LBAs, the high bytes of the sector_count and LBA must be written *before* the low bytes.
+			unsafe {
+				self.drive_select.write(0x40 | (which as u8));
+				// write the high bytes
+				self.sector_count.write((sector_count >> 8) as u8);
+				self.lba_high.write((lba_start >> 40) as u8);
+				self.lba_mid.write( (lba_start >> 32) as u8);
+				self.lba_low.write( (lba_start >> 24) as u8);
+				// write the low bytes
+				self.sector_count.write(sector_count as u8);
+				self.lba_high.write((lba_start >> 16) as u8);
+				self.lba_mid.write( (lba_start >>  8) as u8);
+				self.lba_low.write(  lba_start        as u8);
+				self.command.write(command as u8);
+			}
 		}
 	}
 
@@ -568,6 +969,8 @@ pub struct AtaDrive {
 	identify_data: AtaIdentifyData,
 	/// Whether this drive is a master or slave on the bus.
 	master_slave: BusDriveSelect,
+	/// The mode used to transfer data to and from this drive.
+	transfer_mode: AtaTransferMode,
 }
 
 impl AtaDrive {
@@ -593,9 +996,64 @@ impl AtaDrive {
 			bus, 
 			identify_data,
 			master_slave: which,
+			transfer_mode: AtaTransferMode::Pio,
 		})
 	}
 
+	/// Returns the mode currently used to transfer data to and from this drive.
+	pub fn transfer_mode(&self) -> AtaTransferMode {
+		self.transfer_mode
+	}
+
+	/// Sets the mode used to transfer data to and from this drive
+	/// via its `BlockReader` and `BlockWriter` impls.
+	/// 
+	/// Returns an error if `AtaTransferMode::Dma` is requested but this drive does not support DMA;
+	/// see [`supports_dma()`](#method.supports_dma).
+	pub fn set_transfer_mode(&mut self, mode: AtaTransferMode) -> Result<(), &'static str> {
+		if mode == AtaTransferMode::Dma {
+			if !self.supports_dma() {
+				return Err("AtaDrive::set_transfer_mode(): drive or IDE controller does not support DMA");
+			}
+			let mut bus = self.bus.lock();
+			if let Some(bus_master) = bus.bus_master.as_mut() {
+				if !bus_master.is_dma_capable(self.master_slave) {
+					bus_master.set_dma_capable(self.master_slave);
+				}
+			}
+		}
+		self.transfer_mode = mode;
+		Ok(())
+	}
+
+	/// Returns `true` if this drive can use DMA transfers, 
+	/// which requires both the drive and the bus it sits on to support DMA.
+	pub fn supports_dma(&self) -> bool {
+		// Bit 8 of the capabilities word indicates DMA support.
+		let drive_supports_dma = self.identify_data.capabilities & 0x100 != 0
+			&& (self.identify_data.multiword_dma_support != 0 || self.identify_data.ultra_dma_support != 0);
+		drive_supports_dma && self.bus.lock().bus_master.is_some()
+	}
+
+	/// Checks that a transfer of the given `length_in_bytes` starting at `offset_in_sectors`
+	/// is valid for this drive and transfers no more than `max_sectors`.
+	/// 
+	/// Returns the starting LBA and the number of sectors to transfer.
+	fn check_transfer(&self, length_in_bytes: usize, offset_in_sectors: usize, max_sectors: usize) -> Result<(usize, usize), &'static str> {
+		if offset_in_sectors > self.size_in_blocks() {
+			return Err("offset_in_sectors was out of bounds");
+		}
+		if length_in_bytes % SECTOR_SIZE_IN_BYTES != 0 {
+			return Err("The buffer length must be a multiple of sector size (512) bytes. ATA drives can only transfer at sector granularity.");
+		}
+		let sector_count = length_in_bytes / SECTOR_SIZE_IN_BYTES;
+		if sector_count > max_sectors {
+			error!("AtaDrive: cannot transfer {} sectors, the max is {} sectors per transfer.", sector_count, max_sectors);
+			return Err("AtaDrive: cannot transfer more sectors than the max per transfer");
+		}
+		Ok((offset_in_sectors, sector_count))
+	}
+
 	/// Reads data from this drive starting at the given `offset_in_sectors` into the provided `buffer`.
 	/// The length of the given `buffer` determines the number of bytes to be written.
 	/// 
@@ -668,6 +1126,30 @@ impl AtaDrive {
 		self.bus.lock().write_pio(buffer, self.master_slave, lba_start, sector_count)
 	}
 
+	/// Reads data from this drive starting at the given `offset_in_sectors` into the provided `buffer`
+	/// using a bus-master DMA transfer.
+	/// 
+	/// The same restrictions as [`read_pio()`](#method.read_pio) apply to the `buffer` length and `offset_in_sectors`,
+	/// and no more than 128 sectors can be read in a single transfer.
+	/// 
+	/// Returns the number of sectors (*not bytes*) that were successfully read from the drive.
+	pub fn read_dma(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+		let (lba_start, sector_count) = self.check_transfer(buffer.len(), offset_in_sectors, MAX_DMA_SECTORS_PER_TRANSFER)?;
+		self.bus.lock().read_dma(buffer, self.master_slave, lba_start, sector_count)
+	}
+
+	/// Writes data from the provided `buffer` to this drive, starting at the given `offset_in_sectors` into the drive,
+	/// using a bus-master DMA transfer.
+	/// 
+	/// The same restrictions as [`write_pio()`](#method.write_pio) apply to the `buffer` length and `offset_in_sectors`,
+	/// and no more than 128 sectors can be written in a single transfer.
+	/// 
+	/// Returns the number of sectors (*not bytes*) that were successfully written to the drive.
+	pub fn write_dma(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+		let (lba_start, sector_count) = self.check_transfer(buffer.len(), offset_in_sectors, MAX_DMA_SECTORS_PER_TRANSFER)?;
+		self.bus.lock().write_dma(buffer, self.master_slave, lba_start, sector_count)
+	}
+
 
 	/// Returns `true` if this drive is the master, or `false` if it is the slave 
 	/// on the IDE controller bus.
@@ -696,14 +1178,20 @@ impl KnownLength for AtaDrive {
 }
 impl BlockReader for AtaDrive {
 	fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
-		// TODO: emit a more specific IoError from the read_pio function itself instead of a blind conversion here
-		self.read_pio(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
+		// TODO: emit a more specific IoError from the read functions themselves instead of a blind conversion here
+		match self.transfer_mode {
+			AtaTransferMode::Pio => self.read_pio(buffer, block_offset),
+			AtaTransferMode::Dma => self.read_dma(buffer, block_offset),
+		}.map_err(|_e| IoError::InvalidInput)
 	}
 }
 impl BlockWriter for AtaDrive {
 	fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
-		// TODO: emit a more specific IoError from the read_pio function itself instead of a blind conversion here
-		self.write_pio(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
+		// TODO: emit a more specific IoError from the write functions themselves instead of a blind conversion here
+		match self.transfer_mode {
+			AtaTransferMode::Pio => self.write_pio(buffer, block_offset),
+			AtaTransferMode::Dma => self.write_dma(buffer, block_offset),
+		}.map_err(|_e| IoError::InvalidInput)
 	}
 
 	fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
@@ -754,11 +1242,15 @@ impl IdeController {
 			}
 		};
 
-		// TODO: use the BAR4 for DMA in the future
-		let _bus_master_base = pci_device.bars[4]; 
+		// BAR4 holds the base port of the bus master registers used for DMA, if they exist.
+		// The primary bus's registers are at BAR4 + 0, and the secondary bus's are at BAR4 + 8.
+		let bus_master_base = match pci_device.bars[4] {
+			bar if bar & 0x1 == 0x1 && (bar as u16 & PCI_BAR_PORT_MASK) != 0 => Some(bar as u16 & PCI_BAR_PORT_MASK),
+			_ => None,
+		};
 
-		// Register interrupt handlers for the primary and secondary ATA buses.
-		// They're not yet used for anything but will determine when a DMA transfer has completed.
+		// Register interrupt handlers for the primary and secondary ATA buses,
+		// which determine when a DMA transfer has completed.
 		interrupts::register_interrupt(ATA_PRIMARY_IRQ, primary_ata_handler).map_err(|e| {
 			error!("ATA Primary Bus IRQ {:#X} was already in use by handler {:#X}! Sharing IRQs is currently unsupported.", 
 				ATA_PRIMARY_IRQ, e,
@@ -772,17 +1264,43 @@ impl IdeController {
 			"ATA Secondary Bus IRQ was already in use! Sharing IRQs is currently unsupported."
 		})?;
 
-		let primary_bus = Arc::new(Mutex::new(AtaBus::new(primary_bus_data_port, primary_bus_control_port)));
-		let secondary_bus = Arc::new(Mutex::new(AtaBus::new(secondary_bus_data_port, secondary_bus_control_port)));
-
-		let primary_master   = AtaDrive::new(Arc::clone(&primary_bus), BusDriveSelect::Master);
-		let primary_slave    = AtaDrive::new(primary_bus, BusDriveSelect::Slave);
-		let secondary_master = AtaDrive::new(Arc::clone(&secondary_bus), BusDriveSelect::Master);
-		let secondary_slave  = AtaDrive::new(secondary_bus, BusDriveSelect::Slave);
+		let mut primary_bus = AtaBus::new(primary_bus_data_port, primary_bus_control_port);
+		let mut secondary_bus = AtaBus::new(secondary_bus_data_port, secondary_bus_control_port);
+		if let Some(base) = bus_master_base {
+			pci_device.pci_set_command_bus_master_bit();
+			let setup_bus_master = |bus: &mut AtaBus, port_base: u16, data_port: u16, completion: &'static DmaCompletion| {
+				// The ATA status port is located at `BAR0 + 7`.
+				let ata_status_port = (data_port & PCI_BAR_PORT_MASK) + 7;
+				match AtaBusMaster::new(port_base, ata_status_port, completion) {
+					Ok(bus_master) => bus.bus_master = Some(bus_master),
+					Err(e) => warn!("Failed to set up ATA bus master at port {:#X}, DMA will be unavailable: {}", port_base, e),
+				}
+			};
+			setup_bus_master(&mut primary_bus, base, primary_bus_data_port, &PRIMARY_DMA_COMPLETION);
+			setup_bus_master(&mut secondary_bus, base + 8, secondary_bus_data_port, &SECONDARY_DMA_COMPLETION);
+		}
+		let primary_bus = Arc::new(Mutex::new(primary_bus));
+		let secondary_bus = Arc::new(Mutex::new(secondary_bus));
+
+		// Use DMA for every drive that supports it, falling back to PIO otherwise.
+		let enable_dma = |drive: Result<AtaDrive, &'static str>| drive.map(|mut d| {
+			if d.supports_dma() {
+				if let Err(e) = d.set_transfer_mode(AtaTransferMode::Dma) {
+					warn!("Failed to enable DMA for ATA drive, using PIO instead: {}", e);
+				}
+			}
+			d
+		});
+		let primary_master   = enable_dma(AtaDrive::new(Arc::clone(&primary_bus), BusDriveSelect::Master));
+		let primary_slave    = enable_dma(AtaDrive::new(primary_bus, BusDriveSelect::Slave));
+		let secondary_master = enable_dma(AtaDrive::new(Arc::clone(&secondary_bus), BusDriveSelect::Master));
+		let secondary_slave  = enable_dma(AtaDrive::new(secondary_bus, BusDriveSelect::Slave));
 		
 		let drive_fmt = |drive: &Result<AtaDrive, &str>| -> String {
 			match drive {
-				Ok(d)  => format!("drive initialized, size: {} sectors", d.size_in_blocks()),
+				Ok(d)  => format!("drive initialized, size: {} sectors, transfer mode: {:?}",
+					d.size_in_blocks(), d.transfer_mode(),
+				),
 				Err(e) => e.to_string(),
 			}
 		};
@@ -894,15 +1412,19 @@ const ATA_PRIMARY_IRQ:   u8 = interrupts::IRQ_BASE_OFFSET + 0xE;
 /// Because we perform the typical PIC remapping, the remapped IRQ vector number is 0x2F.
 const ATA_SECONDARY_IRQ: u8 = interrupts::IRQ_BASE_OFFSET + 0xF;
 
-/// The primary ATA interrupt handler. Not yet used for anything, but useful for DMA.
+/// The primary ATA interrupt handler, which signals the completion of DMA transfers on the primary bus.
 extern "x86-interrupt" fn primary_ata_handler(_stack_frame: InterruptStackFrame ) {
-    info!("Primary ATA Interrupt ({:#X})", ATA_PRIMARY_IRQ);
+    if !PRIMARY_DMA_COMPLETION.acknowledge() {
+        trace!("Primary ATA Interrupt ({:#X}) without a DMA transfer", ATA_PRIMARY_IRQ);
+    }
     interrupts::eoi(ATA_PRIMARY_IRQ);
 }
 
-/// The primary ATA interrupt handler. Not yet used for anything, but useful for DMA.
+/// The secondary ATA interrupt handler, which signals the completion of DMA transfers on the secondary bus.
 extern "x86-interrupt" fn secondary_ata_handler(_stack_frame: InterruptStackFrame ) {
-    info!("Secondary ATA Interrupt ({:#X})", ATA_SECONDARY_IRQ);
+    if !SECONDARY_DMA_COMPLETION.acknowledge() {
+        trace!("Secondary ATA Interrupt ({:#X}) without a DMA transfer", ATA_SECONDARY_IRQ);
+    }
     interrupts::eoi(ATA_SECONDARY_IRQ);
 }
 
@@ -1,174 +1,325 @@
-//! A caching layer for block based storage devices.
+//! A caching layer for block-based I/O streams, such as storage devices.
 //! 
 //! For many storage devices, calls to the backing medium are quite expensive. This layer intends to reduce those calls,
-//! improving efficiency in exchange for additional memory usage. Note that this crate is intended to be used as a part of
-//! `block_io`, but should work on its own.
+//! improving efficiency in exchange for additional memory usage.
 //! 
-//! # Limitations
-//! Currently, the `BlockCache` struct is hardcoded to use a `StorageDevice` reference,
-//! when in reality it should just use anything that implements traits like `BlockReader + BlockWriter`. 
+//! A [`BlockCache`] wraps any I/O stream that implements [`BlockReader`] and [`BlockWriter`],
+//! and itself implements those same traits (plus [`KnownLength`], if the inner stream does).
+//! Thus, it can be transparently inserted between a storage device and its users, e.g., 
+//! beneath a [`ByteReaderWriterWrapper`](io::ByteReaderWriterWrapper) used by a filesystem.
+//! 
+//! The cache holds up to a configurable number of blocks and evicts the least-recently used block
+//! when it is full. Writes are handled according to a [`WritePolicy`]:
+//! * [`WritePolicy::WriteThrough`]: writes go to the backing stream immediately,
+//!   so the cache never holds any dirty blocks.
+//! * [`WritePolicy::WriteBack`]: writes only modify the cache, and dirty blocks are written
+//!   to the backing stream when they are evicted or when the cache is flushed.
 //! 
-//! The read and write functions currently only support reading/writing individual blocks from disk even
-//! when the caller might prefer reading a larger number of contiguous blocks. This is inneficient and an
-//! optimized implementation should read multiple blocks at once if possible.
+//! Reads and writes of multiple contiguous blocks are transferred to and from the backing stream
+//! in as few operations as possible.
 //! 
+//! # Limitations
 //! Cached blocks are stored as vectors of bytes on the heap, 
 //! we should do something else such as separate mapped regions. 
-//! Cached blocks cannot yet be dropped to relieve memory pressure. 
 //! 
-//! Note that this cache only holds a reference to the underlying block device.
-//! As such if any other system crates perform writes to the underlying device,
-//! in that case the cache will give incorrect and potentially inconsistent results.
-//! In the long run, the only way around this would be to only expose a `BlockCache`
-//! instead of exposing a `StorageDevice`. I suppose the least disruptive way to implement this
-//! might be with a layer of indirection to an implementor of a BlockReader like trait,
-//! so that the underlying block reader can be switched out when a cache is enabled.
+//! Note that this cache must be the only means of accessing the underlying I/O stream.
+//! If any other system crates perform writes to the underlying device,
+//! the cache will give incorrect and potentially inconsistent results.
 
 #![no_std]
 
-#[macro_use] extern crate alloc;
+extern crate alloc;
 extern crate hashbrown;
-extern crate storage_device;
-
-use alloc::vec::Vec;
-use hashbrown::{
-    HashMap,
-    hash_map::Entry,
-};
-use storage_device::{StorageDevice, StorageDeviceRef};
-use alloc::borrow::{Cow, ToOwned};
-
-/// A cache to store read and written blocks from a storage device.
-pub struct BlockCache {
-    /// The cache of blocks (sectors) read from the storage device,
-    /// a map from block number to data byte array.
-    cache: InternalCache,
-    /// The underlying storage device from where the blocks are read/written.
-    storage_device: StorageDeviceRef,
+extern crate io;
+#[macro_use] extern crate log;
+
+#[cfg(test)]
+mod test;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use hashbrown::HashMap;
+use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
+
+/// The policy that determines when written blocks are sent to the backing I/O stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Every write is immediately forwarded to the backing stream.
+    WriteThrough,
+    /// Writes only modify the cache; modified blocks are written to the backing stream
+    /// upon eviction or when the cache is flushed.
+    WriteBack,
+}
+
+/// A cache of blocks read from and written to a block-based I/O stream.
+pub struct BlockCache<IO: BlockReader + BlockWriter> {
+    /// The underlying I/O stream from where the blocks are read/written.
+    io: IO,
+    /// The size in bytes of each block, cached from the underlying I/O stream.
+    block_size: usize,
+    /// The maximum number of blocks that can be held in the cache.
+    capacity: usize,
+    /// When written blocks are sent to the underlying I/O stream.
+    policy: WritePolicy,
+    /// The cache of blocks, a map from block number to the cached block.
+    cache: HashMap<usize, CachedBlock>,
+    /// The cached block numbers ordered from least- to most-recently used,
+    /// a map from the time of last use to block number.
+    lru: BTreeMap<u64, usize>,
+    /// The counter used to timestamp each use of a cached block.
+    next_timestamp: u64,
 }
 
-impl BlockCache {
-    /// Creates a new `BlockCache` device 
-    pub fn new(storage_device: StorageDeviceRef) -> BlockCache {
+impl<IO: BlockReader + BlockWriter> BlockCache<IO> {
+    /// Creates a new `BlockCache` atop the given `io` stream
+    /// that holds at most `capacity` blocks (at least one).
+    pub fn new(io: IO, capacity: usize, policy: WritePolicy) -> BlockCache<IO> {
         BlockCache {
+            block_size: io.block_size(),
+            io,
+            capacity: capacity.max(1),
+            policy,
             cache: HashMap::new(),
-            storage_device,
+            lru: BTreeMap::new(),
+            next_timestamp: 0,
         }
     }
 
-    /// Flushes the given block to the backing storage device. 
-    /// If the `block_to_flush` is None, all blocks in the entire cache
-    /// will be written back to the storage device.
-    pub fn flush(&mut self, block_num: Option<usize>) -> Result<(), &'static str> {
-        let mut locked_device = self.storage_device.lock();
-        if let Some(bn) = block_num {
-            // Flush just one block
-            if let Some(cached_block) = self.cache.get_mut(&bn) {
-                Self::flush_block(&mut *locked_device, bn, cached_block)?;
+    /// Returns the maximum number of blocks that can be held in this cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of blocks currently held in this cache.
+    pub fn cached_blocks(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the number of dirty blocks in this cache,
+    /// i.e., blocks that have been written but not yet flushed to the backing stream.
+    pub fn dirty_blocks(&self) -> usize {
+        self.cache.values().filter(|b| b.state == CacheState::Modified).count()
+    }
+
+    /// Returns `true` if the given block is held in this cache.
+    pub fn is_cached(&self, block_num: usize) -> bool {
+        self.cache.contains_key(&block_num)
+    }
+
+    /// Returns `true` if the given block is held in this cache and is dirty.
+    pub fn is_dirty(&self, block_num: usize) -> bool {
+        self.cache.get(&block_num).map_or(false, |b| b.state == CacheState::Modified)
+    }
+
+    /// Returns the current write policy of this cache.
+    pub fn policy(&self) -> WritePolicy {
+        self.policy
+    }
+
+    /// Changes the write policy of this cache.
+    ///
+    /// Switching to [`WritePolicy::WriteThrough`] flushes all dirty blocks first.
+    pub fn set_policy(&mut self, policy: WritePolicy) -> Result<(), IoError> {
+        if policy == WritePolicy::WriteThrough {
+            self.flush_dirty_blocks()?;
+        }
+        self.policy = policy;
+        Ok(())
+    }
+
+    /// Writes all dirty blocks back to the underlying I/O stream and discards all cached blocks.
+    pub fn clear(&mut self) -> Result<(), IoError> {
+        self.flush_dirty_blocks()?;
+        self.cache.clear();
+        self.lru.clear();
+        Ok(())
+    }
+
+    /// Returns a reference to the underlying I/O stream.
+    pub fn io(&self) -> &IO {
+        &self.io
+    }
+
+    /// Marks the given cached block as the most-recently used one.
+    fn touch(&mut self, block_num: usize) {
+        if let Some(cached_block) = self.cache.get_mut(&block_num) {
+            self.lru.remove(&cached_block.last_used);
+            cached_block.last_used = self.next_timestamp;
+            self.lru.insert(self.next_timestamp, block_num);
+            self.next_timestamp += 1;
+        }
+    }
+
+    /// Inserts the contents of the given block into the cache with the given `state`,
+    /// replacing any existing cached contents of that block.
+    ///
+    /// If the cache is full, the least-recently used block is evicted first.
+    fn insert(&mut self, block_num: usize, data: &[u8], state: CacheState) -> Result<(), IoError> {
+        if let Some(cached_block) = self.cache.get_mut(&block_num) {
+            cached_block.data.copy_from_slice(data);
+            cached_block.state = state;
+        } else {
+            if self.cache.len() >= self.capacity {
+                self.evict()?;
             }
-            // If the block wasn't in the cache, do nothing.
+            self.cache.insert(block_num, CachedBlock {
+                data: data.to_vec(),
+                state,
+                last_used: 0,
+            });
         }
-        else {
-            // Flush all blocks
-            for (bn, cached_block) in self.cache.iter_mut() {
-                Self::flush_block(&mut *locked_device, *bn, cached_block)?;
+        self.touch(block_num);
+        Ok(())
+    }
+
+    /// Removes the least-recently used block from the cache,
+    /// writing it back to the underlying I/O stream first if it is dirty.
+    fn evict(&mut self) -> Result<(), IoError> {
+        let Some((&timestamp, &block_num)) = self.lru.first_key_value() else {
+            return Ok(());
+        };
+        if let Some(cached_block) = self.cache.get(&block_num) {
+            if cached_block.state == CacheState::Modified {
+                self.io.write_blocks(&cached_block.data, block_num)?;
             }
         }
+        self.lru.remove(&timestamp);
+        self.cache.remove(&block_num);
         Ok(())
     }
 
-    /// An internal function that first checks the cache for a specific block
-    /// in order to avoid reading from the storage device.
-    /// If that block exists in the cache, it is copied into the buffer. 
-    /// If not, it is read from the storage device into the cache, and then copied into the buffer.
-    pub fn read_block(cache: &mut BlockCache, block: usize) -> Result<&[u8], &'static str> {
-        let mut locked_device = cache.storage_device.lock();
-        match cache.cache.entry(block) {
-            Entry::Occupied(occ) => {
-                // An existing entry in the cache can be used directly (without going to the backing store)
-                // if it's in the `Modified` or `Shared` state.
-                // But if it's in the `Invalid` state, we have to re-read the block from the storage device.
-                let cached_block = occ.into_mut();
-                match cached_block.state {
-                    CacheState::Modified | CacheState::Shared => Ok(&cached_block.block),
-                    CacheState::Invalid => {
-                        locked_device.read_blocks(&mut cached_block.block, block)?;
-                        cached_block.state = CacheState::Shared;
-                        Ok(&cached_block.block)
-                    }
-                }
+    /// Writes all dirty blocks back to the underlying I/O stream,
+    /// combining contiguous dirty blocks into a single write.
+    fn flush_dirty_blocks(&mut self) -> Result<(), IoError> {
+        let mut dirty: Vec<usize> = self.cache.iter()
+            .filter(|(_, b)| b.state == CacheState::Modified)
+            .map(|(&block_num, _)| block_num)
+            .collect();
+        dirty.sort_unstable();
+
+        let mut buffer = Vec::new();
+        let mut remaining = dirty.as_slice();
+        while !remaining.is_empty() {
+            let run_len = (1 .. remaining.len())
+                .find(|&i| remaining[i] != remaining[0] + i)
+                .unwrap_or(remaining.len());
+            let (run, rest) = remaining.split_at(run_len);
+            remaining = rest;
+
+            buffer.clear();
+            for block_num in run {
+                buffer.extend_from_slice(&self.cache[block_num].data);
             }
-            Entry::Vacant(vacant) => {
-                // A vacant entry will be read from the backing storage device,
-                // so it will always start out in the `Shared` state.
-                let mut v = vec![0; locked_device.block_size()];
-                locked_device.read_blocks(&mut v, block)?;
-                let cb = CachedBlock {
-                    block: v,
-                    state: CacheState::Shared,
-                };
-                let cached_block = vacant.insert(cb);
-                Ok(&cached_block.block)
+            self.io.write_blocks(&buffer, run[0])?;
+            for block_num in run {
+                if let Some(cached_block) = self.cache.get_mut(block_num) {
+                    cached_block.state = CacheState::Shared;
+                }
             }
         }
+        Ok(())
+    }
+}
+
+impl<IO: BlockReader + BlockWriter> BlockIo for BlockCache<IO> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+}
+
+impl<IO: BlockReader + BlockWriter + KnownLength> KnownLength for BlockCache<IO> {
+    fn len(&self) -> usize {
+        self.io.len()
     }
+}
+
+impl<IO: BlockReader + BlockWriter> BlockReader for BlockCache<IO> {
+    /// Reads blocks from the cache, reading any blocks that aren't cached
+    /// from the underlying I/O stream and caching them.
+    /// 
+    /// Each contiguous run of uncached blocks is read with a single transfer.
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        let block_size = self.block_size;
+        if buffer.len() % block_size != 0 {
+            return Err(IoError::InvalidInput);
+        }
+        let num_blocks = buffer.len() / block_size;
 
-    pub fn write_block(&mut self, block_num: usize, buffer_to_write: Cow<[u8]>)
-    //pub fn write_block(&mut self, block_num: usize, buffer_to_write: Cow<[u8]>)
-        -> Result<(), &'static str> 
-        {
-            let mut locked_device = self.storage_device.lock();
-
-            let owned_buffer: Vec<u8> = match buffer_to_write {
-                Cow::Borrowed(slice_ref) => slice_ref.to_owned(),
-                Cow::Owned(vec_owned) => vec_owned, 
-            };
-
-            let mut new_cached_block = CachedBlock {
-                block: owned_buffer,
-                state: CacheState::Modified,
-            };
-            // Currently using a write-through policy right now, so flush the block immediately
-            BlockCache::flush_block(&mut *locked_device, block_num, &mut new_cached_block)?;
-            self.cache.insert(block_num, new_cached_block);
-
-            Ok(())
-    }
-
-    /// An internal function that writes out the given `cached_block`
-    /// to the given locked `StorageDevice` if the cached block is in the `Modified` state.
-    fn flush_block(locked_device: &mut dyn StorageDevice, block_num: usize, cached_block: &mut CachedBlock) -> Result<(), &'static str> {
-        // we only need to actually write blocks in the `Modified` state.
-        match cached_block.state {
-            CacheState::Shared | CacheState::Invalid => { },
-            CacheState::Modified => {
-                locked_device.write_blocks(&cached_block.block, block_num)?;
-                cached_block.state = CacheState::Shared;
+        let mut i = 0;
+        while i < num_blocks {
+            let block_num = block_offset + i;
+            if let Some(cached_block) = self.cache.get(&block_num) {
+                buffer[i * block_size .. (i + 1) * block_size].copy_from_slice(&cached_block.data);
+                self.touch(block_num);
+                i += 1;
+                continue;
             }
+
+            // Read this block and all subsequent uncached blocks with one transfer.
+            let run_end = (i + 1 .. num_blocks)
+                .find(|j| self.cache.contains_key(&(block_offset + j)))
+                .unwrap_or(num_blocks);
+            self.io.read_blocks(&mut buffer[i * block_size .. run_end * block_size], block_num)?;
+            for j in i .. run_end {
+                self.insert(block_offset + j, &buffer[j * block_size .. (j + 1) * block_size], CacheState::Shared)?;
+            }
+            i = run_end;
         }
-        Ok(())
+        Ok(num_blocks)
     }
 }
 
+impl<IO: BlockReader + BlockWriter> BlockWriter for BlockCache<IO> {
+    /// Writes blocks into the cache, which are also written to the underlying I/O stream
+    /// if this cache uses the [`WritePolicy::WriteThrough`] policy.
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        let block_size = self.block_size;
+        if buffer.len() % block_size != 0 {
+            return Err(IoError::InvalidInput);
+        }
+        let num_blocks = buffer.len() / block_size;
 
+        let state = match self.policy {
+            WritePolicy::WriteThrough => {
+                self.io.write_blocks(buffer, block_offset)?;
+                CacheState::Shared
+            }
+            WritePolicy::WriteBack => CacheState::Modified,
+        };
+        for (i, block) in buffer.chunks_exact(block_size).enumerate() {
+            self.insert(block_offset + i, block, state)?;
+        }
+        Ok(num_blocks)
+    }
 
-/// A block from a storage device stored in a cache.
-/// This currently includes the actual owned cached content as a vector of bytes on the heap,
-/// in addition to the `CacheState` of the cached item.
-/// 
-/// TODO: allow non-dirty blocks to be freed (reclaimed) upon memory pressure. 
-#[derive(Debug)]
-struct CachedBlock { // Not sure if this should be public, but it seems necessary to fix type leak. TODO make non-public.
-    block: Vec<u8>,
-    state: CacheState,
+    /// Writes all dirty blocks back to the underlying I/O stream and then flushes it.
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.flush_dirty_blocks()?;
+        self.io.flush()
+    }
 }
 
-type InternalCache = HashMap<usize, CachedBlock>;
+impl<IO: BlockReader + BlockWriter> Drop for BlockCache<IO> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("BlockCache: failed to flush dirty blocks when dropped: {:?}", e);
+        }
+    }
+}
 
 
-/// The states of an item in the cache, following the MSI cache coherence protocol.
+/// A block from an I/O stream stored in a cache.
 #[derive(Debug)]
-#[allow(dead_code)]
+struct CachedBlock {
+    /// The contents of the block.
+    data: Vec<u8>,
+    /// Whether the contents are in sync with the backing stream.
+    state: CacheState,
+    /// The time at which this block was last used, its key in the LRU list.
+    last_used: u64,
+}
+
+/// The states of an item in the cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum CacheState {
     /// Dirty: the cached item has been modified more recently than the backing store,
     /// so it must be flushed at a future time to guarantee data correctness and consistency.
@@ -178,11 +329,4 @@ enum CacheState {
     /// Clean: the cached item and the backing store are in sync; they have the same value.
     /// A `Shared` cached item can be safely dropped from the cache.
     Shared,
-    /// The cached item is out-of-date and should not be read from,
-    /// as the backing storage has a more recent copy than the cache.
-    /// Therefore, if a read of an `Invalid` cached item is requested,
-    /// it must be re-read from the backing storage.
-    /// An `Invalid` item can still be overwritten in the cache without going to the backing store. 
-    /// An `Invalid` item can be safely dropped from the cache.
-    Invalid,  
 }
@@ -0,0 +1,148 @@
+//! Unit tests for the [`BlockCache`].
+
+extern crate std;
+use super::*;
+use alloc::vec;
+
+const BLOCK_SIZE: usize = 4;
+
+/// An in-memory block device that counts the transfers performed on it.
+struct MemDevice {
+    data: Vec<u8>,
+    reads: usize,
+    writes: usize,
+}
+
+impl MemDevice {
+    fn new(num_blocks: usize) -> MemDevice {
+        MemDevice {
+            data: (0 .. num_blocks * BLOCK_SIZE).map(|i| i as u8).collect(),
+            reads: 0,
+            writes: 0,
+        }
+    }
+}
+
+impl BlockIo for MemDevice {
+    fn block_size(&self) -> usize { BLOCK_SIZE }
+}
+impl BlockReader for MemDevice {
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        let start = block_offset * BLOCK_SIZE;
+        buffer.copy_from_slice(&self.data[start .. start + buffer.len()]);
+        self.reads += 1;
+        Ok(buffer.len() / BLOCK_SIZE)
+    }
+}
+impl BlockWriter for MemDevice {
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        let start = block_offset * BLOCK_SIZE;
+        self.data[start .. start + buffer.len()].copy_from_slice(buffer);
+        self.writes += 1;
+        Ok(buffer.len() / BLOCK_SIZE)
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+/// A `MemDevice` behind a reference, such that its counters can be inspected
+/// while it is used by a `BlockCache`.
+type Cache<'d> = BlockCache<&'d mut MemDevice>;
+
+#[test]
+fn test_multi_block_read_is_one_transfer() {
+    let mut device = MemDevice::new(16);
+    let mut cache: Cache = BlockCache::new(&mut device, 8, WritePolicy::WriteBack);
+    let mut buf = vec![0; 4 * BLOCK_SIZE];
+    cache.read_blocks(&mut buf, 2).unwrap();
+    assert_eq!(buf, (8u8 .. 24).collect::<Vec<_>>());
+    assert_eq!(cache.io().reads, 1);
+
+    // Reading the same blocks again is served entirely from the cache.
+    cache.read_blocks(&mut buf, 2).unwrap();
+    assert_eq!(cache.io().reads, 1);
+
+    // Only the uncached blocks on either side of the cached run are read.
+    let mut buf = vec![0; 8 * BLOCK_SIZE];
+    cache.read_blocks(&mut buf, 0).unwrap();
+    assert_eq!(buf, (0u8 .. 32).collect::<Vec<_>>());
+    assert_eq!(cache.io().reads, 3);
+}
+
+#[test]
+fn test_lru_eviction() {
+    let mut device = MemDevice::new(16);
+    let mut cache: Cache = BlockCache::new(&mut device, 2, WritePolicy::WriteThrough);
+    let mut buf = vec![0; BLOCK_SIZE];
+    cache.read_blocks(&mut buf, 0).unwrap();
+    cache.read_blocks(&mut buf, 1).unwrap();
+    // Use block 0 again so that block 1 becomes the least-recently used.
+    cache.read_blocks(&mut buf, 0).unwrap();
+    cache.read_blocks(&mut buf, 2).unwrap();
+
+    assert_eq!(cache.cached_blocks(), 2);
+    assert!(cache.is_cached(0));
+    assert!(!cache.is_cached(1));
+    assert!(cache.is_cached(2));
+}
+
+#[test]
+fn test_write_back_defers_and_coalesces_writes() {
+    let mut device = MemDevice::new(16);
+    let mut cache: Cache = BlockCache::new(&mut device, 8, WritePolicy::WriteBack);
+    cache.write_blocks(&[0xAA; BLOCK_SIZE], 3).unwrap();
+    cache.write_blocks(&[0xBB; 2 * BLOCK_SIZE], 4).unwrap();
+    cache.write_blocks(&[0xCC; BLOCK_SIZE], 10).unwrap();
+    assert_eq!(cache.io().writes, 0);
+    assert_eq!(cache.dirty_blocks(), 4);
+    assert!(cache.is_dirty(5));
+
+    let mut buf = vec![0; BLOCK_SIZE];
+    cache.read_blocks(&mut buf, 4).unwrap();
+    assert_eq!(buf, [0xBB; BLOCK_SIZE]);
+    assert_eq!(cache.io().reads, 0);
+
+    // Blocks 3-5 are written together, and block 10 separately.
+    cache.flush().unwrap();
+    assert_eq!(cache.io().writes, 2);
+    assert_eq!(cache.dirty_blocks(), 0);
+    drop(cache);
+    assert_eq!(&device.data[3 * BLOCK_SIZE .. 6 * BLOCK_SIZE], [0xAA, 0xAA, 0xAA, 0xAA, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB, 0xBB]);
+    assert_eq!(&device.data[10 * BLOCK_SIZE .. 11 * BLOCK_SIZE], [0xCC; BLOCK_SIZE]);
+}
+
+#[test]
+fn test_write_back_eviction_writes_dirty_block() {
+    let mut device = MemDevice::new(16);
+    let mut cache: Cache = BlockCache::new(&mut device, 1, WritePolicy::WriteBack);
+    cache.write_blocks(&[0xAA; BLOCK_SIZE], 0).unwrap();
+    cache.write_blocks(&[0xBB; BLOCK_SIZE], 1).unwrap();
+    assert_eq!(cache.io().writes, 1);
+    assert_eq!(&cache.io().data[.. BLOCK_SIZE], [0xAA; BLOCK_SIZE]);
+
+    // Dropping the cache flushes the remaining dirty block.
+    drop(cache);
+    assert_eq!(&device.data[BLOCK_SIZE .. 2 * BLOCK_SIZE], [0xBB; BLOCK_SIZE]);
+}
+
+#[test]
+fn test_write_through() {
+    let mut device = MemDevice::new(16);
+    let mut cache: Cache = BlockCache::new(&mut device, 8, WritePolicy::WriteThrough);
+    cache.write_blocks(&[0xAA; 2 * BLOCK_SIZE], 0).unwrap();
+    assert_eq!(cache.io().writes, 1);
+    assert_eq!(cache.dirty_blocks(), 0);
+
+    let mut buf = vec![0; 2 * BLOCK_SIZE];
+    cache.read_blocks(&mut buf, 0).unwrap();
+    assert_eq!(buf, [0xAA; 2 * BLOCK_SIZE]);
+    assert_eq!(cache.io().reads, 0);
+}
+
+#[test]
+fn test_unaligned_buffer_is_rejected() {
+    let mut device = MemDevice::new(16);
+    let mut cache: Cache = BlockCache::new(&mut device, 8, WritePolicy::WriteBack);
+    let mut buf = vec![0; BLOCK_SIZE + 1];
+    assert!(matches!(cache.read_blocks(&mut buf, 0), Err(IoError::InvalidInput)));
+    assert!(matches!(cache.write_blocks(&buf, 0), Err(IoError::InvalidInput)));
+}
@@ -213,6 +213,13 @@ pub fn init(
 
     // 2. Spawn various system tasks/daemons,
     console::start_connection_detection()?;
+    #[cfg(all(remote_shell, target_arch = "x86_64"))]
+    match net::get_default_interface() {
+        Some(interface) => {
+            remote_shell::start(interface, remote_shell::TELNET_PORT)?;
+        }
+        None => log::warn!("remote_shell: no network interface to listen on"),
+    }
 
     // 3. Start the first application(s).
     first_application::start()?;
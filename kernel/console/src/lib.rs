@@ -4,7 +4,7 @@
 
 extern crate alloc;
 
-use alloc::{format, sync::Arc};
+use alloc::{format, string::String, sync::Arc};
 use sync_channel::Receiver;
 use core::sync::atomic::{AtomicU16, Ordering};
 use core2::io::Write;
@@ -110,15 +110,40 @@ fn shell_loop(
         .spawn()?;
 
 
+    let task = spawn_shell(&tty, format!("{address:?}_hull"))?;
+    task.join()?;
+
+    reader_task.kill(KillReason::Requested).unwrap();
+    writer_task.kill(KillReason::Requested).unwrap();
+
+    // Flush the tty in case the reader task didn't run between the last time the
+    // shell wrote something to the slave end and us killing the task.
+    let mut data = [0; 256];
+    if let Ok(len) = tty.master().try_read(&mut data) {
+        port.lock()
+            .write(&data[..len])
+            .map_err(|_| "couldn't write to serial port")?;
+    };
+
+    // TODO: Close port?
+
+    Ok(())
+}
+
+/// Spawns a new `hull` shell task called `name`, whose standard streams are
+/// the slave end of the given `tty`.
+///
+/// The caller is responsible for bridging the master end of the `tty` to the terminal.
+pub fn spawn_shell(tty: &tty::Tty, name: String) -> Result<JoinableTaskRef, &'static str> {
     let new_app_ns = mod_mgmt::create_application_namespace(None)?;
 
     let (app_file, _ns) =
         mod_mgmt::CrateNamespace::get_crate_object_file_starting_with(&new_app_ns, "hull-")
-            .expect("Couldn't find hull in default app namespace");
+            .ok_or("couldn't find hull in default app namespace")?;
 
     let path = app_file.lock().get_absolute_path();
     let task = spawn::new_application_task_builder(path.as_ref(), Some(new_app_ns))?
-        .name(format!("{address:?}_hull"))
+        .name(name)
         .block()
         .spawn()?;
 
@@ -135,23 +160,7 @@ fn shell_loop(
     );
 
     task.unblock().map_err(|_| "couldn't unblock hull task")?;
-    task.join()?;
-
-    reader_task.kill(KillReason::Requested).unwrap();
-    writer_task.kill(KillReason::Requested).unwrap();
-
-    // Flush the tty in case the reader task didn't run between the last time the
-    // shell wrote something to the slave end and us killing the task.
-    let mut data = [0; 256];
-    if let Ok(len) = tty.master().try_read(&mut data) {
-        port.lock()
-            .write(&data[..len])
-            .map_err(|_| "couldn't write to serial port")?;
-    };
-
-    // TODO: Close port?
-
-    Ok(())
+    Ok(task)
 }
 
 fn tty_to_port_loop((port, master): (Arc<IrqSafeMutex<SerialPort>>, tty::Master)) {
@@ -11,8 +11,9 @@ use {
     event_types::Event,
     memory::MemoryManagementInfo,
     alloc::vec::Vec,
-    io::{ByteReaderWriterWrapper, LockableIo, ReaderWriter},
-    storage_manager::StorageDevice,
+    alloc::{format, string::ToString},
+    fs_node::DirRef,
+    vfs_node::VFSDirectory,
     memory::PhysicalAddress,
     serial_port::{SerialPortAddress, init_serial_port, take_serial_port_basic},
 };
@@ -127,8 +128,8 @@ pub fn init(
             if dev.vendor_id == e1000::INTEL_VEND && dev.device_id == e1000::E1000_DEV {
                 info!("e1000 PCI device found at: {:?}", dev.location);
                 let nic = e1000::E1000Nic::init(dev)?;
-                let interface = net::register_device(nic);
-                nic.lock().init_interrupts(interface)?;
+                net::register_device(nic);
+                nic.lock().init_interrupts()?;
 
                 continue;
             }
@@ -156,6 +157,12 @@ pub fn init(
                 ixgbe_devs.push(ixgbe_nic);
                 continue;
             }
+            if virtio_net::is_virtio_net_device(dev) {
+                info!("virtio-net PCI device found at: {:?}", dev.location);
+                let nic = virtio_net::VirtioNetNic::init(dev)?;
+                net::register_device(nic);
+                continue;
+            }
             if dev.vendor_id == mlx5::MLX_VEND && (dev.device_id == mlx5::CONNECTX5_DEV || dev.device_id == mlx5::CONNECTX5_EX_DEV) {
                 info!("mlx5 PCI device found at: {:?}", dev.location);
                 const RX_DESCS: usize = 512;
@@ -188,113 +195,61 @@ pub fn init(
         warn!("Note: no network devices found on this system.");
     }
 
+    // The loopback interface is always available, even without any NICs.
+    #[cfg(target_arch = "x86_64")]
+    loopback::init()?;
+
     // Discover filesystems from each storage device on the storage controllers initialized above
-    // and mount each filesystem to the root directory by default.
+    // and mount each filesystem in the `/mnt` directory by default.
     // No storage device support on aarch64 at the moment
     #[cfg(target_arch = "x86_64")]
-    if false {
-        for storage_device in storage_manager::storage_devices() {
-            let disk = fatfs_adapter::FatFsAdapter::new(
-                ReaderWriter::new(
-                    ByteReaderWriterWrapper::from(
-                        LockableIo::<dyn StorageDevice + Send, spin::Mutex<_>, _>::from(storage_device)
-                    )
-                ),
-            );
-
-            if let Ok(filesystem) = fatfs::FileSystem::new(disk, fatfs::FsOptions::new()) {
-                debug!("FATFS data:
-                    fat_type: {:?},
-                    volume_id: {:X?},
-                    volume_label: {:?},
-                    cluster_size: {:?},
-                    status_flags: {:?},
-                    stats: {:?}",
-                    filesystem.fat_type(),
-                    filesystem.volume_id(),
-                    filesystem.volume_label(),
-                    filesystem.cluster_size(),
-                    filesystem.read_status_flags(),
-                    filesystem.stats(),
-                );
-
-                let root = filesystem.root_dir();
-                debug!("Root directory contents:");
-                for f in root.iter() {
-                    debug!("\t {:X?}", f.map(|entry| (entry.file_name(), entry.attributes(), entry.len())));
-                }
-            }
-        }
-    }
+    mount_storage_devices()?;
 
     Ok(())
 }
 
+/// The name of the directory in the root directory in which filesystems are mounted.
 #[cfg(target_arch = "x86_64")]
-mod fatfs_adapter {
-// TODO: move the following `FatFsAdapter` stuff into a separate crate. 
-
-use derive_more::{From, Into};
+pub const MOUNT_DIRECTORY_NAME: &str = "mnt";
 
-/// An adapter (wrapper type) that implements traits required by the [`fatfs`] crate
-/// for any I/O device that wants to be usable by [`fatfs`].
-///
-/// To meet [`fatfs`]'s requirements, the underlying I/O stream must be able to 
-/// read, write, and seek while tracking its current offset. 
-/// We use traits from the [`core2`] crate to meet these requirements, 
-/// thus, the given `IO` parameter must implement those [`core2`] traits.
+/// Attempts to open a native or FAT filesystem on each storage device
+/// and mounts each one it finds at `/mnt/disk<N>`, where `N` is the ID of its storage device.
 ///
-/// For example, this allows one to access a FAT filesystem 
-/// by reading from or writing to a storage device.
-pub struct FatFsAdapter<IO>(IO);
-impl<IO> FatFsAdapter<IO> {
-    pub fn new(io: IO) -> FatFsAdapter<IO> { FatFsAdapter(io) }
-}
-/// This tells the `fatfs` crate that our read/write/seek functions
-/// may return errors of the type [`FatFsIoErrorAdapter`],
-/// which is a simple wrapper around [`core2::io::Error`].
-impl<IO> fatfs::IoBase for FatFsAdapter<IO> {
-    type Error = FatFsIoErrorAdapter;
-}
-impl<IO> fatfs::Read for FatFsAdapter<IO> where IO: core2::io::Read {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        self.0.read(buf).map_err(Into::into)
-    }
-}
-impl<IO> fatfs::Write for FatFsAdapter<IO> where IO: core2::io::Write {
-    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        self.0.write(buf).map_err(Into::into)
-    }
-    fn flush(&mut self) -> Result<(), Self::Error> {
-        self.0.flush().map_err(Into::into)
-    }
-}
-impl<IO> fatfs::Seek for FatFsAdapter<IO> where IO: core2::io::Seek {
-    fn seek(&mut self, pos: fatfs::SeekFrom) -> Result<u64, Self::Error> {
-        let core2_pos = match pos {
-            fatfs::SeekFrom::Start(s)   => core2::io::SeekFrom::Start(s),
-            fatfs::SeekFrom::Current(c) => core2::io::SeekFrom::Current(c),
-            fatfs::SeekFrom::End(e)     => core2::io::SeekFrom::End(e),
+/// Storage devices that don't contain a supported filesystem are skipped.
+#[cfg(target_arch = "x86_64")]
+fn mount_storage_devices() -> Result<(), &'static str> {
+    for (id, storage_device) in storage_manager::storage_devices_with_ids() {
+        let name = format!("disk{}", id.0);
+        let (fs_root, fs_type, source) = match nativefs::NativeFileSystem::new(storage_device.clone()) {
+            Ok(fs) => (fs.root_dir(name.clone()), "native", format!("nativefs:{id}")),
+            Err(native_err) => match fatfs_node::FatFileSystem::new(storage_device) {
+                Ok(fs) => (fs.root_dir(name.clone()), "FAT", format!("fat:{id}")),
+                Err(fat_err) => {
+                    debug!("No filesystem found on storage device {}: {}; {}", id, native_err, fat_err);
+                    continue;
+                }
+            },
         };
-        self.0.seek(core2_pos).map_err(Into::into)
+
+        let mount_dir = get_or_create_dir(MOUNT_DIRECTORY_NAME.to_string(), root::get_root())?;
+        let mount_point = get_or_create_dir(name, &mount_dir)?;
+        match mount_table::mount(&mount_point, fs_root, source, Some(id)) {
+            Ok(()) => info!("Mounted {} filesystem from storage device {} at {:?}",
+                fs_type, id, mount_point.lock().get_absolute_path()
+            ),
+            Err(e) => error!("Failed to mount {} filesystem from storage device {}: {}", fs_type, id, e),
+        }
     }
+    Ok(())
 }
 
-/// This struct exists to enable us to implement the [`fatfs::IoError`] trait
-/// for the [`core2::io::Error`] trait.
-/// 
-/// This is required because Rust prevents implementing foreign traits for foreign types.
-#[derive(Debug, From, Into)]
-pub struct FatFsIoErrorAdapter(core2::io::Error);
-impl fatfs::IoError for FatFsIoErrorAdapter {
-    fn is_interrupted(&self) -> bool {
-        self.0.kind() == core2::io::ErrorKind::Interrupted
-    }
-    fn new_unexpected_eof_error() -> Self {
-        FatFsIoErrorAdapter(core2::io::ErrorKind::UnexpectedEof.into())
-    }
-    fn new_write_zero_error() -> Self {
-        FatFsIoErrorAdapter(core2::io::ErrorKind::WriteZero.into())
+/// Returns the directory named `name` within the given `parent` directory,
+/// creating it if it doesn't yet exist.
+#[cfg(target_arch = "x86_64")]
+fn get_or_create_dir(name: String, parent: &DirRef) -> Result<DirRef, &'static str> {
+    let existing_dir = parent.lock().get_dir(&name);
+    match existing_dir {
+        Some(dir) => Ok(dir),
+        None => VFSDirectory::create(name, parent),
     }
 }
-}
@@ -0,0 +1,272 @@
+//! A DNS resolver that looks up the IPv4 and IPv6 addresses of hostnames over UDP.
+//!
+//! Queries are sent to the nameservers configured using [`set_nameservers()`],
+//! or if none were configured, to the DNS servers that the network interface
+//! acquired from DHCP.
+//! Answers are cached for as long as their TTL allows.
+//!
+//! A hostname that is already an IP address is returned as is, without any queries,
+//! and `localhost` always resolves to the loopback address.
+
+#![no_std]
+
+extern crate alloc;
+
+mod message;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    str::FromStr,
+    task::{Context, Poll},
+    time::Duration,
+};
+use log::{debug, warn};
+use net::{udp, IpAddress, IpEndpoint, Ipv4Address, NetworkInterface, Socket};
+use spin::Mutex;
+use time::Instant;
+
+pub use message::RecordType;
+
+/// The well-known UDP port of DNS servers.
+pub const DNS_PORT: u16 = 53;
+
+/// How long to wait for a nameserver to respond to a query.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many times a query is sent to each nameserver before trying the next one.
+const QUERY_ATTEMPTS: usize = 2;
+/// The maximum size of a DNS message sent over UDP.
+const MAX_MESSAGE_LEN: usize = 512;
+/// The maximum number of answers held in the cache.
+const CACHE_CAPACITY: usize = 256;
+/// The maximum time for which an answer is cached, regardless of its TTL.
+const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The nameservers that override those acquired from DHCP.
+static NAMESERVERS: Mutex<Vec<IpAddress>> = Mutex::new(Vec::new());
+
+/// Cached answers, keyed by lowercase hostname and record type.
+static CACHE: Mutex<BTreeMap<(String, RecordType), CacheEntry>> = Mutex::new(BTreeMap::new());
+
+struct CacheEntry {
+    addresses: Vec<IpAddress>,
+    expires: Instant,
+}
+
+/// Sets the nameservers that queries are sent to, in order of preference.
+///
+/// If `nameservers` is empty, the DNS servers acquired by each interface from DHCP are used.
+pub fn set_nameservers(nameservers: Vec<IpAddress>) {
+    *NAMESERVERS.lock() = nameservers;
+}
+
+/// Returns the nameservers that queries sent over the given `interface` will go to.
+pub fn nameservers(interface: &NetworkInterface) -> Vec<IpAddress> {
+    let configured = NAMESERVERS.lock().clone();
+    if configured.is_empty() {
+        interface.dns_servers().into_iter().map(IpAddress::Ipv4).collect()
+    } else {
+        configured
+    }
+}
+
+/// Removes all cached answers.
+pub fn clear_cache() {
+    CACHE.lock().clear();
+}
+
+/// Resolves `host` into its IP addresses, with IPv4 addresses first.
+///
+/// If `host` is already an IP address, it is returned without querying any nameservers,
+/// as is the loopback address for `localhost`.
+pub fn resolve(interface: &Arc<NetworkInterface>, host: &str) -> Result<Vec<IpAddress>, &'static str> {
+    if let Ok(address) = IpAddress::from_str(host) {
+        return Ok(vec![address]);
+    }
+    if host.trim_end_matches('.').eq_ignore_ascii_case("localhost") {
+        return Ok(vec![IpAddress::Ipv4(Ipv4Address::new(127, 0, 0, 1))]);
+    }
+
+    let mut addresses = lookup(interface, host, RecordType::A)?;
+    match lookup(interface, host, RecordType::Aaaa) {
+        Ok(ipv6_addresses) => addresses.extend(ipv6_addresses),
+        Err(e) => debug!("dns: failed to look up AAAA records of {host:?}: {e}"),
+    }
+    if addresses.is_empty() {
+        Err("host has no IP addresses")
+    } else {
+        Ok(addresses)
+    }
+}
+
+/// Resolves `host` into a single IP address, preferring IPv4 addresses.
+pub fn resolve_one(interface: &Arc<NetworkInterface>, host: &str) -> Result<IpAddress, &'static str> {
+    resolve(interface, host).map(|addresses| addresses[0])
+}
+
+/// Resolves a `HOST[:PORT]` string into an endpoint,
+/// using `default_port` if no port is specified.
+///
+/// IPv6 addresses with a port must be enclosed in brackets, e.g., `[::1]:80`.
+pub fn resolve_endpoint(
+    interface: &Arc<NetworkInterface>,
+    host_and_port: &str,
+    default_port: u16,
+) -> Result<IpEndpoint, &'static str> {
+    if let Ok(mut endpoint) = IpEndpoint::from_str(host_and_port) {
+        if endpoint.port == 0 {
+            endpoint.port = default_port;
+        }
+        return Ok(endpoint);
+    }
+    let (host, port) = match host_and_port.rsplit_once(':') {
+        // An IPv6 address without brackets or a port.
+        Some((host, _)) if host.contains(':') => (host_and_port, default_port),
+        Some((host, port)) => (host, port.parse().map_err(|_| "invalid port")?),
+        None => (host_and_port, default_port),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok(IpEndpoint::new(resolve_one(interface, host)?, port))
+}
+
+/// Looks up the `record_type` records of `host`, using the cache if possible.
+///
+/// Returns an empty list if the host exists but has no such records.
+pub fn lookup(
+    interface: &Arc<NetworkInterface>,
+    host: &str,
+    record_type: RecordType,
+) -> Result<Vec<IpAddress>, &'static str> {
+    let key = (host.trim_end_matches('.').to_ascii_lowercase(), record_type);
+    if let Some(entry) = CACHE.lock().get(&key) {
+        if entry.expires > Instant::now() {
+            return Ok(entry.addresses.clone());
+        }
+    }
+
+    let nameservers = nameservers(interface);
+    if nameservers.is_empty() {
+        return Err("no DNS nameservers are configured");
+    }
+
+    let mut result = Err("no DNS nameserver responded");
+    'nameservers: for nameserver in nameservers {
+        for _ in 0..QUERY_ATTEMPTS {
+            match query(interface, IpEndpoint::new(nameserver, DNS_PORT), &key.0, record_type) {
+                Ok(Some(answer)) => {
+                    result = Ok(answer);
+                    break 'nameservers;
+                }
+                Ok(None) => debug!("dns: query to {nameserver} timed out"),
+                // The domain name doesn't exist, so asking other nameservers is pointless.
+                Err(message::NAME_ERROR) => {
+                    result = Err(message::NAME_ERROR);
+                    break 'nameservers;
+                }
+                // This nameserver failed or sent a bad response, but another one may not.
+                Err(e) => {
+                    debug!("dns: query to {nameserver} failed: {e}");
+                    result = Err(e);
+                    continue 'nameservers;
+                }
+            }
+        }
+    }
+    let answer = result?;
+
+    if answer.ttl > 0 {
+        let ttl = Duration::from_secs(answer.ttl.into()).min(MAX_TTL);
+        insert_into_cache(key, CacheEntry {
+            addresses: answer.addresses.clone(),
+            expires: Instant::now() + ttl,
+        });
+    }
+    Ok(answer.addresses)
+}
+
+fn insert_into_cache(key: (String, RecordType), entry: CacheEntry) {
+    let mut cache = CACHE.lock();
+    if cache.len() >= CACHE_CAPACITY {
+        let now = Instant::now();
+        cache.retain(|_, entry| entry.expires > now);
+    }
+    if cache.len() >= CACHE_CAPACITY {
+        // Evict the answer that expires the soonest.
+        let soonest = cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.expires)
+            .map(|(key, _)| key.clone());
+        if let Some(soonest) = soonest {
+            cache.remove(&soonest);
+        }
+    }
+    cache.insert(key, entry);
+}
+
+/// Sends a single query to `nameserver` and waits for its answer.
+///
+/// Returns `Ok(None)` if the nameserver didn't respond in time.
+fn query(
+    interface: &Arc<NetworkInterface>,
+    nameserver: IpEndpoint,
+    host: &str,
+    record_type: RecordType,
+) -> Result<Option<message::Answer>, &'static str> {
+    let id = random::next_u32() as u16;
+    let query = message::encode_query(id, host, record_type)?;
+
+    let socket = interface.clone().add_socket(udp::Socket::new(
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; 4], vec![0; 4 * MAX_MESSAGE_LEN]),
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; 1], vec![0; MAX_MESSAGE_LEN]),
+    ));
+    let result = send_query_and_receive(&socket, nameserver, id, record_type, &query);
+    socket.remove();
+    result
+}
+
+fn send_query_and_receive(
+    socket: &Socket<udp::Socket<'static>>,
+    nameserver: IpEndpoint,
+    id: u16,
+    record_type: RecordType,
+    query: &[u8],
+) -> Result<Option<message::Answer>, &'static str> {
+    socket
+        .lock()
+        .bind(net::get_ephemeral_port())
+        .map_err(|_| "failed to bind DNS socket")?;
+    socket.send(query, nameserver).map_err(|_| "failed to send DNS query")?;
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let (waker, blocker) = waker::new_waker();
+    sleep::future::sleep(QUERY_TIMEOUT, waker.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    loop {
+        match socket.poll_recv(&mut context, &mut buf) {
+            Poll::Ready(Ok((len, from))) if from == nameserver => {
+                match message::decode_response(id, record_type, &buf[..len]) {
+                    Ok(Some(answer)) => return Ok(Some(answer)),
+                    // Ignore stray or spoofed responses.
+                    Ok(None) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            Poll::Ready(Ok((_, from))) => {
+                warn!("dns: ignoring datagram from {from}, which isn't the queried nameserver");
+                continue;
+            }
+            Poll::Ready(Err(_)) => return Err("failed to receive DNS response"),
+            Poll::Pending => {}
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        blocker.block();
+    }
+}
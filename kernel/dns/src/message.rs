@@ -0,0 +1,369 @@
+//! Encoding DNS queries and decoding their responses, as specified in RFC 1035.
+
+use alloc::vec::Vec;
+use net::{
+    wire::{Ipv4Address, Ipv6Address},
+    IpAddress,
+};
+
+/// The size of a DNS message header.
+const HEADER_LEN: usize = 12;
+/// The maximum length of a label within a domain name.
+const MAX_LABEL_LEN: usize = 63;
+/// The maximum length of a domain name in its textual form.
+const MAX_NAME_LEN: usize = 253;
+
+/// Header flag: this message is a response.
+const FLAG_RESPONSE: u16 = 1 << 15;
+/// Header flag: the message was truncated to fit into a single datagram.
+const FLAG_TRUNCATED: u16 = 1 << 9;
+/// Header flag: the server should resolve the query recursively.
+const FLAG_RECURSION_DESIRED: u16 = 1 << 8;
+/// The header bits that hold the response code.
+const RCODE_MASK: u16 = 0xF;
+const RCODE_NAME_ERROR: u16 = 3;
+
+/// The error returned when a nameserver reports that the queried domain name doesn't exist.
+///
+/// Unlike other errors, this is a definitive answer that no other nameserver will contradict.
+pub const NAME_ERROR: &str = "domain name does not exist";
+
+/// The `IN` (Internet) class.
+const CLASS_IN: u16 = 1;
+
+/// The kind of DNS record to query for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RecordType {
+    /// An IPv4 address record.
+    A = 1,
+    /// An IPv6 address record.
+    Aaaa = 28,
+}
+
+/// The answer to a query.
+#[derive(Debug)]
+pub struct Answer {
+    /// The addresses in the response's answer records, in the order they were received.
+    pub addresses: Vec<IpAddress>,
+    /// The lowest TTL in seconds of all answer records, for which the answer may be cached.
+    pub ttl: u32,
+}
+
+/// Encodes a recursive query for `record_type` records of `name` with the given message `id`.
+pub fn encode_query(id: u16, name: &str, record_type: RecordType) -> Result<Vec<u8>, &'static str> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err("invalid domain name length");
+    }
+
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, and no answer, authority, or additional records.
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return Err("invalid label in domain name");
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&(record_type as u16).to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Decodes the response to the query with the given `id` and `record_type`.
+///
+/// Returns `Ok(None)` if `response` isn't a response to that query,
+/// in which case the caller should keep waiting for the actual response.
+pub fn decode_response(
+    id: u16,
+    record_type: RecordType,
+    response: &[u8],
+) -> Result<Option<Answer>, &'static str> {
+    if response.len() < HEADER_LEN || read_u16(response, 0)? != id {
+        return Ok(None);
+    }
+    let flags = read_u16(response, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        return Ok(None);
+    }
+    match flags & RCODE_MASK {
+        0 => {}
+        RCODE_NAME_ERROR => return Err(NAME_ERROR),
+        _ => return Err("DNS server failed to resolve the domain name"),
+    }
+    if flags & FLAG_TRUNCATED != 0 {
+        // We don't retry over TCP, but a truncated response still contains some usable records.
+        log::debug!("dns: received truncated response");
+    }
+
+    let question_count = read_u16(response, 4)?;
+    let answer_count = read_u16(response, 6)?;
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..question_count {
+        // Skip the name, type, and class.
+        offset = skip_name(response, offset)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..answer_count {
+        offset = skip_name(response, offset)?;
+        let rtype = read_u16(response, offset)?;
+        let class = read_u16(response, offset + 2)?;
+        let record_ttl = read_u32(response, offset + 4)?;
+        let data_len = read_u16(response, offset + 8)? as usize;
+        let data = response
+            .get(offset + 10..offset + 10 + data_len)
+            .ok_or("malformed DNS response: record data out of bounds")?;
+        offset += 10 + data_len;
+
+        if class != CLASS_IN {
+            continue;
+        }
+        let address = match (rtype, data.len()) {
+            (1, 4) if record_type == RecordType::A => {
+                IpAddress::Ipv4(Ipv4Address::from_bytes(data))
+            }
+            (28, 16) if record_type == RecordType::Aaaa => {
+                IpAddress::Ipv6(Ipv6Address::from_bytes(data))
+            }
+            // Other records, e.g., CNAMEs leading up to the address records,
+            // limit how long the answer is valid, but are otherwise ignored.
+            _ => {
+                ttl = ttl.min(record_ttl);
+                continue;
+            }
+        };
+        ttl = ttl.min(record_ttl);
+        addresses.push(address);
+    }
+
+    if addresses.is_empty() {
+        ttl = 0;
+    }
+    Ok(Some(Answer { addresses, ttl }))
+}
+
+/// Returns the offset just past the (possibly compressed) domain name starting at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize, &'static str> {
+    loop {
+        let len = *message.get(offset).ok_or("malformed DNS response: name out of bounds")?;
+        match len {
+            0 => return Ok(offset + 1),
+            // A pointer to a name elsewhere in the message, which always ends the name.
+            len if len & 0xC0 == 0xC0 => return Ok(offset + 2),
+            len if len as usize <= MAX_LABEL_LEN => offset += 1 + len as usize,
+            _ => return Err("malformed DNS response: invalid label length"),
+        }
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Result<u16, &'static str> {
+    message
+        .get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or("malformed DNS response: truncated")
+}
+
+fn read_u32(message: &[u8], offset: usize) -> Result<u32, &'static str> {
+    message
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or("malformed DNS response: truncated")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, vec};
+
+    const ID: u16 = 0x1234;
+    /// A compression pointer to the name in the question, which starts right after the header.
+    const QUESTION_NAME: [u8; 2] = [0xC0, HEADER_LEN as u8];
+
+    /// Returns a response to a query for `name` with the given response code and answer `records`.
+    fn response(name: &str, record_type: RecordType, rcode: u16, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut message = encode_query(ID, name, record_type).unwrap();
+        let flags = FLAG_RESPONSE | FLAG_RECURSION_DESIRED | rcode;
+        message[2..4].copy_from_slice(&flags.to_be_bytes());
+        message[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for record in records {
+            message.extend_from_slice(record);
+        }
+        message
+    }
+
+    /// Returns a resource record with the given (encoded) `name`.
+    fn record(name: &[u8], rtype: u16, ttl: u32, data: &[u8]) -> Vec<u8> {
+        let mut record = name.to_vec();
+        record.extend_from_slice(&rtype.to_be_bytes());
+        record.extend_from_slice(&CLASS_IN.to_be_bytes());
+        record.extend_from_slice(&ttl.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    fn a_record(name: &[u8], ttl: u32, address: [u8; 4]) -> Vec<u8> {
+        record(name, RecordType::A as u16, ttl, &address)
+    }
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(ID, "www.example.com", RecordType::Aaaa).unwrap();
+        assert_eq!(&query[..HEADER_LEN], [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[HEADER_LEN..], b"\x03www\x07example\x03com\x00\x00\x1C\x00\x01");
+        // A trailing dot denotes the same fully-qualified name.
+        assert_eq!(encode_query(ID, "www.example.com.", RecordType::Aaaa).unwrap(), query);
+    }
+
+    #[test]
+    fn test_encode_name_limits() {
+        let label_63 = "a".repeat(MAX_LABEL_LEN);
+        assert!(encode_query(ID, &label_63, RecordType::A).is_ok());
+        assert!(encode_query(ID, &"a".repeat(MAX_LABEL_LEN + 1), RecordType::A).is_err());
+
+        // Three 63-byte labels, one 61-byte label, and three dots are exactly 253 bytes.
+        let name_253 = format!("{0}.{0}.{0}.{1}", label_63, "b".repeat(61));
+        assert_eq!(name_253.len(), MAX_NAME_LEN);
+        assert!(encode_query(ID, &name_253, RecordType::A).is_ok());
+        assert!(encode_query(ID, &format!("{name_253}b"), RecordType::A).is_err());
+
+        for invalid in ["", ".", "a..b", ".a", "a.b.."] {
+            assert!(encode_query(ID, invalid, RecordType::A).is_err(), "{invalid:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_decode_compressed_answers() {
+        let message = response("example.com", RecordType::A, 0, &[
+            a_record(&QUESTION_NAME, 300, [10, 0, 0, 1]),
+            // An uncompressed name works too.
+            a_record(b"\x07example\x03com\x00", 120, [10, 0, 0, 2]),
+        ]);
+        let answer = decode_response(ID, RecordType::A, &message).unwrap().unwrap();
+        assert_eq!(answer.addresses, [
+            IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 1)),
+            IpAddress::Ipv4(Ipv4Address::new(10, 0, 0, 2)),
+        ]);
+        assert_eq!(answer.ttl, 120);
+    }
+
+    #[test]
+    fn test_decode_cname_chain() {
+        let question_len = encode_query(ID, "www.example.com", RecordType::A).unwrap().len();
+        // The first CNAME's target name, "web.example.com", directly follows its fixed-size fields
+        // and reuses the "example.com" suffix of the question's name.
+        let first_target_offset = (question_len + QUESTION_NAME.len() + 10) as u8;
+        let first_target = [&b"\x03web"[..], &[0xC0, HEADER_LEN as u8 + 4][..]].concat();
+        let second_target = b"\x03cdn\x03net\x00";
+        let message = response("www.example.com", RecordType::A, 0, &[
+            record(&QUESTION_NAME, 5, 600, &first_target),
+            record(&[0xC0, first_target_offset], 5, 60, second_target),
+            a_record(b"\x03cdn\x03net\x00", 3600, [192, 0, 2, 7]),
+        ]);
+        let answer = decode_response(ID, RecordType::A, &message).unwrap().unwrap();
+        assert_eq!(answer.addresses, [IpAddress::Ipv4(Ipv4Address::new(192, 0, 2, 7))]);
+        // The answer is only valid for as long as every record in the chain is.
+        assert_eq!(answer.ttl, 60);
+    }
+
+    #[test]
+    fn test_decode_ignores_other_record_types() {
+        let message = response("example.com", RecordType::Aaaa, 0, &[
+            a_record(&QUESTION_NAME, 300, [10, 0, 0, 1]),
+        ]);
+        let answer = decode_response(ID, RecordType::Aaaa, &message).unwrap().unwrap();
+        assert!(answer.addresses.is_empty());
+        // An answer without addresses must not be cached.
+        assert_eq!(answer.ttl, 0);
+    }
+
+    #[test]
+    fn test_decode_pointer_loops() {
+        // An answer whose name points to itself. Names aren't followed when decoding answers,
+        // so this must neither hang nor overflow the stack.
+        let question_len = encode_query(ID, "example.com", RecordType::A).unwrap().len();
+        let self_pointer = [0xC0, question_len as u8];
+        let message = response("example.com", RecordType::A, 0, &[
+            a_record(&self_pointer, 300, [10, 0, 0, 1]),
+        ]);
+        let answer = decode_response(ID, RecordType::A, &message).unwrap().unwrap();
+        assert_eq!(answer.addresses.len(), 1);
+
+        // A question whose name points to itself.
+        let mut message = vec![0; HEADER_LEN];
+        message[..2].copy_from_slice(&ID.to_be_bytes());
+        message[2..4].copy_from_slice(&FLAG_RESPONSE.to_be_bytes());
+        message[4..6].copy_from_slice(&1u16.to_be_bytes());
+        message.extend_from_slice(&QUESTION_NAME);
+        message.extend_from_slice(&[0, 1, 0, 1]);
+        let answer = decode_response(ID, RecordType::A, &message).unwrap().unwrap();
+        assert!(answer.addresses.is_empty());
+    }
+
+    #[test]
+    fn test_decode_invalid_label_length() {
+        let mut message = response("example.com", RecordType::A, 0, &[
+            a_record(&QUESTION_NAME, 300, [10, 0, 0, 1]),
+        ]);
+        // 0x40 and 0x80 are reserved label types.
+        message[HEADER_LEN] = 0x40;
+        assert!(decode_response(ID, RecordType::A, &message).is_err());
+        message[HEADER_LEN] = 0x80;
+        assert!(decode_response(ID, RecordType::A, &message).is_err());
+    }
+
+    #[test]
+    fn test_decode_truncated_messages() {
+        let message = response("example.com", RecordType::A, 0, &[
+            a_record(&QUESTION_NAME, 300, [10, 0, 0, 1]),
+        ]);
+        // Too short to be a response to our query.
+        for len in 0..HEADER_LEN {
+            assert!(decode_response(ID, RecordType::A, &message[..len]).unwrap().is_none());
+        }
+        // Cut off somewhere in the question or the answer.
+        for len in HEADER_LEN..message.len() {
+            assert!(decode_response(ID, RecordType::A, &message[..len]).is_err(), "length {len} was accepted");
+        }
+
+        // A response with the truncation flag set still yields the records it contains.
+        let mut message = message;
+        message[2] |= (FLAG_TRUNCATED >> 8) as u8;
+        let answer = decode_response(ID, RecordType::A, &message).unwrap().unwrap();
+        assert_eq!(answer.addresses.len(), 1);
+    }
+
+    #[test]
+    fn test_decode_response_codes() {
+        let nxdomain = response("nonexistent.example", RecordType::A, RCODE_NAME_ERROR, &[]);
+        assert_eq!(decode_response(ID, RecordType::A, &nxdomain).unwrap_err(), NAME_ERROR);
+
+        // SERVFAIL and REFUSED.
+        for rcode in [2, 5] {
+            let message = response("example.com", RecordType::A, rcode, &[]);
+            assert_eq!(
+                decode_response(ID, RecordType::A, &message).unwrap_err(),
+                "DNS server failed to resolve the domain name",
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_ignores_other_messages() {
+        let message = response("example.com", RecordType::A, 0, &[
+            a_record(&QUESTION_NAME, 300, [10, 0, 0, 1]),
+        ]);
+        // A response to a different query.
+        assert!(decode_response(ID + 1, RecordType::A, &message).unwrap().is_none());
+        // A query rather than a response.
+        let query = encode_query(ID, "example.com", RecordType::A).unwrap();
+        assert!(decode_response(ID, RecordType::A, &query).unwrap().is_none());
+    }
+}
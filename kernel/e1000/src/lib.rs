@@ -23,15 +23,14 @@ extern crate nic_buffers;
 extern crate nic_queues;
 extern crate nic_initialization;
 extern crate net;
-extern crate deferred_interrupt_tasks;
-extern crate task;
 
 pub mod test_e1000_driver;
 mod regs;
 use regs::*;
 
 use spin::Once; 
-use alloc::{collections::VecDeque, format, sync::Arc, vec::Vec};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::task::Waker;
 use sync_irq::IrqSafeMutex;
 use memory::{PhysicalAddress, BorrowedMappedPages, BorrowedSliceMappedPages, Mutable, map_frame_range, MMIO_FLAGS};
 use pci::{PciDevice, PciConfigSpaceAccessMechanism};
@@ -142,7 +141,8 @@ pub struct E1000Nic {
     regs: BorrowedMappedPages<E1000Registers, Mutable>,
     /// memory-mapped registers holding the MAC address
     mac_regs: BorrowedMappedPages<E1000MacRegisters, Mutable>,
-    deferred_task: Option<task::JoinableTaskRef>,
+    /// Woken whenever frames are received, set by the network interface using this NIC.
+    receive_waker: Option<Waker>,
 }
 
 /// Functions that setup the NIC struct and handle the sending and receiving of packets.
@@ -227,7 +227,7 @@ impl E1000Nic {
             tx_queue: txq,
             regs: mapped_registers,
             mac_regs: mac_registers,
-            deferred_task: None,
+            receive_waker: None,
         };
         
         let nic_ref = E1000_NIC.call_once(|| IrqSafeMutex::new(e1000_nic));
@@ -236,26 +236,15 @@ impl E1000Nic {
     
     /// Initializes the interrupt handler and enables interrupts for this E1000 NIC.
     ///
-    /// The provided `interface` must be the network interface associated with this E1000 NIC.
-    /// This interface will be polled in a deferred task upon an interrupt being triggered
-    /// for a received packet.
-    pub fn init_interrupts(
-        &mut self,
-        interface: Arc<net::NetworkInterface>,
-    ) -> Result<(), &'static str> {
+    /// Upon an interrupt being triggered for a received packet, the network interface
+    /// associated with this E1000 NIC is woken to process the received data.
+    pub fn init_interrupts(&mut self) -> Result<(), &'static str> {
         self.enable_interrupts();
-        let deferred_task = deferred_interrupt_tasks::register_interrupt_handler(
-            self.interrupt_num,
-            e1000_handler,
-            poll_interface,
-            interface,
-            Some(format!("e1000_deferred_task_irq_{:#X}", self.interrupt_num)),
-        )
-        .map_err(|error| {
-            error!("error registering e1000 handler: {:?}", error);
+        let interrupt_num = self.interrupt_num;
+        interrupts::register_interrupt(interrupt_num, e1000_handler).map_err(|e| {
+            error!("e1000 IRQ {:#X} was already in use by handler {:#X}!", interrupt_num, e);
             "e1000 interrupt number was already in use! Sharing IRQs is currently unsupported."
         })?;
-        self.deferred_task = Some(deferred_task);
 
         Ok(())
     }
@@ -429,12 +418,8 @@ impl E1000Nic {
 
         if !handled {
             error!("e1000::handle_interrupt(): unhandled interrupt!  status: {:#X}", status);
-        } else if let Some(ref deferred_task) = self.deferred_task {
-            let _ = deferred_task
-                .unblock()
-                .expect("BUG: e1000::handle_interrupt(): couldn't unblock deferred task");
-        } else {
-            error!("e1000::handle_interrupt(): no deferred task");
+        } else if let Some(ref waker) = self.receive_waker {
+            waker.wake_by_ref();
         }
         //regs.icr.read(); //clear interrupt
         Ok(())
@@ -454,6 +439,11 @@ impl net::NetworkDevice for E1000Nic {
     fn mac_address(&self) -> [u8; 6] {
         self.mac_spoofed.unwrap_or(self.mac_hardware)
     }
+
+    fn set_receive_waker(&mut self, waker: Waker) -> bool {
+        self.receive_waker = Some(waker);
+        true
+    }
 }
 
 extern "x86-interrupt" fn e1000_handler(_stack_frame: InterruptStackFrame) {
@@ -467,15 +457,3 @@ extern "x86-interrupt" fn e1000_handler(_stack_frame: InterruptStackFrame) {
         error!("BUG: e1000_handler(): E1000 NIC hasn't yet been initialized!");
     }
 }
-
-/// This function is used as a deferred interrupt task.
-///
-/// After processing the interrupt, the network interface associated with the `e1000` NIC will be
-/// polled to process the received data.
-///
-/// Returns a result to comply with `deferred_interrupt_task::register_interrupt_handler`'s
-/// signature.
-fn poll_interface(interface: &Arc<net::NetworkInterface>) -> Result<(), ()> {
-    interface.poll();
-    Ok(())
-}
@@ -0,0 +1,542 @@
+//! An implementation of the [`fs_node`] traits for FAT filesystems,
+//! built atop the [`fatfs`] crate.
+//!
+//! A FAT filesystem is opened on a [`StorageDevice`] using [`FatFileSystem::new()`],
+//! and its root directory from [`FatFileSystem::root_dir()`] can then be mounted
+//! into the VFS tree via the `mount_table`, e.g., at `/mnt/disk0`.
+//! After that, its files and directories can be accessed through the regular
+//! [`File`] and [`Directory`] traits, just like those of any other filesystem.
+//!
+//! FAT directory and file nodes are lazily created and not persistent,
+//! similar to those in `task_fs`.
+//! Each [`FatDirectory`] and [`FatFile`] node only stores its path relative to
+//! the root of the FAT filesystem, and the underlying FAT entry is re-opened
+//! for every operation on that node.
+//!
+//! # Limitations
+//! * Nodes from other filesystems cannot be inserted into a FAT directory,
+//!   and nodes cannot be moved out of a FAT filesystem.
+//! * Renaming or moving a node doesn't update existing [`FatFile`] nodes for it or its descendants;
+//!   those must be obtained again. Existing [`FatDirectory`] nodes are updated.
+//! * All nodes within a single FAT filesystem share one lock, so accesses to
+//!   different files on the same filesystem are serialized.
+
+#![no_std]
+
+extern crate alloc;
+
+mod adapter;
+
+pub use adapter::{FatFsAdapter, FatFsIoErrorAdapter};
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use block_cache::{BlockCache, WritePolicy};
+use fatfs::{Read, Seek, SeekFrom, Write};
+use core::time::Duration;
+use fs_node::{DateTime, DirRef, Directory, File, FileOrDir, FileRef, FsNode, Metadata, NodeKind, WeakDirRef};
+use io::{ByteReader, ByteReaderWriterWrapper, ByteWriter, IoError, KnownLength, LockableIo, ReaderWriter};
+use log::{debug, error};
+use memory::MappedPages;
+use spin::Mutex;
+use storage_device::{StorageDevice, StorageDeviceRef};
+
+/// The maximum number of blocks of the underlying storage device cached by each [`FatFileSystem`].
+const CACHE_CAPACITY: usize = 1024;
+
+/// The number of temporary names that [`FatDirectory::move_child()`] tries
+/// when moving a file over an existing one.
+const MAX_TEMP_NAMES: usize = 16;
+
+/// The I/O stream type that a [`FatFileSystem`] uses to access its underlying storage device.
+type Disk = FatFsAdapter<
+    ReaderWriter<
+        ByteReaderWriterWrapper<
+            BlockCache<
+                LockableIo<'static, dyn StorageDevice + Send, Mutex<dyn StorageDevice + Send>, StorageDeviceRef>
+            >
+        >
+    >
+>;
+type TimeProvider = fatfs::DefaultTimeProvider;
+type OemCpConverter = fatfs::LossyOemCpConverter;
+type FatDir<'fs> = fatfs::Dir<'fs, Disk, TimeProvider, OemCpConverter>;
+type FatFileHandle<'fs> = fatfs::File<'fs, Disk, TimeProvider, OemCpConverter>;
+
+
+/// A FAT filesystem that exists on a storage device.
+///
+/// This is shared by all of the [`FatDirectory`] and [`FatFile`] nodes within it.
+pub struct FatFileSystem {
+    fs: Mutex<fatfs::FileSystem<Disk, TimeProvider, OemCpConverter>>,
+    /// The currently-existing directory nodes within this filesystem, keyed by their paths.
+    ///
+    /// There is at most one node per directory, whose path is kept here rather than in the node
+    /// such that it can be updated when the directory or one of its ancestors is moved.
+    /// This also allows a [`FatDirectory`] to determine whether another directory is part of
+    /// this same filesystem, e.g., when moving a node into it.
+    directories: Mutex<BTreeMap<String, WeakDirRef>>,
+}
+
+impl FatFileSystem {
+    /// Attempts to open a FAT filesystem on the given `device`.
+    ///
+    /// Returns an error if the device does not contain a valid FAT filesystem.
+    pub fn new(device: StorageDeviceRef) -> Result<Arc<FatFileSystem>, &'static str> {
+        let disk = FatFsAdapter::new(
+            ReaderWriter::new(
+                ByteReaderWriterWrapper::from(
+                    // Write-through ensures that the disk is always consistent,
+                    // as a mounted FAT filesystem is never explicitly flushed.
+                    BlockCache::new(
+                        LockableIo::<dyn StorageDevice + Send, Mutex<_>, _>::from(device),
+                        CACHE_CAPACITY,
+                        WritePolicy::WriteThrough,
+                    )
+                )
+            ),
+        );
+        let fs = fatfs::FileSystem::new(disk, fatfs::FsOptions::new()).map_err(fat_error)?;
+        debug!("Opened FAT filesystem: fat_type: {:?}, volume_id: {:X?}, volume_label: {:?}, cluster_size: {}",
+            fs.fat_type(),
+            fs.volume_id(),
+            fs.volume_label(),
+            fs.cluster_size(),
+        );
+        Ok(Arc::new(FatFileSystem {
+            fs: Mutex::new(fs),
+            directories: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    /// Returns the node for the root directory of this filesystem, which will be named `name`.
+    ///
+    /// The returned directory has no parent; it is meant to be mounted via the `mount_table`,
+    /// in which case `name` should match the name of the mount point.
+    /// If a node for the root directory already exists, that node is returned as is.
+    pub fn root_dir(self: &Arc<Self>, name: String) -> DirRef {
+        self.dir_node(String::new(), name, Weak::<Mutex<FatDirectory>>::new())
+    }
+
+    /// Returns the node for the directory at the given `path`, creating it if it doesn't exist.
+    fn dir_node(self: &Arc<Self>, path: String, name: String, parent: WeakDirRef) -> DirRef {
+        let mut directories = self.directories.lock();
+        if let Some(dir) = directories.get(&path).and_then(Weak::upgrade) {
+            return dir;
+        }
+        directories.retain(|_, d| d.strong_count() > 0);
+        let dir: DirRef = Arc::new_cyclic(|self_ref: &Weak<Mutex<FatDirectory>>| Mutex::new(FatDirectory {
+            fs: self.clone(),
+            name,
+            parent,
+            self_ref: self_ref.clone(),
+        }));
+        directories.insert(path, Arc::downgrade(&dir));
+        dir
+    }
+
+    /// Opens the directory at the given `path` (relative to the root of this filesystem)
+    /// and invokes the given closure `f` on it.
+    fn with_dir<F, R>(&self, path: &str, f: F) -> Result<R, &'static str>
+    where
+        F: FnOnce(&FatDir<'_>) -> Result<R, &'static str>,
+    {
+        let fs = self.fs.lock();
+        let root = fs.root_dir();
+        if path.is_empty() {
+            f(&root)
+        } else {
+            f(&root.open_dir(path).map_err(fat_error)?)
+        }
+    }
+
+    /// Returns the path of the given directory if it is a [`FatDirectory`] within this filesystem.
+    fn directory_path(&self, dir: &WeakDirRef) -> Option<String> {
+        self.directories.lock().iter()
+            .find(|(_, d)| Weak::ptr_eq(d, dir))
+            .map(|(path, _)| path.clone())
+    }
+
+    /// Updates the paths of the directory nodes at or below `old_path` after it was moved to `new_path`.
+    ///
+    /// Returns the node for the moved directory itself, if one exists.
+    fn move_directories(&self, old_path: &str, new_path: &str) -> Option<DirRef> {
+        let mut directories = self.directories.lock();
+        let old_prefix = format!("{old_path}/");
+        let moved: Vec<String> = directories.keys()
+            .filter(|path| *path == old_path || path.starts_with(&old_prefix))
+            .cloned()
+            .collect();
+        for path in moved {
+            if let Some(dir) = directories.remove(&path) {
+                directories.insert(format!("{new_path}{}", &path[old_path.len()..]), dir);
+            }
+        }
+        directories.get(new_path).and_then(Weak::upgrade)
+    }
+
+    /// Returns the metadata of the file or directory at the given `path`
+    /// (relative to the root of this filesystem), as recorded in its parent's directory entry.
+    fn entry_metadata(&self, path: &str) -> Option<Metadata> {
+        let (dir_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        self.with_dir(dir_path, |dir| {
+            dir.iter()
+                .filter_map(Result::ok)
+                .find(|entry| entry.file_name().eq_ignore_ascii_case(name))
+                .map(|entry| Metadata {
+                    kind: if entry.is_dir() { NodeKind::Directory } else { NodeKind::File },
+                    size: if entry.is_dir() { 0 } else { entry.len() as usize },
+                    created: Some(unix_time(entry.created())),
+                    modified: Some(unix_time(entry.modified())),
+                    read_only: entry.attributes().contains(fatfs::FileAttributes::READ_ONLY),
+                })
+                .ok_or("not found")
+        }).ok()
+    }
+
+    /// Opens the file at the given `path` (relative to the root of this filesystem)
+    /// and invokes the given closure `f` on it.
+    fn with_file<F, R>(&self, path: &str, f: F) -> Result<R, IoError>
+    where
+        F: FnOnce(&mut FatFileHandle<'_>) -> Result<R, IoError>,
+    {
+        let fs = self.fs.lock();
+        let mut file = fs.root_dir().open_file(path).map_err(to_io_error)?;
+        f(&mut file)
+    }
+}
+
+
+/// A directory within a FAT filesystem.
+///
+/// Its path relative to the root of the FAT filesystem is kept by the [`FatFileSystem`].
+pub struct FatDirectory {
+    /// The filesystem that this directory exists within.
+    fs: Arc<FatFileSystem>,
+    /// The name of this directory.
+    name: String,
+    /// The parent directory that contains this directory.
+    parent: WeakDirRef,
+    /// A weak reference to this directory itself, used as the parent of its children.
+    self_ref: WeakDirRef,
+}
+
+impl FatDirectory {
+    /// Returns the path of this directory relative to the root of the FAT filesystem,
+    /// which is empty for the root directory itself.
+    fn path(&self) -> String {
+        self.fs.directory_path(&self.self_ref).unwrap_or_default()
+    }
+
+    /// Returns the path of the child with the given `name`, relative to the root of the FAT filesystem.
+    fn child_path(&self, name: &str) -> String {
+        join_path(&self.path(), name)
+    }
+}
+
+impl Directory for FatDirectory {
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("cannot insert nodes from another filesystem into a FAT directory")
+    }
+
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let (name, is_dir) = self.fs.with_dir(&self.path(), |dir| {
+            dir.iter()
+                .filter_map(Result::ok)
+                .find(|entry| entry.file_name().eq_ignore_ascii_case(name))
+                .map(|entry| (entry.file_name(), entry.is_dir()))
+                .ok_or("not found")
+        }).ok()?;
+
+        let path = self.child_path(&name);
+        if is_dir {
+            Some(FileOrDir::Dir(self.fs.dir_node(path, name, self.self_ref.clone())))
+        } else {
+            let file = FatFile {
+                fs: self.fs.clone(),
+                path,
+                name,
+                parent: self.self_ref.clone(),
+            };
+            Some(FileOrDir::File(Arc::new(Mutex::new(file)) as FileRef))
+        }
+    }
+
+    fn list(&self) -> Vec<String> {
+        let path = self.path();
+        self.fs.with_dir(&path, |dir| {
+            Ok(dir.iter()
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name())
+                .filter(|name| name != "." && name != "..")
+                .collect())
+        }).unwrap_or_else(|e| {
+            error!("FatDirectory::list(): failed to read directory {:?}: {}", path, e);
+            Vec::new()
+        })
+    }
+
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        let name = node.get_name();
+        match self.fs.with_dir(&self.path(), |dir| dir.remove(&name).map_err(fat_error)) {
+            Ok(()) => {
+                let mut old_node = node.clone();
+                old_node.set_parent_dir(Weak::<Mutex<FatDirectory>>::new());
+                Some(old_node)
+            }
+            Err(e) => {
+                error!("FatDirectory::remove(): failed to remove {:?}: {}", self.child_path(&name), e);
+                None
+            }
+        }
+    }
+
+    /// Moves an entry within this FAT filesystem.
+    ///
+    /// Unlike the default implementation, this only modifies the on-disk directory entries,
+    /// so the destination must be a directory within this same FAT filesystem.
+    fn move_child(
+        &mut self,
+        name: &str,
+        new_parent: Option<&mut dyn Directory>,
+        new_parent_ref: WeakDirRef,
+        new_name: &str,
+    ) -> Result<(), &'static str> {
+        let dest_dir_path = match new_parent {
+            None => self.path(),
+            Some(_) => self.fs.directory_path(&new_parent_ref)
+                .ok_or("cannot move nodes out of a FAT filesystem")?,
+        };
+        let src_path = self.child_path(name);
+        let dest_path = join_path(&dest_dir_path, new_name);
+        // FAT names are case-insensitive.
+        let dest_lower = dest_path.to_ascii_lowercase();
+        if dest_lower.starts_with(&format!("{}/", src_path.to_ascii_lowercase())) {
+            return Err("cannot move a directory into itself");
+        }
+        // Otherwise, the destination would be found to be the source itself.
+        if dest_lower.eq_ignore_ascii_case(&src_path) {
+            return Err("FAT doesn't support changing only the case of a name");
+        }
+
+        let is_dir = self.fs.with_dir("", |root| {
+            let is_dir = root.open_dir(&src_path).is_ok();
+            if root.open_file(&dest_path).is_err() {
+                return root.rename(&src_path, root, &dest_path).map_err(fat_error).map(|_| is_dir);
+            }
+            if is_dir {
+                return Err("cannot replace a file with a directory");
+            }
+            // FAT doesn't replace existing entries, so the source is first moved to an unused name
+            // next to the destination. The destination is only removed once that succeeds,
+            // such that a failure leaves both directories unchanged.
+            let temp_path = (0..MAX_TEMP_NAMES)
+                .map(|i| join_path(&dest_dir_path, &format!("~mv{i}.tmp")))
+                .find(|path| root.open_file(path).is_err() && root.open_dir(path).is_err())
+                .ok_or("FAT: couldn't find an unused temporary name to move the file to")?;
+            root.rename(&src_path, root, &temp_path).map_err(fat_error)?;
+            if let Err(e) = root.remove(&dest_path) {
+                if root.rename(&temp_path, root, &src_path).is_err() {
+                    error!("FatDirectory::move_child(): failed to restore {:?} from {:?}", src_path, temp_path);
+                }
+                return Err(fat_error(e));
+            }
+            // This reuses the directory entry that was just freed, so it can only fail if the disk does.
+            root.rename(&temp_path, root, &dest_path).map_err(|e| {
+                error!("FatDirectory::move_child(): {:?} was left at {:?}", src_path, temp_path);
+                fat_error(e)
+            })?;
+            Ok(is_dir)
+        })?;
+
+        if is_dir {
+            if let Some(dir) = self.fs.move_directories(&src_path, &dest_path) {
+                let mut dir = dir.lock();
+                dir.set_name(String::from(new_name))?;
+                dir.set_parent_dir(new_parent_ref);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FsNode for FatDirectory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        let path = self.path();
+        // The root directory of a FAT filesystem has no directory entry.
+        if path.is_empty() {
+            return Metadata::directory(false);
+        }
+        self.fs.entry_metadata(&path).unwrap_or_else(|| Metadata::directory(false))
+    }
+}
+
+
+/// A file within a FAT filesystem.
+pub struct FatFile {
+    /// The filesystem that this file exists within.
+    fs: Arc<FatFileSystem>,
+    /// The path of this file relative to the root of the FAT filesystem.
+    path: String,
+    /// The name of this file.
+    name: String,
+    /// The parent directory that contains this file.
+    parent: WeakDirRef,
+}
+
+impl ByteReader for FatFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        self.fs.with_file(&self.path, |file| {
+            let len = file.seek(SeekFrom::End(0)).map_err(to_io_error)? as usize;
+            if offset > len {
+                return Err(IoError::InvalidInput);
+            }
+            if offset == len {
+                return Ok(0);
+            }
+            file.seek(SeekFrom::Start(offset as u64)).map_err(to_io_error)?;
+            // read from the offset until the end of the file, but not more than the buffer length
+            let read_bytes = core::cmp::min(len - offset, buffer.len());
+            file.read_exact(&mut buffer[..read_bytes]).map_err(to_io_error)?;
+            Ok(read_bytes)
+        })
+    }
+}
+
+impl ByteWriter for FatFile {
+    fn write_at(&mut self, buffer: &[u8], offset: usize) -> Result<usize, IoError> {
+        self.fs.with_file(&self.path, |file| {
+            let len = file.seek(SeekFrom::End(0)).map_err(to_io_error)? as usize;
+            // FAT files cannot be sparse, so we must pad the gap between the end and the offset with zeros.
+            if offset > len {
+                file.write_all(&alloc::vec![0u8; offset - len]).map_err(to_io_error)?;
+            } else {
+                file.seek(SeekFrom::Start(offset as u64)).map_err(to_io_error)?;
+            }
+            file.write_all(buffer).map_err(to_io_error)?;
+            Ok(buffer.len())
+        })
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.fs.with_file(&self.path, |file| file.flush().map_err(to_io_error))
+    }
+}
+
+impl FatFile {
+    /// Returns the length of this file, or an error if it couldn't be read from the filesystem.
+    ///
+    /// Unlike [`KnownLength::len()`], this doesn't conflate an I/O error with an empty file.
+    pub fn try_len(&self) -> Result<usize, IoError> {
+        self.fs.with_file(&self.path, |file| {
+            file.seek(SeekFrom::End(0)).map_err(to_io_error)
+        }).map(|len| len as usize)
+    }
+}
+
+impl KnownLength for FatFile {
+    fn len(&self) -> usize {
+        self.try_len().unwrap_or_else(|e| {
+            error!("FatFile::len(): failed to get the length of {:?}: {:?}", self.path, e);
+            0
+        })
+    }
+}
+
+impl File for FatFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("Mapping a FatFile as a MappedPages object is unimplemented")
+    }
+}
+
+impl FsNode for FatFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.fs.entry_metadata(&self.path).unwrap_or_else(|| Metadata::file(self.len(), false))
+    }
+}
+
+
+/// Returns the path of the entry called `name` within the directory at `dir_path`,
+/// both relative to the root of the FAT filesystem.
+fn join_path(dir_path: &str, name: &str) -> String {
+    if dir_path.is_empty() {
+        String::from(name)
+    } else {
+        format!("{dir_path}/{name}")
+    }
+}
+
+/// Converts a FAT timestamp into the time elapsed since the Unix epoch.
+///
+/// FAT timestamps don't specify a time zone, so they are treated as UTC.
+fn unix_time(date_time: fatfs::DateTime) -> Duration {
+    let (date, time) = (date_time.date, date_time.time);
+    DateTime {
+        year: date.year as u64,
+        month: date.month as u64,
+        day: date.day as u64,
+        hour: time.hour as u64,
+        minute: time.min as u64,
+        second: time.sec as u64,
+    }.to_unix_time() + Duration::from_millis(time.millis as u64)
+}
+
+/// Converts a [`fatfs::Error`] into an [`IoError`].
+fn to_io_error<E>(error: fatfs::Error<E>) -> IoError {
+    match error {
+        fatfs::Error::InvalidInput => IoError::InvalidInput,
+        other => IoError::Other(fat_error(other)),
+    }
+}
+
+/// Converts a [`fatfs::Error`] into a static error string.
+fn fat_error<E>(error: fatfs::Error<E>) -> &'static str {
+    match error {
+        fatfs::Error::Io(_)                        => "FAT: I/O error on the underlying storage device",
+        fatfs::Error::UnexpectedEof                => "FAT: unexpected end of file",
+        fatfs::Error::WriteZero                    => "FAT: failed to write the whole buffer",
+        fatfs::Error::InvalidInput                 => "FAT: invalid input",
+        fatfs::Error::NotFound                     => "FAT: file or directory not found",
+        fatfs::Error::AlreadyExists                => "FAT: file or directory already exists",
+        fatfs::Error::DirectoryIsNotEmpty          => "FAT: directory is not empty",
+        fatfs::Error::CorruptedFileSystem          => "FAT: corrupted filesystem",
+        fatfs::Error::NotEnoughSpace               => "FAT: not enough free space",
+        fatfs::Error::InvalidFileNameLength        => "FAT: invalid file name length",
+        fatfs::Error::UnsupportedFileNameCharacter => "FAT: unsupported character in file name",
+        _                                          => "FAT: unknown error",
+    }
+}
@@ -17,6 +17,7 @@ extern crate memory;
 extern crate io;
 
 use core::fmt;
+use core::time::Duration;
 use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
@@ -66,6 +67,124 @@ pub trait FsNode {
     /// This is useful for ensuring correctness when inserting or removing 
     /// files or directories from their parent directory.
     fn set_parent_dir(&mut self, new_parent: WeakDirRef);
+
+    /// Changes the name of this node to `new_name`.
+    ///
+    /// This does not update the directory that contains this node, which usually
+    /// indexes its children by name; use [`Directory::move_child()`] instead.
+    ///
+    /// The default implementation returns an error, for nodes that cannot be renamed.
+    fn set_name(&mut self, _new_name: String) -> Result<(), &'static str> {
+        Err("this filesystem node cannot be renamed")
+    }
+
+    /// Returns metadata about this node, e.g., its kind, size, and timestamps.
+    fn metadata(&self) -> Metadata;
+}
+
+/// The kind of a filesystem node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    File,
+    Directory,
+}
+
+/// Metadata about a filesystem node, as returned by [`FsNode::metadata()`].
+///
+/// Timestamps are given as the time elapsed since the Unix epoch
+/// (12:00am January 1st 1970), or `None` if the filesystem doesn't track them.
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    /// Whether this node is a file or a directory.
+    pub kind: NodeKind,
+    /// The size in bytes of this node's contents. Directories currently report `0`.
+    pub size: usize,
+    /// When this node was created.
+    pub created: Option<Duration>,
+    /// When this node's contents were last modified.
+    pub modified: Option<Duration>,
+    /// Whether this node's contents cannot be modified.
+    pub read_only: bool,
+}
+
+impl Metadata {
+    /// Returns metadata for a file of the given `size` that has no timestamps.
+    pub fn file(size: usize, read_only: bool) -> Metadata {
+        Metadata { kind: NodeKind::File, size, created: None, modified: None, read_only }
+    }
+
+    /// Returns metadata for a directory that has no timestamps.
+    pub fn directory(read_only: bool) -> Metadata {
+        Metadata { kind: NodeKind::Directory, size: 0, created: None, modified: None, read_only }
+    }
+
+    /// Returns `true` if this is the metadata of a file.
+    pub fn is_file(&self) -> bool {
+        self.kind == NodeKind::File
+    }
+
+    /// Returns `true` if this is the metadata of a directory.
+    pub fn is_dir(&self) -> bool {
+        self.kind == NodeKind::Directory
+    }
+}
+
+/// A date and time of day in UTC, e.g., for converting to and from the timestamps in [`Metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u64,
+    /// From 1 to 12.
+    pub month: u64,
+    /// From 1 to 31.
+    pub day: u64,
+    pub hour: u64,
+    pub minute: u64,
+    pub second: u64,
+}
+
+impl DateTime {
+    /// Returns the date and time at the given time elapsed since the Unix epoch,
+    /// ignoring any fraction of a second.
+    pub fn from_unix_time(since_epoch: Duration) -> DateTime {
+        let secs = since_epoch.as_secs();
+        let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+        // Convert the day count into a civil date using the `civil_from_days` algorithm from
+        // <http://howardhinnant.github.io/date_algorithms.html>.
+        let z = days + 719468;
+        let era = z / 146097;
+        let day_of_era = z - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: (secs_of_day / 60) % 60,
+            second: secs_of_day % 60,
+        }
+    }
+
+    /// Returns the time elapsed since the Unix epoch at this date and time,
+    /// which must not be before the epoch.
+    pub fn to_unix_time(&self) -> Duration {
+        // Count the days since the epoch using the `days_from_civil` algorithm,
+        // the inverse of the one in `from_unix_time()`.
+        let year = if self.month <= 2 { self.year - 1 } else { self.year };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((self.month + 9) % 12) + 2) / 5 + self.day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        Duration::from_secs(days * 86400 + self.hour * 3600 + self.minute * 60 + self.second)
+    }
 }
 
 // Trait for files, implementors of File must also implement FsNode
@@ -114,6 +233,82 @@ pub trait Directory : FsNode {
 
     /// Lists the names of the nodes in this directory.
     fn list(&self) -> Vec<String>;
+
+    /// Creates a new, empty file called `name` within this directory and returns it,
+    /// for directories that can only contain their own kind of file,
+    /// e.g., those of a filesystem on a storage device.
+    /// An existing file with the same name is replaced, but an existing directory is not.
+    ///
+    /// The default implementation returns `None`, which means that the caller
+    /// should instead create a file itself and [`insert()`](Directory::insert) it.
+    fn create_file(&mut self, _name: &str) -> Option<Result<FileRef, &'static str>> {
+        None
+    }
+
+    /// Like [`Directory::create_file()`], but creates a new, empty **directory**.
+    fn create_dir(&mut self, _name: &str) -> Option<Result<DirRef, &'static str>> {
+        None
+    }
+
+    /// Moves the child node called `name` out of this directory and into `new_parent`,
+    /// where it will be called `new_name`.
+    /// If `new_parent` is `None`, the node is renamed within this directory.
+    ///
+    /// `new_parent_ref` must refer to the destination directory,
+    /// i.e., `new_parent` or this directory itself, and becomes the moved node's parent.
+    ///
+    /// An existing file called `new_name` in the destination is replaced,
+    /// but an existing directory is not.
+    /// If an error occurs, both directories are left unchanged.
+    ///
+    /// The caller must hold the locks on both directories for the duration of this call,
+    /// which is what makes the move atomic with respect to other filesystem operations.
+    /// Thus, you should generally use `path::rename()` instead of calling this directly.
+    ///
+    /// The default implementation works for any directory that stores its children in memory:
+    /// it removes the node, renames it with [`FsNode::set_name()`], and inserts it into the destination.
+    fn move_child(
+        &mut self,
+        name: &str,
+        mut new_parent: Option<&mut dyn Directory>,
+        new_parent_ref: WeakDirRef,
+        new_name: &str,
+    ) -> Result<(), &'static str> {
+        let node = self.get(name).ok_or("no such file or directory")?;
+        let existing = match &new_parent {
+            Some(dir) => dir.get(new_name),
+            None => self.get(new_name),
+        };
+        match (&node, existing) {
+            (_, Some(FileOrDir::Dir(_))) => return Err("the destination is an existing directory"),
+            (FileOrDir::Dir(_), Some(FileOrDir::File(_))) => return Err("cannot replace a file with a directory"),
+            _ => { }
+        }
+
+        // Removing the node clears its parent, so save it in case the node must be restored.
+        let old_parent = node.get_parent_dir();
+        let mut node = self.remove(&node).ok_or("failed to remove node from its directory")?;
+        let result = node.set_name(String::from(new_name)).and_then(|_| {
+            let inserted = match new_parent.as_deref_mut() {
+                Some(dir) => dir.insert(node.clone()),
+                None => self.insert(node.clone()),
+            };
+            inserted.map_err(|e| {
+                let _ = node.set_name(String::from(name));
+                e
+            })
+        });
+        match result {
+            Ok(_) => node.set_parent_dir(new_parent_ref),
+            Err(_) => {
+                self.insert(node.clone())?;
+                if let Some(old_parent) = old_parent {
+                    node.set_parent_dir(Arc::downgrade(&old_parent));
+                }
+            }
+        }
+        result.map(|_| ())
+    }
 }
 
 /// Allows us to return a generic type that can be matched by the caller to extract the underlying type
@@ -159,6 +354,20 @@ impl FsNode for FileOrDir {
             FileOrDir::Dir(dir) => dir.lock().set_parent_dir(new_parent),
         }
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        match self {
+            FileOrDir::File(file) => file.lock().set_name(new_name),
+            FileOrDir::Dir(dir) => dir.lock().set_name(new_name),
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        match self {
+            FileOrDir::File(file) => file.lock().metadata(),
+            FileOrDir::Dir(dir) => dir.lock().metadata(),
+        }
+    }
 }
 
 impl KnownLength for FileOrDir {
@@ -8,6 +8,7 @@ extern crate spin;
 extern crate memory;
 extern crate fs_node;
 extern crate io;
+extern crate time;
 
 
 use alloc::{
@@ -17,8 +18,9 @@ use alloc::{
 };
 use io::{ByteReader, ByteWriter, IoError, KnownLength};
 use spin::Mutex;
-use fs_node::{FileOrDir, FileRef, DirRef, WeakDirRef, File, FsNode};
+use fs_node::{FileOrDir, FileRef, DirRef, WeakDirRef, File, FsNode, Metadata, NodeKind};
 use memory::MappedPages;
+use time::{Duration, WallTime};
 
 /// A file in memory that is backed by the heap, i.e., a `Vec`.
 pub struct HeapFile {
@@ -28,6 +30,10 @@ pub struct HeapFile {
     vec: Vec<u8>,
     /// The parent directory that contains this file.
     parent: WeakDirRef,
+    /// When this file was created, as the time since the Unix epoch.
+    created: Duration,
+    /// When this file was last written to, as the time since the Unix epoch.
+    modified: Duration,
 }
 
 impl HeapFile {
@@ -40,10 +46,13 @@ impl HeapFile {
     /// Creates a new `HeapFile` in the given `parent` directory with the contents of the given `Vec`.
     /// No additional allocation or reallocation is performed.
     pub fn from_vec(vec: Vec<u8>, name: String, parent: &DirRef) -> Result<FileRef, &'static str> {
+        let now = time::now::<WallTime>();
         let hf = HeapFile {
             name, 
             vec, 
             parent: Arc::downgrade(parent), 
+            created: now,
+            modified: now,
         };
         let file_ref = Arc::new(Mutex::new(hf)) as FileRef;
         parent.lock().insert(FileOrDir::File(file_ref.clone()))?;
@@ -73,6 +82,7 @@ impl ByteWriter for HeapFile {
 
         // Now, `self.vec` is long enough to accommodate the entire `buffer`.
         self.vec[offset..].copy_from_slice(buffer);
+        self.modified = time::now::<WallTime>();
         
         Ok(buffer.len())
     }
@@ -104,4 +114,19 @@ impl FsNode for HeapFile {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::File,
+            size: self.vec.len(),
+            created: Some(self.created),
+            modified: Some(self.modified),
+            read_only: false,
+        }
+    }
 }
@@ -9,19 +9,7 @@ use alloc::{string::String, sync::Arc, vec, vec::Vec};
 use core::str;
 use log::{debug, error, trace};
 use net::{tcp, IpEndpoint, NetworkInterface, Socket};
-use time::{Duration, Instant};
-
-/// The states that implement the finite state machine for sending and receiving the HTTP request
-/// and response, respectively.
-#[derive(Debug)]
-enum HttpState {
-    /// The socket is connected, but the HTTP request has not yet been sent.
-    Requesting,
-    /// The HTTP request has been sent, but the response has not yet been fully received.
-    ReceivingResponse,
-    /// The response has been received in full, including the headers and the entire content.
-    Responded,
-}
+use time::Duration;
 
 /// Checks to see if the provided HTTP request can be properly parsed, and returns true if so.
 pub fn check_http_request(request_bytes: &[u8]) -> bool {
@@ -103,6 +91,18 @@ impl<'a> HttpClient<'a> {
         Ok(Self { interface, socket })
     }
 
+    /// Creates a new HTTP client connected to `remote_port` on the given `host`,
+    /// which may be a hostname or an IP address.
+    pub fn with_host(
+        interface: &'a Arc<NetworkInterface>,
+        local_port: u16,
+        host: &str,
+        remote_port: u16,
+    ) -> Result<Self, &'static str> {
+        let remote_addr = dns::resolve_one(interface, host)?;
+        Self::new(interface, local_port, IpEndpoint::new(remote_addr, remote_port))
+    }
+
     /// Returns whether the connection used by the client is closed.
     pub fn is_closed(&self) -> bool {
         self.socket.lock().state() == tcp::State::Closed
@@ -111,10 +111,14 @@ impl<'a> HttpClient<'a> {
     /// Aborts the connection.
     pub fn abort(&self) {
         self.socket.lock().abort();
-        self.interface.poll();
+        self.interface.wake();
     }
 
-    /// Sends an HTTP request with an optional timeout.
+    /// Sends an HTTP request and receives the response, with an optional timeout.
+    ///
+    /// The `timeout` applies separately to each part of the request that is sent
+    /// and each part of the response that is received; the current task blocks
+    /// on the socket while waiting, rather than repeatedly polling it.
     pub fn send(
         &mut self,
         request: HttpRequest,
@@ -124,210 +128,125 @@ impl<'a> HttpClient<'a> {
             return Err("http_client: given HTTP request was improperly formatted or incomplete");
         }
 
-        let Self { interface, socket } = self;
+        // Sending waits for the connection to be established, if necessary.
+        debug!("http_client: sending HTTP request: {:?}", request);
+        let mut request_bytes = request.as_bytes();
+        while !request_bytes.is_empty() {
+            let result = match timeout {
+                Some(timeout) => self.socket.send_timeout(request_bytes, timeout)
+                    .ok_or_else(|| timed_out(timeout, "sending the request"))?,
+                None => self.socket.send(request_bytes),
+            };
+            let sent = result.map_err(|_| "http_client: cannot send request")?;
+            request_bytes = &request_bytes[sent..];
+        }
 
-        let mut state = HttpState::Requesting;
         let mut packet_byte_buffer: Vec<u8> = Vec::new();
-        let mut packet_header_length: Option<usize> = None;
-        let mut response_status_code: Option<u16> = None;
-        let mut response_reason: Option<String> = None;
-
-        let startup_time = Instant::now();
-        let mut latest_packet_timestamp = startup_time;
-
+        let mut buf = [0; 1024];
         loop {
-            // check if we have timed out
-            if let Some(timeout) = timeout {
-                if latest_packet_timestamp.elapsed() >= timeout {
-                    error!(
-                        "http_client: timed out after {} ms, in state {:?}",
-                        timeout.as_millis(),
-                        state
-                    );
-                    return Err("http_client: timed out");
+            let result = match timeout {
+                Some(timeout) => self.socket.recv_timeout(&mut buf, timeout)
+                    .ok_or_else(|| timed_out(timeout, "receiving the response"))?,
+                None => self.socket.recv(&mut buf),
+            };
+            let remote_closed = match result {
+                Ok(len) => {
+                    packet_byte_buffer.extend_from_slice(&buf[..len]);
+                    false
                 }
-            }
-
-            let locked = socket.lock();
-            let can_send = locked.can_send();
-            let can_recv = locked.can_recv();
-            let may_recv = locked.may_recv();
-            drop(locked);
-
-            state = match state {
-                HttpState::Requesting if can_send => {
-                    debug!("http_client: sending HTTP request: {:?}", request);
-                    socket
-                        .lock()
-                        .send_slice(request.as_ref())
-                        .map_err(|_| "cannot send request")?;
-                    // Poll the socket to send the packet. Once we have a custom socket type this
-                    // won't be necessary.
-                    interface.poll();
-                    latest_packet_timestamp = Instant::now();
-                    HttpState::ReceivingResponse
+                Err(tcp::RecvError::Finished) => true,
+                Err(_e) => {
+                    error!("http_client: receive error on socket: {:?}", _e);
+                    return Err("receive error on socket");
                 }
+            };
 
-                HttpState::ReceivingResponse if can_recv => {
-                    // Stay in the receiving state for now; will be changed later if we receive the
-                    // entire packet.
-                    let mut new_state = HttpState::ReceivingResponse;
-                    let orig_packet_length = packet_byte_buffer.len();
-
-                    let recv_result = socket.lock().recv(|data| {
-                        // Eagerly append ALL of the received data onto the end of our packet slice,
-                        // which is necessary to attempt to parse it as an HTTP response. Later, we
-                        // can remove bytes towards the end if we ended up appending too many bytes,
-                        // e.g., we received more than enough bytes and some of them were for the
-                        // next packet.
-                        packet_byte_buffer.extend_from_slice(data);
-
-                        let bytes_popped_off = {
-                            // Check to see if we've received the full HTTP response:
-                            // - First, by checking whether we have received all of the headers (and
-                            //   can properly parse them)
-                            // - Second, by getting the content length header and seeing if we've
-                            //   received the full content (in num bytes)
-                            let mut headers = [httparse::EMPTY_HEADER; 64];
-                            let mut response = httparse::Response::new(&mut headers);
-                            match response.parse(&packet_byte_buffer) {
-                                Ok(httparse::Status::Partial) => {
-                                    trace!("http_client: received partial HTTP response...");
-                                    // we haven't received all of the HTTP header bytes yet, so pop
-                                    // off all of the bytes from the recv buffer into our packet
-                                    data.len()
-                                }
-
-                                Ok(httparse::Status::Complete(total_header_len)) => {
-                                    packet_header_length = Some(total_header_len);
-                                    response_status_code = response.code;
-                                    response_reason = response.reason.map(String::from);
-
-                                    // Here: we've received all headers, but we may not be done
-                                    // receiving the full response. If there is a "Content-Length"
-                                    // header present, we can use that to see if all the bytes are
-                                    // received. If there is no such header, then there might be a
-                                    // "Connection: close" header, indicating that the response is
-                                    // complete. If neither headers exist, then there has been an
-                                    // unexpected problem, and we should return an error.
-
-                                    if let Some(content_length_header) =
-                                        response.headers.iter().find(|h| h.name == "Content-Length")
-                                    {
-                                        match str::from_utf8(content_length_header.value)
-                                            .map_err(|_e| {
-                                                "failed to read Content-Length header value as \
-                                                 UTF-8 string"
-                                            })
-                                            .and_then(|s| {
-                                                s.parse::<usize>().map_err(|_e| {
-                                                    "failed to parse Content-Length header value \
-                                                     as usize"
-                                                })
-                                            }) {
-                                            Ok(content_length) => {
-                                                // the total num of bytes that we want is the length
-                                                // of all the headers + the content
-                                                let expected_length =
-                                                    total_header_len + content_length;
-                                                if packet_byte_buffer.len() < expected_length {
-                                                    // here: we haven't gotten all of the content
-                                                    // bytes yet, so we pop off all of the bytes
-                                                    // received so far
-                                                    data.len()
-                                                } else {
-                                                    // here: we *have* received all of the content,
-                                                    // so the full response is ready
-                                                    new_state = HttpState::Responded;
-                                                    // we pop off the exact number of bytes that
-                                                    // make up the rest of the content, leaving the
-                                                    // rest on the recv buffer
-                                                    expected_length - orig_packet_length
-                                                }
-                                            }
-                                            Err(e) => {
-                                                error!("http_client: {}", e);
-                                                // upon error, return 0, which instructs the recv()
-                                                // method to pop off no bytes from the recv buffer
-                                                0
-                                            }
-                                        }
-                                    } else if let Some(_connection_close_header) = response
-                                        .headers
-                                        .iter()
-                                        .find(|h| h.name == "Connection" && h.value == b"close")
-                                    {
-                                        // Here: the remote endpoint closed the connection, meaning
-                                        // that the entire response is on the recv buffer.
-                                        new_state = HttpState::Responded;
-                                        data.len()
-                                    } else {
-                                        error!(
-                                            "http_client: couldn't find Content-Length or \
-                                             Connection header, can't determine end of HTTP \
-                                             response"
-                                        );
-                                        // upon error, return 0, which instructs the recv() method
-                                        // to pop off no bytes from the recv buffer
-                                        0
-                                    }
-                                }
-
-                                Err(_e) => {
-                                    error!("http_client: Error parsing incoming html: {:?}", _e);
-                                    0
-                                }
-                            }
-                        };
-
-                        // Since we eagerly appended all of the received bytes onto this buffer,
-                        // we need to fix that up based on how many bytes we actually ended up
-                        // popping off the recv buffer
-                        packet_byte_buffer.truncate(orig_packet_length + bytes_popped_off);
-
-                        (bytes_popped_off, ())
-                    });
-
-                    if let Err(_e) = recv_result {
-                        error!("http_client: receive error on socket: {:?}", _e);
-                        return Err("receive error on socket");
-                    }
-
-                    // if we just received another packet (the packet buffer changed size), then
-                    // update the timeout deadline
-                    if orig_packet_length != packet_byte_buffer.len() {
-                        latest_packet_timestamp = Instant::now();
-                    }
-
-                    new_state
-                }
-
-                HttpState::Responded => {
-                    break;
-                }
-
-                HttpState::ReceivingResponse if !may_recv => {
-                    error!(
-                        "http_client: socket was closed prematurely before full reponse was \
-                         received!",
-                    );
-                    return Err("socket was closed prematurely before full reponse was received!");
-                }
-
-                _ => state,
+            if let Some(response) = parse_response(&mut packet_byte_buffer, remote_closed)? {
+                return Ok(response);
+            }
+            if remote_closed {
+                error!(
+                    "http_client: socket was closed prematurely before full reponse was \
+                     received!",
+                );
+                return Err("socket was closed prematurely before full reponse was received!");
             }
+            trace!("http_client: received partial HTTP response...");
         }
-
-        Ok(HttpResponse {
-            packet: packet_byte_buffer,
-            header_length: packet_header_length.ok_or(
-                "BUG: received full HTTP response but couldn't determine packet header length",
-            )?,
-            status_code: response_status_code
-                .ok_or("BUG: received full HTTP response but couldn't determine its status code")?,
-            reason: response_reason.ok_or(
-                "BUG: received full HTTP response but couldn't determine its reason phrase",
-            )?,
-        })
     }
 }
+
+/// Logs that sending or receiving (as described by `action`) timed out, and returns an error.
+fn timed_out(timeout: Duration, action: &str) -> &'static str {
+    error!("http_client: timed out after {} ms while {}", timeout.as_millis(), action);
+    "http_client: timed out"
+}
+
+/// Checks whether `packet` contains a complete HTTP response, and returns that response if so.
+///
+/// A response is complete once all of its headers have been received, as well as
+/// its full content as given by its "Content-Length" header; any bytes beyond that are discarded.
+/// Without a "Content-Length" header, a response with a "Connection: close" header
+/// is complete once the remote endpoint has closed the connection, as given by `remote_closed`.
+///
+/// Returns `Ok(None)` if more of the response must be received.
+fn parse_response(packet: &mut Vec<u8>, remote_closed: bool) -> Result<Option<HttpResponse>, &'static str> {
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut response = httparse::Response::new(&mut headers);
+    let header_length = match response.parse(packet) {
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Ok(httparse::Status::Complete(header_length)) => header_length,
+        Err(_e) => {
+            error!("http_client: Error parsing incoming html: {:?}", _e);
+            return Err("http_client: failed to parse HTTP response");
+        }
+    };
+
+    let expected_length = if let Some(content_length_header) =
+        response.headers.iter().find(|h| h.name == "Content-Length")
+    {
+        let content_length = str::from_utf8(content_length_header.value)
+            .map_err(|_e| "failed to read Content-Length header value as UTF-8 string")
+            .and_then(|s| {
+                s.parse::<usize>()
+                    .map_err(|_e| "failed to parse Content-Length header value as usize")
+            })?;
+        // the total num of bytes that we want is the length of all the headers + the content
+        let expected_length = header_length + content_length;
+        if packet.len() < expected_length {
+            return Ok(None);
+        }
+        expected_length
+    } else if response
+        .headers
+        .iter()
+        .any(|h| h.name == "Connection" && h.value == b"close")
+    {
+        // The entire response has been received once the remote endpoint closes the connection.
+        if !remote_closed {
+            return Ok(None);
+        }
+        packet.len()
+    } else {
+        error!(
+            "http_client: couldn't find Content-Length or Connection header, can't determine \
+             end of HTTP response"
+        );
+        return Err("http_client: couldn't determine the end of the HTTP response");
+    };
+
+    let status_code = response
+        .code
+        .ok_or("BUG: received full HTTP response but couldn't determine its status code")?;
+    let reason = response
+        .reason
+        .map(String::from)
+        .ok_or("BUG: received full HTTP response but couldn't determine its reason phrase")?;
+    packet.truncate(expected_length);
+    Ok(Some(HttpResponse {
+        packet: core::mem::take(packet),
+        header_length,
+        status_code,
+        reason,
+    }))
+}
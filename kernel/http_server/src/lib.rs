@@ -0,0 +1,280 @@
+//! A minimal HTTP/1.1 server that routes requests to handlers.
+//!
+//! The server accepts connections on a TCP port of one or more network interfaces and
+//! handles each connection in its own task. Connections are kept alive across
+//! requests, as negotiated by the client, until they have been idle for too long.
+//!
+//! A socket can only accept a single connection, so the server keeps a small backlog
+//! of sockets listening on each interface, such that connections arriving while
+//! another one is being accepted aren't refused.
+//!
+//! ```ignore
+//! let router = Router::new()
+//!     .get("/hello", |_| Response::ok("text/plain", b"Hello, world!\n".to_vec()));
+//! HttpServer::new(interface, 80, router).serve()?;
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+mod request;
+mod response;
+mod router;
+
+pub use request::Request;
+pub use response::{reason_phrase, Response};
+pub use router::{Handler, Router};
+
+use alloc::{format, sync::Arc, vec, vec::Vec};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Poll,
+    time::Duration,
+};
+use log::{debug, error, warn};
+use net::{tcp, IpEndpoint, NetworkInterface, Socket};
+use time::Monotonic;
+
+/// The default TCP port of HTTP servers.
+pub const HTTP_PORT: u16 = 80;
+
+/// The default maximum number of connections that are handled at once.
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+/// The number of sockets listening on each interface at once.
+const LISTEN_BACKLOG: usize = 4;
+/// How long a kept-alive connection may be idle before it is closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+/// How long a client may take to send the rest of a partially received request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a client may stop receiving before a response being sent to it is abandoned.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the client to close its side of a connection that we've closed.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(2);
+const SOCKET_RX_BUFFER_SIZE: usize = 4096;
+const SOCKET_TX_BUFFER_SIZE: usize = 16 * 1024;
+
+type TcpSocket = Socket<tcp::Socket<'static>>;
+
+/// An HTTP server listening on a TCP port of one or more network interfaces.
+pub struct HttpServer {
+    interfaces: Vec<Arc<NetworkInterface>>,
+    port: u16,
+    router: Arc<Router>,
+    max_connections: usize,
+    active_connections: Arc<AtomicUsize>,
+}
+
+impl HttpServer {
+    /// Creates a server that routes the requests it receives on the given `port`
+    /// of `interface` using `router`.
+    pub fn new(interface: Arc<NetworkInterface>, port: u16, router: Router) -> HttpServer {
+        HttpServer {
+            interfaces: vec![interface],
+            port,
+            router: Arc::new(router),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            active_connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Also listens on the given `interface`, e.g., the loopback interface.
+    pub fn interface(mut self, interface: Arc<NetworkInterface>) -> HttpServer {
+        if !self.interfaces.iter().any(|existing| Arc::ptr_eq(existing, &interface)) {
+            self.interfaces.push(interface);
+        }
+        self
+    }
+
+    /// Sets the maximum number of connections that are handled at once.
+    ///
+    /// Further connections are answered with `503 Service Unavailable` and closed.
+    pub fn max_connections(mut self, max_connections: usize) -> HttpServer {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Returns the number of connections currently being handled.
+    pub fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Accepts connections and spawns a task to handle each of them.
+    ///
+    /// This only returns if the server fails to listen on its port on any interface.
+    pub fn serve(self) -> Result<(), &'static str> {
+        let mut backlog = Vec::with_capacity(self.interfaces.len() * LISTEN_BACKLOG);
+        loop {
+            self.fill_backlog(&mut backlog)?;
+            // Accept the connection on whichever socket in the backlog established one first.
+            let (index, result) = net::block_on(|cx| {
+                backlog
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, socket)| match socket.poll_accept(cx) {
+                        Poll::Ready(result) => Some((i, result)),
+                        Poll::Pending => None,
+                    })
+                    .map_or(Poll::Pending, Poll::Ready)
+            });
+            let socket = backlog.remove(index);
+            let remote = match result {
+                Ok(remote) => remote,
+                Err(_) => {
+                    socket.remove();
+                    continue;
+                }
+            };
+            debug!("http_server: accepted connection from {}", remote);
+
+            if self.active_connections.fetch_add(1, Ordering::Relaxed) >= self.max_connections {
+                self.active_connections.fetch_sub(1, Ordering::Relaxed);
+                warn!("http_server: too many connections, rejecting {}", remote);
+                let _ = send_all(&socket, &Response::error(503).to_bytes(false, true));
+                // Don't wait for the client to close its side here, which would delay accepting
+                // further connections; instead, the network task removes the socket once it's closed.
+                socket.remove_when_closed();
+                continue;
+            }
+
+            let connection = Connection {
+                socket,
+                remote,
+                router: self.router.clone(),
+                active_connections: self.active_connections.clone(),
+            };
+            let result = spawn::new_task_builder(connection_task, connection)
+                .name(format!("http_connection_{}", remote))
+                .spawn();
+            if let Err(e) = result {
+                // The connection was dropped along with the task's argument.
+                error!("http_server: failed to spawn connection task: {}", e);
+                self.active_connections.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Adds listening sockets to the backlog until it holds [`LISTEN_BACKLOG`] sockets for each interface.
+    ///
+    /// This only fails if the backlog is left empty, i.e., if no socket is listening.
+    fn fill_backlog(&self, backlog: &mut Vec<TcpSocket>) -> Result<(), &'static str> {
+        for interface in &self.interfaces {
+            let mut listening = backlog.iter().filter(|socket| Arc::ptr_eq(socket.interface(), interface)).count();
+            while listening < LISTEN_BACKLOG {
+                match self.listen(interface) {
+                    Ok(socket) => backlog.push(socket),
+                    Err(e) => {
+                        warn!("http_server: {}", e);
+                        break;
+                    }
+                }
+                listening += 1;
+            }
+        }
+        if backlog.is_empty() {
+            Err("http_server: failed to listen on port")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Adds a socket listening on the server's port of the given `interface`.
+    fn listen(&self, interface: &Arc<NetworkInterface>) -> Result<TcpSocket, &'static str> {
+        let rx_buffer = tcp::SocketBuffer::new(vec![0; SOCKET_RX_BUFFER_SIZE]);
+        let tx_buffer = tcp::SocketBuffer::new(vec![0; SOCKET_TX_BUFFER_SIZE]);
+        let socket = interface.clone().add_socket(tcp::Socket::new(rx_buffer, tx_buffer));
+        let result = socket.lock().listen(self.port);
+        match result {
+            Ok(()) => Ok(socket),
+            Err(_) => {
+                socket.remove();
+                Err("http_server: failed to listen on port")
+            }
+        }
+    }
+}
+
+/// A connection accepted by the server.
+struct Connection {
+    socket: TcpSocket,
+    remote: IpEndpoint,
+    router: Arc<Router>,
+    active_connections: Arc<AtomicUsize>,
+}
+
+/// Handles requests on the given connection until either side closes it.
+fn connection_task(connection: Connection) {
+    let Connection { socket, remote, router, active_connections } = connection;
+    let mut received = Vec::new();
+    let mut chunk = [0; 1024];
+
+    loop {
+        // Handle every request that has been fully received, including pipelined ones.
+        match request::parse(&received, remote) {
+            Ok(Some((request, request_len))) => {
+                received.drain(..request_len);
+                let keep_alive = request.keep_alive();
+                let response = router.handle(&request);
+                debug!("http_server: {} {} {} -> {}", remote, request.method(), request.path(), response.status());
+                let include_body = request.method() != "HEAD";
+                if send_all(&socket, &response.to_bytes(keep_alive, include_body)).is_err() || !keep_alive {
+                    break;
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(response) => {
+                let _ = send_all(&socket, &response.to_bytes(false, true));
+                break;
+            }
+        }
+
+        let timeout = if received.is_empty() { IDLE_TIMEOUT } else { REQUEST_TIMEOUT };
+        match socket.recv_timeout(&mut chunk, timeout) {
+            Some(Ok(len)) => received.extend_from_slice(&chunk[..len]),
+            // The client closed the connection.
+            Some(Err(_)) => break,
+            None if received.is_empty() => break,
+            None => {
+                let _ = send_all(&socket, &Response::error(408).to_bytes(false, true));
+                break;
+            }
+        }
+    }
+
+    close(socket);
+    active_connections.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Sends all of `data`, blocking until it has been enqueued in the socket's transmit buffer.
+///
+/// Fails if the connection was closed, or if the client didn't receive enough
+/// of the data to make room for more within [`SEND_TIMEOUT`].
+fn send_all(socket: &TcpSocket, mut data: &[u8]) -> Result<(), &'static str> {
+    while !data.is_empty() {
+        let sent = socket
+            .send_timeout(data, SEND_TIMEOUT)
+            .ok_or("http_server: timed out sending to client")?
+            .map_err(|_| "http_server: connection closed while sending")?;
+        data = &data[sent..];
+    }
+    Ok(())
+}
+
+/// Gracefully closes the connection and removes its socket from the interface.
+///
+/// This lingers for up to [`LINGER_TIMEOUT`], so it's only called from a connection's own task.
+fn close(socket: TcpSocket) {
+    socket.lock().close();
+    socket.interface().wake();
+    // Wait for the client to close its side too, such that removing the socket
+    // doesn't reset the connection before the response has been delivered.
+    let deadline = time::now::<Monotonic>() + LINGER_TIMEOUT;
+    let mut discard = [0; 256];
+    while let Some(remaining) = deadline.checked_duration_since(time::now::<Monotonic>()) {
+        if !matches!(socket.recv_timeout(&mut discard, remaining), Some(Ok(_))) {
+            break;
+        }
+    }
+    socket.remove();
+}
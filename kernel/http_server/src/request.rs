@@ -0,0 +1,152 @@
+//! Parsing HTTP requests received on a connection.
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::str;
+use net::IpEndpoint;
+use percent_encoding::percent_decode;
+
+use crate::Response;
+
+/// The maximum number of headers in a request.
+const MAX_HEADERS: usize = 64;
+/// The maximum size of a request's request line and headers.
+pub(crate) const MAX_HEADER_SIZE: usize = 8 * 1024;
+/// The maximum size of a request's body.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// An HTTP request received by the server.
+#[derive(Clone, Debug)]
+pub struct Request {
+    method: String,
+    path: String,
+    query: Option<String>,
+    minor_version: u8,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    remote: IpEndpoint,
+}
+
+impl Request {
+    /// Returns the request's method, e.g., `GET`.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the percent-decoded path of the request's target, without its query.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the query of the request's target, i.e., the part after the `?`, if any.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    /// Returns the value of the header with the given `name`, which is case-insensitive.
+    ///
+    /// Returns `None` if the header is missing or its value isn't valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| str::from_utf8(value).ok())
+    }
+
+    /// Returns the request's body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Returns the endpoint of the client that sent the request.
+    pub fn remote_endpoint(&self) -> IpEndpoint {
+        self.remote
+    }
+
+    /// Returns whether the client wants to keep the connection open after the response.
+    ///
+    /// HTTP/1.1 connections are persistent unless the client sends `Connection: close`,
+    /// whereas HTTP/1.0 connections are closed unless it sends `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("Connection");
+        let has_token = |token: &str| {
+            connection.map_or(false, |value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+        };
+        if self.minor_version >= 1 {
+            !has_token("close")
+        } else {
+            has_token("keep-alive")
+        }
+    }
+}
+
+/// Parses the first request in `buf`, which holds the data received from `remote`.
+///
+/// Returns the request and the number of bytes it spans, or `Ok(None)` if the request
+/// hasn't been fully received yet. Returns the error response that should be sent to
+/// the client if the request is invalid or unsupported.
+pub(crate) fn parse(buf: &[u8], remote: IpEndpoint) -> Result<Option<(Request, usize)>, Response> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    let header_len = match request.parse(buf) {
+        Ok(httparse::Status::Complete(header_len)) => header_len,
+        Ok(httparse::Status::Partial) if buf.len() > MAX_HEADER_SIZE => return Err(Response::error(431)),
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(httparse::Error::TooManyHeaders) => return Err(Response::error(431)),
+        Err(_) => return Err(Response::error(400)),
+    };
+
+    let header_value = |name: &str| {
+        request
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+    };
+    if header_value("Transfer-Encoding").is_some() {
+        // Chunked request bodies aren't supported.
+        return Err(Response::error(501));
+    }
+    let content_len = match header_value("Content-Length") {
+        Some(value) => str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .ok_or_else(|| Response::error(400))?,
+        None => 0,
+    };
+    if content_len > MAX_BODY_SIZE {
+        return Err(Response::error(413));
+    }
+    let request_len = header_len + content_len;
+    if buf.len() < request_len {
+        return Ok(None);
+    }
+
+    // `parse()` only completes once the method, target, and version have been parsed.
+    let target = request.path.unwrap_or("/");
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    let path = percent_decode(path.as_bytes())
+        .decode_utf8()
+        .map_err(|_| Response::error(400))?
+        .into_owned();
+
+    let parsed = Request {
+        method: request.method.unwrap_or("GET").to_string(),
+        path,
+        query,
+        minor_version: request.version.unwrap_or(1),
+        headers: request
+            .headers
+            .iter()
+            .map(|header| (header.name.to_string(), header.value.to_vec()))
+            .collect(),
+        body: buf[header_len..request_len].to_vec(),
+        remote,
+    };
+    Ok(Some((parsed, request_len)))
+}
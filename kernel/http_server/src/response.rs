@@ -0,0 +1,102 @@
+//! Building HTTP responses.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+/// An HTTP response to be sent to a client.
+///
+/// The `Content-Length` and `Connection` headers are added by the server
+/// when the response is sent.
+#[derive(Clone, Debug)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Creates a response with the given `status` code and an empty body.
+    pub fn new(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    /// Creates a `200 OK` response with the given `body`, whose media type is `content_type`.
+    pub fn ok(content_type: &str, body: Vec<u8>) -> Response {
+        Response::new(200).with_body(content_type, body)
+    }
+
+    /// Creates a response with the given error `status` code, whose body is its reason phrase.
+    pub fn error(status: u16) -> Response {
+        let body = format!("{} {}\n", status, reason_phrase(status));
+        Response::new(status).with_body("text/plain; charset=utf-8", body.into_bytes())
+    }
+
+    /// Adds a header with the given `name` and `value`.
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the body, whose media type is `content_type`.
+    pub fn with_body(self, content_type: &str, body: Vec<u8>) -> Response {
+        let mut response = self.with_header("Content-Type", content_type);
+        response.body = body;
+        response
+    }
+
+    /// Returns the response's status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Returns the response's body.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Encodes the response as an HTTP/1.1 message.
+    ///
+    /// If `include_body` is `false`, e.g., for a `HEAD` request, the body is omitted,
+    /// but `Content-Length` still reflects its length.
+    pub(crate) fn to_bytes(&self, keep_alive: bool, include_body: bool) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        head.push_str(if keep_alive { "Connection: keep-alive\r\n" } else { "Connection: close\r\n" });
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        if include_body {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+}
+
+/// Returns the standard reason phrase of the given `status` code.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Content Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,95 @@
+//! Routing requests to handlers based on their method and path.
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+
+use crate::{Request, Response};
+
+/// A function that handles a request.
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Routes each request to the handler of the first route that matches it.
+///
+/// A route's path either matches a request's path exactly or, if it ends in `/*`,
+/// matches every path below that prefix, e.g., `/files/*` matches `/files` and `/files/a/b`.
+/// `HEAD` requests are routed like `GET` requests, and the server omits the response body.
+///
+/// Requests that match no route are answered with `404 Not Found`, or with
+/// `405 Method Not Allowed` if only the method didn't match.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+struct Route {
+    /// The method the route matches, or `None` for any method.
+    method: Option<String>,
+    path: String,
+    handler: Handler,
+}
+
+impl Route {
+    fn matches_path(&self, path: &str) -> bool {
+        match self.path.strip_suffix("/*") {
+            Some(prefix) => {
+                path.strip_prefix(prefix).map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+            }
+            None => path == self.path,
+        }
+    }
+
+    fn matches_method(&self, method: &str) -> bool {
+        match self.method {
+            Some(ref route_method) => route_method == method || (route_method == "GET" && method == "HEAD"),
+            None => true,
+        }
+    }
+}
+
+impl Router {
+    /// Creates a router without any routes.
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Adds a route for requests with the given `method` and `path`.
+    pub fn route<H>(mut self, method: &str, path: &str, handler: H) -> Router
+    where
+        H: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method: Some(String::from(method)),
+            path: String::from(path),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Adds a route for `GET` (and `HEAD`) requests with the given `path`.
+    pub fn get<H>(self, path: &str, handler: H) -> Router
+    where
+        H: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.route("GET", path, handler)
+    }
+
+    /// Adds a route for requests with any method and the given `path`.
+    pub fn any<H>(mut self, path: &str, handler: H) -> Router
+    where
+        H: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route { method: None, path: String::from(path), handler: Box::new(handler) });
+        self
+    }
+
+    /// Returns the response of the handler that the given `request` is routed to.
+    pub fn handle(&self, request: &Request) -> Response {
+        let mut path_matched = false;
+        for route in self.routes.iter().filter(|route| route.matches_path(request.path())) {
+            if route.matches_method(request.method()) {
+                return (route.handler)(request);
+            }
+            path_matched = true;
+        }
+        Response::error(if path_matched { 405 } else { 404 })
+    }
+}
@@ -51,6 +51,7 @@ use alloc::{
     sync::Arc,
     vec::Vec,
 };
+use core::task::Waker;
 use sync_irq::IrqSafeMutex;
 use memory::{PhysicalAddress, MappedPages, Mutable, BorrowedSliceMappedPages, BorrowedMappedPages, map_frame_range, MMIO_FLAGS};
 use pci::{PciDevice, MsixVectorTable, PciConfigSpaceAccessMechanism, PciLocation};
@@ -179,6 +180,8 @@ pub struct IxgbeNic {
     tx_queues: Vec<TxQueue<IxgbeTxQueueRegisters,AdvancedTxDescriptor>>,
     /// Registers for the disabled queues
     tx_registers_disabled: Vec<IxgbeTxQueueRegisters>,
+    /// Woken whenever frames are received on queue 0, set by the network interface using this NIC.
+    receive_waker: Option<Waker>,
 }
 
 impl net::NetworkDevice for IxgbeNic {
@@ -191,6 +194,10 @@ impl net::NetworkDevice for IxgbeNic {
     fn receive(&mut self) -> Option<ReceivedFrame> {
         // by default, when using the physical NIC interface, we receive on queue 0.
         let qid = 0;
+        // without interrupts, nothing else moves received packets into the queue's received frames
+        if self.rx_queues[qid].received_frames.is_empty() && !self.interrupt_num.contains_key(&(qid as u8)) {
+            let _ = self.rx_queues[qid].poll_queue_and_store_received_packets();
+        }
         // return one frame from the queue's received frames
         self.rx_queues[qid].received_frames.pop_front()
     }
@@ -198,6 +205,12 @@ impl net::NetworkDevice for IxgbeNic {
     fn mac_address(&self) -> [u8; 6] {
         self.mac_spoofed.unwrap_or(self.mac_hardware)
     }
+
+    fn set_receive_waker(&mut self, waker: Waker) -> bool {
+        self.receive_waker = Some(waker);
+        // without an interrupt for queue 0, the interface must poll us instead
+        self.interrupt_num.contains_key(&0)
+    }
 }
 
 // Functions that setup the NIC struct and handle the sending and receiving of packets.
@@ -381,6 +394,7 @@ impl IxgbeNic {
             num_tx_queues: IXGBE_NUM_TX_QUEUES_ENABLED,
             tx_queues,
             tx_registers_disabled: tx_mapped_registers,
+            receive_waker: None,
         };
 
         info!("Link is up with speed: {} Mb/s", ixgbe_nic.link_speed() as u32);
@@ -1267,6 +1281,11 @@ fn rx_interrupt_handler(qid: u8, nic_id: PciLocation) -> Option<u8> {
         Ok(ixgbe_nic_ref) => {
             let mut ixgbe_nic = ixgbe_nic_ref.lock();
             let _ = ixgbe_nic.rx_queues[qid as usize].poll_queue_and_store_received_packets();
+            if qid == 0 {
+                if let Some(ref waker) = ixgbe_nic.receive_waker {
+                    waker.wake_by_ref();
+                }
+            }
             ixgbe_nic.interrupt_num.get(&qid).cloned()
         }
         Err(e) => {
@@ -9,6 +9,9 @@
 //! Early log messages (before memory management is initialized) are saved
 //! to a static fixed-sized buffer such that they are not lost and
 //! can be retrieved once logging sinks are ready to be used.
+//!
+//! Once a remote sink is started, e.g., one that sends records over the network,
+//! log records are also queued for it; see the [`remote`] module.
 
 #![no_std]
 #![feature(trait_alias)]
@@ -19,6 +22,8 @@ extern crate log;
 extern crate sync_irq;
 extern crate serial_port_basic;
 
+pub mod remote;
+
 use log::{Record, Level, Metadata, Log};
 use core::{fmt::{self, Write}, ops::Deref};
 use sync_irq::IrqSafeMutex;
@@ -253,6 +258,8 @@ impl Log for DummyLogger {
         );
         // If there was an error above, there's literally nothing we can do but ignore it,
         // because there is no other lower-level way to log errors than the serial port.
+
+        remote::queue_record(level_str, file_loc, line_loc, record.args());
         
         #[cfg(mirror_log_to_vga)]
         if let Some(func) = mirror_log::get_log_mirror_function() {
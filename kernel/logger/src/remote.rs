@@ -0,0 +1,186 @@
+//! Queuing log records for remote sinks, which can't be written to while logging.
+//!
+//! A sink that sends records over the network can't be written to directly by the logger,
+//! as logging must never block on (or recurse into) the network stack.
+//! Instead, records are queued here and taken by a task that sends them, such as the one
+//! started by the `udp_log_sink` crate.
+//!
+//! Records are only queued once a sink has been started using [`start_remote_queue()`],
+//! such that records logged after the sink starts but before networking is up are sent once it is.
+//! If records are logged faster than they are taken, further records are dropped
+//! and counted, which the sink can report once there is room again.
+//!
+//! Queuing a record never allocates, as the heap itself logs while it is locked:
+//! the queue is a ring of fixed-size record buffers that are allocated when it is started.
+
+use alloc::{vec, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    task::Waker,
+};
+use crossbeam_utils::atomic::AtomicCell;
+use sync_irq::IrqSafeMutex;
+
+/// The maximum number of records that are queued before further records are dropped.
+pub const REMOTE_QUEUE_CAPACITY: usize = 256;
+
+/// Records longer than this are truncated, e.g., so that each fits in a single datagram.
+pub const MAX_REMOTE_RECORD_LEN: usize = 1400;
+
+static QUEUE: IrqSafeMutex<RecordQueue> = IrqSafeMutex::new(RecordQueue::new());
+
+/// The filter given to [`start_remote_queue()`], or `None` if records aren't being queued.
+static FILTER: AtomicCell<Option<fn() -> bool>> = AtomicCell::new(None);
+const _: () = assert!(AtomicCell::<fn() -> bool>::is_lock_free());
+
+struct RecordQueue {
+    /// The ring of record buffers, which is empty until the queue is started.
+    slots: Vec<RecordSlot>,
+    /// The index in `slots` of the oldest queued record.
+    head: usize,
+    /// The number of queued records.
+    len: usize,
+    /// Whether records are queued at all.
+    enabled: bool,
+    /// The number of records dropped since the count was last taken.
+    dropped: usize,
+    /// Woken when a record is queued.
+    waker: Option<Waker>,
+}
+
+impl RecordQueue {
+    const fn new() -> Self {
+        RecordQueue { slots: Vec::new(), head: 0, len: 0, enabled: false, dropped: 0, waker: None }
+    }
+}
+
+/// A buffer holding a single record, which is truncated if it's too long.
+#[derive(Clone)]
+struct RecordSlot {
+    data: [u8; MAX_REMOTE_RECORD_LEN],
+    len: usize,
+}
+
+impl RecordSlot {
+    const EMPTY: RecordSlot = RecordSlot { data: [0; MAX_REMOTE_RECORD_LEN], len: 0 };
+}
+
+impl Write for RecordSlot {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = MAX_REMOTE_RECORD_LEN - self.len;
+        let mut end = s.len().min(available);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.data[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        // Stop formatting once the record has been truncated.
+        if end < s.len() { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
+/// Queues a log record for the remote sink, if records are being queued.
+///
+/// This never blocks or allocates: if the queue is contended, e.g., because a record is logged
+/// while the queue itself is being modified, or if it is full, the record is dropped.
+pub(crate) fn queue_record(level: &str, file: &str, line: u32, args: &fmt::Arguments) {
+    match FILTER.load() {
+        Some(filter) if filter() => {}
+        _ => return,
+    }
+
+    let waker = {
+        let Some(mut queue) = QUEUE.try_lock() else { return };
+        if !queue.enabled {
+            return;
+        }
+        if queue.len >= queue.slots.len() {
+            queue.dropped += 1;
+            return;
+        }
+        // Format the record directly into the next free slot. If formatting logs anything,
+        // that record is dropped, as the queue is already locked.
+        let index = (queue.head + queue.len) % queue.slots.len();
+        let slot = &mut queue.slots[index];
+        slot.len = 0;
+        let _ = write!(slot, "{}{}:{}: {}", level, file, line, args);
+        queue.len += 1;
+        queue.waker.take()
+    };
+    // Wake the sink outside of the lock, in case waking it logs anything.
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Starts queuing records, but only those logged while the given `filter` returns `true`.
+///
+/// The `filter` is called before each record is queued, e.g., to exclude the records
+/// logged by the sink itself, which would otherwise cause a feedback loop.
+/// It must not log anything.
+///
+/// This allocates the queue's record buffers if they don't exist yet.
+pub fn start_remote_queue(filter: fn() -> bool) {
+    // Allocate the buffers outside of the lock, in case allocating logs anything.
+    let mut slots = if QUEUE.lock().slots.is_empty() {
+        vec![RecordSlot::EMPTY; REMOTE_QUEUE_CAPACITY]
+    } else {
+        Vec::new()
+    };
+    {
+        let mut queue = QUEUE.lock();
+        if queue.slots.is_empty() {
+            core::mem::swap(&mut queue.slots, &mut slots);
+        }
+        queue.enabled = true;
+    }
+    FILTER.store(Some(filter));
+    // If the buffers were allocated by a concurrent call, free ours outside of the lock.
+    drop(slots);
+}
+
+/// Stops queuing records, discarding those that have yet to be taken and freeing the queue's buffers.
+///
+/// The task waiting in [`take_remote_record()`], if any, is woken.
+pub fn stop_remote_queue() {
+    FILTER.store(None);
+    let (slots, waker) = {
+        let mut queue = QUEUE.lock();
+        queue.enabled = false;
+        queue.head = 0;
+        queue.len = 0;
+        queue.dropped = 0;
+        (core::mem::take(&mut queue.slots), queue.waker.take())
+    };
+    // Free the buffers outside of the lock.
+    drop(slots);
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Takes the oldest queued record by copying it into `buf`, which should be at least
+/// [`MAX_REMOTE_RECORD_LEN`] bytes long.
+///
+/// Returns the length of the record along with the number of records dropped since the last call.
+/// If no record is queued, `waker` is woken once one is.
+pub fn take_remote_record(buf: &mut [u8], waker: &Waker) -> Option<(usize, usize)> {
+    let mut queue = QUEUE.lock();
+    if queue.len == 0 {
+        queue.waker = Some(waker.clone());
+        return None;
+    }
+    let head = queue.head;
+    let slot = &queue.slots[head];
+    let len = slot.len.min(buf.len());
+    buf[..len].copy_from_slice(&slot.data[..len]);
+    queue.head = (head + 1) % queue.slots.len();
+    queue.len -= 1;
+    Some((len, core::mem::take(&mut queue.dropped)))
+}
+
+/// Returns the number of queued records and the number dropped since the count was last taken.
+pub fn remote_queue_len() -> (usize, usize) {
+    let queue = QUEUE.lock();
+    (queue.len, queue.dropped)
+}
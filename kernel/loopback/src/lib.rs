@@ -0,0 +1,127 @@
+//! A loopback network device, through which the system can send packets to itself.
+//!
+//! Every packet sent on the device is received by it again.
+//! The device is registered as its own network interface with the address `127.0.0.1/8`,
+//! such that networking applications can talk to servers running within Theseus
+//! without requiring a NIC or an external network.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::task::Waker;
+
+use log::{error, warn};
+use memory::{create_contiguous_mapping, DMA_FLAGS};
+use net::{
+    phy::Medium, DeviceCapabilities, Ipv4Address, Ipv4Cidr, Ipv4Config, NetworkDevice,
+    NetworkInterface,
+};
+use nic_buffers::{ReceiveBuffer, ReceivedFrame, TransmitBuffer};
+use spin::Once;
+use sync_irq::IrqSafeMutex;
+
+/// The IPv4 address of the loopback interface.
+pub const LOOPBACK_ADDRESS: Ipv4Address = Ipv4Address::new(127, 0, 0, 1);
+/// The prefix length of the loopback subnet, `127.0.0.0/8`.
+pub const LOOPBACK_PREFIX_LEN: u8 = 8;
+
+/// The largest packet that can be sent over the loopback device.
+const LOOPBACK_MTU: u16 = 16384;
+/// The maximum number of packets that have been sent but not yet received.
+/// Further packets are dropped, just as a NIC would when its queue is full.
+const QUEUE_CAPACITY: usize = 64;
+
+static LOOPBACK: Once<IrqSafeMutex<Loopback>> = Once::new();
+
+/// The pool of buffers that received packets are copied into.
+static RX_BUFFER_POOL: Once<mpmc::Queue<ReceiveBuffer>> = Once::new();
+
+/// Initializes the loopback device and registers it as a network interface
+/// with the static address `127.0.0.1/8`.
+pub fn init() -> Result<Arc<NetworkInterface>, &'static str> {
+    if LOOPBACK.get().is_some() {
+        return Err("loopback device was already initialized");
+    }
+    RX_BUFFER_POOL.call_once(|| mpmc::Queue::with_capacity(QUEUE_CAPACITY));
+    let device = LOOPBACK.call_once(|| {
+        IrqSafeMutex::new(Loopback {
+            queue: VecDeque::with_capacity(QUEUE_CAPACITY),
+            receive_waker: None,
+        })
+    });
+    let config = Ipv4Config {
+        address: Ipv4Cidr::new(LOOPBACK_ADDRESS, LOOPBACK_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Vec::new(),
+    };
+    Ok(net::register_device_with_static_ipv4(device, config))
+}
+
+/// A network device that receives every packet sent on it.
+pub struct Loopback {
+    /// Packets that have been sent but not yet received.
+    queue: VecDeque<TransmitBuffer>,
+    /// Wakes the interface's network task when a packet is sent.
+    receive_waker: Option<Waker>,
+}
+
+impl Loopback {
+    /// Copies a sent packet into a receive buffer from the pool,
+    /// allocating a new buffer if the pool is empty.
+    fn copy_to_receive_buffer(packet: &TransmitBuffer) -> Result<ReceiveBuffer, &'static str> {
+        let pool = RX_BUFFER_POOL.get().ok_or("loopback buffer pool wasn't initialized")?;
+        let mut buffer = match pool.pop() {
+            Some(buffer) => buffer,
+            None => {
+                let (mp, phys_addr) = create_contiguous_mapping(LOOPBACK_MTU.into(), DMA_FLAGS)?;
+                ReceiveBuffer::new(mp, phys_addr, LOOPBACK_MTU, pool)?
+            }
+        };
+        buffer.set_length(packet.length())?;
+        buffer.copy_from_slice(packet);
+        Ok(buffer)
+    }
+}
+
+impl NetworkDevice for Loopback {
+    fn send(&mut self, buf: TransmitBuffer) {
+        if self.queue.len() >= QUEUE_CAPACITY {
+            warn!("loopback: queue is full, dropping packet");
+            return;
+        }
+        self.queue.push_back(buf);
+        if let Some(ref waker) = self.receive_waker {
+            waker.wake_by_ref();
+        }
+    }
+
+    fn receive(&mut self) -> Option<ReceivedFrame> {
+        let packet = self.queue.pop_front()?;
+        match Self::copy_to_receive_buffer(&packet) {
+            Ok(buffer) => Some(ReceivedFrame(vec![buffer])),
+            Err(e) => {
+                error!("loopback: dropping packet: {e}");
+                None
+            }
+        }
+    }
+
+    /// The loopback device has no hardware address.
+    fn mac_address(&self) -> [u8; 6] {
+        [0; 6]
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ip;
+        caps.max_transmission_unit = LOOPBACK_MTU.into();
+        caps
+    }
+
+    fn set_receive_waker(&mut self, waker: Waker) -> bool {
+        self.receive_waker = Some(waker);
+        true
+    }
+}
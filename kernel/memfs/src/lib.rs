@@ -11,15 +11,17 @@ extern crate fs_node;
 extern crate memory;
 extern crate irq_safety;
 extern crate io;
+extern crate time;
 
 
 use alloc::string::String;
-use fs_node::{DirRef, WeakDirRef, File, FsNode};
+use fs_node::{DirRef, WeakDirRef, File, FsNode, Metadata, NodeKind};
 use memory::{MappedPages, get_kernel_mmi_ref, allocate_pages_by_bytes, PteFlags};
 use alloc::sync::Arc;
 use spin::Mutex;
 use fs_node::{FileOrDir, FileRef};
 use io::{ByteReader, ByteWriter, IoError, KnownLength};
+use time::{Duration, WallTime};
 
 /// The struct that represents a file in memory that is backed by MappedPages
 pub struct MemFile {
@@ -32,6 +34,10 @@ pub struct MemFile {
     mp: MappedPages,
     /// The parent directory that contains this file.
     parent: WeakDirRef,
+    /// When this file was created, as the time since the Unix epoch.
+    created: Duration,
+    /// When this file was last written to, as the time since the Unix epoch.
+    modified: Duration,
 }
 
 impl MemFile {
@@ -43,11 +49,14 @@ impl MemFile {
 
     /// Creates a new `MemFile` in the given `parent` directory with the contents of the given `mapped_pages`.
     pub fn from_mapped_pages(mapped_pages: MappedPages, name: String, len: usize, parent: &DirRef) -> Result<FileRef, &'static str> {
+        let now = time::now::<WallTime>();
         let memfile = MemFile {
             name,
             len,
             mp: mapped_pages, 
             parent: Arc::downgrade(parent), 
+            created: now,
+            modified: now,
         };
         let file_ref = Arc::new(Mutex::new(memfile)) as FileRef;
         parent.lock().insert(FileOrDir::File(file_ref.clone()))?; // adds the newly created file to the tree
@@ -88,6 +97,7 @@ impl ByteWriter for MemFile {
             if end > self.len { 
                 self.len = end; 
             }
+            self.modified = time::now::<WallTime>();
             Ok(buffer.len()) // we wrote all of the requested bytes successfully
         } 
         // if not, we need to reallocate a new mapped pages 
@@ -126,6 +136,7 @@ impl ByteWriter for MemFile {
             }
             self.mp = new_mapped_pages;
             self.len = end;
+            self.modified = time::now::<WallTime>();
             Ok(buffer.len())
         }
     }
@@ -158,4 +169,20 @@ impl FsNode for MemFile {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::File,
+            size: self.len,
+            created: Some(self.created),
+            modified: Some(self.modified),
+            // Existing MappedPages that aren't writable cannot be written to; see `write_at()`.
+            read_only: !self.mp.flags().is_writable() && self.mp.size_in_bytes() != 0,
+        }
+    }
 }
@@ -0,0 +1,180 @@
+//! A registry of filesystems that are mounted over directories in the VFS.
+//!
+//! Mounting a filesystem attaches the root directory of that filesystem
+//! over an existing directory, the *mount point*.
+//! The mount point itself is left untouched in its parent directory;
+//! instead, path resolution (e.g., `path::Path::get()`) consults this table
+//! and transparently substitutes the mounted root directory whenever
+//! it encounters a mount point.
+//! Unmounting the filesystem makes the original contents of the mount point visible again.
+//!
+//! Mounts can be stacked: mounting a filesystem over a directory that is
+//! already a mount point hides the previously-mounted filesystem until
+//! the newer one is unmounted.
+//!
+//! # Limitations
+//! * Mount points are identified by their `DirRef`, so a mount point must be
+//!   a directory that persists in the VFS tree, e.g., a `VFSDirectory`.
+//!   Lazily-generated directories, like those in `task_fs`, cannot be used as mount points.
+//! * The absolute path of a node within a mounted filesystem is derived from
+//!   the name of that filesystem's root directory, so the root directory
+//!   should have the same name as its mount point.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use fs_node::DirRef;
+use spin::Mutex;
+use storage_device::StorageDeviceId;
+
+/// The list of all mounted filesystems, in the order in which they were mounted.
+static MOUNTS: Mutex<Vec<Mount>> = Mutex::new(Vec::new());
+
+/// A filesystem that is mounted over a directory in the VFS.
+#[derive(Clone)]
+pub struct Mount {
+    /// A description of where this filesystem came from, e.g., a storage device.
+    source: String,
+    /// The storage device that this filesystem resides on, if any.
+    device: Option<StorageDeviceId>,
+    /// The absolute path of the mount point at the time this filesystem was mounted.
+    target: String,
+    /// The directory over which this filesystem is mounted.
+    mount_point: DirRef,
+    /// The root directory of the mounted filesystem.
+    root: DirRef,
+}
+
+impl Mount {
+    /// Returns a description of where this filesystem came from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the ID of the storage device that this filesystem resides on,
+    /// or `None` if it doesn't reside on a storage device.
+    pub fn device(&self) -> Option<StorageDeviceId> {
+        self.device
+    }
+
+    /// Returns the absolute path at which this filesystem is mounted.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns the directory over which this filesystem is mounted.
+    pub fn mount_point(&self) -> &DirRef {
+        &self.mount_point
+    }
+
+    /// Returns the root directory of the mounted filesystem.
+    pub fn root(&self) -> &DirRef {
+        &self.root
+    }
+}
+
+/// Mounts the filesystem whose root directory is `fs_root` over the given `mount_point` directory.
+///
+/// The `source` is a human-readable description of the filesystem, e.g., `"memfs"` or `"fat:storage0"`,
+/// and `device` is the ID of the storage device that the filesystem resides on, if any.
+///
+/// The parent directory of `fs_root` is set to the parent of `mount_point`,
+/// such that traversing upwards out of the mounted filesystem works as expected.
+///
+/// Neither the lock on `mount_point` nor the lock on `fs_root` may be held,
+/// because they will be acquired within this function.
+pub fn mount(
+    mount_point: &DirRef,
+    fs_root: DirRef,
+    source: String,
+    device: Option<StorageDeviceId>,
+) -> Result<(), &'static str> {
+    if Arc::ptr_eq(mount_point, root::get_root()) {
+        return Err("cannot mount a filesystem over the root directory");
+    }
+    if Arc::ptr_eq(mount_point, &fs_root) {
+        return Err("cannot mount a directory over itself");
+    }
+
+    let (target, parent) = {
+        let mp = mount_point.lock();
+        (mp.get_absolute_path(), mp.get_parent_dir())
+    };
+    let parent = parent.ok_or("mount point has no parent directory")?;
+
+    // Check and record the mount under a single lock, such that the same filesystem
+    // can't be mounted twice by concurrent callers.
+    {
+        let mut mounts = MOUNTS.lock();
+        if mounts.iter().any(|m| Arc::ptr_eq(&m.root, &fs_root)) {
+            return Err("that filesystem is already mounted");
+        }
+        mounts.push(Mount {
+            source,
+            device,
+            target,
+            mount_point: mount_point.clone(),
+            root: fs_root.clone(),
+        });
+    }
+    fs_root.lock().set_parent_dir(Arc::downgrade(&parent));
+    Ok(())
+}
+
+/// Unmounts the filesystem whose root directory is `fs_root`.
+///
+/// Returns an error if no filesystem is mounted with that root directory,
+/// or if other filesystems are currently mounted within it.
+pub fn unmount(fs_root: &DirRef) -> Result<Mount, &'static str> {
+    let mut mounts = MOUNTS.lock();
+    let index = mounts.iter()
+        .rposition(|m| Arc::ptr_eq(&m.root, fs_root))
+        .ok_or("not a mounted filesystem")?;
+
+    let nested_prefix = format!("{}/", mounts[index].target);
+    let is_busy = mounts.iter().any(|m|
+        Arc::ptr_eq(&m.mount_point, fs_root) || m.target.starts_with(&nested_prefix)
+    );
+    if is_busy {
+        return Err("other filesystems are mounted within that filesystem");
+    }
+
+    let mount = mounts.remove(index);
+    drop(mounts);
+    mount.root.lock().set_parent_dir(Weak::<Mutex<root::RootDirectory>>::new());
+    Ok(mount)
+}
+
+/// Returns a list of all currently-mounted filesystems, in the order in which they were mounted.
+pub fn mounts() -> Vec<Mount> {
+    MOUNTS.lock().clone()
+}
+
+/// Returns the directory that should be visible at the location of the given `dir`.
+///
+/// If `dir` is a mount point, this returns the root directory of the filesystem
+/// most recently mounted over it; otherwise, `dir` itself is returned.
+pub fn resolve(dir: DirRef) -> DirRef {
+    let mounts = MOUNTS.lock();
+    let mut current = dir;
+    while let Some(m) = mounts.iter().rev().find(|m| Arc::ptr_eq(&m.mount_point, &current)) {
+        current = m.root.clone();
+    }
+    current
+}
+
+/// If the given `dir` is the root directory of a mounted filesystem,
+/// this returns the mount point over which it is mounted.
+pub fn mount_point_of(dir: &DirRef) -> Option<DirRef> {
+    MOUNTS.lock().iter()
+        .rev()
+        .find(|m| Arc::ptr_eq(&m.root, dir))
+        .map(|m| m.mount_point.clone())
+}
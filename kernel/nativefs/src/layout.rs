@@ -0,0 +1,283 @@
+//! The on-disk layout of a native filesystem.
+//!
+//! A native filesystem is divided into [`BLOCK_SIZE`]-byte blocks, which are laid out as follows:
+//! 1. The [`Superblock`], at block `0`.
+//! 2. The journal, whose first block is the journal header
+//!    and whose remaining blocks hold at most one transaction at a time.
+//! 3. The block allocation bitmap, with one bit per block in the filesystem.
+//! 4. The inode table, an array of [`INODE_SIZE`]-byte [`Inode`]s.
+//! 5. Data blocks, which hold file contents, directory entries, and indirect block pointers.
+//!
+//! All integers are stored in little-endian byte order.
+
+use alloc::string::String;
+use core::time::Duration;
+
+/// The size in bytes of a block in a native filesystem.
+/// This must be a multiple of the block size of the underlying storage device.
+pub const BLOCK_SIZE: usize = 4096;
+
+/// The magic number at the start of the superblock: `"THSNATFS"`.
+pub const SUPERBLOCK_MAGIC: u64 = u64::from_le_bytes(*b"THSNATFS");
+/// The version of the on-disk layout described by this module.
+pub const VERSION: u32 = 1;
+/// The magic number at the start of the journal header block: `"JHDR"`.
+pub const JOURNAL_HEADER_MAGIC: u32 = u32::from_le_bytes(*b"JHDR");
+/// The magic number at the start of a transaction's descriptor block: `"JDSC"`.
+pub const JOURNAL_DESCRIPTOR_MAGIC: u32 = u32::from_le_bytes(*b"JDSC");
+/// The magic number at the start of a transaction's commit block: `"JCMT"`.
+pub const JOURNAL_COMMIT_MAGIC: u32 = u32::from_le_bytes(*b"JCMT");
+
+/// The offset of the list of target block numbers within a descriptor block.
+pub const DESCRIPTOR_TARGETS_OFFSET: usize = 16;
+/// The maximum number of blocks that one descriptor block can describe.
+pub const MAX_DESCRIPTOR_TARGETS: usize = (BLOCK_SIZE - DESCRIPTOR_TARGETS_OFFSET) / 4;
+
+/// Block number `0` (the superblock) is never used for content, such that it can mark an unused block pointer.
+pub const NULL_BLOCK: u32 = 0;
+
+/// The size in bytes of an inode in the inode table.
+pub const INODE_SIZE: usize = 128;
+/// The number of inodes in each block of the inode table.
+pub const INODES_PER_BLOCK: usize = BLOCK_SIZE / INODE_SIZE;
+/// Inode number `0` is never used, such that it can mark an empty directory entry.
+pub const NULL_INODE: u32 = 0;
+/// The inode number of the root directory.
+pub const ROOT_INODE: u32 = 1;
+/// The number of direct block pointers in an inode.
+pub const DIRECT_POINTERS: usize = 12;
+/// The number of block pointers in an indirect block.
+pub const POINTERS_PER_BLOCK: usize = BLOCK_SIZE / 4;
+
+/// The size in bytes of a directory entry.
+pub const DIR_ENTRY_SIZE: usize = 64;
+/// The number of directory entries in each directory data block.
+pub const DIR_ENTRIES_PER_BLOCK: usize = BLOCK_SIZE / DIR_ENTRY_SIZE;
+/// The maximum length in bytes of a node's name.
+pub const MAX_NAME_LEN: usize = DIR_ENTRY_SIZE - 8;
+
+
+/// The superblock, which describes the geometry of the filesystem.
+#[derive(Clone, Copy, Debug)]
+pub struct Superblock {
+    /// The total number of blocks in the filesystem.
+    pub total_blocks: u32,
+    pub journal_start: u32,
+    pub journal_blocks: u32,
+    pub bitmap_start: u32,
+    pub bitmap_blocks: u32,
+    pub inode_table_start: u32,
+    pub inode_table_blocks: u32,
+    /// The first block that can be allocated to hold data.
+    pub data_start: u32,
+}
+
+impl Superblock {
+    /// The number of bytes covered by the superblock's checksum.
+    const CHECKSUMMED_LEN: usize = 48;
+
+    /// The total number of inodes in the inode table.
+    pub fn inode_count(&self) -> u32 {
+        self.inode_table_blocks * INODES_PER_BLOCK as u32
+    }
+
+    /// The maximum number of metadata blocks that a single transaction can modify,
+    /// which is limited by the size of the journal.
+    pub fn max_transaction_blocks(&self) -> usize {
+        // The journal header, descriptor block, and commit block are not available.
+        core::cmp::min(self.journal_blocks as usize - 3, MAX_DESCRIPTOR_TARGETS)
+    }
+
+    pub fn parse(block: &[u8]) -> Result<Superblock, &'static str> {
+        if read_u64(block, 0) != SUPERBLOCK_MAGIC {
+            return Err("not a native filesystem: bad superblock magic");
+        }
+        if read_u32(block, 8) != VERSION {
+            return Err("unsupported native filesystem version");
+        }
+        if read_u32(block, 12) as usize != BLOCK_SIZE {
+            return Err("unsupported native filesystem block size");
+        }
+        if crc32fast::hash(&block[.. Self::CHECKSUMMED_LEN]) != read_u32(block, Self::CHECKSUMMED_LEN) {
+            return Err("native filesystem superblock checksum mismatch");
+        }
+        let sb = Superblock {
+            total_blocks:       read_u32(block, 16),
+            journal_start:      read_u32(block, 20),
+            journal_blocks:     read_u32(block, 24),
+            bitmap_start:       read_u32(block, 28),
+            bitmap_blocks:      read_u32(block, 32),
+            inode_table_start:  read_u32(block, 36),
+            inode_table_blocks: read_u32(block, 40),
+            data_start:         read_u32(block, 44),
+        };
+        let valid = sb.journal_start == 1
+            && sb.journal_blocks >= 4
+            && sb.bitmap_start == sb.journal_start + sb.journal_blocks
+            && sb.bitmap_blocks as usize * BLOCK_SIZE * 8 >= sb.total_blocks as usize
+            && sb.inode_table_start == sb.bitmap_start + sb.bitmap_blocks
+            && sb.inode_table_blocks > 0
+            && sb.data_start == sb.inode_table_start + sb.inode_table_blocks
+            && sb.data_start < sb.total_blocks;
+        if !valid {
+            return Err("native filesystem superblock has an invalid layout");
+        }
+        Ok(sb)
+    }
+
+    pub fn write(&self, block: &mut [u8]) {
+        block.fill(0);
+        write_u64(block, 0, SUPERBLOCK_MAGIC);
+        write_u32(block, 8, VERSION);
+        write_u32(block, 12, BLOCK_SIZE as u32);
+        write_u32(block, 16, self.total_blocks);
+        write_u32(block, 20, self.journal_start);
+        write_u32(block, 24, self.journal_blocks);
+        write_u32(block, 28, self.bitmap_start);
+        write_u32(block, 32, self.bitmap_blocks);
+        write_u32(block, 36, self.inode_table_start);
+        write_u32(block, 40, self.inode_table_blocks);
+        write_u32(block, 44, self.data_start);
+        let crc = crc32fast::hash(&block[.. Self::CHECKSUMMED_LEN]);
+        write_u32(block, Self::CHECKSUMMED_LEN, crc);
+    }
+}
+
+
+/// The kind of node that an inode describes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InodeKind {
+    Free = 0,
+    File = 1,
+    Directory = 2,
+}
+
+/// An entry in the inode table, which describes one file or directory.
+#[derive(Clone, Debug)]
+pub struct Inode {
+    pub kind: InodeKind,
+    /// The inode number of the directory that contains this directory,
+    /// which is used to prevent moving a directory into itself.
+    /// This is unused for files.
+    pub parent: u32,
+    /// The size in bytes of this node's contents.
+    /// For a directory, this covers all of its directory entry slots, including empty ones.
+    pub size: u64,
+    /// When this node was created, in seconds since the Unix epoch.
+    pub created: u64,
+    /// When this node's contents were last modified, in seconds since the Unix epoch.
+    pub modified: u64,
+    /// The blocks holding the first [`DIRECT_POINTERS`] blocks of content.
+    pub direct: [u32; DIRECT_POINTERS],
+    /// A block of pointers to the subsequent [`POINTERS_PER_BLOCK`] blocks of content.
+    pub indirect: u32,
+    /// A block of pointers to indirect blocks, which point to all remaining blocks of content.
+    pub double_indirect: u32,
+}
+
+impl Inode {
+    pub fn new(kind: InodeKind, parent: u32, now: Duration) -> Inode {
+        Inode {
+            kind,
+            parent,
+            size: 0,
+            created: now.as_secs(),
+            modified: now.as_secs(),
+            direct: [0; DIRECT_POINTERS],
+            indirect: 0,
+            double_indirect: 0,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Inode, &'static str> {
+        let kind = match read_u32(bytes, 0) {
+            0 => InodeKind::Free,
+            1 => InodeKind::File,
+            2 => InodeKind::Directory,
+            _ => return Err("corrupted inode: invalid kind"),
+        };
+        let mut direct = [0; DIRECT_POINTERS];
+        for (i, ptr) in direct.iter_mut().enumerate() {
+            *ptr = read_u32(bytes, 32 + i * 4);
+        }
+        Ok(Inode {
+            kind,
+            parent:          read_u32(bytes, 4),
+            size:            read_u64(bytes, 8),
+            created:         read_u64(bytes, 16),
+            modified:        read_u64(bytes, 24),
+            direct,
+            indirect:        read_u32(bytes, 80),
+            double_indirect: read_u32(bytes, 84),
+        })
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) {
+        bytes[.. INODE_SIZE].fill(0);
+        write_u32(bytes, 0, self.kind as u32);
+        write_u32(bytes, 4, self.parent);
+        write_u64(bytes, 8, self.size);
+        write_u64(bytes, 16, self.created);
+        write_u64(bytes, 24, self.modified);
+        for (i, ptr) in self.direct.iter().enumerate() {
+            write_u32(bytes, 32 + i * 4, *ptr);
+        }
+        write_u32(bytes, 80, self.indirect);
+        write_u32(bytes, 84, self.double_indirect);
+    }
+
+    pub fn created(&self) -> Duration {
+        Duration::from_secs(self.created)
+    }
+
+    pub fn modified(&self) -> Duration {
+        Duration::from_secs(self.modified)
+    }
+}
+
+
+/// An entry in a directory's contents, which links a name to an inode.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    /// The inode of the named node, or [`NULL_INODE`] if this entry slot is empty.
+    pub inode: u32,
+    /// The kind of the named node, duplicated from its inode to speed up lookups.
+    pub kind: InodeKind,
+    pub name: String,
+}
+
+impl DirEntry {
+    pub fn parse(bytes: &[u8]) -> DirEntry {
+        let name_len = core::cmp::min(bytes[4] as usize, MAX_NAME_LEN);
+        DirEntry {
+            inode: read_u32(bytes, 0),
+            kind: if bytes[5] == InodeKind::Directory as u8 { InodeKind::Directory } else { InodeKind::File },
+            name: String::from_utf8_lossy(&bytes[8 .. 8 + name_len]).into_owned(),
+        }
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) {
+        bytes[.. DIR_ENTRY_SIZE].fill(0);
+        write_u32(bytes, 0, self.inode);
+        bytes[4] = self.name.len() as u8;
+        bytes[5] = self.kind as u8;
+        bytes[8 .. 8 + self.name.len()].copy_from_slice(self.name.as_bytes());
+    }
+}
+
+
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset .. offset + 4].try_into().unwrap())
+}
+
+pub fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset .. offset + 8].try_into().unwrap())
+}
+
+pub fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset .. offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    bytes[offset .. offset + 8].copy_from_slice(&value.to_le_bytes());
+}
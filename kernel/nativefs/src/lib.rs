@@ -0,0 +1,373 @@
+//! A simple journaling filesystem native to Theseus, which persists files and directories
+//! on a [`StorageDevice`] and implements the [`fs_node`] traits.
+//!
+//! A storage device is formatted with a new, empty native filesystem using [`format()`],
+//! e.g., via the `mkfs` application.
+//! Then, a native filesystem is opened on that device using [`NativeFileSystem::new()`],
+//! and its root directory from [`NativeFileSystem::root_dir()`] can be mounted
+//! into the VFS tree via the `mount_table`, which `device_manager` does at boot.
+//!
+//! The filesystem uses a classic Unix-like layout with a block bitmap, an inode table,
+//! and direct, indirect, and double-indirect block pointers; see the [`layout`] module.
+//!
+//! # Crash safety
+//! All metadata changes made by a single operation, e.g., creating, removing, or renaming a node,
+//! are written to a journal before being applied, and the journal is replayed when the filesystem
+//! is next opened. Thus, each such operation is atomic, including renaming a node
+//! and moving it between directories within the same filesystem.
+//! File contents are written before the metadata that references them is committed,
+//! so a crash never exposes a newly-allocated block with stale contents.
+//!
+//! # Creating nodes
+//! Files and directories within a native filesystem can only be native nodes,
+//! so nodes from other filesystems cannot be [inserted](Directory::insert) into a [`NativeDirectory`].
+//! Instead, new nodes are created directly on the device via [`Directory::create_file()`]
+//! and [`Directory::create_dir()`], which callers must use instead of `MemFile::create()`
+//! and `VFSDirectory::create()`.
+//!
+//! # Limitations
+//! * Overwriting existing file contents is not journaled,
+//!   so a crash during such a write may leave the file partially updated.
+//! * Removing a directory removes each of its descendants in a separate transaction,
+//!   so a crash during a recursive removal may leave some descendants behind.
+//! * Files cannot be truncated or shrunk.
+//! * A [`NativeDirectory`] or [`NativeFile`] node is only a handle to an inode, created on demand
+//!   by [`Directory::get()`], so several nodes may exist for the same inode.
+//!   They all see the same contents, but a node's name and parent are fixed when it's created,
+//!   so after a node is renamed or moved, only newly-obtained nodes reflect its new location.
+//! * Nodes cannot be moved or copied between a native filesystem and any other filesystem.
+//! * The whole filesystem is protected by a single lock around its on-disk volume,
+//!   so at most one operation on it can be in progress at once.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod layout;
+mod volume;
+
+#[cfg(test)]
+mod test;
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use fs_node::{DirRef, Directory, File, FileOrDir, FileRef, FsNode, Metadata, NodeKind, WeakDirRef};
+use io::{ByteReader, ByteWriter, IoError, KnownLength};
+use layout::{InodeKind, ROOT_INODE};
+use log::error;
+use memory::MappedPages;
+use spin::Mutex;
+use storage_device::StorageDeviceRef;
+use volume::Volume;
+
+/// Options for formatting a storage device with a new native filesystem.
+#[derive(Clone, Copy, Debug)]
+pub struct FormatOptions {
+    /// The number of blocks reserved for the journal,
+    /// which limits how much metadata a single operation can modify.
+    pub journal_blocks: u32,
+    /// The number of bytes of storage per inode,
+    /// which determines how many files and directories the filesystem can hold.
+    pub bytes_per_inode: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            journal_blocks: 256,
+            bytes_per_inode: 16384,
+        }
+    }
+}
+
+/// Formats the given `device` with a new, empty native filesystem,
+/// which destroys all existing data on the device.
+pub fn format(device: StorageDeviceRef, options: &FormatOptions) -> Result<(), &'static str> {
+    Volume::format(device, options)
+}
+
+
+/// A native filesystem that exists on a storage device.
+///
+/// This is shared by all of the [`NativeDirectory`] and [`NativeFile`] nodes within it.
+pub struct NativeFileSystem {
+    volume: Mutex<Volume>,
+    /// The inode of each live [`NativeDirectory`] node in this filesystem, keyed by the node's address.
+    ///
+    /// A node is added when it's created and removed when it's dropped.
+    /// Given only a [`WeakDirRef`], e.g., the destination of a move,
+    /// this tells us whether it's a directory in this filesystem and which inode it refers to.
+    directory_inodes: Mutex<BTreeMap<usize, u32>>,
+}
+
+/// Returns the address of the given directory node, which identifies it in
+/// [`NativeFileSystem::directory_inodes`].
+fn node_address(dir: &WeakDirRef) -> usize {
+    dir.as_ptr() as *const () as usize
+}
+
+impl NativeFileSystem {
+    /// Attempts to open a native filesystem on the given `device`,
+    /// replaying any committed transaction in its journal.
+    ///
+    /// Returns an error if the device does not contain a valid native filesystem.
+    pub fn new(device: StorageDeviceRef) -> Result<Arc<NativeFileSystem>, &'static str> {
+        Ok(Arc::new(NativeFileSystem {
+            volume: Mutex::new(Volume::open(device)?),
+            directory_inodes: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    /// Returns a new node for the root directory of this filesystem, which will be named `name`.
+    ///
+    /// The returned directory has no parent; it is meant to be mounted via the `mount_table`,
+    /// in which case `name` should match the name of the mount point.
+    pub fn root_dir(self: &Arc<Self>, name: String) -> DirRef {
+        NativeDirectory::new_ref(self.clone(), ROOT_INODE, name, Weak::<Mutex<NativeDirectory>>::new())
+    }
+
+    /// Returns the inode of the given directory if it is a [`NativeDirectory`] within this filesystem.
+    fn directory_inode(&self, dir: &WeakDirRef) -> Option<u32> {
+        self.directory_inodes.lock().get(&node_address(dir)).copied()
+    }
+
+    /// Returns the metadata of the given inode.
+    fn metadata(&self, inode_num: u32) -> Result<Metadata, &'static str> {
+        let inode = self.volume.lock().read_inode(inode_num)?;
+        Ok(Metadata {
+            kind: if inode.kind == InodeKind::Directory { NodeKind::Directory } else { NodeKind::File },
+            size: if inode.kind == InodeKind::Directory { 0 } else { inode.size as usize },
+            created: Some(inode.created()),
+            modified: Some(inode.modified()),
+            read_only: false,
+        })
+    }
+}
+
+/// Removes the node called `name` from the directory `dir` if it is a file.
+///
+/// Returns an error if it is a directory, which cannot be replaced.
+fn replace_existing_file(volume: &mut Volume, dir: u32, name: &str) -> Result<(), &'static str> {
+    match volume.lookup(dir, name)? {
+        Some((_, entry)) if entry.kind == InodeKind::Directory => Err("cannot replace an existing directory"),
+        Some(_) => volume.unlink(dir, name),
+        None => Ok(()),
+    }
+}
+
+
+/// A directory within a native filesystem.
+pub struct NativeDirectory {
+    /// The filesystem that this directory exists within.
+    fs: Arc<NativeFileSystem>,
+    /// The inode that describes this directory.
+    inode: u32,
+    /// The name of this directory.
+    name: String,
+    /// The parent directory that contains this directory.
+    parent: WeakDirRef,
+    /// A weak reference to this directory itself, used as the parent of its children.
+    self_ref: WeakDirRef,
+}
+
+impl NativeDirectory {
+    /// Returns a new node for the directory `inode` and registers it in `fs`'s `directory_inodes`.
+    fn new_ref(fs: Arc<NativeFileSystem>, inode: u32, name: String, parent: WeakDirRef) -> DirRef {
+        let mut directory_inodes = fs.directory_inodes.lock();
+        let dir: DirRef = Arc::new_cyclic(|self_ref: &Weak<Mutex<NativeDirectory>>| Mutex::new(NativeDirectory {
+            fs: fs.clone(),
+            inode,
+            name,
+            parent,
+            self_ref: self_ref.clone(),
+        }));
+        directory_inodes.insert(node_address(&Arc::downgrade(&dir)), inode);
+        dir
+    }
+
+    /// Creates a new, empty inode of the given `kind` called `name` in this directory,
+    /// replacing an existing file of the same name.
+    fn create_inode(&mut self, name: &str, kind: InodeKind) -> Result<u32, &'static str> {
+        self.fs.volume.lock().transaction(|volume| {
+            replace_existing_file(volume, self.inode, name)?;
+            volume.create(self.inode, name, kind)
+        })
+    }
+
+    /// Returns a new node for the given directory entry within this directory.
+    fn child_node(&self, inode: u32, kind: InodeKind, name: String) -> FileOrDir {
+        if kind == InodeKind::Directory {
+            FileOrDir::Dir(self.dir_node(inode, name))
+        } else {
+            FileOrDir::File(self.file_node(inode, name))
+        }
+    }
+
+    /// Returns a new node for the child directory `inode` called `name`.
+    fn dir_node(&self, inode: u32, name: String) -> DirRef {
+        NativeDirectory::new_ref(self.fs.clone(), inode, name, self.self_ref.clone())
+    }
+
+    /// Returns a new node for the child file `inode` called `name`.
+    fn file_node(&self, inode: u32, name: String) -> FileRef {
+        let file = NativeFile {
+            fs: self.fs.clone(),
+            inode,
+            name,
+            parent: self.self_ref.clone(),
+        };
+        Arc::new(Mutex::new(file)) as FileRef
+    }
+}
+
+impl Directory for NativeDirectory {
+    fn insert(&mut self, _node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        Err("cannot insert nodes from another filesystem into a native directory; create them in it instead")
+    }
+
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let (_, entry) = self.fs.volume.lock().lookup(self.inode, name).ok()??;
+        Some(self.child_node(entry.inode, entry.kind, entry.name))
+    }
+
+    fn list(&self) -> Vec<String> {
+        match self.fs.volume.lock().dir_entries(self.inode) {
+            Ok(entries) => entries.into_iter().map(|(_, entry)| entry.name).collect(),
+            Err(e) => {
+                error!("NativeDirectory::list(): failed to read directory {:?}: {}", self.name, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        let name = node.get_name();
+        match self.fs.volume.lock().remove_tree(self.inode, &name) {
+            Ok(()) => {
+                let mut old_node = node.clone();
+                old_node.set_parent_dir(Weak::<Mutex<NativeDirectory>>::new());
+                Some(old_node)
+            }
+            Err(e) => {
+                error!("NativeDirectory::remove(): failed to remove {:?} from {:?}: {}", name, self.name, e);
+                None
+            }
+        }
+    }
+
+    fn create_file(&mut self, name: &str) -> Option<Result<FileRef, &'static str>> {
+        Some(self.create_inode(name, InodeKind::File).map(|inode| self.file_node(inode, String::from(name))))
+    }
+
+    fn create_dir(&mut self, name: &str) -> Option<Result<DirRef, &'static str>> {
+        Some(self.create_inode(name, InodeKind::Directory).map(|inode| self.dir_node(inode, String::from(name))))
+    }
+
+    /// Moves an entry within this native filesystem as a single atomic transaction.
+    ///
+    /// Unlike the default implementation, this only modifies the on-disk directory entries,
+    /// so the destination must be a directory within this same native filesystem.
+    fn move_child(
+        &mut self,
+        name: &str,
+        new_parent: Option<&mut dyn Directory>,
+        new_parent_ref: WeakDirRef,
+        new_name: &str,
+    ) -> Result<(), &'static str> {
+        let dest_dir = match new_parent {
+            None => self.inode,
+            Some(_) => self.fs.directory_inode(&new_parent_ref)
+                .ok_or("cannot move nodes out of a native filesystem")?,
+        };
+        self.fs.volume.lock().transaction(|volume| volume.rename(self.inode, name, dest_dir, new_name))
+    }
+}
+
+impl Drop for NativeDirectory {
+    fn drop(&mut self) {
+        self.fs.directory_inodes.lock().remove(&node_address(&self.self_ref));
+    }
+}
+
+impl FsNode for NativeDirectory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.fs.metadata(self.inode).unwrap_or_else(|_| Metadata::directory(false))
+    }
+}
+
+
+/// A file within a native filesystem.
+pub struct NativeFile {
+    /// The filesystem that this file exists within.
+    fs: Arc<NativeFileSystem>,
+    /// The inode that describes this file.
+    inode: u32,
+    /// The name of this file.
+    name: String,
+    /// The parent directory that contains this file.
+    parent: WeakDirRef,
+}
+
+impl ByteReader for NativeFile {
+    fn read_at(&mut self, buffer: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        self.fs.volume.lock().read_file(self.inode, buffer, offset)
+    }
+}
+
+impl ByteWriter for NativeFile {
+    fn write_at(&mut self, buffer: &[u8], offset: usize) -> Result<usize, IoError> {
+        self.fs.volume.lock()
+            .transaction(|volume| volume.write_file(self.inode, buffer, offset))
+            .map_err(IoError::from)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.fs.volume.lock().flush().map_err(IoError::from)
+    }
+}
+
+impl KnownLength for NativeFile {
+    fn len(&self) -> usize {
+        self.fs.volume.lock().read_inode(self.inode).map_or(0, |inode| inode.size as usize)
+    }
+}
+
+impl File for NativeFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("Mapping a NativeFile as a MappedPages object is unimplemented")
+    }
+}
+
+impl FsNode for NativeFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.fs.metadata(self.inode).unwrap_or_else(|_| Metadata::file(self.len(), false))
+    }
+}
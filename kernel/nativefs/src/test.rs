@@ -0,0 +1,215 @@
+//! Unit tests for formatting, opening, journaling, and modifying a native filesystem.
+
+extern crate std;
+use super::*;
+use alloc::vec;
+use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
+use layout::{BLOCK_SIZE, DIRECT_POINTERS, POINTERS_PER_BLOCK};
+use spin::Mutex;
+use storage_device::StorageDevice;
+
+/// The block size of the in-memory storage device, which is smaller than a filesystem block.
+const DEVICE_BLOCK_SIZE: usize = 512;
+/// The size of each test filesystem in filesystem blocks, which is large enough
+/// to hold a file that reaches into its double-indirect blocks.
+const FS_BLOCKS: usize = 2048;
+
+/// An in-memory storage device with [`DEVICE_BLOCK_SIZE`]-byte blocks.
+struct MemDevice(Vec<u8>);
+
+impl StorageDevice for MemDevice {
+    fn size_in_blocks(&self) -> usize { self.0.len() / DEVICE_BLOCK_SIZE }
+}
+impl BlockIo for MemDevice {
+    fn block_size(&self) -> usize { DEVICE_BLOCK_SIZE }
+}
+impl KnownLength for MemDevice {
+    fn len(&self) -> usize { self.0.len() }
+}
+impl BlockReader for MemDevice {
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        let start = block_offset * DEVICE_BLOCK_SIZE;
+        let src = self.0.get(start .. start + buffer.len()).ok_or(IoError::InvalidInput)?;
+        buffer.copy_from_slice(src);
+        Ok(buffer.len() / DEVICE_BLOCK_SIZE)
+    }
+}
+impl BlockWriter for MemDevice {
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        let start = block_offset * DEVICE_BLOCK_SIZE;
+        let dest = self.0.get_mut(start .. start + buffer.len()).ok_or(IoError::InvalidInput)?;
+        dest.copy_from_slice(buffer);
+        Ok(buffer.len() / DEVICE_BLOCK_SIZE)
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+/// Returns an empty, unformatted storage device large enough for a test filesystem.
+fn empty_device() -> StorageDeviceRef {
+    Arc::new(Mutex::new(MemDevice(vec![0; FS_BLOCKS * BLOCK_SIZE])))
+}
+
+/// Returns an empty storage device freshly formatted with a native filesystem.
+fn formatted_device() -> StorageDeviceRef {
+    let device = empty_device();
+    format(device.clone(), &FormatOptions::default()).unwrap();
+    device
+}
+
+/// Returns the root directory of the native filesystem on the given `device`.
+fn open_root(device: &StorageDeviceRef) -> DirRef {
+    NativeFileSystem::new(device.clone()).unwrap().root_dir(String::from("root"))
+}
+
+/// Reads the entire contents of the given file.
+fn read_all(file: &FileRef) -> Vec<u8> {
+    let mut file = file.lock();
+    let mut contents = vec![0; file.len()];
+    assert_eq!(file.read_at(&mut contents, 0).unwrap(), contents.len());
+    contents
+}
+
+#[test]
+fn test_format_then_open() {
+    let device = formatted_device();
+    let root = open_root(&device);
+    assert!(root.lock().list().is_empty());
+    assert!(root.lock().metadata().is_dir());
+
+    let file = root.lock().create_file("hello").unwrap().unwrap();
+    file.lock().write_at(b"hello, world", 0).unwrap();
+    root.lock().create_dir("subdir").unwrap().unwrap();
+    drop((root, file));
+
+    let root = open_root(&device);
+    let mut names = root.lock().list();
+    names.sort();
+    assert_eq!(names, ["hello", "subdir"]);
+    assert_eq!(read_all(&root.lock().get_file("hello").unwrap()), b"hello, world");
+    assert!(root.lock().get_dir("subdir").unwrap().lock().list().is_empty());
+}
+
+#[test]
+fn test_open_unformatted_device() {
+    assert!(NativeFileSystem::new(empty_device()).is_err());
+}
+
+#[test]
+fn test_replay_committed_transaction() {
+    let device = formatted_device();
+    {
+        let mut volume = Volume::open(device.clone()).unwrap();
+        volume.create(ROOT_INODE, "journaled", InodeKind::File).unwrap();
+        // Simulate a crash after the transaction reached the journal but before it was checkpointed.
+        let blocks = volume.log_transaction().unwrap();
+        assert!(!blocks.is_empty());
+    }
+    // The checkpoint never happened, so only replaying the journal makes the new file visible.
+    let mut volume = Volume::open(device.clone()).unwrap();
+    assert!(volume.lookup(ROOT_INODE, "journaled").unwrap().is_some());
+    drop(volume);
+
+    // The replayed transaction was retired, so opening the filesystem again doesn't replay it again.
+    let root = open_root(&device);
+    assert_eq!(root.lock().list(), ["journaled"]);
+}
+
+#[test]
+fn test_discard_uncommitted_transaction() {
+    let device = formatted_device();
+    let logged_blocks = {
+        let mut volume = Volume::open(device.clone()).unwrap();
+        volume.create(ROOT_INODE, "torn", InodeKind::File).unwrap();
+        volume.log_transaction().unwrap().len()
+    };
+    // Simulate a crash in the middle of writing the log by erasing its commit block,
+    // which follows the journal header, the descriptor block, and the logged blocks.
+    let commit_block = 1 + 1 + 1 + logged_blocks;
+    device.lock()
+        .write_blocks(&vec![0; BLOCK_SIZE], commit_block * BLOCK_SIZE / DEVICE_BLOCK_SIZE)
+        .unwrap();
+
+    let root = open_root(&device);
+    assert!(root.lock().list().is_empty());
+}
+
+#[test]
+fn test_move_child_across_directories() {
+    let device = formatted_device();
+    let root = open_root(&device);
+    let src = root.lock().create_dir("src").unwrap().unwrap();
+    let dest = root.lock().create_dir("dest").unwrap().unwrap();
+    let file = src.lock().create_file("file").unwrap().unwrap();
+    file.lock().write_at(b"contents", 0).unwrap();
+
+    src.lock().move_child("file", Some(&mut *dest.lock() as &mut dyn Directory), Arc::downgrade(&dest), "moved").unwrap();
+    assert!(src.lock().get("file").is_none());
+    assert_eq!(read_all(&dest.lock().get_file("moved").unwrap()), b"contents");
+
+    // A directory cannot be moved into its own descendant, and a failed move changes nothing.
+    let child = src.lock().create_dir("child").unwrap().unwrap();
+    let result = root.lock().move_child("src", Some(&mut *child.lock() as &mut dyn Directory), Arc::downgrade(&child), "src");
+    assert!(result.is_err());
+    drop((root, src, dest, file, child));
+
+    let root = open_root(&device);
+    let mut names = root.lock().list();
+    names.sort();
+    assert_eq!(names, ["dest", "src"]);
+    assert_eq!(root.lock().get_dir("src").unwrap().lock().list(), ["child"]);
+    assert_eq!(root.lock().get_dir("dest").unwrap().lock().list(), ["moved"]);
+}
+
+#[test]
+fn test_rename_is_atomic_across_a_crash() {
+    let device = formatted_device();
+    let (src, dest) = {
+        let mut volume = Volume::open(device.clone()).unwrap();
+        let (src, dest) = volume.transaction(|volume| {
+            let src = volume.create(ROOT_INODE, "src", InodeKind::Directory)?;
+            let dest = volume.create(ROOT_INODE, "dest", InodeKind::Directory)?;
+            volume.create(src, "file", InodeKind::File)?;
+            Ok((src, dest))
+        }).unwrap();
+        volume.rename(src, "file", dest, "file").unwrap();
+        // Both directories are modified by a single transaction, which crashes before its checkpoint.
+        volume.log_transaction().unwrap();
+        (src, dest)
+    };
+
+    let mut volume = Volume::open(device).unwrap();
+    assert!(volume.lookup(src, "file").unwrap().is_none());
+    assert!(volume.lookup(dest, "file").unwrap().is_some());
+}
+
+#[test]
+fn test_file_growth_across_block_boundaries() {
+    let device = formatted_device();
+    let root = open_root(&device);
+    let file = root.lock().create_file("big").unwrap().unwrap();
+
+    // Writes that straddle a block boundary, the end of the direct blocks,
+    // and the end of the indirect blocks, each of which extends the file.
+    let boundaries = [
+        BLOCK_SIZE,
+        DIRECT_POINTERS * BLOCK_SIZE,
+        (DIRECT_POINTERS + POINTERS_PER_BLOCK) * BLOCK_SIZE,
+    ];
+    let mut expected = Vec::new();
+    for (i, boundary) in boundaries.iter().enumerate() {
+        let data: Vec<u8> = (0 .. 300).map(|b| (b + i) as u8).collect();
+        let offset = boundary - 100;
+        file.lock().write_at(&data, offset).unwrap();
+        // The skipped-over region of the file reads as zeros.
+        expected.resize(offset, 0);
+        expected.extend_from_slice(&data);
+        assert_eq!(file.lock().len(), offset + data.len());
+    }
+    assert_eq!(read_all(&file), expected);
+    drop((root, file));
+
+    let root = open_root(&device);
+    let file = root.lock().get_file("big").unwrap();
+    assert_eq!(read_all(&file), expected);
+    assert_eq!(file.lock().metadata().size, expected.len());
+}
@@ -0,0 +1,788 @@
+//! Low-level access to the blocks, inodes, and directory entries of a native filesystem,
+//! including the metadata journal.
+//!
+//! All modifications to metadata blocks (the bitmap, inode table, directory contents,
+//! and indirect blocks) are made within a transaction, see [`Volume::transaction()`].
+//! Until a transaction is committed, its modified blocks are only held in memory,
+//! so aborting a transaction simply discards them.
+//! Committing a transaction first writes all of its modified blocks to the journal,
+//! followed by a commit block with a checksum, and only then writes them to their home locations.
+//! Thus, if a crash occurs, the journal can be replayed upon the next mount
+//! such that either all or none of a transaction's changes are visible.
+//!
+//! File contents are not journaled; they are written directly to newly-allocated
+//! or existing data blocks before the transaction that references them is committed.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    vec,
+    vec::Vec,
+};
+use block_cache::{BlockCache, WritePolicy};
+use core::time::Duration;
+use io::{BlockIo, BlockReader, BlockWriter, IoError, LockableIo};
+use log::{debug, info};
+use spin::Mutex;
+use storage_device::{StorageDevice, StorageDeviceRef};
+use time::WallTime;
+use crate::{FormatOptions, layout::*};
+
+/// The maximum number of blocks of the underlying storage device cached by each [`Volume`].
+const CACHE_CAPACITY: usize = 1024;
+/// The number of blocks tracked by each block of the allocation bitmap.
+const BITS_PER_BITMAP_BLOCK: u32 = (BLOCK_SIZE * 8) as u32;
+/// The minimum number of blocks in a native filesystem.
+const MIN_TOTAL_BLOCKS: u32 = 128;
+/// The minimum number of blocks in the journal.
+const MIN_JOURNAL_BLOCKS: u32 = 16;
+/// The number of blocks zeroed at once when formatting a filesystem.
+const FORMAT_CHUNK_BLOCKS: u32 = 64;
+
+/// The I/O stream type that a [`Volume`] uses to access its underlying storage device.
+type Disk = BlockCache<LockableIo<'static, dyn StorageDevice + Send, Mutex<dyn StorageDevice + Send>, StorageDeviceRef>>;
+
+
+/// A native filesystem on a storage device.
+pub struct Volume {
+    /// Write-through ensures that journal and checkpoint writes reach the device in order.
+    disk: Disk,
+    sb: Superblock,
+    /// The number of storage device blocks in each filesystem block.
+    device_blocks_per_block: usize,
+    /// The sequence number of the next transaction to be committed.
+    sequence: u64,
+    /// The metadata blocks modified by the current transaction.
+    transaction: BTreeMap<u32, Vec<u8>>,
+    /// The block at which to start searching for a free block.
+    next_free_block: u32,
+    /// The inode at which to start searching for a free inode.
+    next_free_inode: u32,
+}
+
+impl Volume {
+    /// Opens the native filesystem on the given `device`, replaying its journal if necessary.
+    pub fn open(device: StorageDeviceRef) -> Result<Volume, &'static str> {
+        let (mut disk, device_blocks_per_block, device_total_blocks) = Self::disk(device)?;
+        let mut block = vec![0; BLOCK_SIZE];
+        disk.read_blocks(&mut block, 0)?;
+        let sb = Superblock::parse(&block)?;
+        if sb.total_blocks > device_total_blocks {
+            return Err("native filesystem is larger than its storage device");
+        }
+        let mut volume = Volume {
+            disk,
+            sb,
+            device_blocks_per_block,
+            sequence: 0,
+            transaction: BTreeMap::new(),
+            next_free_block: sb.data_start,
+            next_free_inode: ROOT_INODE + 1,
+        };
+        volume.replay_journal()?;
+        if volume.read_inode(ROOT_INODE)?.kind != InodeKind::Directory {
+            return Err("native filesystem root directory is corrupted");
+        }
+        debug!("Opened native filesystem: {:?}, sequence: {}", volume.sb, volume.sequence);
+        Ok(volume)
+    }
+
+    /// Creates a new, empty native filesystem on the given `device`,
+    /// overwriting any existing contents.
+    pub fn format(device: StorageDeviceRef, options: &FormatOptions) -> Result<(), &'static str> {
+        let (disk, device_blocks_per_block, device_total_blocks) = Self::disk(device)?;
+        let total_blocks = device_total_blocks;
+        if total_blocks < MIN_TOTAL_BLOCKS {
+            return Err("storage device is too small for a native filesystem");
+        }
+        let journal_blocks = options.journal_blocks.clamp(MIN_JOURNAL_BLOCKS, total_blocks / 8);
+        let bitmap_blocks = total_blocks.div_ceil(BITS_PER_BITMAP_BLOCK);
+        let inode_count = (total_blocks as usize * BLOCK_SIZE / options.bytes_per_inode.max(INODE_SIZE))
+            .max(INODES_PER_BLOCK);
+        let inode_table_blocks = inode_count.div_ceil(INODES_PER_BLOCK) as u32;
+        let sb = Superblock {
+            total_blocks,
+            journal_start: 1,
+            journal_blocks,
+            bitmap_start: 1 + journal_blocks,
+            bitmap_blocks,
+            inode_table_start: 1 + journal_blocks + bitmap_blocks,
+            inode_table_blocks,
+            data_start: 1 + journal_blocks + bitmap_blocks + inode_table_blocks,
+        };
+        if sb.data_start >= total_blocks {
+            return Err("storage device is too small for a native filesystem");
+        }
+
+        let mut volume = Volume {
+            disk,
+            sb,
+            device_blocks_per_block,
+            sequence: 1,
+            transaction: BTreeMap::new(),
+            next_free_block: sb.data_start,
+            next_free_inode: ROOT_INODE + 1,
+        };
+
+        // Zero the superblock and all metadata blocks, which leaves the journal empty and all inodes free.
+        let zeros = vec![0; FORMAT_CHUNK_BLOCKS as usize * BLOCK_SIZE];
+        let mut block_num = 0;
+        while block_num < sb.data_start {
+            let count = core::cmp::min(FORMAT_CHUNK_BLOCKS, sb.data_start - block_num);
+            volume.write_block(block_num, &zeros[.. count as usize * BLOCK_SIZE])?;
+            block_num += count;
+        }
+        volume.write_journal_header(1)?;
+
+        // Mark all blocks before the data region as allocated.
+        let mut bitmap = vec![0u8; BLOCK_SIZE];
+        for i in 0 .. sb.bitmap_blocks {
+            let first = i * BITS_PER_BITMAP_BLOCK;
+            if first >= sb.data_start {
+                break;
+            }
+            bitmap.fill(0);
+            for bit in 0 .. core::cmp::min(sb.data_start - first, BITS_PER_BITMAP_BLOCK) as usize {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            }
+            volume.write_block(sb.bitmap_start + i, &bitmap)?;
+        }
+
+        let mut inode_block = vec![0; BLOCK_SIZE];
+        let root_offset = (ROOT_INODE as usize % INODES_PER_BLOCK) * INODE_SIZE;
+        Inode::new(InodeKind::Directory, ROOT_INODE, now()).write(&mut inode_block[root_offset ..]);
+        volume.write_block(sb.inode_table_start + ROOT_INODE / INODES_PER_BLOCK as u32, &inode_block)?;
+        volume.flush()?;
+
+        // The superblock is written last, such that an interrupted format leaves no valid filesystem.
+        let mut block = vec![0; BLOCK_SIZE];
+        sb.write(&mut block);
+        volume.write_block(0, &block)?;
+        volume.flush()?;
+        info!("Formatted native filesystem: {:?}", sb);
+        Ok(())
+    }
+
+    /// Wraps the given `device` in a block cache, and returns it along with the number of
+    /// device blocks per filesystem block and the number of filesystem blocks that fit on the device.
+    fn disk(device: StorageDeviceRef) -> Result<(Disk, usize, u32), &'static str> {
+        let (device_block_size, device_blocks) = {
+            let locked_device = device.lock();
+            (locked_device.block_size(), locked_device.size_in_blocks())
+        };
+        if device_block_size == 0 || BLOCK_SIZE % device_block_size != 0 {
+            return Err("storage device block size is incompatible with a native filesystem");
+        }
+        let device_blocks_per_block = BLOCK_SIZE / device_block_size;
+        let total_blocks = core::cmp::min(device_blocks / device_blocks_per_block, u32::MAX as usize) as u32;
+        let disk = BlockCache::new(
+            LockableIo::<dyn StorageDevice + Send, Mutex<_>, _>::from(device),
+            CACHE_CAPACITY,
+            WritePolicy::WriteThrough,
+        );
+        Ok((disk, device_blocks_per_block, total_blocks))
+    }
+
+    /// Reads one or more contiguous blocks, starting at `block_num`, into the given `buffer`.
+    fn read_block(&mut self, block_num: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let count = buffer.len() / BLOCK_SIZE;
+        if block_num as usize + count > self.sb.total_blocks as usize {
+            return Err("native filesystem block number is out of bounds");
+        }
+        self.disk.read_blocks(buffer, block_num as usize * self.device_blocks_per_block)?;
+        Ok(())
+    }
+
+    /// Writes one or more contiguous blocks, starting at `block_num`, from the given `buffer`.
+    fn write_block(&mut self, block_num: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        let count = buffer.len() / BLOCK_SIZE;
+        if block_num as usize + count > self.sb.total_blocks as usize {
+            return Err("native filesystem block number is out of bounds");
+        }
+        self.disk.write_blocks(buffer, block_num as usize * self.device_blocks_per_block)?;
+        Ok(())
+    }
+
+    /// Flushes all writes to the underlying storage device.
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        BlockWriter::flush(&mut self.disk).map_err(Into::into)
+    }
+
+
+    // ----------------------------------- Journaling -----------------------------------
+
+    /// Runs the given closure `f` as a single transaction.
+    ///
+    /// If `f` succeeds, all of the metadata it modified is atomically committed;
+    /// otherwise, all of its metadata modifications are discarded.
+    pub fn transaction<R>(
+        &mut self,
+        f: impl FnOnce(&mut Volume) -> Result<R, &'static str>,
+    ) -> Result<R, &'static str> {
+        self.transaction.clear();
+        match f(self) {
+            Ok(result) => {
+                self.commit()?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.transaction.clear();
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes the current transaction to the journal and then to the blocks' home locations.
+    fn commit(&mut self) -> Result<(), &'static str> {
+        let blocks = self.log_transaction()?;
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        self.checkpoint(&blocks)
+    }
+
+    /// Writes the current transaction to the journal, after which it will survive a crash,
+    /// and returns the blocks it modified, which have yet to be written to their home locations.
+    pub(crate) fn log_transaction(&mut self) -> Result<BTreeMap<u32, Vec<u8>>, &'static str> {
+        let blocks = core::mem::take(&mut self.transaction);
+        if blocks.is_empty() {
+            return Ok(blocks);
+        }
+        if blocks.len() > self.sb.max_transaction_blocks() {
+            return Err("transaction is too large for the native filesystem's journal");
+        }
+
+        // The log consists of a descriptor block, the modified blocks, and a commit block.
+        let count = blocks.len();
+        let mut log = vec![0; (count + 2) * BLOCK_SIZE];
+        write_u32(&mut log, 0, JOURNAL_DESCRIPTOR_MAGIC);
+        write_u32(&mut log, 4, count as u32);
+        write_u64(&mut log, 8, self.sequence);
+        for (i, (block_num, data)) in blocks.iter().enumerate() {
+            write_u32(&mut log, DESCRIPTOR_TARGETS_OFFSET + i * 4, *block_num);
+            log[(i + 1) * BLOCK_SIZE .. (i + 2) * BLOCK_SIZE].copy_from_slice(data);
+        }
+        let commit_offset = (count + 1) * BLOCK_SIZE;
+        let crc = crc32fast::hash(&log[.. commit_offset]);
+        write_u32(&mut log, commit_offset, JOURNAL_COMMIT_MAGIC);
+        write_u64(&mut log, commit_offset + 8, self.sequence);
+        write_u32(&mut log, commit_offset + 16, crc);
+        self.write_block(self.sb.journal_start + 1, &log)?;
+        self.flush()?;
+        Ok(blocks)
+    }
+
+    /// Writes the given blocks of a transaction that is safely in the journal to their home locations,
+    /// and then retires that transaction.
+    fn checkpoint(&mut self, blocks: &BTreeMap<u32, Vec<u8>>) -> Result<(), &'static str> {
+        for (block_num, data) in blocks.iter() {
+            self.write_block(*block_num, data)?;
+        }
+        self.flush()?;
+        self.write_journal_header(self.sequence + 1)
+    }
+
+    /// Replays the transaction in the journal if it was fully committed but not yet retired.
+    fn replay_journal(&mut self) -> Result<(), &'static str> {
+        let journal_start = self.sb.journal_start;
+        let mut header = vec![0; BLOCK_SIZE];
+        self.read_block(journal_start, &mut header)?;
+        if read_u32(&header, 0) != JOURNAL_HEADER_MAGIC {
+            return Err("native filesystem journal header is corrupted");
+        }
+        self.sequence = read_u64(&header, 8);
+
+        let mut descriptor = vec![0; BLOCK_SIZE];
+        self.read_block(journal_start + 1, &mut descriptor)?;
+        if read_u32(&descriptor, 0) != JOURNAL_DESCRIPTOR_MAGIC || read_u64(&descriptor, 8) != self.sequence {
+            return Ok(());
+        }
+        let count = read_u32(&descriptor, 4) as usize;
+        if count == 0 || count > self.sb.max_transaction_blocks() {
+            return Err("native filesystem journal descriptor is corrupted");
+        }
+
+        let mut log = vec![0; (count + 2) * BLOCK_SIZE];
+        self.read_block(journal_start + 1, &mut log)?;
+        let commit_offset = (count + 1) * BLOCK_SIZE;
+        if read_u32(&log, commit_offset) != JOURNAL_COMMIT_MAGIC
+            || read_u64(&log, commit_offset + 8) != self.sequence
+            || read_u32(&log, commit_offset + 16) != crc32fast::hash(&log[.. commit_offset])
+        {
+            debug!("Discarding incomplete transaction {} in native filesystem journal", self.sequence);
+            return Ok(());
+        }
+
+        info!("Replaying transaction {} ({} blocks) from native filesystem journal", self.sequence, count);
+        for i in 0 .. count {
+            let block_num = read_u32(&log, DESCRIPTOR_TARGETS_OFFSET + i * 4);
+            if block_num < self.sb.bitmap_start {
+                return Err("native filesystem journal targets a reserved block");
+            }
+            self.write_block(block_num, &log[(i + 1) * BLOCK_SIZE .. (i + 2) * BLOCK_SIZE])?;
+        }
+        self.flush()?;
+        self.write_journal_header(self.sequence + 1)
+    }
+
+    /// Writes the journal header with the given `sequence` number,
+    /// which retires all transactions with lower sequence numbers.
+    fn write_journal_header(&mut self, sequence: u64) -> Result<(), &'static str> {
+        let mut header = vec![0; BLOCK_SIZE];
+        write_u32(&mut header, 0, JOURNAL_HEADER_MAGIC);
+        write_u64(&mut header, 8, sequence);
+        self.write_block(self.sb.journal_start, &header)?;
+        self.flush()?;
+        self.sequence = sequence;
+        Ok(())
+    }
+
+    /// Returns the contents of the given metadata block as seen by the current transaction.
+    fn read_meta(&mut self, block_num: u32) -> Result<Vec<u8>, &'static str> {
+        if let Some(data) = self.transaction.get(&block_num) {
+            return Ok(data.clone());
+        }
+        let mut data = vec![0; BLOCK_SIZE];
+        self.read_block(block_num, &mut data)?;
+        Ok(data)
+    }
+
+    /// Modifies the given metadata block within the current transaction.
+    fn modify_meta<R>(&mut self, block_num: u32, f: impl FnOnce(&mut [u8]) -> R) -> Result<R, &'static str> {
+        if let Some(data) = self.transaction.get_mut(&block_num) {
+            return Ok(f(data));
+        }
+        let mut data = self.read_meta(block_num)?;
+        let result = f(&mut data);
+        self.transaction.insert(block_num, data);
+        Ok(result)
+    }
+
+    /// Initializes the given newly-allocated metadata block to all zeros within the current transaction.
+    fn zero_meta(&mut self, block_num: u32) {
+        self.transaction.insert(block_num, vec![0; BLOCK_SIZE]);
+    }
+
+
+    // ------------------------------ Block and inode allocation ------------------------------
+
+    /// Allocates a free block.
+    ///
+    /// The block's contents are unspecified, so it must be fully initialized by the caller.
+    fn alloc_block(&mut self) -> Result<u32, &'static str> {
+        let (data_start, total_blocks) = (self.sb.data_start, self.sb.total_blocks);
+        let start = self.next_free_block.clamp(data_start, total_blocks);
+        let block_num = match self.find_free_block(start, total_blocks)? {
+            Some(b) => b,
+            None => self.find_free_block(data_start, start)?
+                .ok_or("no free blocks remain in the native filesystem")?,
+        };
+        self.set_block_allocated(block_num, true)?;
+        self.next_free_block = block_num + 1;
+        Ok(block_num)
+    }
+
+    /// Returns the first free block in the range `from..to`.
+    fn find_free_block(&mut self, from: u32, to: u32) -> Result<Option<u32>, &'static str> {
+        let mut block_num = from;
+        while block_num < to {
+            let bitmap = self.read_meta(self.sb.bitmap_start + block_num / BITS_PER_BITMAP_BLOCK)?;
+            let end = core::cmp::min(to, (block_num / BITS_PER_BITMAP_BLOCK + 1) * BITS_PER_BITMAP_BLOCK);
+            while block_num < end {
+                let bit = (block_num % BITS_PER_BITMAP_BLOCK) as usize;
+                if bitmap[bit / 8] == 0xFF {
+                    block_num = (block_num | 7) + 1;
+                } else if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+                    return Ok(Some(block_num));
+                } else {
+                    block_num += 1;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn set_block_allocated(&mut self, block_num: u32, allocated: bool) -> Result<(), &'static str> {
+        let bit = (block_num % BITS_PER_BITMAP_BLOCK) as usize;
+        self.modify_meta(self.sb.bitmap_start + block_num / BITS_PER_BITMAP_BLOCK, |bitmap| {
+            if allocated {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+            } else {
+                bitmap[bit / 8] &= !(1 << (bit % 8));
+            }
+        })
+    }
+
+    /// Frees the given block.
+    fn free_block(&mut self, block_num: u32) -> Result<(), &'static str> {
+        if block_num < self.sb.data_start || block_num >= self.sb.total_blocks {
+            return Err("native filesystem has a corrupted block pointer");
+        }
+        // Any pending changes to a freed metadata block must not overwrite
+        // the block after it has been reallocated, e.g., to hold file contents.
+        self.transaction.remove(&block_num);
+        self.set_block_allocated(block_num, false)
+    }
+
+    /// Allocates a free inode and initializes it as an empty node of the given `kind`.
+    fn alloc_inode(&mut self, kind: InodeKind, parent: u32) -> Result<u32, &'static str> {
+        let table_blocks = self.sb.inode_table_blocks;
+        let start_block = (self.next_free_inode / INODES_PER_BLOCK as u32) % table_blocks;
+        for i in 0 .. table_blocks {
+            let table_block = (start_block + i) % table_blocks;
+            let data = self.read_meta(self.sb.inode_table_start + table_block)?;
+            for slot in 0 .. INODES_PER_BLOCK {
+                let inode_num = table_block * INODES_PER_BLOCK as u32 + slot as u32;
+                if inode_num != NULL_INODE && read_u32(&data, slot * INODE_SIZE) == InodeKind::Free as u32 {
+                    self.write_inode(inode_num, &Inode::new(kind, parent, now()))?;
+                    self.next_free_inode = inode_num + 1;
+                    return Ok(inode_num);
+                }
+            }
+        }
+        Err("no free inodes remain in the native filesystem")
+    }
+
+    /// Returns the inode table block and the offset within it of the given inode.
+    fn inode_location(&self, inode_num: u32) -> Result<(u32, usize), &'static str> {
+        if inode_num == NULL_INODE || inode_num >= self.sb.inode_count() {
+            return Err("invalid inode number");
+        }
+        Ok((
+            self.sb.inode_table_start + inode_num / INODES_PER_BLOCK as u32,
+            (inode_num as usize % INODES_PER_BLOCK) * INODE_SIZE,
+        ))
+    }
+
+    pub fn read_inode(&mut self, inode_num: u32) -> Result<Inode, &'static str> {
+        let (block_num, offset) = self.inode_location(inode_num)?;
+        let data = self.read_meta(block_num)?;
+        Inode::parse(&data[offset .. offset + INODE_SIZE])
+    }
+
+    fn write_inode(&mut self, inode_num: u32, inode: &Inode) -> Result<(), &'static str> {
+        let (block_num, offset) = self.inode_location(inode_num)?;
+        self.modify_meta(block_num, |data| inode.write(&mut data[offset ..]))
+    }
+
+
+    // ---------------------------------- Content blocks ----------------------------------
+
+    /// Returns the block that holds the content block at `index` within the given `inode`.
+    ///
+    /// If that content block doesn't exist and `allocate` is `true`, it is allocated
+    /// (along with any necessary indirect blocks), but its contents are not initialized.
+    fn content_block(&mut self, inode: &mut Inode, index: usize, allocate: bool) -> Result<Option<u32>, &'static str> {
+        if index < DIRECT_POINTERS {
+            if inode.direct[index] == NULL_BLOCK && allocate {
+                inode.direct[index] = self.alloc_block()?;
+            }
+            return Ok(Some(inode.direct[index]).filter(|&b| b != NULL_BLOCK));
+        }
+        let index = index - DIRECT_POINTERS;
+        if index < POINTERS_PER_BLOCK {
+            let Some(indirect) = self.pointer_block(&mut inode.indirect, allocate)? else { return Ok(None) };
+            return self.pointer_entry(indirect, index, allocate, false);
+        }
+        let index = index - POINTERS_PER_BLOCK;
+        if index >= POINTERS_PER_BLOCK * POINTERS_PER_BLOCK {
+            return Err("file is too large for a native filesystem");
+        }
+        let Some(double_indirect) = self.pointer_block(&mut inode.double_indirect, allocate)? else { return Ok(None) };
+        let Some(indirect) = self.pointer_entry(double_indirect, index / POINTERS_PER_BLOCK, allocate, true)? else {
+            return Ok(None);
+        };
+        self.pointer_entry(indirect, index % POINTERS_PER_BLOCK, allocate, false)
+    }
+
+    /// Returns the indirect block referenced by the given inode `pointer`,
+    /// allocating an empty one if it doesn't exist and `allocate` is `true`.
+    fn pointer_block(&mut self, pointer: &mut u32, allocate: bool) -> Result<Option<u32>, &'static str> {
+        if *pointer == NULL_BLOCK {
+            if !allocate {
+                return Ok(None);
+            }
+            *pointer = self.alloc_block()?;
+            self.zero_meta(*pointer);
+        }
+        Ok(Some(*pointer))
+    }
+
+    /// Returns the block referenced by the pointer at `index` within the indirect block `block_num`,
+    /// allocating it if it doesn't exist and `allocate` is `true`.
+    /// A newly-allocated block is zeroed if it will hold further pointers, i.e., `is_indirect` is `true`.
+    fn pointer_entry(&mut self, block_num: u32, index: usize, allocate: bool, is_indirect: bool) -> Result<Option<u32>, &'static str> {
+        let pointer = read_u32(&self.read_meta(block_num)?, index * 4);
+        if pointer != NULL_BLOCK {
+            return Ok(Some(pointer));
+        }
+        if !allocate {
+            return Ok(None);
+        }
+        let new_block = self.alloc_block()?;
+        if is_indirect {
+            self.zero_meta(new_block);
+        }
+        self.modify_meta(block_num, |data| write_u32(data, index * 4, new_block))?;
+        Ok(Some(new_block))
+    }
+
+    /// Frees all of the content blocks of the given `inode`, along with its indirect blocks.
+    fn free_content(&mut self, inode: &mut Inode) -> Result<(), &'static str> {
+        for pointer in inode.direct.iter_mut() {
+            if *pointer != NULL_BLOCK {
+                self.free_block(*pointer)?;
+                *pointer = NULL_BLOCK;
+            }
+        }
+        if inode.indirect != NULL_BLOCK {
+            self.free_pointer_block(inode.indirect, 1)?;
+            inode.indirect = NULL_BLOCK;
+        }
+        if inode.double_indirect != NULL_BLOCK {
+            self.free_pointer_block(inode.double_indirect, 2)?;
+            inode.double_indirect = NULL_BLOCK;
+        }
+        inode.size = 0;
+        Ok(())
+    }
+
+    /// Frees the given indirect block and all blocks referenced by it, up to the given `depth`.
+    fn free_pointer_block(&mut self, block_num: u32, depth: usize) -> Result<(), &'static str> {
+        let data = self.read_meta(block_num)?;
+        for i in 0 .. POINTERS_PER_BLOCK {
+            let pointer = read_u32(&data, i * 4);
+            if pointer == NULL_BLOCK {
+                continue;
+            }
+            if depth > 1 {
+                self.free_pointer_block(pointer, depth - 1)?;
+            } else {
+                self.free_block(pointer)?;
+            }
+        }
+        self.free_block(block_num)
+    }
+
+
+    // ------------------------------------ Directories ------------------------------------
+
+    /// Returns all non-empty entries in the given directory, along with their slot indices.
+    pub fn dir_entries(&mut self, dir: u32) -> Result<Vec<(usize, DirEntry)>, &'static str> {
+        let mut inode = self.read_inode(dir)?;
+        if inode.kind != InodeKind::Directory {
+            return Err("not a directory");
+        }
+        let slots = inode.size as usize / DIR_ENTRY_SIZE;
+        let mut entries = Vec::new();
+        for block_index in 0 .. slots.div_ceil(DIR_ENTRIES_PER_BLOCK) {
+            let Some(block_num) = self.content_block(&mut inode, block_index, false)? else { continue };
+            let data = self.read_meta(block_num)?;
+            for i in 0 .. DIR_ENTRIES_PER_BLOCK {
+                let slot = block_index * DIR_ENTRIES_PER_BLOCK + i;
+                if slot >= slots {
+                    break;
+                }
+                let entry = DirEntry::parse(&data[i * DIR_ENTRY_SIZE .. (i + 1) * DIR_ENTRY_SIZE]);
+                if entry.inode != NULL_INODE {
+                    entries.push((slot, entry));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the entry called `name` in the given directory, along with its slot index.
+    pub fn lookup(&mut self, dir: u32, name: &str) -> Result<Option<(usize, DirEntry)>, &'static str> {
+        Ok(self.dir_entries(dir)?.into_iter().find(|(_, entry)| entry.name == name))
+    }
+
+    /// Adds an entry called `name` for the given inode to the given directory.
+    fn dir_add(&mut self, dir: u32, name: &str, inode_num: u32, kind: InodeKind) -> Result<(), &'static str> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN || name.contains('/') || name == "." || name == ".." {
+            return Err("invalid name for a node in a native filesystem");
+        }
+        let slots = self.read_inode(dir)?.size as usize / DIR_ENTRY_SIZE;
+        let used_slots: Vec<usize> = self.dir_entries(dir)?.into_iter().map(|(slot, _)| slot).collect();
+        let slot = (0 .. slots).find(|s| used_slots.binary_search(s).is_err()).unwrap_or(slots);
+
+        let mut inode = self.read_inode(dir)?;
+        let block_index = slot / DIR_ENTRIES_PER_BLOCK;
+        let block_num = self.content_block(&mut inode, block_index, true)?
+            .ok_or("failed to allocate a directory block")?;
+        if slot == slots {
+            if slot % DIR_ENTRIES_PER_BLOCK == 0 {
+                self.zero_meta(block_num);
+            }
+            inode.size += DIR_ENTRY_SIZE as u64;
+        }
+        let entry = DirEntry { inode: inode_num, kind, name: String::from(name) };
+        let offset = (slot % DIR_ENTRIES_PER_BLOCK) * DIR_ENTRY_SIZE;
+        self.modify_meta(block_num, |data| entry.write(&mut data[offset ..]))?;
+        inode.modified = now().as_secs();
+        self.write_inode(dir, &inode)
+    }
+
+    /// Clears the entry at the given `slot` in the given directory.
+    fn dir_remove(&mut self, dir: u32, slot: usize) -> Result<(), &'static str> {
+        let mut inode = self.read_inode(dir)?;
+        let block_num = self.content_block(&mut inode, slot / DIR_ENTRIES_PER_BLOCK, false)?
+            .ok_or("native filesystem directory is corrupted")?;
+        let offset = (slot % DIR_ENTRIES_PER_BLOCK) * DIR_ENTRY_SIZE;
+        self.modify_meta(block_num, |data| data[offset .. offset + DIR_ENTRY_SIZE].fill(0))?;
+        inode.modified = now().as_secs();
+        self.write_inode(dir, &inode)
+    }
+
+    /// Creates a new empty node of the given `kind` called `name` in the given directory,
+    /// and returns its inode number.
+    pub fn create(&mut self, dir: u32, name: &str, kind: InodeKind) -> Result<u32, &'static str> {
+        if self.lookup(dir, name)?.is_some() {
+            return Err("a node with that name already exists");
+        }
+        let inode_num = self.alloc_inode(kind, dir)?;
+        self.dir_add(dir, name, inode_num, kind)?;
+        Ok(inode_num)
+    }
+
+    /// Removes the file or empty directory called `name` from the given directory
+    /// and frees its inode and contents.
+    pub fn unlink(&mut self, dir: u32, name: &str) -> Result<(), &'static str> {
+        let (slot, entry) = self.lookup(dir, name)?.ok_or("no such file or directory")?;
+        let mut inode = self.read_inode(entry.inode)?;
+        if inode.kind == InodeKind::Directory && !self.dir_entries(entry.inode)?.is_empty() {
+            return Err("directory is not empty");
+        }
+        self.free_content(&mut inode)?;
+        self.write_inode(entry.inode, &Inode::new(InodeKind::Free, NULL_INODE, Duration::ZERO))?;
+        self.next_free_inode = core::cmp::min(self.next_free_inode, entry.inode);
+        self.dir_remove(dir, slot)
+    }
+
+    /// Removes the node called `name` from the given directory, including all of its contents.
+    ///
+    /// Each descendant of a directory is removed in its own transaction,
+    /// so this must not be called within a transaction.
+    pub fn remove_tree(&mut self, dir: u32, name: &str) -> Result<(), &'static str> {
+        let (_, entry) = self.lookup(dir, name)?.ok_or("no such file or directory")?;
+        if entry.kind == InodeKind::Directory {
+            for (_, child) in self.dir_entries(entry.inode)? {
+                self.remove_tree(entry.inode, &child.name)?;
+            }
+        }
+        self.transaction(|volume| volume.unlink(dir, name))
+    }
+
+    /// Moves the node called `name` from the directory `src_dir` into the directory `dest_dir`,
+    /// where it will be called `new_name`.
+    ///
+    /// An existing file called `new_name` is replaced, but an existing directory is not.
+    pub fn rename(&mut self, src_dir: u32, name: &str, dest_dir: u32, new_name: &str) -> Result<(), &'static str> {
+        let (slot, entry) = self.lookup(src_dir, name)?.ok_or("no such file or directory")?;
+        if src_dir == dest_dir && name == new_name {
+            return Ok(());
+        }
+        if entry.kind == InodeKind::Directory {
+            // Ensure the destination isn't the moved directory or one of its descendants.
+            let mut ancestor = dest_dir;
+            for _ in 0 .. self.sb.inode_count() {
+                if ancestor == entry.inode {
+                    return Err("cannot move a directory into itself");
+                }
+                if ancestor == ROOT_INODE {
+                    break;
+                }
+                ancestor = self.read_inode(ancestor)?.parent;
+            }
+        }
+        if let Some((_, existing)) = self.lookup(dest_dir, new_name)? {
+            match (entry.kind, existing.kind) {
+                (_, InodeKind::Directory) => return Err("the destination is an existing directory"),
+                (InodeKind::Directory, _) => return Err("cannot replace a file with a directory"),
+                _ => self.unlink(dest_dir, new_name)?,
+            }
+        }
+        self.dir_remove(src_dir, slot)?;
+        self.dir_add(dest_dir, new_name, entry.inode, entry.kind)?;
+        if entry.kind == InodeKind::Directory {
+            let mut inode = self.read_inode(entry.inode)?;
+            inode.parent = dest_dir;
+            self.write_inode(entry.inode, &inode)?;
+        }
+        Ok(())
+    }
+
+
+    // ---------------------------------------- Files ----------------------------------------
+
+    /// Reads the contents of the given file starting at `offset` into `buffer`.
+    pub fn read_file(&mut self, file: u32, buffer: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let mut inode = self.read_inode(file)?;
+        if inode.kind != InodeKind::File {
+            return Err(IoError::Other("not a file"));
+        }
+        let size = inode.size as usize;
+        if offset >= size {
+            return Err(IoError::InvalidInput);
+        }
+        let len = core::cmp::min(buffer.len(), size - offset);
+        let mut block = vec![0; BLOCK_SIZE];
+        let mut done = 0;
+        while done < len {
+            let position = offset + done;
+            let within = position % BLOCK_SIZE;
+            let count = core::cmp::min(BLOCK_SIZE - within, len - done);
+            match self.content_block(&mut inode, position / BLOCK_SIZE, false)? {
+                Some(block_num) => {
+                    self.read_block(block_num, &mut block)?;
+                    buffer[done .. done + count].copy_from_slice(&block[within .. within + count]);
+                }
+                // A sparse region of the file.
+                None => buffer[done .. done + count].fill(0),
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    /// Writes `buffer` into the given file starting at `offset`, extending the file if necessary.
+    ///
+    /// This must be called within a transaction.
+    pub fn write_file(&mut self, file: u32, buffer: &[u8], offset: usize) -> Result<usize, &'static str> {
+        let mut inode = self.read_inode(file)?;
+        if inode.kind != InodeKind::File {
+            return Err("not a file");
+        }
+        let mut block = vec![0; BLOCK_SIZE];
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done;
+            let within = position % BLOCK_SIZE;
+            let count = core::cmp::min(BLOCK_SIZE - within, buffer.len() - done);
+            let index = position / BLOCK_SIZE;
+            let block_num = match self.content_block(&mut inode, index, false)? {
+                Some(block_num) => {
+                    if count < BLOCK_SIZE {
+                        self.read_block(block_num, &mut block)?;
+                    }
+                    block_num
+                }
+                None => {
+                    block.fill(0);
+                    self.content_block(&mut inode, index, true)?.ok_or("failed to allocate a file block")?
+                }
+            };
+            block[within .. within + count].copy_from_slice(&buffer[done .. done + count]);
+            self.write_block(block_num, &block)?;
+            done += count;
+        }
+        inode.size = core::cmp::max(inode.size, (offset + buffer.len()) as u64);
+        inode.modified = now().as_secs();
+        self.write_inode(file, &inode)?;
+        Ok(buffer.len())
+    }
+}
+
+fn now() -> Duration {
+    time::now::<WallTime>()
+}
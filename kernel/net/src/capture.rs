@@ -0,0 +1,255 @@
+//! Capturing the frames sent and received by a network interface, e.g., to debug the network stack.
+//!
+//! A capture is started using [`NetworkInterface::start_capture()`], after which a copy of
+//! every frame that passes through the interface's device and matches the capture's [`Filter`]
+//! is queued until it is taken using [`Capture::recv()`].
+//! The capture stops when its [`Capture`] handle is dropped.
+//!
+//! Captured frames can be saved in the pcap file format, which tools like Wireshark can open,
+//! by writing [`pcap_file_header()`] followed by each frame's [`CapturedFrame::pcap_record()`].
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{task::Waker, time::Duration};
+
+use smoltcp::phy::Medium;
+use sync_irq::IrqSafeMutex;
+use time::WallTime;
+
+use crate::NetworkInterface;
+
+mod filter;
+
+pub use filter::{Filter, PacketInfo};
+
+/// The maximum number of captured frames that are queued before further frames are dropped.
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The link-layer header type of captured frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkType {
+    /// Frames start with an Ethernet header.
+    Ethernet,
+    /// Frames are raw IPv4 or IPv6 packets without a link-layer header, e.g., on loopback.
+    Ip,
+}
+
+impl LinkType {
+    /// Returns the `LINKTYPE_*` value that identifies this link type in pcap files.
+    pub fn pcap_link_type(self) -> u32 {
+        match self {
+            LinkType::Ethernet => 1,
+            LinkType::Ip => 101,
+        }
+    }
+
+    pub(crate) fn from_medium(medium: Medium) -> LinkType {
+        match medium {
+            Medium::Ip => LinkType::Ip,
+            _ => LinkType::Ethernet,
+        }
+    }
+}
+
+/// Whether a frame was received or sent by the interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+/// A copy of a frame that passed through a captured interface.
+#[derive(Clone, Debug)]
+pub struct CapturedFrame {
+    /// When the frame was captured, as the time since the Unix epoch.
+    pub timestamp: Duration,
+    pub direction: Direction,
+    /// The length of the frame, which may be longer than the captured `data`.
+    pub original_len: usize,
+    /// The frame's contents, truncated to the capture's snapshot length.
+    pub data: Vec<u8>,
+}
+
+impl CapturedFrame {
+    /// Returns this frame encoded as a pcap record, with its header in little-endian byte order.
+    pub fn pcap_record(&self) -> Vec<u8> {
+        let mut record = Vec::with_capacity(16 + self.data.len());
+        record.extend_from_slice(&(self.timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&self.timestamp.subsec_micros().to_le_bytes());
+        record.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(self.original_len as u32).to_le_bytes());
+        record.extend_from_slice(&self.data);
+        record
+    }
+}
+
+/// Returns the header of a pcap file holding frames of the given `link_type`,
+/// each truncated to at most `snap_len` bytes.
+///
+/// The header uses little-endian byte order and microsecond timestamps.
+pub fn pcap_file_header(link_type: LinkType, snap_len: usize) -> [u8; 24] {
+    let mut header = [0; 24];
+    header[0..4].copy_from_slice(&0xA1B2_C3D4_u32.to_le_bytes());
+    // Version 2.4, followed by the unused time zone offset and timestamp accuracy.
+    header[4..6].copy_from_slice(&2_u16.to_le_bytes());
+    header[6..8].copy_from_slice(&4_u16.to_le_bytes());
+    header[16..20].copy_from_slice(&(snap_len as u32).to_le_bytes());
+    header[20..24].copy_from_slice(&link_type.pcap_link_type().to_le_bytes());
+    header
+}
+
+/// The state of an ongoing capture, shared by the interface and the [`Capture`] handle.
+pub(crate) struct CaptureState {
+    link_type: LinkType,
+    filter: Option<Filter>,
+    snap_len: usize,
+    // Frames are captured while the device is locked, i.e., with interrupts disabled,
+    // so the queue's holder must not be preempted.
+    queue: IrqSafeMutex<CaptureQueue>,
+}
+
+struct CaptureQueue {
+    frames: VecDeque<CapturedFrame>,
+    /// The number of matching frames that were dropped because the queue was full.
+    dropped: usize,
+    /// Woken when a frame is queued.
+    waker: Option<Waker>,
+}
+
+impl CaptureState {
+    /// Queues a copy of the given `frame` if it matches the capture's filter.
+    pub(crate) fn capture(&self, direction: Direction, frame: &[u8]) {
+        if let Some(ref filter) = self.filter {
+            if !filter.matches(&PacketInfo::parse(self.link_type, frame)) {
+                return;
+            }
+        }
+        let mut queue = self.queue.lock();
+        if queue.frames.len() >= QUEUE_CAPACITY {
+            queue.dropped += 1;
+            return;
+        }
+        queue.frames.push_back(CapturedFrame {
+            timestamp: time::now::<WallTime>(),
+            direction,
+            original_len: frame.len(),
+            data: frame[..frame.len().min(self.snap_len)].to_vec(),
+        });
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A handle to an ongoing capture of a network interface's frames.
+///
+/// The capture stops when this is dropped.
+pub struct Capture {
+    state: Arc<CaptureState>,
+    interface: Arc<NetworkInterface>,
+}
+
+impl Capture {
+    pub(crate) fn new(
+        interface: Arc<NetworkInterface>,
+        link_type: LinkType,
+        filter: Option<Filter>,
+        snap_len: usize,
+    ) -> (Capture, Arc<CaptureState>) {
+        let state = Arc::new(CaptureState {
+            link_type,
+            filter,
+            snap_len,
+            queue: IrqSafeMutex::new(CaptureQueue {
+                frames: VecDeque::new(),
+                dropped: 0,
+                waker: None,
+            }),
+        });
+        (Capture { state: state.clone(), interface }, state)
+    }
+
+    /// Returns the interface being captured.
+    pub fn interface(&self) -> &Arc<NetworkInterface> {
+        &self.interface
+    }
+
+    /// Returns the link-layer header type of the captured frames.
+    pub fn link_type(&self) -> LinkType {
+        self.state.link_type
+    }
+
+    /// Returns the maximum number of bytes captured from each frame.
+    pub fn snap_len(&self) -> usize {
+        self.state.snap_len
+    }
+
+    /// Returns the number of matching frames that were dropped because
+    /// they weren't received from this capture quickly enough.
+    pub fn dropped(&self) -> usize {
+        self.state.queue.lock().dropped
+    }
+
+    /// Takes the next captured frame, if one has been captured.
+    pub fn try_recv(&self) -> Option<CapturedFrame> {
+        self.state.queue.lock().frames.pop_front()
+    }
+
+    /// Takes the next captured frame, blocking until one is captured.
+    pub fn recv(&self) -> CapturedFrame {
+        let (waker, blocker) = waker::new_waker();
+        loop {
+            {
+                let mut queue = self.state.queue.lock();
+                if let Some(frame) = queue.frames.pop_front() {
+                    return frame;
+                }
+                queue.waker = Some(waker.clone());
+            }
+            blocker.block();
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.interface.stop_capture(&self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_pcap_file_header() {
+        let header = pcap_file_header(LinkType::Ethernet, 65535);
+        assert_eq!(header, [
+            0xD4, 0xC3, 0xB2, 0xA1, // magic number
+            2, 0, 4, 0, // version 2.4
+            0, 0, 0, 0, // time zone offset
+            0, 0, 0, 0, // timestamp accuracy
+            0xFF, 0xFF, 0, 0, // snapshot length
+            1, 0, 0, 0, // LINKTYPE_ETHERNET
+        ]);
+        let header = pcap_file_header(LinkType::Ip, 128);
+        assert_eq!(header[16..], [128, 0, 0, 0, 101, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_pcap_record() {
+        let frame = CapturedFrame {
+            timestamp: Duration::new(0x0102_0304, 5_006_999),
+            direction: Direction::Sent,
+            original_len: 1500,
+            data: vec![0xAA, 0xBB, 0xCC],
+        };
+        assert_eq!(frame.pcap_record(), [
+            0x04, 0x03, 0x02, 0x01, // seconds
+            0x8E, 0x13, 0, 0, // microseconds, rounded down
+            3, 0, 0, 0, // captured length
+            0xDC, 0x05, 0, 0, // original length
+            0xAA, 0xBB, 0xCC,
+        ]);
+    }
+}
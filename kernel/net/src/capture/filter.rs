@@ -0,0 +1,497 @@
+//! Filters that select which frames are captured, using a subset of tcpdump's syntax.
+//!
+//! A filter is made of the following primitives:
+//! * `arp`, `ip`, `ip6`, `tcp`, `udp`, `icmp`, `icmp6`: frames with the given protocol.
+//! * `[src|dst] host ADDR`: packets from and/or to the given IP address.
+//! * `[src|dst] port PORT`: TCP or UDP packets from and/or to the given port.
+//!
+//! Primitives can be combined using `and` (`&&`), `or` (`||`), `not` (`!`), and parentheses,
+//! e.g., `tcp and (port 80 or port 443) and not host 10.0.2.2`.
+//! `not` binds tighter than `and`, which binds tighter than `or`.
+//! Up to 32 `not`s and parentheses can be nested within each other.
+
+use alloc::{boxed::Box, vec::Vec};
+use core::str::FromStr;
+
+use smoltcp::wire::{Ipv4Address, Ipv6Address};
+
+use super::LinkType;
+use crate::IpAddress;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const IP_PROTOCOL_ICMP: u8 = 1;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+const IP_PROTOCOL_ICMPV6: u8 = 58;
+
+/// The maximum depth of `not`s and parentheses nested within each other,
+/// which bounds the recursion of parsing a filter and of matching frames against it.
+const MAX_NESTING: usize = 32;
+
+/// A protocol that a filter can match on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Arp,
+    Ipv4,
+    Ipv6,
+    Tcp,
+    Udp,
+    Icmp,
+    Icmpv6,
+}
+
+/// Which of a packet's addresses or ports a filter matches on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Source,
+    Destination,
+    /// Either the source or the destination.
+    Any,
+}
+
+/// A filter that selects which frames are captured.
+///
+/// Filters are usually parsed from a string; see the [module-level docs](self).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Protocol(Protocol),
+    Host(Endpoint, IpAddress),
+    Port(Endpoint, u16),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    /// Returns whether the packet described by `info` matches this filter.
+    pub fn matches(&self, info: &PacketInfo) -> bool {
+        match self {
+            Filter::Protocol(protocol) => info.has_protocol(*protocol),
+            Filter::Host(endpoint, address) => {
+                endpoint_matches(*endpoint, info.src_addr, info.dst_addr, *address)
+            }
+            Filter::Port(endpoint, port) => {
+                endpoint_matches(*endpoint, info.src_port, info.dst_port, *port)
+            }
+            Filter::Not(filter) => !filter.matches(info),
+            Filter::And(left, right) => left.matches(info) && right.matches(info),
+            Filter::Or(left, right) => left.matches(info) || right.matches(info),
+        }
+    }
+}
+
+fn endpoint_matches<T: PartialEq>(endpoint: Endpoint, src: Option<T>, dst: Option<T>, value: T) -> bool {
+    let src_matches = src.as_ref() == Some(&value);
+    let dst_matches = dst.as_ref() == Some(&value);
+    match endpoint {
+        Endpoint::Source => src_matches,
+        Endpoint::Destination => dst_matches,
+        Endpoint::Any => src_matches || dst_matches,
+    }
+}
+
+impl FromStr for Filter {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s);
+        let mut parser = Parser { tokens: &tokens, position: 0, depth: 0 };
+        let filter = parser.parse_or()?;
+        if parser.position != tokens.len() {
+            return Err("unexpected token in filter");
+        }
+        Ok(filter)
+    }
+}
+
+/// Splits a filter into words and the punctuation tokens `(`, `)`, and `!`.
+fn tokenize(s: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    for word in s.split_whitespace() {
+        let mut rest = word;
+        while !rest.is_empty() {
+            if let Some(stripped) = rest.strip_prefix(['(', ')']) {
+                tokens.push(&rest[..1]);
+                rest = stripped;
+            } else if rest.starts_with('!') && !rest.starts_with("!=") {
+                tokens.push(&rest[..1]);
+                rest = &rest[1..];
+            } else {
+                let end = rest.find(['(', ')']).unwrap_or(rest.len());
+                tokens.push(&rest[..end]);
+                rest = &rest[end..];
+            }
+        }
+    }
+    tokens
+}
+
+struct Parser<'t> {
+    tokens: &'t [&'t str],
+    position: usize,
+    /// How many `not`s and parentheses enclose the current position.
+    depth: usize,
+}
+
+impl<'t> Parser<'t> {
+    fn peek(&self) -> Option<&'t str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<&'t str> {
+        let token = self.peek();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, &'static str> {
+        let mut filter = self.parse_and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.next();
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, &'static str> {
+        let mut filter = self.parse_unary()?;
+        while matches!(self.peek(), Some("and" | "&&")) {
+            self.next();
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter, &'static str> {
+        match self.next().ok_or("filter ended unexpectedly")? {
+            "not" | "!" => self.nested(Self::parse_unary).map(|filter| Filter::Not(Box::new(filter))),
+            "(" => {
+                let filter = self.nested(Self::parse_or)?;
+                match self.next() {
+                    Some(")") => Ok(filter),
+                    _ => Err("missing closing parenthesis in filter"),
+                }
+            }
+            "src" => self.parse_qualified(Endpoint::Source),
+            "dst" => self.parse_qualified(Endpoint::Destination),
+            "host" | "port" => {
+                self.position -= 1;
+                self.parse_qualified(Endpoint::Any)
+            }
+            "arp" => Ok(Filter::Protocol(Protocol::Arp)),
+            "ip" => Ok(Filter::Protocol(Protocol::Ipv4)),
+            "ip6" => Ok(Filter::Protocol(Protocol::Ipv6)),
+            "tcp" => Ok(Filter::Protocol(Protocol::Tcp)),
+            "udp" => Ok(Filter::Protocol(Protocol::Udp)),
+            "icmp" => Ok(Filter::Protocol(Protocol::Icmp)),
+            "icmp6" => Ok(Filter::Protocol(Protocol::Icmpv6)),
+            _ => Err("unknown primitive in filter"),
+        }
+    }
+
+    /// Parses the operand of a `not` or the contents of parentheses using `parse`,
+    /// failing if they are nested too deeply.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Filter, &'static str>,
+    ) -> Result<Filter, &'static str> {
+        if self.depth >= MAX_NESTING {
+            return Err("filter is nested too deeply");
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses a `host ADDR` or `port PORT` primitive that applies to the given `endpoint`.
+    fn parse_qualified(&mut self, endpoint: Endpoint) -> Result<Filter, &'static str> {
+        let kind = self.next();
+        let value = self.next().ok_or("filter ended unexpectedly")?;
+        match kind {
+            Some("host") => IpAddress::from_str(value)
+                .map(|address| Filter::Host(endpoint, address))
+                .map_err(|_| "invalid IP address in filter"),
+            Some("port") => value
+                .parse()
+                .map(|port| Filter::Port(endpoint, port))
+                .map_err(|_| "invalid port in filter"),
+            _ => Err("expected `host` or `port` in filter"),
+        }
+    }
+}
+
+/// The protocol headers of a frame that filters match on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PacketInfo {
+    /// The frame's EtherType, or the one implied by the IP version for frames
+    /// without a link-layer header.
+    pub ethertype: Option<u16>,
+    pub src_addr: Option<IpAddress>,
+    pub dst_addr: Option<IpAddress>,
+    /// The protocol carried by the IP packet.
+    pub ip_protocol: Option<u8>,
+    /// The length of the IP packet's payload.
+    pub ip_payload_len: Option<usize>,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+}
+
+impl PacketInfo {
+    /// Parses the headers of a frame with the given `link_type`.
+    ///
+    /// Headers that are missing or truncated are left as `None`.
+    pub fn parse(link_type: LinkType, frame: &[u8]) -> PacketInfo {
+        let mut info = PacketInfo::default();
+        let packet = match link_type {
+            LinkType::Ethernet => {
+                info.ethertype = read_u16(frame, 12);
+                frame.get(ETHERNET_HEADER_LEN..).unwrap_or_default()
+            }
+            LinkType::Ip => {
+                info.ethertype = match frame.first().map(|byte| byte >> 4) {
+                    Some(4) => Some(ETHERTYPE_IPV4),
+                    Some(6) => Some(ETHERTYPE_IPV6),
+                    _ => None,
+                };
+                frame
+            }
+        };
+
+        let payload = match info.ethertype {
+            Some(ETHERTYPE_IPV4) if packet.len() >= 20 => {
+                let header_len = usize::from(packet[0] & 0xF) * 4;
+                let fragment_offset = read_u16(packet, 6).unwrap_or_default() & 0x1FFF;
+                info.src_addr = Some(IpAddress::Ipv4(Ipv4Address::from_bytes(&packet[12..16])));
+                info.dst_addr = Some(IpAddress::Ipv4(Ipv4Address::from_bytes(&packet[16..20])));
+                info.ip_protocol = Some(packet[9]);
+                let total_len = usize::from(read_u16(packet, 2).unwrap_or_default());
+                info.ip_payload_len = total_len.checked_sub(header_len);
+                // Only the first fragment holds the transport header.
+                if fragment_offset == 0 { packet.get(header_len..) } else { None }
+            }
+            Some(ETHERTYPE_IPV6) if packet.len() >= 40 => {
+                info.src_addr = Some(IpAddress::Ipv6(Ipv6Address::from_bytes(&packet[8..24])));
+                info.dst_addr = Some(IpAddress::Ipv6(Ipv6Address::from_bytes(&packet[24..40])));
+                info.ip_protocol = Some(packet[6]);
+                info.ip_payload_len = read_u16(packet, 4).map(usize::from);
+                packet.get(40..)
+            }
+            _ => None,
+        };
+
+        if let (Some(IP_PROTOCOL_TCP | IP_PROTOCOL_UDP), Some(payload)) = (info.ip_protocol, payload) {
+            info.src_port = read_u16(payload, 0);
+            info.dst_port = read_u16(payload, 2);
+        }
+        info
+    }
+
+    /// Returns whether the frame contains a header of the given `protocol`.
+    pub fn has_protocol(&self, protocol: Protocol) -> bool {
+        match protocol {
+            Protocol::Arp => self.ethertype == Some(ETHERTYPE_ARP),
+            Protocol::Ipv4 => self.ethertype == Some(ETHERTYPE_IPV4),
+            Protocol::Ipv6 => self.ethertype == Some(ETHERTYPE_IPV6),
+            Protocol::Tcp => self.ip_protocol == Some(IP_PROTOCOL_TCP),
+            Protocol::Udp => self.ip_protocol == Some(IP_PROTOCOL_UDP),
+            Protocol::Icmp => self.ip_protocol == Some(IP_PROTOCOL_ICMP),
+            Protocol::Icmpv6 => self.ip_protocol == Some(IP_PROTOCOL_ICMPV6),
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes.get(offset..offset + 2).map(|word| u16::from_be_bytes([word[0], word[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::String, vec};
+
+    const CLIENT: [u8; 4] = [10, 0, 2, 15];
+    const SERVER: [u8; 4] = [93, 184, 216, 34];
+
+    fn parse(filter: &str) -> Result<Filter, &'static str> {
+        filter.parse()
+    }
+
+    fn host(endpoint: Endpoint, address: [u8; 4]) -> Filter {
+        Filter::Host(endpoint, IpAddress::Ipv4(Ipv4Address(address)))
+    }
+
+    /// Returns an IPv4 packet from `CLIENT` to `SERVER` with the given `protocol` and `payload`.
+    fn ipv4_packet(protocol: u8, fragment_offset: u16, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&fragment_offset.to_be_bytes());
+        packet.extend_from_slice(&[64, protocol, 0, 0]);
+        packet.extend_from_slice(&CLIENT);
+        packet.extend_from_slice(&SERVER);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// Returns a TCP or UDP header with the given ports, followed by zeros.
+    fn transport_header(src_port: u16, dst_port: u16, len: usize) -> Vec<u8> {
+        let mut header = vec![0; len];
+        header[0..2].copy_from_slice(&src_port.to_be_bytes());
+        header[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        header
+    }
+
+    fn ethernet_frame(ethertype: u16, packet: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; ETHERNET_HEADER_LEN];
+        frame[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(packet);
+        frame
+    }
+
+    #[test]
+    fn test_parse_primitives() {
+        assert_eq!(parse("arp"), Ok(Filter::Protocol(Protocol::Arp)));
+        assert_eq!(parse("icmp6"), Ok(Filter::Protocol(Protocol::Icmpv6)));
+        assert_eq!(parse("host 10.0.2.15"), Ok(host(Endpoint::Any, CLIENT)));
+        assert_eq!(parse("src host 10.0.2.15"), Ok(host(Endpoint::Source, CLIENT)));
+        assert_eq!(parse("dst port 53"), Ok(Filter::Port(Endpoint::Destination, 53)));
+        assert_eq!(
+            parse("host ::1"),
+            Ok(Filter::Host(Endpoint::Any, IpAddress::Ipv6(Ipv6Address::LOOPBACK))),
+        );
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        // `not` binds tighter than `and`, which binds tighter than `or`.
+        assert_eq!(
+            parse("not udp and port 80 or tcp"),
+            Ok(Filter::Or(
+                Box::new(Filter::And(
+                    Box::new(Filter::Not(Box::new(Filter::Protocol(Protocol::Udp)))),
+                    Box::new(Filter::Port(Endpoint::Any, 80)),
+                )),
+                Box::new(Filter::Protocol(Protocol::Tcp)),
+            )),
+        );
+        // Punctuation doesn't need to be separated by whitespace.
+        assert_eq!(
+            parse("!(tcp||udp)&&ip"),
+            Ok(Filter::And(
+                Box::new(Filter::Not(Box::new(Filter::Or(
+                    Box::new(Filter::Protocol(Protocol::Tcp)),
+                    Box::new(Filter::Protocol(Protocol::Udp)),
+                )))),
+                Box::new(Filter::Protocol(Protocol::Ipv4)),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for filter in [
+            "",
+            "tcp and",
+            "(tcp",
+            "tcp)",
+            "tcp udp",
+            "host",
+            "host 300.0.0.1",
+            "port 65536",
+            "src tcp",
+            "ethernet",
+        ] {
+            assert!(parse(filter).is_err(), "{filter:?} should be invalid");
+        }
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        let nested_nots = |depth: usize| "not ".repeat(depth) + "tcp";
+        assert!(parse(&nested_nots(MAX_NESTING)).is_ok());
+        assert_eq!(parse(&nested_nots(MAX_NESTING + 1)), Err("filter is nested too deeply"));
+
+        let nested_parentheses = |depth: usize| "(".repeat(depth) + "tcp" + &")".repeat(depth);
+        assert!(parse(&nested_parentheses(MAX_NESTING)).is_ok());
+        assert_eq!(parse(&nested_parentheses(MAX_NESTING + 1)), Err("filter is nested too deeply"));
+
+        // A filter this deep would overflow the stack if its nesting weren't limited.
+        let deep: String = "!".repeat(100_000) + "tcp";
+        assert_eq!(parse(&deep), Err("filter is nested too deeply"));
+    }
+
+    #[test]
+    fn test_parse_ethernet_ipv4_tcp() {
+        let segment = transport_header(49152, 80, 20);
+        let frame = ethernet_frame(ETHERTYPE_IPV4, &ipv4_packet(IP_PROTOCOL_TCP, 0, &segment));
+        let info = PacketInfo::parse(LinkType::Ethernet, &frame);
+        assert_eq!(info, PacketInfo {
+            ethertype: Some(ETHERTYPE_IPV4),
+            src_addr: Some(IpAddress::Ipv4(Ipv4Address(CLIENT))),
+            dst_addr: Some(IpAddress::Ipv4(Ipv4Address(SERVER))),
+            ip_protocol: Some(IP_PROTOCOL_TCP),
+            ip_payload_len: Some(20),
+            src_port: Some(49152),
+            dst_port: Some(80),
+        });
+
+        assert!(parse("ip and tcp and dst port 80").unwrap().matches(&info));
+        assert!(parse("src host 10.0.2.15 and port 49152").unwrap().matches(&info));
+        assert!(!parse("dst host 10.0.2.15 or udp or ip6 or arp").unwrap().matches(&info));
+    }
+
+    #[test]
+    fn test_parse_raw_ipv6_udp() {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&8_u16.to_be_bytes());
+        packet.extend_from_slice(&[IP_PROTOCOL_UDP, 64]);
+        packet.extend_from_slice(&Ipv6Address::LOOPBACK.0);
+        packet.extend_from_slice(&Ipv6Address::LOOPBACK.0);
+        packet.extend_from_slice(&transport_header(5353, 53, 8));
+
+        let info = PacketInfo::parse(LinkType::Ip, &packet);
+        assert_eq!(info.ethertype, Some(ETHERTYPE_IPV6));
+        assert_eq!(info.dst_addr, Some(IpAddress::Ipv6(Ipv6Address::LOOPBACK)));
+        assert_eq!(info.ip_payload_len, Some(8));
+        assert_eq!((info.src_port, info.dst_port), (Some(5353), Some(53)));
+        assert!(parse("ip6 and udp and port 53 and host ::1").unwrap().matches(&info));
+    }
+
+    #[test]
+    fn test_parse_partial_headers() {
+        // A frame truncated within its IP header only has an EtherType.
+        let frame = ethernet_frame(ETHERTYPE_IPV4, &[0x45, 0, 0, 40]);
+        let info = PacketInfo::parse(LinkType::Ethernet, &frame);
+        assert_eq!(info, PacketInfo { ethertype: Some(ETHERTYPE_IPV4), ..Default::default() });
+
+        // Fragments other than the first don't hold a transport header.
+        let segment = transport_header(49152, 80, 20);
+        let frame = ethernet_frame(ETHERTYPE_IPV4, &ipv4_packet(IP_PROTOCOL_TCP, 185, &segment));
+        let info = PacketInfo::parse(LinkType::Ethernet, &frame);
+        assert_eq!(info.ip_protocol, Some(IP_PROTOCOL_TCP));
+        assert_eq!((info.src_port, info.dst_port), (None, None));
+        assert!(!parse("port 80").unwrap().matches(&info));
+
+        // A transport header truncated after its source port.
+        let frame = ethernet_frame(ETHERTYPE_IPV4, &ipv4_packet(IP_PROTOCOL_UDP, 0, &[0xC0, 0x00, 0x00]));
+        let info = PacketInfo::parse(LinkType::Ethernet, &frame);
+        assert_eq!((info.src_port, info.dst_port), (Some(49152), None));
+
+        assert_eq!(PacketInfo::parse(LinkType::Ethernet, &[]), PacketInfo::default());
+        assert_eq!(PacketInfo::parse(LinkType::Ip, &[0x10; 40]), PacketInfo::default());
+    }
+
+    #[test]
+    fn test_parse_arp() {
+        let frame = ethernet_frame(ETHERTYPE_ARP, &[0; 28]);
+        let info = PacketInfo::parse(LinkType::Ethernet, &frame);
+        assert!(parse("arp and not ip").unwrap().matches(&info));
+        assert!(!parse("host 10.0.2.15").unwrap().matches(&info));
+    }
+}
@@ -1,8 +1,11 @@
 use alloc::vec;
+use core::task::Waker;
 
 use log::error;
 use nic_buffers::{ReceivedFrame, TransmitBuffer};
 use smoltcp::phy;
+
+use crate::capture::{CaptureState, Direction};
 pub use smoltcp::phy::DeviceCapabilities;
 
 /// Standard maximum transition unit for ethernet cards.
@@ -37,6 +40,15 @@ pub trait NetworkDevice: Send + Sync {
         caps.max_transmission_unit = STANDARD_MTU;
         caps
     }
+
+    /// Sets the waker that the device should wake whenever it receives frames,
+    /// typically from its receive interrupt handler.
+    ///
+    /// Returns whether the device will do so. If not, the interface polls the
+    /// device periodically instead.
+    fn set_receive_waker(&mut self, _waker: Waker) -> bool {
+        false
+    }
 }
 
 /// Wrapper around a network device.
@@ -48,10 +60,12 @@ pub trait NetworkDevice: Send + Sync {
 /// ```
 pub(crate) struct DeviceWrapper<'a> {
     pub(crate) inner: &'a mut dyn NetworkDevice,
+    /// The interface's ongoing packet capture, if any.
+    pub(crate) capture: Option<&'a CaptureState>,
 }
 
 impl<'a> phy::Device for DeviceWrapper<'a> {
-    type RxToken<'b> = RxToken where Self: 'b;
+    type RxToken<'b> = RxToken<'b> where Self: 'b;
 
     type TxToken<'c> = TxToken<'c> where Self: 'c;
 
@@ -60,11 +74,23 @@ impl<'a> phy::Device for DeviceWrapper<'a> {
         _: smoltcp::time::Instant,
     ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
         let frame = self.inner.receive()?;
-        Some((RxToken { inner: frame }, TxToken { device: self.inner }))
+        Some((
+            RxToken {
+                inner: frame,
+                capture: self.capture,
+            },
+            TxToken {
+                device: self.inner,
+                capture: self.capture,
+            },
+        ))
     }
 
     fn transmit(&mut self, _: smoltcp::time::Instant) -> Option<Self::TxToken<'_>> {
-        Some(TxToken { device: self.inner })
+        Some(TxToken {
+            device: self.inner,
+            capture: self.capture,
+        })
     }
 
     fn capabilities(&self) -> DeviceCapabilities {
@@ -73,11 +99,12 @@ impl<'a> phy::Device for DeviceWrapper<'a> {
 }
 
 /// The receive token.
-pub(crate) struct RxToken {
+pub(crate) struct RxToken<'a> {
     inner: ReceivedFrame,
+    capture: Option<&'a CaptureState>,
 }
 
-impl phy::RxToken for RxToken {
+impl<'a> phy::RxToken for RxToken<'a> {
     fn consume<R, F>(mut self, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
@@ -93,6 +120,9 @@ impl phy::RxToken for RxToken {
             .0
             .first_mut()
             .expect("received frame spanning no buffers");
+        if let Some(capture) = self.capture {
+            capture.capture(Direction::Received, slice);
+        }
         f(slice)
     }
 }
@@ -100,6 +130,7 @@ impl phy::RxToken for RxToken {
 /// The transmit token.
 pub(crate) struct TxToken<'a> {
     device: &'a mut dyn NetworkDevice,
+    capture: Option<&'a CaptureState>,
 }
 
 impl<'a> phy::TxToken for TxToken<'a> {
@@ -112,6 +143,9 @@ impl<'a> phy::TxToken for TxToken<'a> {
                 // This will only fail if the underlying memory allocation fails.
                 let mut buf = TransmitBuffer::new(len).expect("failed to allocate transmit buffer");
                 let ret = f(&mut buf);
+                if let Some(capture) = self.capture {
+                    capture.capture(Direction::Sent, &buf);
+                }
                 self.device.send(buf);
                 ret
             }
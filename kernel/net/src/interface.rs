@@ -1,7 +1,13 @@
 use alloc::{sync::Arc, vec::Vec};
-use core::marker::PhantomData;
+use core::{marker::PhantomData, task::Waker, time::Duration};
 
-use smoltcp::{iface, phy::DeviceCapabilities, socket::AnySocket, wire};
+use log::{info, warn};
+use smoltcp::{
+    iface::{self, SocketHandle},
+    phy::{DeviceCapabilities, Medium},
+    socket::{dhcpv4, tcp, AnySocket},
+    wire::{self, HardwareAddress, Ipv4Address, Ipv4Cidr},
+};
 pub use smoltcp::{
     iface::SocketSet,
     wire::{IpAddress, IpCidr},
@@ -9,49 +15,94 @@ pub use smoltcp::{
 use sync_block::Mutex;
 use sync_irq::IrqSafeMutex;
 
-use crate::{device::DeviceWrapper, NetworkDevice, Socket};
+use crate::{
+    capture::{Capture, CaptureState, Filter, LinkType},
+    device::DeviceWrapper,
+    NetworkDevice, Socket,
+};
+
+/// The IPv4 configuration of a network interface,
+/// either acquired from a DHCP server or set statically.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ipv4Config {
+    /// The interface's address and the prefix length of its subnet.
+    pub address: Ipv4Cidr,
+    /// The router through which packets leaving the subnet are sent.
+    pub gateway: Option<Ipv4Address>,
+    /// The DNS servers that should be used to resolve hostnames.
+    pub dns_servers: Vec<Ipv4Address>,
+}
+
+/// The current IPv4 state of a network interface.
+struct Ipv4State {
+    /// The interface's DHCP client socket, if DHCP is enabled.
+    dhcp: Option<SocketHandle>,
+    /// The configuration currently applied to the interface.
+    config: Option<Ipv4Config>,
+}
 
 /// A network interface.
 ///
 /// This is a wrapper around a network device which provides higher level
 /// abstractions such as polling sockets.
+///
+/// Each registered interface is polled by its own network task,
+/// so sockets don't need to poll the interface themselves.
 pub struct NetworkInterface {
     pub(crate) inner: Mutex<iface::Interface>,
-    device: &'static IrqSafeMutex<dyn crate::NetworkDevice>,
+    pub(crate) device: &'static IrqSafeMutex<dyn crate::NetworkDevice>,
     pub(crate) sockets: Mutex<SocketSet<'static>>,
+    /// TCP sockets that were closed by their owners, which are removed
+    /// from `sockets` once their connections have fully closed.
+    pub(crate) closing: Mutex<Vec<SocketHandle>>,
+    ipv4: Mutex<Ipv4State>,
+    /// Wakes the interface's network task, once it has started.
+    pub(crate) task_waker: spin::Once<Waker>,
+    /// The interface's ongoing packet capture, if any.
+    capture: spin::Mutex<Option<Arc<CaptureState>>>,
 }
 
 impl NetworkInterface {
-    pub(crate) fn new<T>(device: &'static IrqSafeMutex<T>, ip: IpCidr, gateway: IpAddress) -> Self
+    /// Creates a new interface for the given `device`.
+    ///
+    /// If `static_ipv4` is `None`, the interface acquires its IPv4 configuration using DHCP.
+    pub(crate) fn new<T>(device: &'static IrqSafeMutex<T>, static_ipv4: Option<Ipv4Config>) -> Self
     where
         T: NetworkDevice,
     {
-        let hardware_addr = wire::EthernetAddress(device.lock().mac_address()).into();
-
         let mut wrapper = DeviceWrapper {
             inner: &mut *device.lock(),
+            capture: None,
         };
 
+        let hardware_addr = match wrapper.inner.capabilities().medium {
+            Medium::Ip => HardwareAddress::Ip,
+            _ => wire::EthernetAddress(wrapper.inner.mac_address()).into(),
+        };
         let mut config = iface::Config::new(hardware_addr);
         config.random_seed = random::next_u64();
 
-        let mut interface =
-            iface::Interface::new(config, &mut wrapper, smoltcp::time::Instant::ZERO);
-        interface.update_ip_addrs(|ip_addrs| {
-            // NOTE: This won't fail as ip_addrs has a capacity of 2 (defined in smoltcp)
-            // and this is the only address we are pushing.
-            ip_addrs.push(ip).unwrap();
-        });
-        match gateway {
-            IpAddress::Ipv4(addr) => interface.routes_mut().add_default_ipv4_route(addr),
-            IpAddress::Ipv6(addr) => interface.routes_mut().add_default_ipv6_route(addr),
-        }
-        .expect("btree map route storage exhausted");
+        let mut interface = iface::Interface::new(config, &mut wrapper, crate::now());
+        let mut sockets = SocketSet::new(Vec::new());
+        let dhcp = match static_ipv4 {
+            Some(ref config) => {
+                apply_ipv4_config(&mut interface, Some(config));
+                None
+            }
+            None => Some(sockets.add(dhcpv4::Socket::new())),
+        };
 
         Self {
             inner: Mutex::new(interface),
             device,
-            sockets: Mutex::new(SocketSet::new(Vec::new())),
+            sockets: Mutex::new(sockets),
+            closing: Mutex::new(Vec::new()),
+            ipv4: Mutex::new(Ipv4State {
+                dhcp,
+                config: static_ipv4,
+            }),
+            task_waker: spin::Once::new(),
+            capture: spin::Mutex::new(None),
         }
     }
 
@@ -70,19 +121,202 @@ impl NetworkInterface {
 
     /// Polls the sockets associated with the interface.
     ///
+    /// This also runs the interface's DHCP client, if enabled,
+    /// which acquires, renews, and applies its lease as needed.
+    ///
     /// Returns a boolean indicating whether the readiness of any socket may
     /// have changed.
     pub fn poll(&self) -> bool {
+        let capture = self.capture.lock().clone();
         let mut inner = self.inner.lock();
         let mut wrapper = DeviceWrapper {
             inner: &mut *self.device.lock(),
+            capture: capture.as_deref(),
         };
         let mut sockets = self.sockets.lock();
 
-        inner.poll(smoltcp::time::Instant::ZERO, &mut wrapper, &mut sockets)
+        let readiness_changed = inner.poll(crate::now(), &mut wrapper, &mut sockets);
+
+        self.closing.lock().retain(|&handle| {
+            let closed = sockets.get::<tcp::Socket>(handle).state() == tcp::State::Closed;
+            if closed {
+                sockets.remove(handle);
+            }
+            !closed
+        });
+
+        let mut ipv4 = self.ipv4.lock();
+        if let Some(dhcp) = ipv4.dhcp {
+            match sockets.get_mut::<dhcpv4::Socket>(dhcp).poll() {
+                Some(dhcpv4::Event::Configured(lease)) => {
+                    let config = Ipv4Config {
+                        address: lease.address,
+                        gateway: lease.router,
+                        dns_servers: lease.dns_servers.iter().copied().collect(),
+                    };
+                    if ipv4.config.as_ref() != Some(&config) {
+                        info!(
+                            "{}: acquired DHCP lease for {} from {}, gateway: {:?}, DNS servers: {:?}",
+                            inner.hardware_addr(), config.address, lease.server.address,
+                            config.gateway, config.dns_servers,
+                        );
+                        apply_ipv4_config(&mut inner, Some(&config));
+                        ipv4.config = Some(config);
+                    }
+                }
+                Some(dhcpv4::Event::Deconfigured) => {
+                    if ipv4.config.is_some() {
+                        warn!("{}: lost DHCP lease", inner.hardware_addr());
+                    }
+                    apply_ipv4_config(&mut inner, None);
+                    ipv4.config = None;
+                }
+                None => {}
+            }
+        }
+
+        readiness_changed
+    }
+
+    /// Returns how long until the interface next needs to be polled,
+    /// e.g., to retransmit a TCP segment, assuming no frames are received before then.
+    ///
+    /// Returns `None` if there are no pending timers.
+    pub fn poll_delay(&self) -> Option<Duration> {
+        let mut inner = self.inner.lock();
+        let sockets = self.sockets.lock();
+        inner
+            .poll_delay(crate::now(), &sockets)
+            .map(|delay| Duration::from_micros(delay.total_micros()))
+    }
+
+    /// Wakes the interface's network task, such that it polls the interface soon.
+    ///
+    /// This is safe to call from an interrupt handler.
+    pub fn wake(&self) {
+        if let Some(waker) = self.task_waker.get() {
+            waker.wake_by_ref();
+        }
+    }
+
+    /// Returns the current IPv4 configuration of the interface,
+    /// or `None` if it has no IPv4 address, e.g., while waiting for a DHCP lease.
+    pub fn ipv4_config(&self) -> Option<Ipv4Config> {
+        self.ipv4.lock().config.clone()
+    }
+
+    /// Returns the current IPv4 address of the interface.
+    pub fn ipv4_address(&self) -> Option<Ipv4Cidr> {
+        self.ipv4.lock().config.as_ref().map(|config| config.address)
+    }
+
+    /// Returns the current IPv4 gateway of the interface.
+    pub fn gateway(&self) -> Option<Ipv4Address> {
+        self.ipv4.lock().config.as_ref().and_then(|config| config.gateway)
+    }
+
+    /// Returns the DNS servers that the interface was configured with.
+    pub fn dns_servers(&self) -> Vec<Ipv4Address> {
+        self.ipv4.lock().config.as_ref().map(|config| config.dns_servers.clone()).unwrap_or_default()
+    }
+
+    /// Returns whether this is a loopback interface,
+    /// i.e., its IPv4 address is in the `127.0.0.0/8` block.
+    pub fn is_loopback(&self) -> bool {
+        self.ipv4_address().map_or(false, |cidr| cidr.address().is_loopback())
+    }
+
+    /// Returns whether the interface has the given IP `address`.
+    pub fn has_ip_address(&self, address: IpAddress) -> bool {
+        self.inner.lock().has_ip_addr(address)
+    }
+
+    /// Returns whether the interface acquires its IPv4 configuration using DHCP.
+    pub fn is_dhcp_enabled(&self) -> bool {
+        self.ipv4.lock().dhcp.is_some()
+    }
+
+    /// Statically sets the IPv4 configuration of the interface, disabling its DHCP client.
+    pub fn set_static_ipv4_config(&self, config: Ipv4Config) {
+        let mut inner = self.inner.lock();
+        let mut sockets = self.sockets.lock();
+        let mut ipv4 = self.ipv4.lock();
+        if let Some(dhcp) = ipv4.dhcp.take() {
+            sockets.remove(dhcp);
+        }
+        apply_ipv4_config(&mut inner, Some(&config));
+        ipv4.config = Some(config);
+    }
+
+    /// Enables the interface's DHCP client, discarding its current IPv4 configuration.
+    ///
+    /// The interface has no IPv4 address until a lease is acquired.
+    pub fn enable_dhcp(&self) {
+        let mut inner = self.inner.lock();
+        let mut sockets = self.sockets.lock();
+        let mut ipv4 = self.ipv4.lock();
+        if ipv4.dhcp.is_none() {
+            ipv4.dhcp = Some(sockets.add(dhcpv4::Socket::new()));
+            apply_ipv4_config(&mut inner, None);
+            ipv4.config = None;
+        }
     }
 
     pub fn capabilities(&self) -> DeviceCapabilities {
         self.device.lock().capabilities()
     }
+
+    /// Starts capturing the frames sent and received by the interface.
+    ///
+    /// Only frames that match `filter`, if given, are captured, and each is
+    /// truncated to `snap_len` bytes. The capture stops when the returned
+    /// [`Capture`] is dropped.
+    ///
+    /// Returns an error if the interface is already being captured.
+    pub fn start_capture(
+        self: &Arc<Self>,
+        filter: Option<Filter>,
+        snap_len: usize,
+    ) -> Result<Capture, &'static str> {
+        let link_type = LinkType::from_medium(self.capabilities().medium);
+        let mut current = self.capture.lock();
+        if current.is_some() {
+            return Err("interface is already being captured");
+        }
+        let (capture, state) = Capture::new(self.clone(), link_type, filter, snap_len);
+        *current = Some(state);
+        Ok(capture)
+    }
+
+    /// Returns whether the interface's frames are currently being captured.
+    pub fn is_capturing(&self) -> bool {
+        self.capture.lock().is_some()
+    }
+
+    /// Stops the given capture, if it is still the interface's ongoing capture.
+    pub(crate) fn stop_capture(&self, state: &Arc<CaptureState>) {
+        let mut current = self.capture.lock();
+        if current.as_ref().map_or(false, |current| Arc::ptr_eq(current, state)) {
+            *current = None;
+        }
+    }
+}
+
+/// Replaces the IPv4 address and default route of the given interface with those in `config`.
+fn apply_ipv4_config(interface: &mut iface::Interface, config: Option<&Ipv4Config>) {
+    interface.update_ip_addrs(|ip_addrs| {
+        ip_addrs.retain(|cidr| !matches!(cidr, IpCidr::Ipv4(_)));
+        if let Some(config) = config {
+            // NOTE: This won't fail as ip_addrs has a capacity of 2 (defined in smoltcp)
+            // and this is the only IPv4 address.
+            ip_addrs.push(IpCidr::Ipv4(config.address)).unwrap();
+        }
+    });
+    interface.routes_mut().remove_default_ipv4_route();
+    if let Some(gateway) = config.and_then(|config| config.gateway) {
+        interface
+            .routes_mut()
+            .add_default_ipv4_route(gateway)
+            .expect("btree map route storage exhausted");
+    }
 }
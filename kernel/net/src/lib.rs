@@ -4,33 +4,25 @@ extern crate alloc;
 
 use alloc::{sync::Arc, vec::Vec};
 
-use smoltcp::wire::Ipv4Address;
+use log::error;
 use spin::Mutex;
 use sync_irq::IrqSafeMutex;
 
+pub mod capture;
 mod device;
 mod interface;
+mod network_task;
 mod socket;
 
 pub use device::{DeviceCapabilities, NetworkDevice};
-pub use interface::{IpAddress, IpCidr, NetworkInterface, SocketSet};
+pub use interface::{IpAddress, IpCidr, Ipv4Config, NetworkInterface, SocketSet};
 pub use smoltcp::{
     phy,
-    socket::{icmp, tcp, udp},
+    socket::{icmp, tcp, udp, AnySocket},
     time::Instant,
-    wire::{self, IpEndpoint},
+    wire::{self, IpEndpoint, Ipv4Address, Ipv4Cidr},
 };
-pub use socket::{LockedSocket, Socket};
-
-/// A randomly chosen IP address that must be outside of the DHCP range.
-///
-/// The default QEMU user-slirp network gives IP address of `10.0.2.*`.
-const DEFAULT_LOCAL_IP: &str = "10.0.2.15/24";
-
-/// Standard home router address.
-///
-/// `10.0.2.2` is the default QEMU user-slirp networking gateway IP.
-const DEFAULT_GATEWAY_IP: IpAddress = IpAddress::Ipv4(Ipv4Address::new(10, 0, 2, 2));
+pub use socket::{block_on, AcceptError, LockedSocket, Socket};
 
 // TODO: Make mutex rwlock?
 // TODO: Use atomic append-only vec?
@@ -40,18 +32,43 @@ static NETWORK_INTERFACES: Mutex<Vec<Arc<NetworkInterface>>> = Mutex::new(Vec::n
 ///
 /// The function will convert the device to an interface and it will then be
 /// accessible using [`get_interfaces()`].
+///
+/// A network task is spawned to poll the interface whenever the device
+/// receives frames or the interface's sockets need attention.
+///
+/// The interface acquires its IPv4 address and gateway using DHCP,
+/// which progresses as the interface is polled.
+/// Thus, the interface has no address until a DHCP server has responded.
 pub fn register_device<T>(device: &'static IrqSafeMutex<T>) -> Arc<NetworkInterface>
 where
     T: 'static + NetworkDevice + Send,
 {
-    let interface = NetworkInterface::new(
-        device,
-        // TODO: use DHCP to acquire an IP address and gateway.
-        DEFAULT_LOCAL_IP.parse().unwrap(),
-        DEFAULT_GATEWAY_IP,
-    );
-
-    let interface_arc = Arc::new(interface);
+    register(device, None)
+}
+
+/// Registers a network device whose interface has the given static IPv4
+/// configuration, e.g., a loopback device.
+///
+/// Unlike [`register_device()`], the interface doesn't run a DHCP client,
+/// so it has its address as soon as it is registered.
+pub fn register_device_with_static_ipv4<T>(
+    device: &'static IrqSafeMutex<T>,
+    config: Ipv4Config,
+) -> Arc<NetworkInterface>
+where
+    T: 'static + NetworkDevice + Send,
+{
+    register(device, Some(config))
+}
+
+fn register<T>(device: &'static IrqSafeMutex<T>, static_ipv4: Option<Ipv4Config>) -> Arc<NetworkInterface>
+where
+    T: 'static + NetworkDevice + Send,
+{
+    let interface_arc = Arc::new(NetworkInterface::new(device, static_ipv4));
+    if let Err(e) = network_task::spawn(interface_arc.clone()) {
+        error!("failed to spawn network task: {e}");
+    }
     NETWORK_INTERFACES.lock().push(interface_arc.clone());
     interface_arc
 }
@@ -61,9 +78,32 @@ pub fn get_interfaces() -> &'static Mutex<Vec<Arc<NetworkInterface>>> {
     &NETWORK_INTERFACES
 }
 
-/// Returns the first available interface.
+/// Returns the first available interface that isn't a loopback interface,
+/// or the loopback interface if there is no other.
 pub fn get_default_interface() -> Option<Arc<NetworkInterface>> {
-    NETWORK_INTERFACES.lock().first().cloned()
+    let interfaces = NETWORK_INTERFACES.lock();
+    interfaces
+        .iter()
+        .find(|interface| !interface.is_loopback())
+        .or_else(|| interfaces.first())
+        .cloned()
+}
+
+/// Returns the interface through which packets to the given `address` should be sent.
+///
+/// This is the interface that has `address` as one of its own addresses,
+/// the loopback interface for loopback addresses,
+/// or the default interface otherwise.
+pub fn get_interface_for(address: IpAddress) -> Option<Arc<NetworkInterface>> {
+    let is_loopback = matches!(address, IpAddress::Ipv4(address) if address.is_loopback());
+    let found = NETWORK_INTERFACES
+        .lock()
+        .iter()
+        .find(|interface| {
+            interface.has_ip_address(address) || (is_loopback && interface.is_loopback())
+        })
+        .cloned();
+    found.or_else(get_default_interface)
 }
 
 /// Returns a port in the range reserved for private, dynamic, and ephemeral
@@ -81,3 +121,9 @@ pub fn get_ephemeral_port() -> u16 {
     let mut rng = random::init_rng::<rand_chacha::ChaChaRng>().unwrap();
     rng.gen_range(RANGE_START..=RANGE_END)
 }
+
+/// Returns the current time as a smoltcp timestamp, based on the monotonic clock.
+pub(crate) fn now() -> Instant {
+    let since_boot = time::Instant::now().duration_since(time::Instant::ZERO);
+    Instant::from_micros(since_boot.as_micros() as i64)
+}
@@ -0,0 +1,117 @@
+//! The task that drives a network interface.
+//!
+//! Each registered interface is polled by its own task, which sleeps until
+//! either its device receives frames, a socket operation needs the interface
+//! to transmit, or smoltcp's next timer (e.g., a TCP retransmission) expires.
+
+use alloc::{format, sync::Arc, task::Wake};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+    time::Duration,
+};
+
+use smoltcp::wire::HardwareAddress;
+use task::TaskRef;
+
+use crate::NetworkInterface;
+
+/// How often an interface is polled if its device can't notify us of received frames.
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Wakes an interface's network task.
+///
+/// Waking only sets a flag and unblocks the task,
+/// so it is safe to do so from an interrupt handler.
+struct NetworkTaskWaker {
+    task: TaskRef,
+    /// Whether the task was woken since it last checked.
+    pending: AtomicBool,
+}
+
+impl Wake for NetworkTaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.pending.store(true, Ordering::Release);
+        let _ = self.task.unblock();
+    }
+}
+
+impl NetworkTaskWaker {
+    /// Blocks the current task, which must be the network task, until it is woken.
+    ///
+    /// Returns immediately if the task was woken since this was last called.
+    fn wait(&self) {
+        let held_preemption = preemption::hold_preemption();
+        if self.pending.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let _ = self.task.block();
+        // The waker may have run after we checked the flag but before we blocked,
+        // in which case its unblock was lost.
+        if self.pending.swap(false, Ordering::AcqRel) {
+            let _ = self.task.unblock();
+            return;
+        }
+        drop(held_preemption);
+        task::schedule();
+    }
+}
+
+/// Spawns the task that polls the given `interface` for as long as the system runs.
+pub(crate) fn spawn(interface: Arc<NetworkInterface>) -> Result<(), &'static str> {
+    let hardware_addr = interface.inner.lock().hardware_addr();
+    let name = match (hardware_addr, interface.ipv4_address()) {
+        // Devices without a hardware address, e.g., loopback, are named by their IP address.
+        (HardwareAddress::Ip, Some(cidr)) => format!("network_task_{}", cidr.address()),
+        (hardware_addr, _) => format!("network_task_{hardware_addr}"),
+    };
+    spawn::new_task_builder(network_task, interface)
+        .name(name)
+        .spawn()?;
+    Ok(())
+}
+
+fn network_task(interface: Arc<NetworkInterface>) {
+    let task = task::get_my_current_task().expect("network_task(): failed to get current task");
+    let task_waker = Arc::new(NetworkTaskWaker {
+        task,
+        // Poll once right away, e.g., to start DHCP discovery.
+        pending: AtomicBool::new(true),
+    });
+    let waker = Waker::from(task_waker.clone());
+    interface.task_waker.call_once(|| waker.clone());
+    let receive_notifications = interface.device.lock().set_receive_waker(waker.clone());
+
+    // The time at which a previously-armed sleep timer will wake this task.
+    let mut armed_timer: Option<time::Instant> = None;
+
+    loop {
+        task_waker.wait();
+        interface.poll();
+
+        let mut delay = interface.poll_delay();
+        if !receive_notifications {
+            delay = Some(delay.map_or(RECEIVE_POLL_INTERVAL, |d| d.min(RECEIVE_POLL_INTERVAL)));
+        }
+        let Some(delay) = delay else { continue };
+
+        if delay.is_zero() {
+            // The interface has more work to do right away, e.g., frames to transmit.
+            task_waker.pending.store(true, Ordering::Release);
+            task::schedule();
+            continue;
+        }
+
+        // Don't pile up sleep timers: only arm one if it fires before the one already armed.
+        let now = time::Instant::now();
+        let deadline = now + delay;
+        if armed_timer.map_or(true, |armed| armed <= now || deadline < armed) {
+            sleep::future::sleep(delay, waker.clone());
+            armed_timer = Some(deadline);
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use crate::NetworkInterface;
 use alloc::sync::Arc;
 use core::{
+    future::poll_fn,
     marker::PhantomData,
     ops::{Deref, DerefMut},
+    task::{Context, Poll},
+    time::Duration,
 };
 use smoltcp::{
     iface::{SocketHandle, SocketSet},
-    socket::AnySocket,
+    socket::{tcp, udp, AnySocket},
     wire::{IpEndpoint, IpListenEndpoint},
 };
 use sync_block::MutexGuard;
@@ -18,6 +21,10 @@ pub use smoltcp::socket::tcp::ConnectError;
 /// In order to use the socket, it must be locked using the [`lock`] method.
 /// This will lock the interface's list of sockets, and so the guard returned by
 /// [`lock`] must be dropped before calling [`Interface::poll`].
+///
+/// TCP and UDP sockets also offer blocking and async operations, e.g., `recv`
+/// and `recv_async`, which wait for the interface's network task to make
+/// progress rather than polling the interface themselves.
 pub struct Socket<T>
 where
     // TODO: Relax 'static lifetime.
@@ -38,7 +45,7 @@ where
     phantom_data: PhantomData<T>,
 }
 
-impl<'a> LockedSocket<'a, smoltcp::socket::tcp::Socket<'static>> {
+impl<'a> LockedSocket<'a, tcp::Socket<'static>> {
     pub fn connect<R, L>(
         &mut self,
         remote_endpoint: R,
@@ -86,4 +93,258 @@ where
             phantom_data: PhantomData,
         }
     }
+
+    /// Returns the interface that the socket was added to.
+    pub fn interface(&self) -> &Arc<NetworkInterface> {
+        &self.interface
+    }
+
+    /// Removes the socket from its interface,
+    /// discarding any data that has yet to be sent or received.
+    pub fn remove(self) {
+        self.interface.sockets.lock().remove(self.handle);
+    }
+}
+
+/// An error returned when accepting a connection on a TCP socket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AcceptError {
+    /// The socket is neither listening nor connecting, e.g., because it was closed.
+    InvalidState,
+}
+
+impl Socket<tcp::Socket<'static>> {
+    /// Closes the connection gracefully, and then removes the socket from its interface
+    /// once the connection has fully closed, i.e., after any remaining data has been sent.
+    ///
+    /// This is how a socket should be disposed of if its owner doesn't wait for the connection to close.
+    pub fn remove_when_closed(self) {
+        self.lock().close();
+        self.interface.closing.lock().push(self.handle);
+        self.interface.wake();
+    }
+
+    /// Polls for data to be received into `buf`.
+    ///
+    /// Returns `Ok(0)` if `buf` is empty. Returns [`tcp::RecvError::Finished`]
+    /// once the remote endpoint has closed the connection and all data has been received.
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, tcp::RecvError>> {
+        let mut socket = self.lock();
+        if socket.can_recv() || !(socket.may_recv() || is_connecting(&socket)) {
+            let result = socket.recv_slice(buf);
+            drop(socket);
+            // Freeing up space in the receive buffer may need to be advertised to the remote.
+            self.interface.wake();
+            Poll::Ready(result)
+        } else {
+            socket.register_recv_waker(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Polls for `data` to be enqueued in the transmit buffer.
+    ///
+    /// Returns how many bytes were enqueued, which may be fewer than `data.len()`.
+    pub fn poll_send(&self, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize, tcp::SendError>> {
+        let mut socket = self.lock();
+        if socket.can_send() || !(socket.may_send() || is_connecting(&socket)) {
+            let result = socket.send_slice(data);
+            drop(socket);
+            self.interface.wake();
+            Poll::Ready(result)
+        } else {
+            socket.register_send_waker(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Polls for the socket's connection to be established.
+    ///
+    /// This completes once a listening socket has accepted a connection,
+    /// or once a connecting socket has connected,
+    /// returning the remote endpoint of the connection.
+    ///
+    /// A listening socket accepts only a single connection; to accept another
+    /// connection, add another socket listening on the same endpoint.
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<Result<IpEndpoint, AcceptError>> {
+        let mut socket = self.lock();
+        if is_connecting(&socket) {
+            socket.register_recv_waker(cx.waker());
+            return Poll::Pending;
+        }
+        Poll::Ready(socket.remote_endpoint().ok_or(AcceptError::InvalidState))
+    }
+
+    /// Receives data into `buf`, blocking until at least one byte is available.
+    ///
+    /// See [`poll_recv`](Self::poll_recv) for details.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<usize, tcp::RecvError> {
+        block_on(|cx| self.poll_recv(cx, buf))
+    }
+
+    /// Like [`recv`](Self::recv), but gives up after `timeout`, in which case `None` is returned.
+    pub fn recv_timeout(&self, buf: &mut [u8], timeout: Duration) -> Option<Result<usize, tcp::RecvError>> {
+        block_on_timeout(timeout, |cx| self.poll_recv(cx, buf))
+    }
+
+    /// Enqueues `data` for transmission, blocking until there is space in the transmit buffer.
+    ///
+    /// See [`poll_send`](Self::poll_send) for details.
+    pub fn send(&self, data: &[u8]) -> Result<usize, tcp::SendError> {
+        block_on(|cx| self.poll_send(cx, data))
+    }
+
+    /// Like [`send`](Self::send), but gives up after `timeout`, in which case `None` is returned.
+    pub fn send_timeout(&self, data: &[u8], timeout: Duration) -> Option<Result<usize, tcp::SendError>> {
+        block_on_timeout(timeout, |cx| self.poll_send(cx, data))
+    }
+
+    /// Blocks until the socket's connection is established.
+    ///
+    /// See [`poll_accept`](Self::poll_accept) for details.
+    pub fn accept(&self) -> Result<IpEndpoint, AcceptError> {
+        block_on(|cx| self.poll_accept(cx))
+    }
+
+    /// Like [`accept`](Self::accept), but gives up after `timeout`, in which case `None` is returned.
+    pub fn accept_timeout(&self, timeout: Duration) -> Option<Result<IpEndpoint, AcceptError>> {
+        block_on_timeout(timeout, |cx| self.poll_accept(cx))
+    }
+
+    /// The async version of [`recv`](Self::recv).
+    pub async fn recv_async(&self, buf: &mut [u8]) -> Result<usize, tcp::RecvError> {
+        poll_fn(|cx| self.poll_recv(cx, buf)).await
+    }
+
+    /// The async version of [`send`](Self::send).
+    pub async fn send_async(&self, data: &[u8]) -> Result<usize, tcp::SendError> {
+        poll_fn(|cx| self.poll_send(cx, data)).await
+    }
+
+    /// The async version of [`accept`](Self::accept).
+    pub async fn accept_async(&self) -> Result<IpEndpoint, AcceptError> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+}
+
+/// Returns whether the given TCP socket is waiting for its connection to be established.
+fn is_connecting(socket: &tcp::Socket) -> bool {
+    matches!(
+        socket.state(),
+        tcp::State::Listen | tcp::State::SynSent | tcp::State::SynReceived
+    )
+}
+
+impl Socket<udp::Socket<'static>> {
+    /// Polls for a datagram to be received into `buf`.
+    ///
+    /// Returns the length of the datagram and the endpoint that sent it.
+    /// The socket must be bound.
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<(usize, IpEndpoint), udp::RecvError>> {
+        let mut socket = self.lock();
+        if socket.can_recv() || !socket.is_open() {
+            let result = socket
+                .recv_slice(buf)
+                .map(|(len, metadata)| (len, metadata.endpoint));
+            Poll::Ready(result)
+        } else {
+            socket.register_recv_waker(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    /// Polls for `data` to be enqueued for transmission to `endpoint`.
+    pub fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        data: &[u8],
+        endpoint: IpEndpoint,
+    ) -> Poll<Result<(), udp::SendError>> {
+        let mut socket = self.lock();
+        match socket.send_slice(data, endpoint) {
+            Err(udp::SendError::BufferFull) => {
+                socket.register_send_waker(cx.waker());
+                Poll::Pending
+            }
+            result => {
+                drop(socket);
+                self.interface.wake();
+                Poll::Ready(result)
+            }
+        }
+    }
+
+    /// Receives a datagram into `buf`, blocking until one is available.
+    ///
+    /// See [`poll_recv`](Self::poll_recv) for details.
+    pub fn recv(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), udp::RecvError> {
+        block_on(|cx| self.poll_recv(cx, buf))
+    }
+
+    /// Enqueues `data` for transmission to `endpoint`,
+    /// blocking until there is space in the transmit buffer.
+    pub fn send(&self, data: &[u8], endpoint: IpEndpoint) -> Result<(), udp::SendError> {
+        block_on(|cx| self.poll_send(cx, data, endpoint))
+    }
+
+    /// The async version of [`recv`](Self::recv).
+    pub async fn recv_async(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), udp::RecvError> {
+        poll_fn(|cx| self.poll_recv(cx, buf)).await
+    }
+
+    /// The async version of [`send`](Self::send).
+    pub async fn send_async(&self, data: &[u8], endpoint: IpEndpoint) -> Result<(), udp::SendError> {
+        poll_fn(|cx| self.poll_send(cx, data, endpoint)).await
+    }
+}
+
+/// Blocks the current task until `poll` is ready.
+///
+/// The waker passed to `poll` is registered with a socket while the interface's
+/// sockets are locked, so it can't miss a wakeup from the network task.
+///
+/// This can wait on several sockets at once, e.g., by polling each of them in turn,
+/// as long as `poll` registers the waker with every socket that returned pending.
+pub fn block_on<R>(mut poll: impl FnMut(&mut Context<'_>) -> Poll<R>) -> R {
+    let (waker, blocker) = waker::new_waker();
+    let mut context = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(result) = poll(&mut context) {
+            return result;
+        }
+        blocker.block();
+    }
+}
+
+/// Like [`block_on`], but gives up once `timeout` has elapsed, returning `None`.
+///
+/// The same waker is also registered to be woken at the deadline,
+/// so the current task doesn't need to wake up periodically to check it.
+fn block_on_timeout<R>(timeout: Duration, mut poll: impl FnMut(&mut Context<'_>) -> Poll<R>) -> Option<R> {
+    let deadline = time::now::<time::Monotonic>() + timeout;
+    let (waker, blocker) = waker::new_waker();
+    let mut context = Context::from_waker(&waker);
+    let mut timer_armed = false;
+    loop {
+        if let Poll::Ready(result) = poll(&mut context) {
+            return Some(result);
+        }
+        if !timer_armed {
+            if sleep::future::sleep_until(deadline, &waker).is_ready() {
+                return None;
+            }
+            timer_armed = true;
+        } else if time::now::<time::Monotonic>() >= deadline {
+            return None;
+        }
+        blocker.block();
+    }
 }
@@ -117,9 +117,11 @@ impl ReceiveBuffer {
 
     /// Sets the buffers length.
     ///
-    /// Returns an error if the length is greater than the current length.
+    /// Returns an error if the length is greater than the size of the underlying memory.
+    /// This allows a buffer that was returned to its pool, which resets its length to 0,
+    /// to be reused for a new packet.
     pub fn set_length(&mut self, length: u16) -> Result<(), &'static str> {
-        if length > self.length {
+        if usize::from(length) > self.mp.size_in_bytes() {
             Err("ReceiveBuffer::set_length(): length too long")
         } else {
             self.length = length;
@@ -14,6 +14,7 @@ extern crate http_client;
 extern crate itertools;
 extern crate time;
 extern crate net;
+extern crate dns;
 
 use core::str;
 use alloc::{
@@ -27,19 +28,26 @@ use sha3::{Digest, Sha3_512};
 use percent_encoding::{DEFAULT_ENCODE_SET, utf8_percent_encode};
 use http_client::{HttpResponse, HttpClient, check_http_request};
 use time::{Duration, Instant};
-use net::{IpEndpoint, wire::Ipv4Address, NetworkInterface};
+use net::{IpEndpoint, NetworkInterface};
 
-/// The IP address of the update server.
-const DEFAULT_DESTINATION_IP_ADDR: [u8; 4] = [10, 0, 2, 2]; // the IP of the host machine when running on QEMU.
+/// The hostname or IP address of the update server.
+pub const DEFAULT_DESTINATION_HOST: &str = "10.0.2.2"; // the IP of the host machine when running on QEMU.
 
 /// The TCP port on the update server that listens for update requests 
-const DEFAULT_DESTINATION_PORT: u16 = 8090;
+pub const DEFAULT_DESTINATION_PORT: u16 = 8090;
 
-/// The default remote endpoint, server IP and port, of the update server.
-pub fn default_remote_endpoint() -> IpEndpoint {
-    IpEndpoint::new(
-        Ipv4Address::from_bytes(&DEFAULT_DESTINATION_IP_ADDR).into(), 
-        DEFAULT_DESTINATION_PORT
+/// Resolves the remote endpoint, server IP and port, of the update server.
+///
+/// The `destination` is a `HOST[:PORT]` string, where `HOST` is a hostname or an IP address;
+/// if it's `None`, the default update server is used.
+pub fn remote_endpoint(
+    iface: &Arc<NetworkInterface>,
+    destination: Option<&str>,
+) -> Result<IpEndpoint, &'static str> {
+    dns::resolve_endpoint(
+        iface,
+        destination.unwrap_or(DEFAULT_DESTINATION_HOST),
+        DEFAULT_DESTINATION_PORT,
     )
 }
 
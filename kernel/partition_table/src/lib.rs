@@ -0,0 +1,399 @@
+//! Parsing of MBR and GPT partition tables on storage devices.
+//!
+//! [`read_partitions()`] reads the partition table from any [`StorageDevice`]
+//! and returns each partition within it as a [`Partition`],
+//! which is itself a [`StorageDevice`] that covers only that partition's range of blocks.
+//! Thus, a filesystem can be opened on a partition just like on an entire device.
+//!
+//! Both the legacy MBR format (including logical partitions within an extended partition)
+//! and the GUID Partition Table (GPT) format are supported.
+//! A GPT is expected to be preceded by a protective MBR, as required by the UEFI specification;
+//! only the primary GPT header is used, and its checksums are verified.
+//!
+//! # Limitations
+//! * Partition table entries are given in units of the device's block size,
+//!   which must be at least 512 bytes.
+//! * Writes to a partition are bounds-checked, but nothing prevents
+//!   the underlying device from also being accessed directly.
+
+#![no_std]
+
+extern crate alloc;
+
+#[cfg(test)]
+mod test;
+
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
+use log::{debug, warn};
+use storage_device::{StorageDevice, StorageDeviceRef};
+
+/// The size in bytes of an MBR or GPT header, which occupies the beginning of a block.
+const SECTOR_SIZE: usize = 512;
+/// The offset of the first of the four partition entries in an MBR (or EBR).
+const MBR_ENTRIES_OFFSET: usize = 446;
+/// The size in bytes of an MBR partition entry.
+const MBR_ENTRY_SIZE: usize = 16;
+/// The signature at the end of a valid MBR (or EBR).
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// The MBR partition type of a protective MBR, which indicates that the device uses a GPT.
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+/// The MBR partition types of extended partitions, which contain a chain of logical partitions.
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+/// The maximum number of logical partitions that will be read from an extended partition,
+/// which protects against cycles in a corrupted chain of EBRs.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+/// The signature at the beginning of a GPT header.
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// The maximum number of GPT partition entries that will be read.
+const MAX_GPT_ENTRIES: usize = 1024;
+
+/// A globally-unique identifier, as used in a GPT.
+///
+/// The bytes are stored in the on-disk (mixed-endian) order.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15],
+        )
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// The type of a partition, as given by its partition table entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionType {
+    /// The one-byte partition type (system ID) of an MBR partition, e.g., `0x0C` for FAT32.
+    Mbr(u8),
+    /// The partition type GUID of a GPT partition.
+    Gpt(Guid),
+}
+
+/// A partition of a storage device, which is itself a [`StorageDevice`].
+///
+/// All block offsets used to access a `Partition` are relative to the start of that partition,
+/// and accesses beyond the end of the partition are rejected.
+pub struct Partition {
+    /// The storage device on which this partition resides.
+    device: StorageDeviceRef,
+    /// The number of this partition, starting at 1.
+    /// For MBR, logical partitions are numbered starting at 5.
+    number: usize,
+    /// The type of this partition.
+    partition_type: PartitionType,
+    /// The name of this partition, which is only available for GPT partitions.
+    name: Option<String>,
+    /// The block offset at which this partition begins on the underlying device.
+    start_block: usize,
+    /// The size of this partition in number of blocks.
+    num_blocks: usize,
+    /// The block size of the underlying device.
+    block_size: usize,
+}
+
+impl Partition {
+    /// Returns the number of this partition, starting at 1.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Returns the type of this partition.
+    pub fn partition_type(&self) -> PartitionType {
+        self.partition_type
+    }
+
+    /// Returns the name of this partition, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the block offset at which this partition begins on the underlying device.
+    pub fn start_block(&self) -> usize {
+        self.start_block
+    }
+
+    /// Returns the storage device on which this partition resides.
+    pub fn device(&self) -> &StorageDeviceRef {
+        &self.device
+    }
+
+    /// Converts the given `block_offset` within this partition into an offset
+    /// on the underlying device, after checking that a transfer of `buffer_len` bytes fits.
+    fn device_block_offset(&self, buffer_len: usize, block_offset: usize) -> Result<usize, IoError> {
+        let num_blocks = buffer_len.div_ceil(self.block_size);
+        match block_offset.checked_add(num_blocks) {
+            Some(end) if end <= self.num_blocks => Ok(self.start_block + block_offset),
+            _ => Err(IoError::InvalidInput),
+        }
+    }
+}
+
+impl fmt::Debug for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Partition")
+            .field("number", &self.number)
+            .field("partition_type", &self.partition_type)
+            .field("name", &self.name)
+            .field("start_block", &self.start_block)
+            .field("num_blocks", &self.num_blocks)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StorageDevice for Partition {
+    fn size_in_blocks(&self) -> usize {
+        self.num_blocks
+    }
+}
+impl BlockIo for Partition {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+}
+impl KnownLength for Partition {
+    fn len(&self) -> usize {
+        self.num_blocks * self.block_size
+    }
+}
+impl BlockReader for Partition {
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        let device_block_offset = self.device_block_offset(buffer.len(), block_offset)?;
+        self.device.lock().read_blocks(buffer, device_block_offset)
+    }
+}
+impl BlockWriter for Partition {
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        let device_block_offset = self.device_block_offset(buffer.len(), block_offset)?;
+        self.device.lock().write_blocks(buffer, device_block_offset)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.device.lock().flush()
+    }
+}
+
+
+/// Reads the partition table on the given storage `device` and returns its partitions.
+///
+/// Returns an empty list if the device doesn't have a valid MBR or GPT,
+/// e.g., if it contains a filesystem directly,
+/// or an error if the device couldn't be read or has a corrupted GPT.
+///
+/// The lock on `device` must not be held, as it is acquired within this function.
+pub fn read_partitions(device: &StorageDeviceRef) -> Result<Vec<Partition>, &'static str> {
+    let (block_size, device_blocks) = {
+        let locked_device = device.lock();
+        (locked_device.block_size(), locked_device.size_in_blocks())
+    };
+    if block_size < SECTOR_SIZE {
+        return Err("partition tables on devices with blocks smaller than 512 bytes are unsupported");
+    }
+
+    let mbr = read_blocks(device, 0, 1)?;
+    let Some(entries) = parse_mbr(&mbr, device_blocks) else {
+        debug!("No partition table found on storage device");
+        return Ok(Vec::new());
+    };
+
+    let new_partition = |number, partition_type, name, start_block, num_blocks| Partition {
+        device: device.clone(),
+        number,
+        partition_type,
+        name,
+        start_block,
+        num_blocks,
+        block_size,
+    };
+
+    if entries.iter().any(|e| e.partition_type == MBR_TYPE_GPT_PROTECTIVE) {
+        return Ok(read_gpt(device, block_size, device_blocks)?
+            .into_iter()
+            .map(|(number, entry)| new_partition(
+                number,
+                PartitionType::Gpt(entry.type_guid),
+                Some(entry.name),
+                entry.first_block,
+                entry.last_block - entry.first_block + 1,
+            ))
+            .collect()
+        );
+    }
+
+    let mut partitions = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.is_unused() {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&entry.partition_type) {
+            for (j, logical) in read_logical_partitions(device, entry.start_block, device_blocks)?.into_iter().enumerate() {
+                partitions.push(new_partition(5 + j, PartitionType::Mbr(logical.partition_type), None, logical.start_block, logical.num_blocks));
+            }
+        } else {
+            partitions.push(new_partition(i + 1, PartitionType::Mbr(entry.partition_type), None, entry.start_block, entry.num_blocks));
+        }
+    }
+    Ok(partitions)
+}
+
+/// Reads `count` blocks starting at `block_offset` from the given `device`.
+fn read_blocks(device: &StorageDeviceRef, block_offset: usize, count: usize) -> Result<Vec<u8>, &'static str> {
+    let mut locked_device = device.lock();
+    let mut buffer = vec![0; count * locked_device.block_size()];
+    locked_device.read_blocks(&mut buffer, block_offset)?;
+    Ok(buffer)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset .. offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset .. offset + 8].try_into().unwrap())
+}
+
+/// A partition entry in an MBR or EBR.
+#[derive(Debug)]
+struct MbrEntry {
+    status: u8,
+    partition_type: u8,
+    start_block: usize,
+    num_blocks: usize,
+}
+
+impl MbrEntry {
+    fn is_unused(&self) -> bool {
+        self.partition_type == 0 || self.num_blocks == 0
+    }
+}
+
+/// Parses the four partition entries in the given MBR or EBR `sector`.
+///
+/// Returns `None` if the sector isn't a valid MBR, e.g., because it is the boot sector
+/// of a filesystem that occupies the whole device, which also ends with the MBR signature.
+/// In that case, the partition entries are part of the boot code, so they are very unlikely
+/// to all have a valid status byte and lie within the device's `device_blocks`.
+fn parse_mbr(sector: &[u8], device_blocks: usize) -> Option<[MbrEntry; 4]> {
+    if sector[SECTOR_SIZE - 2 .. SECTOR_SIZE] != MBR_SIGNATURE {
+        return None;
+    }
+    let entries: [MbrEntry; 4] = core::array::from_fn(|i| {
+        let entry = &sector[MBR_ENTRIES_OFFSET + i * MBR_ENTRY_SIZE ..][.. MBR_ENTRY_SIZE];
+        MbrEntry {
+            status: entry[0],
+            partition_type: entry[4],
+            start_block: read_u32(entry, 8) as usize,
+            num_blocks: read_u32(entry, 12) as usize,
+        }
+    });
+    let is_valid = entries.iter().all(|e|
+        (e.status == 0x00 || e.status == 0x80) && (e.is_unused()
+            // A protective MBR may claim more blocks than the device has.
+            || e.partition_type == MBR_TYPE_GPT_PROTECTIVE
+            || e.start_block + e.num_blocks <= device_blocks)
+    );
+    let any_used = entries.iter().any(|e| !e.is_unused());
+    (is_valid && any_used).then_some(entries)
+}
+
+/// Reads the chain of EBRs within the extended partition that begins at `extended_start`,
+/// returning an entry for each logical partition with its absolute starting block.
+fn read_logical_partitions(device: &StorageDeviceRef, extended_start: usize, device_blocks: usize) -> Result<Vec<MbrEntry>, &'static str> {
+    let mut logical_partitions = Vec::new();
+    let mut ebr_block = extended_start;
+    while logical_partitions.len() < MAX_LOGICAL_PARTITIONS {
+        let ebr = read_blocks(device, ebr_block, 1)?;
+        let Some([mut logical, next, ..]) = parse_mbr(&ebr, device_blocks) else {
+            warn!("Invalid EBR at block {} of extended partition", ebr_block);
+            break;
+        };
+        // The first entry is relative to this EBR, and the second (the next EBR)
+        // is relative to the start of the extended partition.
+        logical.start_block += ebr_block;
+        if !logical.is_unused() {
+            logical_partitions.push(logical);
+        }
+        if next.is_unused() {
+            break;
+        }
+        ebr_block = extended_start + next.start_block;
+    }
+    Ok(logical_partitions)
+}
+
+/// A partition entry in a GPT.
+struct GptEntry {
+    type_guid: Guid,
+    first_block: usize,
+    /// The last block of the partition, inclusive.
+    last_block: usize,
+    name: String,
+}
+
+/// Reads the GPT that begins at block 1 of the given `device`,
+/// returning each used entry along with its partition number.
+fn read_gpt(device: &StorageDeviceRef, block_size: usize, device_blocks: usize) -> Result<Vec<(usize, GptEntry)>, &'static str> {
+    let header_block = read_blocks(device, 1, 1)?;
+    let header = &header_block[.. SECTOR_SIZE];
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err("missing GPT header signature");
+    }
+    let header_size = read_u32(header, 12) as usize;
+    if !(92 ..= SECTOR_SIZE).contains(&header_size) {
+        return Err("invalid GPT header size");
+    }
+    let mut header_for_crc = header[.. header_size].to_vec();
+    header_for_crc[16..20].fill(0);
+    if crc32fast::hash(&header_for_crc) != read_u32(header, 16) {
+        return Err("GPT header checksum mismatch");
+    }
+
+    let entries_start = read_u64(header, 72) as usize;
+    let num_entries = read_u32(header, 80) as usize;
+    let entry_size = read_u32(header, 84) as usize;
+    if num_entries > MAX_GPT_ENTRIES || entry_size < 128 || entry_size > block_size {
+        return Err("unsupported GPT partition entry array size");
+    }
+    let entries_len = num_entries * entry_size;
+    let entries_blocks = read_blocks(device, entries_start, entries_len.div_ceil(block_size))?;
+    let entries = &entries_blocks[.. entries_len];
+    if crc32fast::hash(entries) != read_u32(header, 88) {
+        return Err("GPT partition entry array checksum mismatch");
+    }
+
+    let mut gpt_entries = Vec::new();
+    for (i, entry) in entries.chunks_exact(entry_size).enumerate() {
+        let type_guid = Guid(entry[0..16].try_into().unwrap());
+        if type_guid.0 == [0; 16] {
+            continue;
+        }
+        let first_block = read_u64(entry, 32) as usize;
+        let last_block = read_u64(entry, 40) as usize;
+        if first_block > last_block || last_block >= device_blocks {
+            warn!("Ignoring GPT partition {} with invalid block range {}..={}", i + 1, first_block, last_block);
+            continue;
+        }
+        let name_utf16 = entry[56..128]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0);
+        let name = char::decode_utf16(name_utf16)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        gpt_entries.push((i + 1, GptEntry { type_guid, first_block, last_block, name }));
+    }
+    Ok(gpt_entries)
+}
@@ -0,0 +1,140 @@
+//! Unit tests for parsing MBR and GPT partition tables.
+
+extern crate std;
+use super::*;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+const NUM_BLOCKS: usize = 256;
+
+/// An in-memory storage device with 512-byte blocks.
+struct MemDevice(Vec<u8>);
+
+impl StorageDevice for MemDevice {
+    fn size_in_blocks(&self) -> usize { self.0.len() / SECTOR_SIZE }
+}
+impl BlockIo for MemDevice {
+    fn block_size(&self) -> usize { SECTOR_SIZE }
+}
+impl KnownLength for MemDevice {
+    fn len(&self) -> usize { self.0.len() }
+}
+impl BlockReader for MemDevice {
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        let start = block_offset * SECTOR_SIZE;
+        let src = self.0.get(start .. start + buffer.len()).ok_or(IoError::InvalidInput)?;
+        buffer.copy_from_slice(src);
+        Ok(buffer.len() / SECTOR_SIZE)
+    }
+}
+impl BlockWriter for MemDevice {
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        let start = block_offset * SECTOR_SIZE;
+        let dest = self.0.get_mut(start .. start + buffer.len()).ok_or(IoError::InvalidInput)?;
+        dest.copy_from_slice(buffer);
+        Ok(buffer.len() / SECTOR_SIZE)
+    }
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+fn device_from(image: Vec<u8>) -> StorageDeviceRef {
+    Arc::new(Mutex::new(MemDevice(image)))
+}
+
+/// Writes an MBR partition entry into the sector at `sector_start` in the given `image`.
+fn write_mbr_entry(image: &mut [u8], sector_start: usize, index: usize, partition_type: u8, start: u32, count: u32) {
+    let entry = &mut image[sector_start + MBR_ENTRIES_OFFSET + index * MBR_ENTRY_SIZE ..][.. MBR_ENTRY_SIZE];
+    entry[4] = partition_type;
+    entry[8..12].copy_from_slice(&start.to_le_bytes());
+    entry[12..16].copy_from_slice(&count.to_le_bytes());
+    image[sector_start + 510 .. sector_start + 512].copy_from_slice(&MBR_SIGNATURE);
+}
+
+#[test]
+fn test_mbr_with_logical_partitions() {
+    let mut image = vec![0; NUM_BLOCKS * SECTOR_SIZE];
+    write_mbr_entry(&mut image, 0, 0, 0x0C, 8, 32);
+    write_mbr_entry(&mut image, 0, 1, 0x05, 64, 128);
+    // The first EBR, at the start of the extended partition.
+    write_mbr_entry(&mut image, 64 * SECTOR_SIZE, 0, 0x83, 2, 30);
+    write_mbr_entry(&mut image, 64 * SECTOR_SIZE, 1, 0x05, 32, 64);
+    // The second EBR, whose logical partition is relative to itself.
+    write_mbr_entry(&mut image, 96 * SECTOR_SIZE, 0, 0x0B, 4, 16);
+    // Mark the first block of partition 1 so it can be read back.
+    image[8 * SECTOR_SIZE] = 0xAB;
+
+    let device = device_from(image);
+    let mut partitions = read_partitions(&device).unwrap();
+    let summary: Vec<_> = partitions.iter()
+        .map(|p| (p.number(), p.partition_type(), p.start_block(), p.size_in_blocks()))
+        .collect();
+    assert_eq!(summary, [
+        (1, PartitionType::Mbr(0x0C), 8, 32),
+        (5, PartitionType::Mbr(0x83), 66, 30),
+        (6, PartitionType::Mbr(0x0B), 100, 16),
+    ]);
+
+    let partition = &mut partitions[0];
+    assert_eq!(partition.len(), 32 * SECTOR_SIZE);
+    let mut buf = vec![0; SECTOR_SIZE];
+    partition.read_blocks(&mut buf, 0).unwrap();
+    assert_eq!(buf[0], 0xAB);
+    // Accesses beyond the end of the partition are rejected.
+    assert!(partition.read_blocks(&mut buf, 32).is_err());
+    assert!(partition.write_blocks(&buf, 32).is_err());
+}
+
+#[test]
+fn test_gpt() {
+    let mut image = vec![0; NUM_BLOCKS * SECTOR_SIZE];
+    write_mbr_entry(&mut image, 0, 0, MBR_TYPE_GPT_PROTECTIVE, 1, u32::MAX);
+
+    // Two 128-byte partition entries at block 2, the second of which is unused.
+    let type_guid = [0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B];
+    let entries_start = 2 * SECTOR_SIZE;
+    let entry = &mut image[entries_start .. entries_start + 128];
+    entry[0..16].copy_from_slice(&type_guid);
+    entry[32..40].copy_from_slice(&34u64.to_le_bytes());
+    entry[40..48].copy_from_slice(&99u64.to_le_bytes());
+    for (i, c) in "EFI".encode_utf16().enumerate() {
+        entry[56 + 2 * i .. 58 + 2 * i].copy_from_slice(&c.to_le_bytes());
+    }
+    let entries_crc = crc32fast::hash(&image[entries_start .. entries_start + 256]);
+
+    let header = &mut image[SECTOR_SIZE .. SECTOR_SIZE + 92];
+    header[0..8].copy_from_slice(GPT_SIGNATURE);
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&2u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let header_crc = crc32fast::hash(header);
+    header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+    let partitions = read_partitions(&device_from(image.clone())).unwrap();
+    assert_eq!(partitions.len(), 1);
+    assert_eq!(partitions[0].number(), 1);
+    assert_eq!(partitions[0].partition_type(), PartitionType::Gpt(Guid(type_guid)));
+    assert_eq!(std::format!("{}", Guid(type_guid)), "C12A7328-F81F-11D2-BA4B-00A0C93EC93B");
+    assert_eq!(partitions[0].name(), Some("EFI"));
+    assert_eq!(partitions[0].start_block(), 34);
+    assert_eq!(partitions[0].size_in_blocks(), 66);
+
+    // A corrupted partition entry array is detected.
+    image[entries_start + 32] = 35;
+    assert!(read_partitions(&device_from(image)).is_err());
+}
+
+#[test]
+fn test_no_partition_table() {
+    // A FAT boot sector occupying the whole device ends with the MBR signature,
+    // but its boot code doesn't form valid partition entries.
+    let mut image = vec![0; NUM_BLOCKS * SECTOR_SIZE];
+    image[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    image[MBR_ENTRIES_OFFSET .. 510].fill(0xF4);
+    image[510..512].copy_from_slice(&MBR_SIGNATURE);
+    assert!(read_partitions(&device_from(image)).unwrap().is_empty());
+
+    let blank = vec![0; NUM_BLOCKS * SECTOR_SIZE];
+    assert!(read_partitions(&device_from(blank)).unwrap().is_empty());
+}
@@ -9,7 +9,7 @@ extern crate alloc;
 
 mod component;
 
-use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+use alloc::{borrow::ToOwned, format, string::String, sync::Arc, vec, vec::Vec};
 use core::{
     borrow::Borrow,
     fmt::{self, Display},
@@ -17,6 +17,7 @@ use core::{
 };
 
 pub use component::{Component, Components};
+use fs_node::{DirRef, Directory, FileOrDir, FsNode};
 
 /// A slice of a path.
 ///
@@ -289,6 +290,10 @@ impl Path {
     /// The path can be relative or absolute.
     ///
     /// If the path does not point to a file system object, `None` is returned.
+    ///
+    /// Filesystems mounted via the [`mount_table`] are honored:
+    /// any directory that is a mount point is transparently replaced by
+    /// the root directory of the filesystem mounted over it.
     #[inline]
     pub fn get(&self, cwd: &fs_node::DirRef) -> Option<fs_node::FileOrDir> {
         let mut iter = self.components().peekable();
@@ -297,7 +302,7 @@ impl Path {
                 iter.next();
                 root::get_root().clone()
             }
-            _ => cwd.clone(),
+            _ => mount_table::resolve(cwd.clone()),
         };
 
         while let Some(component) = iter.next() {
@@ -305,19 +310,29 @@ impl Path {
                 Component::RootDir => current = root::get_root().clone(),
                 Component::CurDir => {}
                 Component::ParentDir => {
-                    let temp = current.lock().get_parent_dir()?;
-                    current = temp;
+                    // Leaving a mounted filesystem's root directory goes to its mount point's parent.
+                    let temp = match mount_table::mount_point_of(&current) {
+                        Some(mount_point) => mount_point.lock().get_parent_dir()?,
+                        None => current.lock().get_parent_dir()?,
+                    };
+                    current = mount_table::resolve(temp);
                 }
                 Component::Normal(name) => {
                     if iter.peek().is_none() {
-                        return current.lock().get(name);
+                        let node = current.lock().get(name);
+                        return match node {
+                            Some(fs_node::FileOrDir::Dir(directory)) => {
+                                Some(fs_node::FileOrDir::Dir(mount_table::resolve(directory)))
+                            }
+                            other => other,
+                        };
                     } else {
                         let temp = match current.lock().get(name) {
                             Some(fs_node::FileOrDir::Dir(directory)) => directory,
                             // Path didn't exist or had a file in the middle e.g. /dir/file/dir
                             _ => return None,
                         };
-                        current = temp;
+                        current = mount_table::resolve(temp);
                     }
                 }
             }
@@ -444,6 +459,76 @@ impl Path {
     }
 }
 
+/// Renames or moves the file or directory at path `from` to path `to`.
+///
+/// Both paths can be relative or absolute.
+/// The parent directory of `to` must already exist,
+/// and the final component of `to` becomes the node's new name.
+/// If `to` refers to an existing file, that file is replaced;
+/// if it refers to an existing directory, an error is returned.
+///
+/// The move is atomic with respect to other filesystem operations,
+/// as both the source and destination directories are locked throughout;
+/// see [`Directory::move_child()`].
+///
+/// Neither the root directory nor the root directory of a mounted filesystem can be moved,
+/// nor can a directory that contains mount points.
+pub fn rename(from: &Path, to: &Path, cwd: &DirRef) -> Result<(), &'static str> {
+    let node = from.get(cwd).ok_or("source path does not exist")?;
+    let new_name = to.file_name().ok_or("destination path has no file name")?;
+    let old_parent = from.parent()
+        .and_then(|p| p.get_dir(cwd))
+        .ok_or("source path has no parent directory")?;
+    let new_parent = to.parent()
+        .and_then(|p| p.get_dir(cwd))
+        .ok_or("destination's parent directory does not exist")?;
+
+    if let FileOrDir::Dir(dir) = &node {
+        if Arc::ptr_eq(dir, root::get_root()) || mount_table::mount_point_of(dir).is_some() {
+            return Err("cannot move the root directory of a filesystem");
+        }
+        let nested_prefix = format!("{}/", node.get_absolute_path());
+        if mount_table::mounts().iter().any(|m| m.target().starts_with(&nested_prefix)) {
+            return Err("cannot move a directory that contains mount points");
+        }
+        // Walk up from the destination to ensure it isn't within the directory being moved.
+        let mut ancestor = Some(new_parent.clone());
+        while let Some(current) = ancestor {
+            if Arc::ptr_eq(&current, dir) {
+                return Err("cannot move a directory into itself");
+            }
+            if Arc::ptr_eq(&current, root::get_root()) {
+                break;
+            }
+            ancestor = match mount_table::mount_point_of(&current) {
+                Some(mount_point) => mount_point.lock().get_parent_dir(),
+                None => current.lock().get_parent_dir(),
+            };
+        }
+    }
+
+    let name = node.get_name();
+    let new_parent_ref = Arc::downgrade(&new_parent);
+
+    if Arc::ptr_eq(&old_parent, &new_parent) {
+        if name == new_name {
+            return Ok(());
+        }
+        return old_parent.lock().move_child(&name, None, new_parent_ref, new_name);
+    }
+
+    // Always lock the two directories in the same order to avoid deadlocking with a concurrent rename.
+    let source_first = (Arc::as_ptr(&old_parent) as *const u8) < (Arc::as_ptr(&new_parent) as *const u8);
+    let (mut source, mut dest) = if source_first {
+        let source = old_parent.lock();
+        (source, new_parent.lock())
+    } else {
+        let dest = new_parent.lock();
+        (old_parent.lock(), dest)
+    };
+    source.move_child(&name, Some(&mut *dest as &mut dyn Directory), new_parent_ref, new_name)
+}
+
 /// An owned, mutable path.
 ///
 /// This type is just a wrapper around a [`String`].
@@ -0,0 +1,201 @@
+//! A telnet-style remote shell service, for interacting with Theseus over the network.
+//!
+//! The service listens on a TCP port and runs a `hull` shell for each connection.
+//! Like the serial [`console`], each connection gets its own [`tty::Tty`], whose master end
+//! is bridged to the connection and whose slave end is the shell's standard streams.
+//! Thus, line editing and Ctrl+C work the same way as on the serial console.
+//!
+//! The service is started during boot if Theseus is built with the `remote_shell` config option,
+//! e.g., `make run THESEUS_CONFIG="remote_shell"`, after which a client can connect to it with:
+//! ```sh
+//! telnet <ADDRESS> 23
+//! ```
+//! With QEMU's user networking, the port must be forwarded to the host to be reachable,
+//! e.g., with `hostfwd=tcp::2323-:23`.
+//! There is no authentication, so the service should only be used on trusted networks.
+
+#![no_std]
+
+extern crate alloc;
+
+mod telnet;
+
+use alloc::{format, sync::Arc, vec, vec::Vec};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+use log::{error, info, warn};
+use net::{tcp, IpEndpoint, NetworkInterface, Socket};
+use task::{JoinableTaskRef, KillReason, TaskRef};
+
+/// The default TCP port of telnet servers.
+pub const TELNET_PORT: u16 = 23;
+
+const SOCKET_BUFFER_SIZE: usize = 4096;
+/// The number of sockets listening at once, such that connections arriving
+/// while another one is being accepted aren't refused.
+const LISTEN_BACKLOG: usize = 4;
+
+type TcpSocket = Socket<tcp::Socket<'static>>;
+
+/// Starts a task that listens on the given `port` of `interface`
+/// and runs a shell for each connection it accepts.
+///
+/// Returns the newly-spawned listener task.
+pub fn start(interface: Arc<NetworkInterface>, port: u16) -> Result<JoinableTaskRef, &'static str> {
+    spawn::new_task_builder(listener_task, (interface, port))
+        .name(format!("remote_shell_listener_{port}"))
+        .spawn()
+}
+
+/// The entry point for the listener task.
+fn listener_task((interface, port): (Arc<NetworkInterface>, u16)) -> Result<(), &'static str> {
+    info!("remote_shell: listening on port {}", port);
+    let mut backlog = Vec::with_capacity(LISTEN_BACKLOG);
+    loop {
+        fill_backlog(&interface, port, &mut backlog)?;
+        // Accept the connection on whichever socket in the backlog established one first.
+        let (index, result) = net::block_on(|cx| {
+            backlog
+                .iter()
+                .enumerate()
+                .find_map(|(i, socket)| match socket.poll_accept(cx) {
+                    Poll::Ready(result) => Some((i, result)),
+                    Poll::Pending => None,
+                })
+                .map_or(Poll::Pending, Poll::Ready)
+        });
+        let socket = backlog.remove(index);
+        let remote = match result {
+            Ok(remote) => remote,
+            Err(_) => {
+                socket.remove();
+                continue;
+            }
+        };
+
+        if spawn::new_task_builder(session_task, (socket, remote))
+            .name(format!("remote_shell_{remote}"))
+            .spawn()
+            .is_err()
+        {
+            warn!("remote_shell: failed to spawn session for {}", remote);
+        }
+    }
+}
+
+/// Adds sockets listening on the given `port` of `interface` to the backlog until it's full.
+///
+/// This only fails if the backlog is left empty, i.e., if no socket is listening.
+fn fill_backlog(interface: &Arc<NetworkInterface>, port: u16, backlog: &mut Vec<TcpSocket>) -> Result<(), &'static str> {
+    while backlog.len() < LISTEN_BACKLOG {
+        let socket = interface.clone().add_socket(tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER_SIZE]),
+        ));
+        let result = socket.lock().listen(port);
+        if result.is_err() {
+            socket.remove();
+            if backlog.is_empty() {
+                return Err("remote_shell: failed to listen on port");
+            }
+            break;
+        }
+        backlog.push(socket);
+    }
+    Ok(())
+}
+
+/// The entry point for the task that manages the shell of one connection.
+fn session_task((socket, remote): (TcpSocket, IpEndpoint)) -> Result<(), &'static str> {
+    info!("remote_shell: creating new tty for {}", remote);
+    let socket = Arc::new(socket);
+    send_all(&socket, &telnet::NEGOTIATION)?;
+
+    let tty = tty::Tty::new();
+    let shell = console::spawn_shell(&tty, format!("remote_shell_{remote}_hull"))?;
+
+    let closed = Arc::new(AtomicBool::new(false));
+    let output_task = spawn::new_task_builder(tty_to_socket_loop, (tty.master(), socket.clone(), closed.clone()))
+        .name(format!("tty_to_{remote}"))
+        .spawn()?;
+    let input_task = spawn::new_task_builder(socket_to_tty_loop, (socket.clone(), tty.master(), (*shell).clone()))
+        .name(format!("{remote}_to_tty"))
+        .spawn()?;
+
+    shell.join()?;
+    info!("remote_shell: shell for {} exited", remote);
+
+    // Wake the output task so it sees that the shell has exited,
+    // after it has sent everything the shell wrote.
+    // NUL bytes are ignored by telnet clients.
+    closed.store(true, Ordering::Release);
+    let _ = tty.slave().write_byte(0);
+    output_task.join()?;
+
+    // Closing the connection makes the client close its side too,
+    // at which point the input task exits.
+    socket.lock().close();
+    socket.interface().wake();
+    input_task.join()?;
+
+    match Arc::try_unwrap(socket) {
+        Ok(socket) => socket.remove(),
+        Err(_) => error!("BUG: remote_shell: socket for {} is still in use", remote),
+    }
+    Ok(())
+}
+
+/// Sends the shell's output to the client until the shell has exited.
+fn tty_to_socket_loop((master, socket, closed): (tty::Master, Arc<TcpSocket>, Arc<AtomicBool>)) {
+    let mut data = [0; 256];
+    let mut encoded = Vec::with_capacity(data.len());
+    loop {
+        let len = match master.read(&mut data) {
+            Ok(len) => len,
+            Err(e) => {
+                error!("remote_shell: couldn't read from master: {e}");
+                continue;
+            }
+        };
+        encoded.clear();
+        telnet::encode(&data[..len], &mut encoded);
+        // If the client has disconnected, the output is discarded until the shell exits.
+        let _ = send_all(&socket, &encoded);
+
+        if closed.load(Ordering::Acquire) {
+            return;
+        }
+    }
+}
+
+/// Passes the client's input to the shell until the client disconnects,
+/// at which point the shell is killed.
+fn socket_to_tty_loop((socket, master, shell): (Arc<TcpSocket>, tty::Master, TaskRef)) {
+    let mut decoder = telnet::Decoder::default();
+    let mut data = [0; 256];
+    let mut decoded = Vec::with_capacity(data.len());
+    while let Ok(len) = socket.recv(&mut data) {
+        decoded.clear();
+        decoder.decode(&data[..len], &mut decoded);
+        if let Err(e) = master.write(&decoded) {
+            error!("remote_shell: couldn't write to master: {e}");
+        }
+    }
+
+    // The shell may have already exited, in which case there's nothing to kill.
+    if !shell.has_exited() {
+        info!("remote_shell: client disconnected, killing its shell");
+        let _ = shell.kill(KillReason::Requested);
+    }
+}
+
+/// Sends all of `data`, blocking until it has been enqueued in the socket's transmit buffer.
+fn send_all(socket: &TcpSocket, mut data: &[u8]) -> Result<(), &'static str> {
+    while !data.is_empty() {
+        let sent = socket.send(data).map_err(|_| "remote_shell: connection closed")?;
+        data = &data[sent..];
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! The subset of the telnet protocol (RFC 854) needed to drive a character-mode terminal.
+//!
+//! The server asks the client to let it echo input and to stop sending go-aheads,
+//! which puts most clients into character mode, i.e., every keystroke is sent as is.
+//! Input is then passed to the TTY just like bytes received on a serial port, so the
+//! line discipline handles editing, echoing, and control characters like Ctrl+C.
+
+use alloc::vec::Vec;
+
+/// "Interpret as command": introduces a telnet command.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+/// Starts subnegotiation, which ends with `IAC SE`.
+const SB: u8 = 250;
+/// Erase character.
+const EC: u8 = 247;
+/// Interrupt process, sent by some clients in place of Ctrl+C.
+const IP: u8 = 244;
+const SE: u8 = 240;
+
+const OPTION_ECHO: u8 = 1;
+const OPTION_SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// The options that the server announces when a client connects.
+pub(crate) const NEGOTIATION: [u8; 6] = [IAC, WILL, OPTION_ECHO, IAC, WILL, OPTION_SUPPRESS_GO_AHEAD];
+
+/// The control characters that the line discipline interprets.
+const CTRL_C: u8 = 0x03;
+const DEL: u8 = 0x7f;
+
+/// Strips telnet commands from the data received from a client.
+#[derive(Default)]
+pub(crate) struct Decoder {
+    state: State,
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Data,
+    /// The previous byte was a carriage return, which may be followed by a line feed or NUL.
+    CarriageReturn,
+    /// The previous byte was `IAC`.
+    Command,
+    /// The previous bytes were `IAC` and an option negotiation command.
+    Option,
+    /// Within a subnegotiation.
+    Subnegotiation,
+    /// The previous byte was `IAC` within a subnegotiation.
+    SubnegotiationCommand,
+}
+
+impl Decoder {
+    /// Decodes the given bytes received from the client,
+    /// appending the terminal input they contain to `output`.
+    pub(crate) fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (&self.state, byte) {
+                (State::Data | State::CarriageReturn, IAC) => State::Command,
+                // Clients send Enter as `CR LF` or `CR NUL`,
+                // whereas serial terminals just send `CR`.
+                (State::CarriageReturn, b'\n' | 0) => State::Data,
+                (State::Data | State::CarriageReturn, b'\r') => {
+                    output.push(b'\r');
+                    State::CarriageReturn
+                }
+                (State::Data | State::CarriageReturn, _) => {
+                    output.push(byte);
+                    State::Data
+                }
+                (State::Command, IAC) => {
+                    // An escaped 255 data byte.
+                    output.push(IAC);
+                    State::Data
+                }
+                (State::Command, WILL | WONT | DO | DONT) => State::Option,
+                (State::Command, SB) => State::Subnegotiation,
+                (State::Command, command) => {
+                    match command {
+                        IP => output.push(CTRL_C),
+                        EC => output.push(DEL),
+                        // Other commands, e.g., no-ops and go-aheads, are ignored.
+                        _ => {}
+                    }
+                    State::Data
+                }
+                // Option negotiations are ignored, since we only ever use the options we announced.
+                (State::Option, _) => State::Data,
+                (State::Subnegotiation, IAC) => State::SubnegotiationCommand,
+                (State::Subnegotiation, _) => State::Subnegotiation,
+                (State::SubnegotiationCommand, SE) => State::Data,
+                (State::SubnegotiationCommand, _) => State::Subnegotiation,
+            };
+        }
+    }
+}
+
+/// Appends the given terminal output to `output`, escaping any bytes that
+/// would otherwise be interpreted as telnet commands.
+pub(crate) fn encode(input: &[u8], output: &mut Vec<u8>) {
+    for &byte in input {
+        if byte == IAC {
+            output.push(IAC);
+        }
+        output.push(byte);
+    }
+}
@@ -14,7 +14,7 @@ use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::{Arc, Weak};
 use alloc::collections::BTreeMap;
-use fs_node::{DirRef, Directory, FileOrDir, FsNode, WeakDirRef};
+use fs_node::{DirRef, Directory, FileOrDir, FsNode, Metadata, WeakDirRef};
 
 
 pub const ROOT_DIRECTORY_NAME: &str = "";
@@ -97,4 +97,8 @@ impl FsNode for RootDirectory {
     fn set_parent_dir(&mut self, _: WeakDirRef) {
         // do nothing
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::directory(false)
+    }
 }
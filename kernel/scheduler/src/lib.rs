@@ -16,6 +16,9 @@
 
 use interrupts::{self, CPU_LOCAL_TIMER_IRQ, interrupt_handler, eoi, EoiBehaviour};
 
+#[cfg(all(tickless, target_arch = "x86_64"))]
+mod tickless;
+
 /// Re-exports for convenience and legacy compatibility.
 pub use task::scheduler::{inherit_priority, priority, schedule, set_priority};
 
@@ -29,8 +32,20 @@ pub use task::scheduler::{inherit_priority, priority, schedule, set_priority};
 /// - `make`: round-robin scheduler
 /// - `make THESEUS_CONFIG=epoch_scheduler`: epoch scheduler
 /// - `make THESEUS_CONFIG=priority_scheduler`: priority scheduler
+/// - `make THESEUS_CONFIG=cfs_scheduler`: completely fair scheduler
+///
+/// Regardless of the policy, tasks spawned with a real-time policy
+/// (see `spawn::TaskBuilder::fifo()` and `spawn::TaskBuilder::deadline()`)
+/// are scheduled ahead of all others by the `scheduler_realtime` policy.
+///
+/// On x86_64, `make THESEUS_CONFIG=tickless` additionally enables tickless scheduling,
+/// in which each CPU's timer is programmed one-shot for the end of the current timeslice
+/// or the next `sleep` deadline, and idle CPUs stop ticking.
 pub fn init() -> Result<(), &'static str> {
     #[cfg(target_arch = "x86_64")] {
+        #[cfg(tickless)]
+        tickless::init()?;
+
         interrupts::register_interrupt(
             CPU_LOCAL_TIMER_IRQ,
             timer_tick_handler,
@@ -51,6 +66,8 @@ pub fn init() -> Result<(), &'static str> {
 interrupt_handler!(timer_tick_handler, _, _stack_frame, {
     #[cfg(target_arch = "aarch64")]
     generic_timer_aarch64::set_next_timer_interrupt(get_timeslice_ticks());
+    #[cfg(all(tickless, target_arch = "x86_64"))]
+    tickless::rearm_timer();
 
     // tick count, only used for debugging
     if false {
@@ -68,7 +85,7 @@ interrupt_handler!(timer_tick_handler, _, _stack_frame, {
     // because we switch tasks here, which doesn't return.
     eoi(CPU_LOCAL_TIMER_IRQ);
 
-    schedule();
+    task::scheduler::preempt();
 
     EoiBehaviour::HandlerSentEoi
 });
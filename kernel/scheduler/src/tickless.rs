@@ -0,0 +1,131 @@
+//! Tickless scheduling, in which each CPU's timer is programmed one-shot
+//! rather than firing periodically.
+//!
+//! Whenever a CPU switches to a task, its timer is programmed to fire when that task's
+//! timeslice ends or when the next sleeping task should be woken up, whichever comes first.
+//! When a CPU switches to its idle task, the timer only fires to wake up the next sleeping task,
+//! and not at all if there is none, so idle CPUs stop ticking.
+//! Because an idle CPU no longer ticks, it halts until it is sent a reschedule IPI,
+//! which happens whenever a task is added to its run queue or a blocked task becomes runnable,
+//! such that the CPU switches to that task.
+//!
+//! Sleeping tasks are woken up by whichever CPU's timer fires first once their sleep ends,
+//! so sleeps are no longer rounded up to the next tick. The exception is a sleep that begins
+//! without the CPU switching tasks, e.g., that of an async sleep future, which may only end
+//! once the current timeslice of some CPU does.
+
+use core::{
+    convert::TryFrom,
+    sync::atomic::{fence, AtomicBool, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use apic::{ApicId, LapicIpiDestination};
+use cpu::CpuId;
+use interrupts::{eoi, interrupt_handler, EoiBehaviour};
+use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
+use task::TaskRef;
+use time::{now, Monotonic};
+
+const TIMESLICE: Duration = Duration::from_micros(CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u64);
+
+/// The number of CPUs whose idleness is tracked, which covers every xAPIC ID.
+///
+/// Idle CPUs with a higher ID can't be sent a reschedule IPI, so their timer keeps ticking.
+const MAX_IDLE_CPUS: usize = 256;
+
+/// Whether each CPU is running its idle task, indexed by CPU ID.
+static IDLE_CPUS: [AtomicBool; MAX_IDLE_CPUS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NOT_IDLE: AtomicBool = AtomicBool::new(false);
+    [NOT_IDLE; MAX_IDLE_CPUS]
+};
+
+/// The interrupt number of the reschedule IPI, which is sent to idle CPUs to wake them up.
+static RESCHEDULE_IPI_IRQ: AtomicU8 = AtomicU8::new(0);
+
+/// Registers the reschedule IPI handler and the hooks that program each CPU's timer
+/// whenever it switches tasks and wake up idle CPUs when a task becomes ready to run.
+pub(crate) fn init() -> Result<(), &'static str> {
+    let irq = interrupts::register_msi_interrupt(reschedule_ipi_handler)?;
+    RESCHEDULE_IPI_IRQ.store(irq, Ordering::Relaxed);
+    task::scheduler::set_wake_hook(wake_idle_cpus)?;
+    task::scheduler::set_schedule_hook(program_timer)
+}
+
+// Invokes the scheduler on an idle CPU that was sent a reschedule IPI.
+interrupt_handler!(reschedule_ipi_handler, _, _stack_frame, {
+    // Acknowledge the interrupt before switching tasks, which doesn't return.
+    eoi(RESCHEDULE_IPI_IRQ.load(Ordering::Relaxed));
+
+    task::scheduler::preempt();
+
+    EoiBehaviour::HandlerSentEoi
+});
+
+/// Programs the current CPU's timer to fire at the end of a full timeslice.
+///
+/// This is called from the timer interrupt handler before scheduling,
+/// in case the scheduler can't switch tasks, e.g., because preemption is disabled.
+pub(crate) fn rearm_timer() {
+    set_timer(Some(TIMESLICE));
+}
+
+/// Programs the current CPU's timer for the `next` task that it is about to run.
+fn program_timer(next: &TaskRef) {
+    let can_be_woken = set_idle(cpu::current_cpu(), next.is_an_idle_task);
+    let now = now::<Monotonic>();
+    let next_wakeup = sleep::next_wakeup_time();
+    let deadline = if next.is_an_idle_task && can_be_woken {
+        next_wakeup
+    } else {
+        let timeslice_end = now + TIMESLICE;
+        Some(next_wakeup.map_or(timeslice_end, |wakeup| wakeup.min(timeslice_end)))
+    };
+    set_timer(deadline.map(|deadline| deadline.duration_since(now)));
+}
+
+/// Records whether the given CPU is about to run its idle task.
+///
+/// Returns `false` if the CPU's idleness isn't tracked, in which case it can't be woken up.
+fn set_idle(cpu_id: CpuId, idle: bool) -> bool {
+    let Some(flag) = IDLE_CPUS.get(cpu_id.value() as usize) else { return false };
+    flag.store(idle, Ordering::Relaxed);
+    // Order the store before this CPU next checks its run queue,
+    // such that a task added in the meantime is either seen or followed by an IPI.
+    // This pairs with the fence in `wake_idle_cpus()`.
+    fence(Ordering::SeqCst);
+    true
+}
+
+/// Sends a reschedule IPI to the given CPU, or to all CPUs if `None`, that are idle.
+fn wake_idle_cpus(cpu_id: Option<CpuId>) {
+    // Order the caller's adding or unblocking of a task before checking which CPUs are idle.
+    // This pairs with the fence in `set_idle()`.
+    fence(Ordering::SeqCst);
+    let Some(lapic) = apic::get_my_apic() else { return };
+    let irq = RESCHEDULE_IPI_IRQ.load(Ordering::Relaxed);
+    let is_idle = |id: usize| IDLE_CPUS.get(id).map_or(false, |idle| idle.load(Ordering::Relaxed));
+
+    if let Some(cpu_id) = cpu_id {
+        if is_idle(cpu_id.value() as usize) {
+            lapic.write().send_ipi(irq, LapicIpiDestination::One(cpu_id.into()));
+        }
+        return;
+    }
+    for id in (0..MAX_IDLE_CPUS).filter(|&id| is_idle(id)) {
+        if let Ok(apic_id) = ApicId::try_from(id as u32) {
+            lapic.write().send_ipi(irq, LapicIpiDestination::One(apic_id));
+        }
+    }
+}
+
+fn set_timer(timeout: Option<Duration>) {
+    if let Some(lapic) = apic::get_my_apic() {
+        let mut lapic = lapic.write();
+        // CPUs whose timer can't be programmed one-shot keep ticking periodically.
+        if lapic.supports_oneshot_timer() {
+            let _ = lapic.set_timer_oneshot(timeout);
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! This crate implements a completely fair scheduling policy, modeled after Linux's CFS.
+//!
+//! Each task accumulates *virtual runtime*: the time it has actually run,
+//! scaled by the weight of a default-priority task relative to its own weight.
+//! The scheduler always picks the runnable task with the least virtual runtime,
+//! so each runnable task receives CPU time in proportion to its weight,
+//! regardless of how often tasks block or yield before their timeslice ends.
+//!
+//! Weights are derived from priorities in the same way that Linux derives them from nice values:
+//! priorities range from 0 (nice 19) to 39 (nice -20), with a default of 19 (nice 0),
+//! and each step in priority changes a task's weight by about 25%.
+//!
+//! A task isn't credited for all the time it was blocked, which would let it monopolize the CPU
+//! once it wakes up. Instead, its virtual runtime is raised to slightly less than that of the
+//! other runnable tasks, such that interactive tasks run soon after waking without starving others.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+use task::TaskRef;
+use time::Instant;
+
+const DEFAULT_PRIORITY: u8 = 19;
+const MAX_PRIORITY: u8 = 39;
+
+/// The weight of a task with the default priority.
+const DEFAULT_WEIGHT: u64 = 1024;
+
+/// The weights of nice values -20 to 19, taken from Linux's `sched_prio_to_weight`.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, // -20
+    29154, 23254, 18705, 14949, 11916, // -15
+    9548, 7620, 6100, 4904, 3906, // -10
+    3121, 2501, 1991, 1586, 1277, // -5
+    1024, 820, 655, 526, 423, // 0
+    335, 272, 215, 172, 137, // 5
+    110, 87, 70, 56, 45, // 10
+    36, 29, 23, 18, 15, // 15
+];
+
+/// How much less virtual runtime, in nanoseconds, a task that was blocked may have
+/// than the least-run runnable task when it is next picked.
+const SLEEPER_CREDIT_NANOS: u64 = 4_000_000;
+
+/// An instance of a completely fair scheduler, typically one per CPU.
+pub struct Scheduler {
+    idle_task: TaskRef,
+    /// The tasks in the run queue ordered by virtual runtime,
+    /// with ties broken by the order in which they were inserted.
+    tree: BTreeMap<(u64, u64), TaskRef>,
+    /// The scheduling state of each task in `tree`, by task ID.
+    entities: BTreeMap<usize, Entity>,
+    /// A monotonically increasing lower bound on the virtual runtime of runnable tasks.
+    min_vruntime: u64,
+    next_sequence: u64,
+    /// The ID of the task that was last picked to run and when it was picked.
+    current: Option<(usize, Instant)>,
+}
+
+struct Entity {
+    /// The task's key in the tree, whose first element is its virtual runtime.
+    key: (u64, u64),
+    priority: u8,
+}
+
+impl Scheduler {
+    /// Creates a new completely fair scheduler instance with the given idle task.
+    pub fn new(idle_task: TaskRef) -> Self {
+        Self {
+            idle_task,
+            tree: BTreeMap::new(),
+            entities: BTreeMap::new(),
+            min_vruntime: 0,
+            next_sequence: 0,
+            current: None,
+        }
+    }
+
+    fn insert(&mut self, task: TaskRef, vruntime: u64, priority: u8) {
+        let key = (vruntime, self.next_sequence);
+        self.next_sequence += 1;
+        self.entities.insert(task.id, Entity { key, priority });
+        self.tree.insert(key, task);
+    }
+
+    fn take(&mut self, task_id: usize) -> Option<(TaskRef, Entity)> {
+        let entity = self.entities.remove(&task_id)?;
+        let task = self.tree.remove(&entity.key)?;
+        Some((task, entity))
+    }
+
+    /// Charges the task that was last picked to run for the time it has run since.
+    fn update_current(&mut self, now: Instant) {
+        let Some((task_id, picked)) = self.current.take() else { return };
+        let Some((task, entity)) = self.take(task_id) else { return };
+        let ran = now.duration_since(picked).as_nanos() as u64;
+        let vruntime = entity.key.0.saturating_add(ran.saturating_mul(DEFAULT_WEIGHT) / weight(entity.priority));
+        self.insert(task, vruntime, entity.priority);
+    }
+}
+
+impl task::scheduler::Scheduler for Scheduler {
+    fn next(&mut self) -> TaskRef {
+        let now = time::now::<time::Monotonic>();
+        self.update_current(now);
+
+        let Some((&(vruntime, _), task)) = self.tree.iter().find(|(_, task)| task.is_runnable()) else {
+            return self.idle_task.clone();
+        };
+        let task = task.clone();
+
+        let floor = self.min_vruntime.saturating_sub(SLEEPER_CREDIT_NANOS);
+        if vruntime < floor {
+            if let Some((task, entity)) = self.take(task.id) {
+                self.insert(task, floor, entity.priority);
+            }
+        }
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        self.current = Some((task.id, now));
+        task
+    }
+
+    fn add(&mut self, task: TaskRef) {
+        if !self.entities.contains_key(&task.id) {
+            // New tasks start out even with the least-run runnable task.
+            self.insert(task, self.min_vruntime, DEFAULT_PRIORITY);
+        }
+    }
+
+    fn busyness(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn remove(&mut self, task: &TaskRef) -> bool {
+        if matches!(self.current, Some((id, _)) if id == task.id) {
+            self.current = None;
+        }
+        self.take(task.id).is_some()
+    }
+
+    fn as_priority_scheduler(&mut self) -> Option<&mut dyn task::scheduler::PriorityScheduler> {
+        Some(self)
+    }
+
+    fn drain(&mut self) -> Box<dyn Iterator<Item = TaskRef> + '_> {
+        self.entities.clear();
+        self.current = None;
+        Box::new(core::mem::take(&mut self.tree).into_values())
+    }
+
+    fn tasks(&self) -> Vec<TaskRef> {
+        self.tree.values().cloned().collect()
+    }
+}
+
+impl task::scheduler::PriorityScheduler for Scheduler {
+    fn set_priority(&mut self, task: &TaskRef, priority: u8) -> bool {
+        // Charge the task at its old weight for the time it has run so far.
+        if matches!(self.current, Some((id, _)) if id == task.id) {
+            let now = time::now::<time::Monotonic>();
+            self.update_current(now);
+            self.current = Some((task.id, now));
+        }
+        match self.entities.get_mut(&task.id) {
+            Some(entity) => {
+                entity.priority = core::cmp::min(priority, MAX_PRIORITY);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn priority(&mut self, task: &TaskRef) -> Option<u8> {
+        self.entities.get(&task.id).map(|entity| entity.priority)
+    }
+}
+
+/// Returns the weight of a task with the given priority.
+fn weight(priority: u8) -> u64 {
+    NICE_TO_WEIGHT[(MAX_PRIORITY - priority.min(MAX_PRIORITY)) as usize]
+}
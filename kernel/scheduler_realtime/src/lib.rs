@@ -0,0 +1,323 @@
+//! This crate implements real-time scheduling policies on top of a normal scheduling policy.
+//!
+//! The [`Scheduler`] wraps the normal scheduler of a CPU and schedules the tasks
+//! that have a [`RealTimePolicy`] ahead of all other tasks:
+//! 1. Deadline tasks are scheduled by earliest deadline first (EDF),
+//!    with each task's CPU time limited by a constant bandwidth server (CBS).
+//!    A task that uses up its `runtime` is throttled until its next period,
+//!    which prevents it from jeopardizing the deadlines of other tasks.
+//! 2. FIFO tasks are scheduled by priority, and run until they block or yield.
+//!    Tasks with the same priority run in the order that they became runnable,
+//!    and a task that yields goes behind the others of its priority.
+//! 3. All other tasks are scheduled by the normal scheduler.
+//!
+//! A deadline task is only admitted if the total density (`runtime / deadline`)
+//! of the CPU's deadline tasks remains within [`MAX_DEADLINE_UTILIZATION`],
+//! which is sufficient for all of them to meet their deadlines under EDF.
+//! Its density is reserved when it is admitted, until it is added or its reservation is released.
+//! The remainder is left for FIFO and normal tasks.
+//!
+//! Real-time tasks preempt other tasks the next time the CPU schedules,
+//! so their latency is bounded by the scheduler's timer period.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::time::Duration;
+
+use task::{scheduler::PriorityScheduler, RealTimePolicy, TaskRef};
+use time::Instant;
+
+/// The maximum fraction of a CPU, in parts per million, that deadline tasks may reserve.
+pub const MAX_DEADLINE_UTILIZATION: u64 = 950_000;
+
+/// A scheduler that runs real-time tasks ahead of those of a normal scheduler.
+pub struct Scheduler {
+    normal: Box<dyn task::scheduler::Scheduler>,
+    /// The FIFO tasks, ordered from highest to lowest priority, then by when they became runnable.
+    fifo: Vec<FifoTask>,
+    deadline: Vec<DeadlineTask>,
+    /// The sum of the densities of all deadline tasks, in parts per million.
+    utilization: u64,
+    /// The sum of the densities of deadline tasks that were admitted but not yet added.
+    reserved: u64,
+    /// The ID of the deadline task that was last picked to run and when it was picked.
+    current: Option<(usize, Instant)>,
+}
+
+struct FifoTask {
+    task: TaskRef,
+    priority: u8,
+    /// Whether the task was runnable the last time it was checked.
+    was_runnable: bool,
+}
+
+struct DeadlineTask {
+    task: TaskRef,
+    runtime: Duration,
+    relative_deadline: Duration,
+    period: Duration,
+    /// The deadline of the task's current period.
+    deadline: Instant,
+    /// The CPU time that the task may still use before its deadline.
+    budget: Duration,
+    /// When the task's budget will be replenished, if it has been used up.
+    throttled_until: Option<Instant>,
+    /// Whether the task was runnable the last time it was checked.
+    was_runnable: bool,
+}
+
+impl Scheduler {
+    /// Creates a new real-time scheduler that schedules all other tasks with the given `normal` scheduler.
+    pub fn new<T>(normal: T) -> Self
+    where
+        T: task::scheduler::Scheduler,
+    {
+        Self {
+            normal: Box::new(normal),
+            fifo: Vec::new(),
+            deadline: Vec::new(),
+            utilization: 0,
+            reserved: 0,
+            current: None,
+        }
+    }
+
+    /// Returns the total density of this scheduler's deadline tasks, in parts per million.
+    pub fn deadline_utilization(&self) -> u64 {
+        self.utilization
+    }
+
+    fn insert_fifo(&mut self, task: FifoTask) {
+        let index = self
+            .fifo
+            .iter()
+            .position(|t| t.priority < task.priority)
+            .unwrap_or(self.fifo.len());
+        self.fifo.insert(index, task);
+    }
+
+    /// Charges the deadline task that was last picked to run for the time it has run since,
+    /// throttling it if it has used up its budget.
+    fn update_current(&mut self, now: Instant) {
+        let Some((task_id, picked)) = self.current.take() else { return };
+        let Some(t) = self.deadline.iter_mut().find(|t| t.task.id == task_id) else { return };
+        t.budget = t.budget.saturating_sub(now.duration_since(picked));
+        if t.budget.is_zero() {
+            t.throttled_until = Some(t.deadline - t.relative_deadline + t.period);
+        }
+    }
+
+    /// Replenishes the budgets of deadline tasks whose period has ended,
+    /// and starts a new period for tasks that have just become runnable.
+    fn update_deadlines(&mut self, now: Instant) {
+        for t in self.deadline.iter_mut() {
+            let runnable = t.task.is_runnable();
+            if let Some(until) = t.throttled_until {
+                if now >= until {
+                    t.throttled_until = None;
+                    t.budget = t.runtime;
+                    t.deadline += t.period;
+                    if t.deadline <= now {
+                        t.deadline = now + t.relative_deadline;
+                    }
+                }
+            } else if runnable && !t.was_runnable {
+                // If the task were to use its remaining budget before its current deadline,
+                // it would exceed its bandwidth, so it starts a new period instead.
+                let exceeds_bandwidth = match t.deadline.checked_duration_since(now) {
+                    Some(window) if !window.is_zero() => {
+                        t.budget.as_nanos() * t.relative_deadline.as_nanos()
+                            > window.as_nanos() * t.runtime.as_nanos()
+                    }
+                    _ => true,
+                };
+                if exceeds_bandwidth {
+                    t.deadline = now + t.relative_deadline;
+                    t.budget = t.runtime;
+                }
+            }
+            t.was_runnable = runnable;
+        }
+    }
+
+    fn next_deadline_task(&self) -> Option<&TaskRef> {
+        self.deadline
+            .iter()
+            .filter(|t| t.throttled_until.is_none() && t.task.is_runnable())
+            .min_by_key(|t| t.deadline)
+            .map(|t| &t.task)
+    }
+
+    fn next_fifo_task(&mut self) -> Option<&TaskRef> {
+        // Tasks that have become runnable since they were last checked go to the back of their priority.
+        let mut woken = Vec::new();
+        self.fifo.retain_mut(|t| {
+            let runnable = t.task.is_runnable();
+            let was_runnable = core::mem::replace(&mut t.was_runnable, runnable);
+            if runnable && !was_runnable {
+                woken.push((t.task.clone(), t.priority));
+                false
+            } else {
+                true
+            }
+        });
+        for (task, priority) in woken {
+            self.insert_fifo(FifoTask { task, priority, was_runnable: true });
+        }
+
+        self.fifo.iter().find(|t| t.task.is_runnable()).map(|t| &t.task)
+    }
+}
+
+impl task::scheduler::Scheduler for Scheduler {
+    fn next(&mut self) -> TaskRef {
+        let now = time::now::<time::Monotonic>();
+        self.update_current(now);
+        self.update_deadlines(now);
+
+        if let Some(task) = self.next_deadline_task().cloned() {
+            self.current = Some((task.id, now));
+            return task;
+        }
+        if let Some(task) = self.next_fifo_task() {
+            return task.clone();
+        }
+        self.normal.next()
+    }
+
+    fn add(&mut self, task: TaskRef) {
+        match task.real_time_policy() {
+            Some(RealTimePolicy::Fifo { priority }) => {
+                self.insert_fifo(FifoTask { task, priority, was_runnable: false });
+            }
+            Some(RealTimePolicy::Deadline { runtime, deadline, period }) => {
+                self.utilization += density(runtime, deadline);
+                self.deadline.push(DeadlineTask {
+                    task,
+                    runtime,
+                    relative_deadline: deadline,
+                    period,
+                    deadline: time::now::<time::Monotonic>() + deadline,
+                    budget: runtime,
+                    throttled_until: None,
+                    was_runnable: false,
+                });
+            }
+            None => self.normal.add(task),
+        }
+    }
+
+    fn admit(&mut self, policy: &RealTimePolicy) -> Result<(), &'static str> {
+        match *policy {
+            RealTimePolicy::Fifo { .. } => Ok(()),
+            RealTimePolicy::Deadline { runtime, deadline, period } => {
+                if runtime.is_zero() || runtime > deadline || deadline > period {
+                    return Err("deadline parameters must satisfy 0 < runtime <= deadline <= period");
+                }
+                let density = density(runtime, deadline);
+                if self.utilization + self.reserved + density > MAX_DEADLINE_UTILIZATION {
+                    return Err("deadline task would exceed the CPU's real-time bandwidth");
+                }
+                self.reserved += density;
+                Ok(())
+            }
+        }
+    }
+
+    fn release(&mut self, policy: &RealTimePolicy) {
+        if let RealTimePolicy::Deadline { runtime, deadline, .. } = *policy {
+            // The reservation may have been lost if this scheduler replaced the one that made it.
+            self.reserved = self.reserved.saturating_sub(density(runtime, deadline));
+        }
+    }
+
+    fn yielded(&mut self, task: &TaskRef) {
+        if let Some(index) = self.fifo.iter().position(|t| t.task == *task) {
+            // Inserting the task again puts it behind the other tasks of its priority.
+            let t = self.fifo.remove(index);
+            self.insert_fifo(t);
+        } else {
+            self.normal.yielded(task);
+        }
+    }
+
+    fn busyness(&self) -> usize {
+        self.normal.busyness() + self.fifo.len() + self.deadline.len()
+    }
+
+    fn remove(&mut self, task: &TaskRef) -> bool {
+        if let Some(index) = self.fifo.iter().position(|t| t.task == *task) {
+            self.fifo.remove(index);
+            true
+        } else if let Some(index) = self.deadline.iter().position(|t| t.task == *task) {
+            let t = self.deadline.remove(index);
+            self.utilization -= density(t.runtime, t.relative_deadline);
+            if matches!(self.current, Some((id, _)) if id == task.id) {
+                self.current = None;
+            }
+            true
+        } else {
+            self.normal.remove(task)
+        }
+    }
+
+    fn as_priority_scheduler(&mut self) -> Option<&mut dyn task::scheduler::PriorityScheduler> {
+        Some(self)
+    }
+
+    fn drain(&mut self) -> Box<dyn Iterator<Item = TaskRef> + '_> {
+        self.utilization = 0;
+        self.reserved = 0;
+        self.current = None;
+        Box::new(
+            self.fifo
+                .drain(..)
+                .map(|t| t.task)
+                .chain(self.deadline.drain(..).map(|t| t.task))
+                .chain(self.normal.drain()),
+        )
+    }
+
+    fn tasks(&self) -> Vec<TaskRef> {
+        let mut tasks: Vec<TaskRef> = self
+            .fifo
+            .iter()
+            .map(|t| t.task.clone())
+            .chain(self.deadline.iter().map(|t| t.task.clone()))
+            .collect();
+        tasks.extend(self.normal.tasks());
+        tasks
+    }
+}
+
+impl task::scheduler::PriorityScheduler for Scheduler {
+    fn set_priority(&mut self, task: &TaskRef, priority: u8) -> bool {
+        if let Some(index) = self.fifo.iter().position(|t| t.task == *task) {
+            let mut t = self.fifo.remove(index);
+            t.priority = priority;
+            self.insert_fifo(t);
+            true
+        } else {
+            self.normal
+                .as_priority_scheduler()
+                .map_or(false, |normal| normal.set_priority(task, priority))
+        }
+    }
+
+    fn priority(&mut self, task: &TaskRef) -> Option<u8> {
+        if let Some(t) = self.fifo.iter().find(|t| t.task == *task) {
+            Some(t.priority)
+        } else {
+            self.normal.as_priority_scheduler().and_then(|normal| normal.priority(task))
+        }
+    }
+}
+
+/// Returns the fraction of a CPU, in parts per million, that a task with the given
+/// deadline parameters may use.
+fn density(runtime: Duration, deadline: Duration) -> u64 {
+    (runtime.as_nanos() * 1_000_000 / deadline.as_nanos().max(1)) as u64
+}
@@ -118,6 +118,13 @@ pub fn unblock_sleeping_tasks() {
     }
 }
 
+/// Returns the time at which the next sleeping task should be unblocked,
+/// or `None` if no tasks are sleeping.
+pub fn next_wakeup_time() -> Option<Instant> {
+    let next_unblock_time = NEXT_DELAYED_TASK_UNBLOCK_TIME.load();
+    (next_unblock_time != Instant::MAX).then_some(next_unblock_time)
+}
+
 /// Blocks the current task by putting it to sleep for `duration` ticks.
 ///
 /// Returns the current task's run state if it can't be blocked.
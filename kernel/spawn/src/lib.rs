@@ -16,7 +16,7 @@
 
 extern crate alloc;
 
-use core::{marker::PhantomData, mem, ops::Deref, sync::atomic::{fence, Ordering}};
+use core::{marker::PhantomData, mem, ops::Deref, sync::atomic::{fence, Ordering}, time::Duration};
 use alloc::{
     boxed::Box,
     format,
@@ -30,7 +30,7 @@ use debugit::debugit;
 use spin::Mutex;
 use memory::{get_kernel_mmi_ref, MmiRef};
 use stack::Stack;
-use task::{Task, TaskRef, RestartInfo, RunState, JoinableTaskRef, ExitableTaskRef, FailureCleanupFunction};
+use task::{Task, TaskRef, RestartInfo, RunState, JoinableTaskRef, ExitableTaskRef, FailureCleanupFunction, RealTimePolicy};
 use task_struct::ExposedTask;
 use mod_mgmt::{CrateNamespace, SectionType, SECTION_HASH_DELIMITER};
 use path::{Path, PathBuf};
@@ -64,10 +64,14 @@ pub fn init(
             let scheduler = scheduler_epoch::Scheduler::new(idle_task);
         } else if #[cfg(priority_scheduler)] {
             let scheduler = scheduler_priority::Scheduler::new(idle_task);
+        } else if #[cfg(cfs_scheduler)] {
+            let scheduler = scheduler_cfs::Scheduler::new(idle_task);
         } else {
             let scheduler = scheduler_round_robin::Scheduler::new(idle_task);
         }
     }
+    // Real-time tasks are scheduled ahead of those of the normal policy.
+    let scheduler = scheduler_realtime::Scheduler::new(scheduler);
     task::scheduler::set_policy(cpu_id, scheduler);
     task::scheduler::add_task_to(cpu_id, exitable_bootstrap_task.clone());
 
@@ -285,6 +289,7 @@ pub struct TaskBuilder<F, A, R> {
     stack: Option<Stack>,
     parent: Option<TaskRef>,
     pin_on_cpu: Option<CpuId>,
+    real_time_policy: Option<RealTimePolicy>,
     blocked: bool,
     idle: bool,
     post_build_function: Option<Box<
@@ -311,6 +316,7 @@ impl<F, A, R> TaskBuilder<F, A, R>
             stack: None,
             parent: None,
             pin_on_cpu: None,
+            real_time_policy: None,
             blocked: false,
             idle: false,
             post_build_function: None,
@@ -353,6 +359,28 @@ impl<F, A, R> TaskBuilder<F, A, R>
         self
     }
 
+    /// Schedule the new Task with a first-in, first-out real-time policy at the given `priority`,
+    /// such that it runs ahead of all normal tasks until it blocks or yields.
+    ///
+    /// See [`RealTimePolicy::Fifo`] for more details.
+    pub fn fifo(mut self, priority: u8) -> TaskBuilder<F, A, R> {
+        self.real_time_policy = Some(RealTimePolicy::Fifo { priority });
+        self
+    }
+
+    /// Schedule the new Task with a deadline real-time policy, such that it is guaranteed
+    /// `runtime` of CPU time within `deadline` of the start of every `period`.
+    ///
+    /// The parameters must satisfy `0 < runtime <= deadline <= period`.
+    /// Spawning the new Task fails if its CPU's scheduler cannot guarantee this
+    /// in addition to the guarantees given to existing deadline tasks.
+    ///
+    /// See [`RealTimePolicy::Deadline`] for more details.
+    pub fn deadline(mut self, runtime: Duration, deadline: Duration, period: Duration) -> TaskBuilder<F, A, R> {
+        self.real_time_policy = Some(RealTimePolicy::Deadline { runtime, deadline, period });
+        self
+    }
+
     /// Mark this new Task as a SIMD-enabled Task 
     /// that can run SIMD instructions and use SIMD registers.
     #[cfg(simd_personality)]
@@ -379,6 +407,14 @@ impl<F, A, R> TaskBuilder<F, A, R>
     /// It does not switch to it immediately; that will happen on the next scheduler invocation.
     #[inline(never)]
     pub fn spawn(self) -> Result<JoinableTaskRef, &'static str> {
+        // Real-time tasks must be admitted before they're created,
+        // as there is no way to dispose of a task that has never run.
+        // The admission reserves the task's resources, which are released if spawning fails.
+        let admission = match self.real_time_policy {
+            Some(policy) if !self.idle => Some(task::scheduler::admit_real_time_task(&policy, self.pin_on_cpu)?),
+            _ => None,
+        };
+
         let mut new_task = Task::new(
             self.stack,
             task::get_my_current_task()
@@ -390,7 +426,11 @@ impl<F, A, R> TaskBuilder<F, A, R>
         new_task.name = self.name.unwrap_or_else(|| String::from(core::any::type_name::<F>()));
 
         let exposed = ExposedTask { task: new_task };
-        exposed.inner().lock().pinned_cpu = self.pin_on_cpu;
+        {
+            let mut inner = exposed.inner().lock();
+            inner.pinned_cpu = self.pin_on_cpu;
+            inner.real_time_policy = self.real_time_policy;
+        }
         let ExposedTask { task: mut new_task } = exposed;    
 
         #[cfg(simd_personality)] {  
@@ -444,7 +484,9 @@ impl<F, A, R> TaskBuilder<F, A, R>
         
         // Idle tasks are not stored on the run queue.
         if !self.idle {
-            if let Some(cpu) = self.pin_on_cpu {
+            if let Some(admission) = admission {
+                admission.add(task_ref.clone());
+            } else if let Some(cpu) = self.pin_on_cpu {
                 task::scheduler::add_task_to(cpu, task_ref.clone());
             } else {
                 task::scheduler::add_task(task_ref.clone());
@@ -1003,7 +1045,10 @@ fn remove_current_task_from_runqueue(current_task: &ExitableTaskRef) {
     task::scheduler::remove_task(current_task);
 }
 
-/// A basic idle task that does nothing but loop endlessly.
+/// A basic idle task that halts the CPU whenever there is nothing else to run.
+///
+/// The CPU is woken up by the next interrupt, e.g., its timer or, in tickless mode,
+/// a reschedule IPI sent when a task is added to its run queue or becomes runnable.
 ///
 /// Note: the current spawn API does not support spawning a task with the return type `!`,
 /// so we use `()` here instead. 
@@ -1011,8 +1056,22 @@ fn remove_current_task_from_runqueue(current_task: &ExitableTaskRef) {
 fn idle_task_entry(_cpu_id: CpuId) {
     info!("Entered idle task loop on core {}: {:?}", cpu::current_cpu(), task::get_my_current_task());
     loop {
-        // TODO: put this core into a low-power state
-        core::hint::spin_loop();
+        // Check for runnable tasks before each halt, in case one became runnable
+        // after the last interrupt invoked the scheduler.
+        if !task::schedule() {
+            halt();
+        }
+    }
+}
+
+/// Enables interrupts and halts the current CPU until the next interrupt arrives.
+fn halt() {
+    // SAFETY: halting only pauses this CPU, and the idle task always runs with interrupts enabled.
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("sti; hlt", options(nomem, nostack));
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("wfi", options(nomem, nostack));
     }
 }
 
@@ -62,6 +62,7 @@ use alloc::{
     boxed::Box,
     sync::Arc,
 };
+use core::fmt;
 use spin::Mutex;
 use downcast_rs::Downcast;
 use io::{BlockIo, KnownLength, BlockReader, BlockWriter};
@@ -106,3 +107,19 @@ impl_downcast!(StorageDevice);
 /// A trait object wrapped in an Arc and Mutex that allows 
 /// arbitrary storage devices to be shared in a thread-safe manner.
 pub type StorageDeviceRef = Arc<Mutex<dyn StorageDevice + Send>>;
+
+/// The unique ID of a storage device, assigned when it's registered with the storage manager.
+///
+/// Unlike a device's position in `storage_manager::storage_devices()`, which changes as
+/// more devices and partitions are found, a device's ID never changes and is never reused.
+/// Thus, it can be used to refer to a specific device later on, e.g., in the source of a mount.
+///
+/// An ID is displayed as `storage<N>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StorageDeviceId(pub usize);
+
+impl fmt::Display for StorageDeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "storage{}", self.0)
+    }
+}
@@ -8,12 +8,17 @@ extern crate alloc;
 extern crate spin;
 extern crate pci;
 extern crate ata;
+extern crate ahci;
+extern crate virtio_blk;
+extern crate partition_table;
 extern crate storage_device;
 
 use alloc::{
+    format,
     vec::Vec,
     sync::Arc,
 };
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Mutex;
 use pci::PciDevice;
 
@@ -22,6 +27,19 @@ pub use storage_device::*;
 /// A list of all of the available and initialized storage controllers that exist on this system.
 static STORAGE_CONTROLLERS: Mutex<Vec<StorageControllerRef>> = Mutex::new(Vec::new());
 
+/// A list of all of the partitions found on the storage devices attached to the above controllers,
+/// along with the ID of each partition and the ID of the storage device that contains it.
+static PARTITIONS: Mutex<Vec<(StorageDeviceId, StorageDeviceId, StorageDeviceRef)>> = Mutex::new(Vec::new());
+
+/// All storage devices and partitions on this system, in the order they were registered,
+/// along with the ID assigned to each one.
+///
+/// A storage device that contains partitions isn't included, only its partitions are.
+static STORAGE_DEVICES: Mutex<Vec<(StorageDeviceId, StorageDeviceRef)>> = Mutex::new(Vec::new());
+
+/// The ID that will be assigned to the next registered storage device.
+static NEXT_STORAGE_DEVICE_ID: AtomicUsize = AtomicUsize::new(0);
+
 /// Returns an iterator over all initialized storage controllers on this system.
 /// 
 /// This function requires allocation, as it currently clones the list of storage controllers,\
@@ -30,17 +48,58 @@ pub fn storage_controllers() -> impl Iterator<Item = StorageControllerRef> {
     STORAGE_CONTROLLERS.lock().clone().into_iter()
 }
 
-/// Returns an iterator over all storage devices attached to the storage controllers on this system.
+/// Returns an iterator over all storage devices attached to the storage controllers on this system,
+/// including all of the partitions on those devices (see [`partitions()`]),
+/// in the order they were registered.
+///
+/// A device that contains partitions is represented only by its partitions, such that
+/// the same blocks can't be accessed through two independent storage devices.
 ///
-/// This function requires allocation, as it currently clones the list of storage devices (lazily)
-/// within each storage controller, effectively a `Vec<Arc<Vec<Arc<StorageDevice>>>>`.
+/// This function requires allocation, as it currently clones the list of storage devices,
+/// effectively a `Vec<Arc<StorageDevice>>`.
 pub fn storage_devices() -> impl Iterator<Item = StorageDeviceRef> {
-    storage_controllers()
-        .flat_map(|c| c.lock()
-            .devices()
-            .collect::<Vec<StorageDeviceRef>>()
-            .into_iter()
-    )
+    storage_devices_with_ids().map(|(_, device)| device)
+}
+
+/// Like [`storage_devices()`], but also returns the ID of each storage device.
+pub fn storage_devices_with_ids() -> impl Iterator<Item = (StorageDeviceId, StorageDeviceRef)> {
+    STORAGE_DEVICES.lock().clone().into_iter()
+}
+
+/// Returns the storage device with the given `id`, if it exists.
+///
+/// This returns `None` for a device that contains partitions; see [`partitions_of()`].
+pub fn storage_device(id: StorageDeviceId) -> Option<StorageDeviceRef> {
+    STORAGE_DEVICES.lock().iter()
+        .find(|(device_id, _)| *device_id == id)
+        .map(|(_, device)| device.clone())
+}
+
+/// Returns a new, unique storage device ID.
+fn next_storage_device_id() -> StorageDeviceId {
+    StorageDeviceId(NEXT_STORAGE_DEVICE_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Assigns a new ID to the given `device` and adds it to the list of storage devices.
+fn register_storage_device(device: StorageDeviceRef) -> StorageDeviceId {
+    let id = next_storage_device_id();
+    STORAGE_DEVICES.lock().push((id, device));
+    id
+}
+
+/// Returns an iterator over all partitions found on the storage devices on this system.
+///
+/// Each partition is a [`partition_table::Partition`], which is itself a storage device.
+pub fn partitions() -> impl Iterator<Item = StorageDeviceRef> {
+    PARTITIONS.lock().clone().into_iter().map(|(_, _, partition)| partition)
+}
+
+/// Returns the IDs of all partitions found on the storage device with the given `id`.
+pub fn partitions_of(id: StorageDeviceId) -> Vec<StorageDeviceId> {
+    PARTITIONS.lock().iter()
+        .filter(|(_, device_id, _)| *device_id == id)
+        .map(|(partition_id, _, _)| *partition_id)
+        .collect()
 }
 
 
@@ -52,18 +111,53 @@ pub fn storage_devices() -> impl Iterator<Item = StorageDeviceRef> {
 /// * `Ok(None)` if the given `PciDevice` isn't a supported storage device,
 /// * An error if it fails to initialize a supported storage device.
 pub fn init_device(pci_device: &PciDevice) -> Result<Option<StorageControllerRef>, &'static str> {
-    // We currently only support IDE controllers for ATA drives (aka PATA).
-    let storage_controller = if pci_device.class == 0x01 && pci_device.subclass == 0x01 {
+    // We currently support IDE controllers for ATA drives (aka PATA), 
+    // AHCI controllers for SATA drives, and virtio block devices.
+    let storage_controller_ref: StorageControllerRef = if pci_device.class == 0x01 && pci_device.subclass == 0x01 {
         info!("IDE controller PCI device found at: {:?}", pci_device.location);
-        let ide_controller = ata::IdeController::new(pci_device)?;
-        let storage_controller_ref: StorageControllerRef = Arc::new(Mutex::new(ide_controller));
-        STORAGE_CONTROLLERS.lock().push(Arc::clone(&storage_controller_ref));
-        Some(storage_controller_ref)
-    } 
+        Arc::new(Mutex::new(ata::IdeController::new(pci_device)?))
+    }
+    else if pci_device.class == 0x01 && pci_device.subclass == 0x06 && pci_device.prog_if == 0x01 {
+        info!("AHCI controller PCI device found at: {:?}", pci_device.location);
+        Arc::new(Mutex::new(ahci::AhciController::new(pci_device)?))
+    }
+    else if virtio_blk::is_virtio_blk_device(pci_device) {
+        info!("virtio block PCI device found at: {:?}", pci_device.location);
+        Arc::new(Mutex::new(virtio_blk::VirtioBlkController::new(pci_device)?))
+    }
     // Here: in the future, handle other supported storage devices
     else {
-        None
+        return Ok(None);
     };
-    
-    Ok(storage_controller)
+
+    STORAGE_CONTROLLERS.lock().push(Arc::clone(&storage_controller_ref));
+    register_devices(&storage_controller_ref);
+    Ok(Some(storage_controller_ref))
+}
+
+/// Reads the partition table of each storage device attached to the given `storage_controller`,
+/// and registers either each of its partitions or, if it has none, the device itself.
+fn register_devices(storage_controller: &StorageControllerRef) {
+    let devices: Vec<StorageDeviceRef> = storage_controller.lock().devices().collect();
+    for device in devices {
+        let id = next_storage_device_id();
+        let partitions = partition_table::read_partitions(&device).unwrap_or_else(|e| {
+            warn!("Failed to read the partition table of storage device {}: {}", id, e);
+            Vec::new()
+        });
+        // Registering both the device and its partitions would let the same blocks be opened twice,
+        // each with its own block cache, so a partitioned device is only accessible via its partitions.
+        if partitions.is_empty() {
+            STORAGE_DEVICES.lock().push((id, device));
+            info!("Registered storage device {}", id);
+            continue;
+        }
+        for partition in partitions {
+            let description = format!("{:?}", partition);
+            let partition_ref: StorageDeviceRef = Arc::new(Mutex::new(partition));
+            let partition_id = register_storage_device(partition_ref.clone());
+            PARTITIONS.lock().push((partition_id, id, partition_ref));
+            info!("Found partition on storage device {}, registered as {}: {}", id, partition_id, description);
+        }
+    }
 }
@@ -62,8 +62,8 @@ use task_struct::ExposedTask;
 
 // Re-export main types from `task_struct`.
 pub use task_struct::{
-    ExitValue, InheritedStates, KillHandler, KillReason,
-    PanicInfoOwned, RestartInfo, RunState, Task,
+    CpuStats, ExitValue, InheritedStates, KillHandler, KillReason,
+    PanicInfoOwned, RealTimePolicy, RestartInfo, RunState, Task,
 };
 #[cfg(simd_personality)]
 pub use task_struct::SimdExt;
@@ -831,6 +831,11 @@ fn task_switch_inner(
     // Mark the current task as no longer running
     curr.0.task.running_on_cpu().store(None.into());
 
+    // Charge the current task for the CPU time it used and start timing the next task.
+    let now = time::now::<time::Monotonic>();
+    curr.0.task.account_switch_out(now);
+    next.0.task.account_switch_in(cpu_id, now);
+
     // After this point, we may need to mutate the `curr_task_tls_slot` (if curr has exited),
     // so we use local variables to store some necessary info about the curr task
     // and then end our immutable borrow of the current task.
@@ -1116,6 +1121,7 @@ pub fn bootstrap_task(
     // Update other relevant states for this new bootstrapped task.
     joinable_taskref.0.task.runstate().store(RunState::Runnable);
     joinable_taskref.0.task.running_on_cpu().store(Some(cpu_id).into()); 
+    joinable_taskref.0.task.account_switch_in(cpu_id, time::now::<time::Monotonic>());
     joinable_taskref.0.task.inner().lock().pinned_cpu = Some(cpu_id); // can only run on this CPU core
     // Set this task as this CPU's current task, as it's already running.
     joinable_taskref.set_as_current_task();
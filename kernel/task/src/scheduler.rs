@@ -2,10 +2,14 @@ use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::ptr;
 
 use cpu::CpuId;
-use spin::Mutex;
+use spin::{Mutex, Once};
 use sync_preemption::PreemptionSafeMutex;
 
-use crate::TaskRef;
+use crate::{RealTimePolicy, TaskRef};
+
+mod balance;
+
+pub use balance::{is_load_balancing_enabled, load_balance_stats, set_load_balancing, LoadBalanceStats};
 
 /// List of all the schedulers on the system.
 ///
@@ -26,6 +30,65 @@ static SCHEDULER: Option<Arc<ConcurrentScheduler>> = None;
 
 type ConcurrentScheduler = PreemptionSafeMutex<dyn Scheduler>;
 
+/// A function that [`schedule()`] calls with the task it is about to switch to.
+///
+/// See [`set_schedule_hook()`].
+static SCHEDULE_HOOK: Once<fn(&TaskRef)> = Once::new();
+
+/// Sets a function that [`schedule()`] calls on each CPU with the task it has chosen
+/// to run next, right before switching to it, e.g., to program the CPU's timer
+/// for that task's timeslice.
+///
+/// The hook is called with preemption disabled, so it must not block.
+///
+/// Returns an error if a hook was already set.
+pub fn set_schedule_hook(hook: fn(&TaskRef)) -> Result<(), &'static str> {
+    let mut was_set = false;
+    SCHEDULE_HOOK.call_once(|| {
+        was_set = true;
+        hook
+    });
+    if was_set {
+        Ok(())
+    } else {
+        Err("a schedule hook was already set")
+    }
+}
+
+/// A function that is called with the CPU whose run queue holds a task that became ready to run,
+/// or `None` if that task may be in any run queue.
+///
+/// See [`set_wake_hook()`].
+static WAKE_HOOK: Once<fn(Option<CpuId>)> = Once::new();
+
+/// Sets a function that is called whenever a task may have become ready to run on an idle CPU,
+/// e.g., to send that CPU an interrupt such that its idle task stops halting.
+///
+/// The hook is called with the CPU whose run queue a task was added to
+/// or whose run queue holds a blocked task that was made runnable,
+/// or with `None` if that task isn't known to be in any run queue.
+/// The hook may be called from interrupt handlers, so it must not block.
+///
+/// Returns an error if a hook was already set.
+pub fn set_wake_hook(hook: fn(Option<CpuId>)) -> Result<(), &'static str> {
+    let mut was_set = false;
+    WAKE_HOOK.call_once(|| {
+        was_set = true;
+        hook
+    });
+    if !was_set {
+        return Err("a wake hook was already set");
+    }
+    task_struct::set_unblock_hook(|task| wake(task.run_queue_cpu()))
+}
+
+/// Invokes the wake hook, if any, for the given CPU or for all CPUs.
+fn wake(cpu_id: Option<CpuId>) {
+    if let Some(hook) = WAKE_HOOK.get() {
+        hook(cpu_id);
+    }
+}
+
 /// Yields the current CPU by selecting a new `Task` to run next,
 /// and then switches to that new `Task`.
 ///
@@ -41,6 +104,19 @@ type ConcurrentScheduler = PreemptionSafeMutex<dyn Scheduler>;
 ///   continue running.
 #[doc(alias("yield"))]
 pub fn schedule() -> bool {
+    schedule_inner(true)
+}
+
+/// Like [`schedule()`], but preempts the current task rather than having it yield the CPU,
+/// e.g., when called from a timer interrupt handler.
+///
+/// The difference is that a scheduler may treat a task that yields differently,
+/// e.g., by letting other tasks of the same priority run first.
+pub fn preempt() -> bool {
+    schedule_inner(false)
+}
+
+fn schedule_inner(yielding: bool) -> bool {
     let preemption_guard = preemption::hold_preemption();
     // If preemption was not previously enabled (before we disabled it above),
     // then we shouldn't perform a task switch here.
@@ -51,10 +127,22 @@ pub fn schedule() -> bool {
 
     let cpu_id = preemption_guard.cpu_id();
 
+    balance::balance(cpu_id, &preemption_guard);
+
     let next_task = SCHEDULER.update_guarded(
-        |scheduler| scheduler.as_ref().unwrap().lock().next(),
+        |scheduler| {
+            let mut scheduler = scheduler.as_ref().unwrap().lock();
+            if yielding {
+                let _ = crate::with_current_task(|current| scheduler.yielded(current));
+            }
+            scheduler.next()
+        },
         &preemption_guard,
     );
+    balance::update_work_request(&next_task, &preemption_guard);
+    if let Some(hook) = SCHEDULE_HOOK.get() {
+        hook(&next_task);
+    }
 
     let (did_switch, recovered_preemption_guard) =
         super::task_switch(next_task, cpu_id, preemption_guard);
@@ -93,16 +181,21 @@ where
 
             let mut new_scheduler = scheduler.lock();
             for task in old_scheduler.lock().drain() {
-                new_scheduler.add(task);
+                add_to(&mut *new_scheduler, cpu_id, task);
             }
         }
 
         locked.push((cpu_id, scheduler.clone() as _));
         *current_scheduler = Some(scheduler as _);
     });
+    drop(locked);
+    balance::init_current_cpu(cpu_id);
 }
 
 /// Adds the given task to the least busy run queue.
+///
+/// Tasks that aren't pinned to a CPU may later be migrated to another run queue
+/// by load balancing; see [`set_load_balancing()`].
 pub fn add_task(task: TaskRef) {
     let locked = SCHEDULERS.lock();
 
@@ -117,14 +210,17 @@ pub fn add_task(task: TaskRef) {
         }
     }
 
-    locked[least_busy_index.unwrap()].1.lock().add(task);
+    let (cpu_id, scheduler) = &locked[least_busy_index.unwrap()];
+    add_to(&mut *scheduler.lock(), *cpu_id, task);
+    wake(Some(*cpu_id));
 }
 
 /// Adds the given task to the specified CPU's run queue.
 pub fn add_task_to(cpu_id: CpuId, task: TaskRef) {
     for (cpu, scheduler) in SCHEDULERS.lock().iter() {
         if *cpu == cpu_id {
-            scheduler.lock().add(task);
+            add_to(&mut *scheduler.lock(), cpu_id, task);
+            wake(Some(cpu_id));
             return;
         }
     }
@@ -132,7 +228,97 @@ pub fn add_task_to(cpu_id: CpuId, task: TaskRef) {
 
 /// Adds the given task to the current CPU's run queue.
 pub fn add_task_to_current(task: TaskRef) {
-    SCHEDULER.update(|scheduler| scheduler.as_ref().unwrap().lock().add(task))
+    let cpu_id = cpu::current_cpu();
+    SCHEDULER.update(|scheduler| add_to(&mut *scheduler.as_ref().unwrap().lock(), cpu_id, task));
+    wake(Some(cpu_id));
+}
+
+/// Adds the given task to the run queue of `scheduler`, which belongs to the given CPU,
+/// recording that CPU as the one whose run queue holds the task.
+fn add_to(scheduler: &mut dyn Scheduler, cpu_id: CpuId, task: TaskRef) {
+    task.0.task.run_queue_cpu().store(Some(cpu_id).into());
+    scheduler.add(task);
+}
+
+/// A reservation of a CPU's resources for a task with a real-time policy.
+///
+/// The reservation is released when the admitted task is added to the CPU's run queue
+/// using [`Admission::add`], or when this is dropped without adding it,
+/// e.g., because spawning the task failed.
+pub struct Admission {
+    cpu: CpuId,
+    /// The admitted policy, which is `None` once the reservation has been released.
+    policy: Option<RealTimePolicy>,
+}
+
+impl Admission {
+    /// Returns the CPU whose run queue the task will be added to.
+    pub fn cpu(&self) -> CpuId {
+        self.cpu
+    }
+
+    /// Adds the admitted task to the CPU's run queue.
+    ///
+    /// The reservation is released in the same critical section as the task is added,
+    /// such that no other task can be admitted in between based on the resources it frees.
+    pub fn add(mut self, task: TaskRef) {
+        let Some(policy) = self.policy.take() else { return };
+        let cpu_id = self.cpu;
+        with_scheduler_of(cpu_id, |scheduler| {
+            scheduler.release(&policy);
+            add_to(scheduler, cpu_id, task);
+        });
+        wake(Some(self.cpu));
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        if let Some(policy) = self.policy.take() {
+            with_scheduler_of(self.cpu, |scheduler| scheduler.release(&policy));
+        }
+    }
+}
+
+/// Calls `f` with the given CPU's scheduler locked, if that CPU has one.
+fn with_scheduler_of(cpu_id: CpuId, f: impl FnOnce(&mut dyn Scheduler)) {
+    let scheduler = SCHEDULERS
+        .lock()
+        .iter()
+        .find(|(cpu, _)| *cpu == cpu_id)
+        .map(|(_, scheduler)| scheduler.clone());
+    if let Some(scheduler) = scheduler {
+        f(&mut *scheduler.lock());
+    }
+}
+
+/// Finds a CPU whose scheduler admits a task with the given real-time `policy`,
+/// considering only `cpu_id` if given, and reserves the resources the task needs there.
+///
+/// This must be called before the task is spawned, because a task cannot be
+/// disposed of before it has run. If admitted, the task must be added using
+/// the returned [`Admission`], or else its reservation is released when that is dropped.
+///
+/// Returns the reason that the last scheduler considered rejected the task if none admit it.
+pub fn admit_real_time_task(
+    policy: &RealTimePolicy,
+    cpu_id: Option<CpuId>,
+) -> Result<Admission, &'static str> {
+    let mut schedulers = SCHEDULERS.lock().clone();
+    schedulers.retain(|(cpu, _)| cpu_id.map_or(true, |cpu_id| *cpu == cpu_id));
+    schedulers.sort_by_cached_key(|(_, scheduler)| scheduler.lock().busyness());
+
+    let mut reason = "no such CPU";
+    for (cpu, scheduler) in schedulers {
+        // Checking and reserving the resources happen with the scheduler locked,
+        // so concurrent admissions can't both be granted the same resources.
+        let result = scheduler.lock().admit(policy);
+        match result {
+            Ok(()) => return Ok(Admission { cpu, policy: Some(*policy) }),
+            Err(e) => reason = e,
+        }
+    }
+    Err(reason)
 }
 
 /// Removes the given task from all run queues.
@@ -176,6 +362,33 @@ pub trait Scheduler: Send + Sync + 'static {
     /// Removes a task from the run queue.
     fn remove(&mut self, task: &TaskRef) -> bool;
 
+    /// Checks whether a task with the given real-time `policy` can be added to the run queue
+    /// without jeopardizing the guarantees given to the real-time tasks already in it,
+    /// and if so, reserves the resources that the task needs until they are [released].
+    ///
+    /// By default, schedulers don't support real-time policies and reject all such tasks.
+    ///
+    /// [released]: Self::release
+    fn admit(&mut self, policy: &RealTimePolicy) -> Result<(), &'static str> {
+        let _ = policy;
+        Err("scheduler doesn't support real-time tasks")
+    }
+
+    /// Releases the resources reserved by a successful call to [`admit`](Self::admit)
+    /// with the same `policy`, either right before the admitted task is added
+    /// or because it won't be added after all.
+    fn release(&mut self, policy: &RealTimePolicy) {
+        let _ = policy;
+    }
+
+    /// Notifies the scheduler that the given task, which may be runnable,
+    /// is yielding the CPU rather than being preempted.
+    ///
+    /// By default, this does nothing.
+    fn yielded(&mut self, task: &TaskRef) {
+        let _ = task;
+    }
+
     /// Returns a reference to this scheduler as a priority scheduler, if it is one.
     fn as_priority_scheduler(&mut self) -> Option<&mut dyn PriorityScheduler>;
 
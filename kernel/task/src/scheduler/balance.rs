@@ -0,0 +1,304 @@
+//! Load balancing between the per-CPU run queues.
+//!
+//! A new task is added to the least busy run queue when it is spawned,
+//! but after that, the loads of the CPUs drift apart as tasks block, exit, and are spawned.
+//! To even them out, each CPU periodically checks whether its run queue is busier than
+//! the least busy one, and if so, migrates some of its tasks there.
+//! A CPU that has nothing to run also requests work from the others,
+//! which then migrate a task to it the next time they schedule, rather than waiting
+//! for the next periodic check. Each CPU responds to each request only once, though;
+//! if it has nothing to migrate then, the request is left to the periodic checks.
+//!
+//! Tasks are only ever migrated *away* from the CPU performing the migration,
+//! because only that CPU can be sure that none of the tasks in its run queue
+//! is in the middle of being switched to or from.
+//! Idle tasks, tasks pinned to a CPU, running tasks, and real-time tasks are never migrated,
+//! the latter because they were admitted based on the load of their CPU's scheduler.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use cpu::CpuId;
+use preemption::PreemptionGuard;
+use spin::Mutex;
+
+use super::SCHEDULERS;
+use crate::TaskRef;
+
+/// How many calls to `schedule` a CPU makes between periodic load balancing checks.
+///
+/// On a busy CPU, this is roughly the number of timeslices between checks.
+const BALANCE_INTERVAL: usize = 16;
+
+/// The minimum difference in busyness between two CPUs at which tasks are migrated periodically.
+const MIN_IMBALANCE: usize = 2;
+
+/// The maximum number of tasks that are migrated at once.
+const MAX_MIGRATIONS: usize = 4;
+
+/// Whether load balancing is enabled.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The number of CPUs that are currently requesting work.
+static WORK_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of work requests made so far, which identifies the most recent one.
+static WORK_REQUEST_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// The load balancing state of every CPU.
+///
+/// Like [`SCHEDULERS`], this is only ever locked with `try_lock` from `schedule`.
+static LOADS: Mutex<Vec<Arc<CpuLoad>>> = Mutex::new(Vec::new());
+
+/// A reference to the current CPU's load balancing state.
+#[cls::cpu_local]
+static LOAD: Option<Arc<CpuLoad>> = None;
+
+struct CpuLoad {
+    cpu: CpuId,
+    /// The number of calls to `schedule` on this CPU.
+    schedule_calls: AtomicUsize,
+    /// Whether this CPU had nothing to run the last time it scheduled.
+    wants_work: AtomicBool,
+    /// The [`WORK_REQUEST_GENERATION`] of the most recent work request this CPU responded to.
+    seen_work_requests: AtomicUsize,
+    balance_checks: AtomicUsize,
+    migrated_out: AtomicUsize,
+    migrated_in: AtomicUsize,
+    work_requests: AtomicUsize,
+}
+
+impl CpuLoad {
+    fn new(cpu: CpuId) -> Self {
+        Self {
+            cpu,
+            schedule_calls: AtomicUsize::new(0),
+            wants_work: AtomicBool::new(false),
+            seen_work_requests: AtomicUsize::new(0),
+            balance_checks: AtomicUsize::new(0),
+            migrated_out: AtomicUsize::new(0),
+            migrated_in: AtomicUsize::new(0),
+            work_requests: AtomicUsize::new(0),
+        }
+    }
+
+    fn request_work(&self) {
+        if !self.wants_work.swap(true, Ordering::Relaxed) {
+            WORK_REQUESTS.fetch_add(1, Ordering::Relaxed);
+            WORK_REQUEST_GENERATION.fetch_add(1, Ordering::Relaxed);
+            self.work_requests.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn clear_work_request(&self) {
+        if self.wants_work.swap(false, Ordering::Relaxed) {
+            WORK_REQUESTS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Load balancing statistics of a CPU, as returned by [`load_balance_stats()`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadBalanceStats {
+    /// The number of times this CPU checked whether to migrate tasks to other CPUs.
+    pub balance_checks: usize,
+    /// The number of tasks this CPU migrated to other CPUs.
+    pub migrated_out: usize,
+    /// The number of tasks other CPUs migrated to this CPU.
+    pub migrated_in: usize,
+    /// The number of times this CPU had nothing to run and requested work from other CPUs.
+    pub work_requests: usize,
+    /// Whether this CPU is currently requesting work.
+    pub wants_work: bool,
+}
+
+/// Enables or disables load balancing between CPUs.
+///
+/// Load balancing is enabled by default.
+pub fn set_load_balancing(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether load balancing between CPUs is enabled.
+pub fn is_load_balancing_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the load balancing statistics of each CPU.
+pub fn load_balance_stats() -> Vec<(CpuId, LoadBalanceStats)> {
+    LOADS
+        .lock()
+        .iter()
+        .map(|load| {
+            let stats = LoadBalanceStats {
+                balance_checks: load.balance_checks.load(Ordering::Relaxed),
+                migrated_out: load.migrated_out.load(Ordering::Relaxed),
+                migrated_in: load.migrated_in.load(Ordering::Relaxed),
+                work_requests: load.work_requests.load(Ordering::Relaxed),
+                wants_work: load.wants_work.load(Ordering::Relaxed),
+            };
+            (load.cpu, stats)
+        })
+        .collect()
+}
+
+/// Sets up the current CPU's load balancing state, if it hasn't been already.
+pub(super) fn init_current_cpu(cpu_id: CpuId) {
+    LOAD.update(|current_load| {
+        if current_load.is_none() {
+            let load = Arc::new(CpuLoad::new(cpu_id));
+            LOADS.lock().push(load.clone());
+            *current_load = Some(load);
+        }
+    });
+}
+
+/// Migrates tasks from the current CPU's run queue to a less busy one, if needed.
+///
+/// This must be called from `schedule` before the next task is chosen,
+/// with preemption having been enabled beforehand, such that this CPU holds no scheduler locks.
+pub(super) fn balance(cpu_id: CpuId, guard: &PreemptionGuard) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    LOAD.update_guarded(
+        |load| {
+            let Some(load) = load else { return };
+            let calls = load.schedule_calls.fetch_add(1, Ordering::Relaxed) + 1;
+            // A CPU that had nothing to run has nothing to migrate either. This also keeps
+            // idle CPUs that call `schedule` whenever they're interrupted from contending for the locks.
+            if load.wants_work.load(Ordering::Relaxed) {
+                return;
+            }
+            // Respond to a work request only once rather than on every call while it's outstanding.
+            let generation = WORK_REQUEST_GENERATION.load(Ordering::Relaxed);
+            let new_work_request = WORK_REQUESTS.load(Ordering::Relaxed) > 0
+                && load.seen_work_requests.swap(generation, Ordering::Relaxed) != generation;
+            if calls % BALANCE_INTERVAL == 0 || new_work_request {
+                push_tasks(cpu_id, load);
+            }
+        },
+        guard,
+    );
+}
+
+/// Records whether the current CPU has work to do, based on the `next` task it chose to run.
+pub(super) fn update_work_request(next: &TaskRef, guard: &PreemptionGuard) {
+    LOAD.update_guarded(
+        |load| {
+            if let Some(load) = load {
+                if next.is_an_idle_task {
+                    load.request_work();
+                } else {
+                    load.clear_work_request();
+                }
+            }
+        },
+        guard,
+    );
+}
+
+/// Migrates tasks from the current CPU to the CPU that requests work
+/// or is least busy, if it is sufficiently less busy than the current CPU.
+fn push_tasks(cpu_id: CpuId, load: &CpuLoad) {
+    // The current task may have been interrupted while holding either lock.
+    let Some(schedulers) = SCHEDULERS.try_lock() else { return };
+    let Some(loads) = LOADS.try_lock() else { return };
+    load.balance_checks.fetch_add(1, Ordering::Relaxed);
+
+    // Find the tasks that could be migrated before looking for a CPU to migrate them to,
+    // such that a run queue with nothing to migrate isn't compared against all the others.
+    let Some((_, source)) = schedulers.iter().find(|(cpu, _)| *cpu == cpu_id) else { return };
+    let (source_busyness, mut candidates) = {
+        let source = source.lock();
+        let busyness = source.busyness();
+        // Whether another CPU requests work or is less busy, the current task stays here,
+        // so there must be at least one other task to migrate.
+        if busyness < 2 {
+            return;
+        }
+        let candidates: Vec<TaskRef> = source.tasks().into_iter().filter(is_migratable).collect();
+        (busyness, candidates)
+    };
+    if candidates.is_empty() {
+        return;
+    }
+    // Migrating blocked tasks doesn't relieve this CPU, so prefer runnable ones.
+    candidates.sort_by_key(|task| !task.is_runnable());
+
+    let wants_work = |cpu: CpuId| {
+        loads
+            .iter()
+            .find(|load| load.cpu == cpu)
+            .map_or(false, |load| load.wants_work.load(Ordering::Relaxed))
+    };
+
+    // Only one scheduler is locked at a time to avoid deadlocking with other CPUs.
+    let mut target = None;
+    for (cpu, scheduler) in schedulers.iter() {
+        if *cpu == cpu_id {
+            continue;
+        }
+        let busyness = scheduler.lock().busyness();
+        // CPUs that request work take precedence over those that are merely less busy.
+        let candidate = (!wants_work(*cpu), busyness);
+        if target.as_ref().map_or(true, |(_, _, best)| candidate < *best) {
+            target = Some((*cpu, scheduler, candidate));
+        }
+    }
+    let Some((target_cpu, target, (no_request, target_busyness))) = target else { return };
+
+    let count = if !no_request {
+        // Keep the current task here and give the idle CPU one of the others.
+        1
+    } else {
+        if source_busyness < target_busyness + MIN_IMBALANCE {
+            return;
+        }
+        ((source_busyness - target_busyness) / 2).min(MAX_MIGRATIONS)
+    };
+
+    let migrated = {
+        let mut source = source.lock();
+        let mut migrated = Vec::with_capacity(count);
+        for task in candidates.into_iter().take(count) {
+            // Priorities are stored in the run queue, so they must be carried over.
+            let priority = source
+                .as_priority_scheduler()
+                .and_then(|priority_scheduler| priority_scheduler.priority(&task));
+            if source.remove(&task) {
+                migrated.push((task, priority));
+            }
+        }
+        migrated
+    };
+    if migrated.is_empty() {
+        return;
+    }
+
+    let num_migrated = migrated.len();
+    let mut target = target.lock();
+    for (task, priority) in migrated {
+        super::add_to(&mut *target, target_cpu, task.clone());
+        if let (Some(priority), Some(priority_scheduler)) = (priority, target.as_priority_scheduler()) {
+            priority_scheduler.set_priority(&task, priority);
+        }
+    }
+    drop(target);
+    super::wake(Some(target_cpu));
+
+    load.migrated_out.fetch_add(num_migrated, Ordering::Relaxed);
+    if let Some(target_load) = loads.iter().find(|load| load.cpu == target_cpu) {
+        target_load.migrated_in.fetch_add(num_migrated, Ordering::Relaxed);
+        target_load.clear_work_request();
+    }
+}
+
+/// Returns whether the given task in the current CPU's run queue may be migrated to another CPU.
+fn is_migratable(task: &TaskRef) -> bool {
+    !task.is_an_idle_task
+        && !task.is_running()
+        && !task.has_exited()
+        && task.pinned_cpu().is_none()
+        && task.real_time_policy().is_none()
+}
@@ -6,6 +6,7 @@
 //! 2) TaskDir: the lazily computed directory that contains files and directories 
 //!     relevant to that task
 //! 3) TaskFile: lazily computed file that holds information about the task
+//!    StatFile: lazily computed file that holds the task's CPU usage statistics
 //! 4) MmiDir: lazily computed directory that holds subdirectories and files
 //!     about the task's memory management information
 //! 5) MmiFile: lazily computed file that contains information about the task's
@@ -19,9 +20,9 @@
 //! 
 //! The hierarchy (tree) is as follows:
 //! 
-//!             TaskDir
-//!         TaskFile    MmiDir
-//!                         MmiFile
+//!                 TaskDir
+//!         TaskFile    StatFile    MmiDir
+//!                                     MmiFile
 //! 
 
 #[macro_use] extern crate alloc;
@@ -39,7 +40,7 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::Arc;
-use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, File, FileRef, FsNode};
+use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, File, FileRef, FsNode, Metadata};
 use memory::MappedPages;
 use task::WeakTaskRef;
 use path::{Path, PathBuf};
@@ -104,6 +105,10 @@ impl FsNode for TaskFs {
     fn set_parent_dir(&mut self, _new_parent: WeakDirRef) {
         // do nothing
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::directory(true)
+    }
 }
 
 impl Directory for TaskFs {
@@ -183,6 +188,11 @@ impl Directory for TaskDir {
             return Some(FileOrDir::File(Arc::new(Mutex::new(task_file)) as FileRef));
         }
 
+        if child_name == "stat" {
+            let stat_file = StatFile::new(self.task_id, self.taskref.clone());
+            return Some(FileOrDir::File(Arc::new(Mutex::new(stat_file)) as FileRef));
+        }
+
         if child_name == "mmi" {
             let mmi_dir = MmiDir::new(self.task_id, self.taskref.clone());
             return Some(FileOrDir::Dir(Arc::new(Mutex::new(mmi_dir)) as DirRef));
@@ -193,7 +203,7 @@ impl Directory for TaskDir {
 
     /// Returns a string listing all the children in the directory
     fn list(&self) -> Vec<String> {
-        let children = vec!["mmi".to_string(), "taskInfo".to_string()];
+        let children = vec!["mmi".to_string(), "stat".to_string(), "taskInfo".to_string()];
         children
     }
 
@@ -218,6 +228,10 @@ impl FsNode for TaskDir {
     fn set_parent_dir(&mut self, _: WeakDirRef) {
         // do nothing
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::directory(true)
+    }
 }
 
 
@@ -290,6 +304,10 @@ impl FsNode for TaskFile {
     fn set_parent_dir(&mut self, _: WeakDirRef) {
         // do nothing
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::file(self.len(), true)
+    }
 }
 
 impl ByteReader for TaskFile {
@@ -325,6 +343,99 @@ impl File for TaskFile {
 
 
 
+/// Lazily computed file that holds the CPU usage statistics of this task,
+/// i.e., its total run time in nanoseconds, the number of times it was switched to,
+/// and the CPU it last ran on.
+pub struct StatFile {
+    taskref: WeakTaskRef,
+    task_id: usize,
+    path: PathBuf, 
+}
+
+impl StatFile {
+    pub fn new(task_id: usize, taskref: WeakTaskRef) -> StatFile {
+        StatFile {
+            taskref,
+            task_id,
+            path: PathBuf::from(format!("{TASKS_DIRECTORY_PATH}/{task_id}/stat")), 
+        }
+    }
+
+    /// Generates the CPU usage statistics string.
+    fn generate(&self) -> String {
+        let Some(taskref) = self.taskref.upgrade() else {
+            return String::from("Task Not Found");
+        };
+
+        let stats = taskref.cpu_stats();
+        let last_cpu = stats.last_cpu.map(|cpu| format!("{cpu}")).unwrap_or_else(|| String::from("-"));
+        format!("{0:<10} {1}\n{2:<10} {3}\n{4:<10} {5}\n", 
+            "runtime_ns", stats.runtime.as_nanos(),
+            "switches", stats.context_switches,
+            "last cpu", last_cpu,
+        )
+    }
+}
+
+impl FsNode for StatFile {
+    fn get_absolute_path(&self) -> String {
+        self.path.clone().into()
+    }
+
+    fn get_name(&self) -> String {
+        "stat".to_string()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        let path = PathBuf::from(format!("{}/{}", TASKS_DIRECTORY_PATH, self.task_id));
+        match Path::get_absolute(&path) {
+            Some(FileOrDir::Dir(d)) => Some(d),
+            _ => None,
+        }
+    }
+
+    fn set_parent_dir(&mut self, _: WeakDirRef) {
+        // do nothing
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::file(self.len(), true)
+    }
+}
+
+impl ByteReader for StatFile {
+    fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, IoError> {
+        let output = self.generate();
+        if offset > output.len() {
+            return Err(IoError::InvalidInput);
+        }
+        let count = core::cmp::min(buf.len(), output.len() - offset);
+        buf[..count].copy_from_slice(&output.as_bytes()[offset..(offset + count)]);
+        Ok(count)
+    }
+}
+
+impl ByteWriter for StatFile {
+    fn write_at(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, IoError> {
+        Err(IoError::from("not permitted to write task contents through the task VFS"))
+    } 
+    fn flush(&mut self) -> Result<(), IoError> { Ok(()) }
+}
+
+impl KnownLength for StatFile {
+    fn len(&self) -> usize {
+        self.generate().len() 
+    }
+}
+
+impl File for StatFile {
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        Err("task files are autogenerated, cannot be memory mapped")
+    }
+}
+
+
+
 
 
 
@@ -393,6 +504,10 @@ impl FsNode for MmiDir {
     fn set_parent_dir(&mut self, _: WeakDirRef) {
         // do nothing
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::directory(true)
+    }
 }
 
 
@@ -444,6 +559,10 @@ impl FsNode for MmiFile {
     fn set_parent_dir(&mut self, _: WeakDirRef) {
         // do nothing
     }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::file(self.len(), true)
+    }
 }
 
 impl ByteReader for MmiFile {
@@ -17,8 +17,9 @@ use core::{
     hash::{Hash, Hasher},
     ops::Deref,
     panic::PanicInfo,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
     task::Waker,
+    time::Duration,
 };
 use alloc::{
     boxed::Box,
@@ -36,6 +37,31 @@ use kernel_config::memory::KERNEL_STACK_SIZE_IN_PAGES;
 use mod_mgmt::{AppCrateRef, CrateNamespace, TlsDataImage};
 use environment::Environment;
 use spin::Mutex;
+use time::Instant;
+
+/// A function that [`Task::unblock()`] calls whenever it makes a blocked task runnable.
+///
+/// See [`set_unblock_hook()`].
+static UNBLOCK_HOOK: spin::Once<fn(&Task)> = spin::Once::new();
+
+/// Sets a function that [`Task::unblock()`] calls with each task that it makes runnable,
+/// e.g., to wake up an idle CPU that can run it.
+///
+/// The hook may be called from interrupt handlers, so it must not block.
+///
+/// Returns an error if a hook was already set.
+pub fn set_unblock_hook(hook: fn(&Task)) -> Result<(), &'static str> {
+    let mut was_set = false;
+    UNBLOCK_HOOK.call_once(|| {
+        was_set = true;
+        hook
+    });
+    if was_set {
+        Ok(())
+    } else {
+        Err("an unblock hook was already set")
+    }
+}
 
 /// The function signature of the callback that will be invoked when a `Task`
 /// panics or otherwise fails, e.g., a machine exception occurs.
@@ -154,6 +180,30 @@ pub enum SimdExt {
     None,
 }
 
+/// A real-time scheduling policy, under which a task takes precedence over all tasks
+/// that don't have one, regardless of the normal scheduling policy.
+///
+/// A task's real-time policy is set when it is spawned, and its scheduler
+/// must admit it before it can be spawned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RealTimePolicy {
+    /// The task runs until it blocks or yields, as long as no task with a higher priority
+    /// or a deadline policy is runnable.
+    /// Tasks with the same priority run in the order that they became runnable,
+    /// and a task that yields goes behind the other runnable tasks of its priority.
+    Fifo {
+        priority: u8,
+    },
+    /// The task is guaranteed `runtime` of CPU time within `deadline` of the start of
+    /// every `period`, and is throttled if it tries to use more.
+    /// Tasks with the earliest deadline run first.
+    Deadline {
+        runtime: Duration,
+        deadline: Duration,
+        period: Duration,
+    },
+}
+
 /// A struct holding data items needed to restart a `Task`.
 pub struct RestartInfo {
     /// Stores the argument of the task for restartable tasks
@@ -195,9 +245,32 @@ pub struct TaskInner {
     pub restart_info: Option<RestartInfo>,
     /// The waker that is awoken when this task completes.
     pub waker: Option<Waker>,
+    /// The real-time scheduling policy of this task, if any.
+    pub real_time_policy: Option<RealTimePolicy>,
 }
 
 
+/// A snapshot of how much CPU time a `Task` has used, as returned by [`Task::cpu_stats()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuStats {
+    /// The total time that the task has run for, including its current run if it is running.
+    pub runtime: Duration,
+    /// The number of times that the task has been switched to.
+    pub context_switches: usize,
+    /// The CPU that the task is running on or last ran on, or `None` if it has never run.
+    pub last_cpu: Option<CpuId>,
+}
+
+/// The CPU time accounting of a `Task`, which is updated upon every task switch.
+struct CpuAccounting {
+    /// The total time that the task ran for before its current run, in nanoseconds.
+    runtime_nanos: AtomicU64,
+    context_switches: AtomicUsize,
+    last_cpu: AtomicCell<OptionalCpuId>,
+    /// When the task was last switched to, or `Instant::MAX` if it isn't running.
+    switched_in_at: AtomicCell<Instant>,
+}
+
 /// A structure that contains contextual information for a thread of execution. 
 ///
 /// # Implementation note
@@ -224,6 +297,11 @@ pub struct Task {
     ///
     /// This is not public because it permits interior mutability.
     running_on_cpu: AtomicCell<OptionalCpuId>,
+    /// The CPU whose run queue this Task was most recently added to;
+    /// `None` if it hasn't been added to a run queue.
+    ///
+    /// This is not public because it permits interior mutability.
+    run_queue_cpu: AtomicCell<OptionalCpuId>,
     /// The runnability of this task, i.e., whether it's eligible to be scheduled in.
     ///
     /// This is not public because it permits interior mutability.
@@ -250,6 +328,10 @@ pub struct Task {
     /// Upon each task switch, we must set the value of the TLS base register 
     /// (e.g., FsBase on x86_64) to the value of this TLS area's self pointer.
     tls_area: TlsDataImage,
+    /// How much CPU time this task has used.
+    ///
+    /// This is not public because it permits interior mutability.
+    cpu_accounting: CpuAccounting,
     
     #[cfg(simd_personality)]
     /// Whether this Task is SIMD enabled and what level of SIMD extensions it uses.
@@ -259,6 +341,7 @@ pub struct Task {
 // Ensure that atomic fields in the `Tast` struct are actually lock-free atomics.
 const _: () = assert!(AtomicCell::<OptionalCpuId>::is_lock_free());
 const _: () = assert!(AtomicCell::<RunState>::is_lock_free());
+const _: () = assert!(AtomicCell::<Instant>::is_lock_free());
 
 impl fmt::Debug for Task {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -331,10 +414,12 @@ impl Task {
                 env,
                 restart_info: None,
                 waker: None,
+                real_time_policy: None,
             }),
             id: task_id,
             name: format!("task_{task_id}"),
             running_on_cpu: AtomicCell::new(None.into()),
+            run_queue_cpu: AtomicCell::new(None.into()),
             runstate: AtomicCell::new(RunState::Initing),
             suspended: AtomicBool::new(false),
             mmi,
@@ -342,6 +427,12 @@ impl Task {
             app_crate,
             namespace,
             tls_area,
+            cpu_accounting: CpuAccounting {
+                runtime_nanos: AtomicU64::new(0),
+                context_switches: AtomicUsize::new(0),
+                last_cpu: AtomicCell::new(None.into()),
+                switched_in_at: AtomicCell::new(Instant::MAX),
+            },
 
             #[cfg(simd_personality)]
             simd: SimdExt::None,
@@ -374,12 +465,37 @@ impl Task {
         self.running_on_cpu.load().into()
     }
 
+    /// Returns the ID of the CPU whose run queue this `Task` is in,
+    /// or `None` if it hasn't been added to a run queue.
+    pub fn run_queue_cpu(&self) -> Option<CpuId> {
+        self.run_queue_cpu.load().into()
+    }
+
     /// Returns the ID of the CPU this `Task` is pinned on,
     /// or `None` if it is not pinned.
     pub fn pinned_cpu(&self) -> Option<CpuId> {
         self.inner.lock().pinned_cpu
     }
 
+    /// Returns the real-time scheduling policy of this `Task`,
+    /// or `None` if it is scheduled by the normal scheduling policy.
+    pub fn real_time_policy(&self) -> Option<RealTimePolicy> {
+        self.inner.lock().real_time_policy
+    }
+
+    /// Returns a snapshot of how much CPU time this `Task` has used.
+    pub fn cpu_stats(&self) -> CpuStats {
+        let accounting = &self.cpu_accounting;
+        let runtime = Duration::from_nanos(accounting.runtime_nanos.load(Ordering::Relaxed));
+        // A task that isn't running has an `Instant::MAX` switch-in time, so this is zero.
+        let current_run = Instant::now().duration_since(accounting.switched_in_at.load());
+        CpuStats {
+            runtime: runtime + current_run,
+            context_switches: accounting.context_switches.load(Ordering::Relaxed),
+            last_cpu: accounting.last_cpu.load().into(),
+        }
+    }
+
     /// Returns the current [`RunState`] of this `Task`.
     pub fn runstate(&self) -> RunState {
         self.runstate.load()
@@ -508,6 +624,9 @@ impl Task {
         use RunState::{Blocked, Runnable};
 
         if self.runstate.compare_exchange(Blocked, Runnable).is_ok() {
+            if let Some(hook) = UNBLOCK_HOOK.get() {
+                hook(self);
+            }
             Ok(Blocked)
         } else if self.runstate.compare_exchange(Runnable, Runnable).is_ok() {
             // warn!("Unblocked an already runnable task: {:?}", self);
@@ -609,10 +728,32 @@ impl ExposedTask {
         &self.running_on_cpu
     }
     #[inline(always)]
+    pub fn run_queue_cpu(&self) -> &AtomicCell<OptionalCpuId> {
+        &self.run_queue_cpu
+    }
+    #[inline(always)]
     pub fn runstate(&self) -> &AtomicCell<RunState> {
         &self.runstate
     }
 }
+// The CPU time accounting is updated through these rather than exposed directly.
+impl ExposedTask {
+    /// Records that this task was switched to on the given CPU at the given time.
+    pub fn account_switch_in(&self, cpu_id: CpuId, now: Instant) {
+        let accounting = &self.cpu_accounting;
+        accounting.context_switches.fetch_add(1, Ordering::Relaxed);
+        accounting.last_cpu.store(Some(cpu_id).into());
+        accounting.switched_in_at.store(now);
+    }
+
+    /// Records that this task was switched away from at the given time.
+    pub fn account_switch_out(&self, now: Instant) {
+        let accounting = &self.cpu_accounting;
+        let switched_in_at = accounting.switched_in_at.swap(Instant::MAX);
+        let ran = now.duration_since(switched_in_at).as_nanos() as u64;
+        accounting.runtime_nanos.fetch_add(ran, Ordering::Relaxed);
+    }
+}
 
 
 /// The states used to initialize a new `Task` when creating it; see [`Task::new()`].
@@ -0,0 +1,185 @@
+//! A log sink that sends each log record as a UDP datagram to a remote endpoint,
+//! e.g., to be received by `tools/receive_udp_messages` or `socat` on another machine.
+//!
+//! The sink takes records from the logger's [remote queue](logger::remote) in its own task,
+//! so records logged after the sink is started but before networking is up are sent
+//! once it is. Records are sent without blocking: if the socket's transmit buffer is full,
+//! e.g., because the network is congested, records are dropped and counted instead,
+//! and a note with the number of dropped records is sent once there is room again.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{format, sync::Arc, vec};
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use net::{udp, IpEndpoint, NetworkInterface, Socket};
+use sync_irq::IrqSafeMutex;
+
+/// The default port that records are sent to, on which `tools/receive_udp_messages` listens.
+pub const DEFAULT_PORT: u16 = 5901;
+
+/// How often the sink's task checks whether networking is up.
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const UDP_PACKETS: usize = 64;
+const UDP_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The currently running sink, if any.
+static SINK: IrqSafeMutex<Option<Arc<SinkState>>> = IrqSafeMutex::new(None);
+/// The ID of the sink's task, whose own records are never sent to avoid a feedback loop.
+static SINK_TASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+struct SinkState {
+    remote: IpEndpoint,
+    stopped: AtomicBool,
+    /// Whether networking is up and the sink has started sending records.
+    sending: AtomicBool,
+    sent: AtomicUsize,
+    dropped: AtomicUsize,
+}
+
+/// The status of the UDP log sink, as returned by [`status()`].
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    /// The endpoint that records are sent to.
+    pub remote: IpEndpoint,
+    /// Whether records are being sent, as opposed to being queued until networking is up.
+    pub sending: bool,
+    /// The number of records waiting to be sent.
+    pub queued: usize,
+    /// The number of records sent so far.
+    pub sent: usize,
+    /// The number of records dropped so far due to congestion.
+    pub dropped: usize,
+}
+
+/// Starts sending log records to the given `remote` endpoint.
+///
+/// Records are queued until the network interface that routes to `remote`
+/// exists and has an IPv4 address.
+///
+/// Returns an error if the sink is already running.
+pub fn start(remote: IpEndpoint) -> Result<(), &'static str> {
+    let state = Arc::new(SinkState {
+        remote,
+        stopped: AtomicBool::new(false),
+        sending: AtomicBool::new(false),
+        sent: AtomicUsize::new(0),
+        dropped: AtomicUsize::new(0),
+    });
+    {
+        let mut sink = SINK.lock();
+        if sink.is_some() {
+            return Err("the UDP log sink is already running");
+        }
+        *sink = Some(state.clone());
+    }
+
+    // The task mustn't run before the logger knows not to queue its own records.
+    let task = spawn::new_task_builder(sink_task, state)
+        .name(format!("udp_log_sink_{remote}"))
+        .block()
+        .spawn()
+        .map_err(|e| {
+            SINK.lock().take();
+            e
+        })?;
+    SINK_TASK_ID.store(task.id, Ordering::Relaxed);
+    logger::remote::start_remote_queue(is_not_sink_task);
+    task.unblock().map_err(|_| "couldn't unblock the UDP log sink task")?;
+    Ok(())
+}
+
+/// The logger's filter for queued records, which excludes those logged by the sink's own task.
+fn is_not_sink_task() -> bool {
+    task::get_my_current_task_id() != SINK_TASK_ID.load(Ordering::Relaxed)
+}
+
+/// Stops the UDP log sink, discarding any records that have yet to be sent.
+///
+/// Records are no longer queued until the sink is started again.
+///
+/// Returns an error if the sink isn't running.
+pub fn stop() -> Result<(), &'static str> {
+    let state = SINK.lock().take().ok_or("the UDP log sink isn't running")?;
+    state.stopped.store(true, Ordering::Release);
+    // This also wakes the sink's task, so that it notices it was stopped.
+    logger::remote::stop_remote_queue();
+    Ok(())
+}
+
+/// Returns the status of the UDP log sink, or `None` if it isn't running.
+pub fn status() -> Option<Status> {
+    let state = SINK.lock().clone()?;
+    let (queued, dropped) = logger::remote::remote_queue_len();
+    Some(Status {
+        remote: state.remote,
+        sending: state.sending.load(Ordering::Relaxed),
+        queued,
+        sent: state.sent.load(Ordering::Relaxed),
+        dropped: state.dropped.load(Ordering::Relaxed) + dropped,
+    })
+}
+
+/// The entry point for the task that sends queued records.
+fn sink_task(state: Arc<SinkState>) {
+    let stopped = || state.stopped.load(Ordering::Acquire);
+
+    // Wait for networking to be up.
+    let interface = loop {
+        if stopped() {
+            return;
+        }
+        let interface = net::get_interface_for(state.remote.addr)
+            .filter(|interface| interface.ipv4_address().is_some());
+        if let Some(interface) = interface {
+            break interface;
+        }
+        let _ = sleep::sleep(NETWORK_POLL_INTERVAL);
+    };
+
+    // The socket only sends, so its receive buffer is empty.
+    let socket = interface.clone().add_socket(udp::Socket::new(
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; 1], vec![0; 0]),
+        udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0; UDP_BUFFER_SIZE]),
+    ));
+    let result = socket.lock().bind(net::get_ephemeral_port());
+    if result.is_err() {
+        socket.remove();
+        return;
+    }
+    state.sending.store(true, Ordering::Relaxed);
+
+    let (waker, blocker) = waker::new_waker();
+    let mut record = [0; logger::remote::MAX_REMOTE_RECORD_LEN];
+    while !stopped() {
+        let Some((len, dropped)) = logger::remote::take_remote_record(&mut record, &waker) else {
+            blocker.block();
+            continue;
+        };
+        if dropped > 0 {
+            state.dropped.fetch_add(dropped, Ordering::Relaxed);
+            let note = format!("---- {dropped} log records were dropped ----");
+            send(&state, &socket, &interface, note.as_bytes());
+        }
+        send(&state, &socket, &interface, &record[..len]);
+    }
+    socket.remove();
+}
+
+/// Sends the given record without blocking, dropping it if the socket's transmit buffer is full.
+fn send(state: &SinkState, socket: &Socket<udp::Socket<'static>>, interface: &NetworkInterface, record: &[u8]) {
+    let result = socket.lock().send_slice(record, state.remote);
+    match result {
+        Ok(()) => {
+            state.sent.fetch_add(1, Ordering::Relaxed);
+            interface.wake();
+        }
+        Err(_) => {
+            state.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
@@ -11,13 +11,15 @@ extern crate alloc;
 extern crate spin;
 extern crate fs_node;
 extern crate memory;
+extern crate time;
 
 use alloc::string::String;
 use alloc::vec::Vec;
 use spin::Mutex;
 use alloc::sync::{Arc, Weak};
 use alloc::collections::BTreeMap;
-use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, FsNode};
+use fs_node::{DirRef, WeakDirRef, Directory, FileOrDir, FsNode, Metadata, NodeKind};
+use time::{Duration, WallTime};
 
 
 /// A struct that represents a node in the VFS 
@@ -28,26 +30,48 @@ pub struct VFSDirectory {
     pub children: BTreeMap<String, FileOrDir>,
     /// A weak reference to the parent directory
     pub parent: WeakDirRef,
+    /// When this directory was created, as the time since the Unix epoch
+    pub created: Duration,
+    /// When a child was last inserted into or removed from this directory, as the time since the Unix epoch
+    pub modified: Duration,
 }
 
 impl VFSDirectory {
     /// Creates a new directory and passes a pointer to the new directory created as output
     pub fn create(name: String, parent: &DirRef)  -> Result<DirRef, &'static str> {
         // creates a copy of the parent pointer so that we can add the newly created folder to the parent's children later
+        let now = time::now::<WallTime>();
         let directory = VFSDirectory {
             name,
             children: BTreeMap::new(),
             parent: Arc::downgrade(parent),
+            created: now,
+            modified: now,
         };
         let dir_ref = Arc::new(Mutex::new(directory)) as DirRef;
         parent.lock().insert(FileOrDir::Dir(dir_ref.clone()))?;
         Ok(dir_ref)
     }
+
+    /// Creates a new directory that has no parent and is not inserted into any other directory,
+    /// e.g., the root directory of a new filesystem that will be mounted elsewhere.
+    pub fn new_root(name: String) -> DirRef {
+        let now = time::now::<WallTime>();
+        let directory = VFSDirectory {
+            name,
+            children: BTreeMap::new(),
+            parent: Weak::<Mutex<VFSDirectory>>::new(),
+            created: now,
+            modified: now,
+        };
+        Arc::new(Mutex::new(directory)) as DirRef
+    }
 }
 
 impl Directory for VFSDirectory {
     fn insert(&mut self, node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
         let name = node.get_name();
+        self.modified = time::now::<WallTime>();
         if let Some(mut old_node) = self.children.insert(name, node) {
             old_node.set_parent_dir(Weak::<Mutex<VFSDirectory>>::new());
             Ok(Some(old_node))
@@ -68,6 +92,7 @@ impl Directory for VFSDirectory {
     fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
         if let Some(mut old_node) = self.children.remove(&node.get_name()) {
             old_node.set_parent_dir(Weak::<Mutex<VFSDirectory>>::new());
+            self.modified = time::now::<WallTime>();
             Some(old_node)
         } else {
             None
@@ -88,4 +113,19 @@ impl FsNode for VFSDirectory {
     fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
         self.parent = new_parent;
     }
+
+    fn set_name(&mut self, new_name: String) -> Result<(), &'static str> {
+        self.name = new_name;
+        Ok(())
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: NodeKind::Directory,
+            size: 0,
+            created: Some(self.created),
+            modified: Some(self.modified),
+            read_only: false,
+        }
+    }
 }
@@ -0,0 +1,465 @@
+//! Support for virtio devices that use the legacy PCI transport.
+//!
+//! This crate provides the pieces shared by all virtio device drivers:
+//! * [`LegacyTransport`]: access to a virtio device's common registers through its PCI I/O port BAR,
+//!   including device status handling and feature negotiation.
+//! * [`Virtqueue`]: a split virtqueue, the ring of buffers through which
+//!   the driver and the device exchange requests.
+//!
+//! The legacy transport is supported by "transitional" virtio PCI devices,
+//! which is what QEMU exposes by default for devices on a conventional PCI bus.
+//! Modern-only (virtio 1.0) devices that lack an I/O port BAR are not yet supported.
+//!
+//! See the virtio specification for more details:
+//! <https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-1120002>
+
+#![no_std]
+
+use core::{mem::size_of, sync::atomic::{fence, Ordering}};
+use bitflags::bitflags;
+use log::debug;
+use memory::{create_contiguous_mapping, MappedPages, PhysicalAddress, DMA_FLAGS};
+use pci::PciDevice;
+use port_io::{Port, PortIn, PortOut};
+use volatile::Volatile;
+use zerocopy::FromBytes;
+
+/// The PCI vendor ID of all virtio devices.
+pub const VIRTIO_PCI_VENDOR_ID: u16 = 0x1AF4;
+/// The range of PCI device IDs used by transitional virtio devices,
+/// which are the devices that support the legacy transport.
+pub const VIRTIO_PCI_TRANSITIONAL_DEVICE_IDS: core::ops::RangeInclusive<u16> = 0x1000 ..= 0x103F;
+
+/// The PCI device ID of a transitional virtio network device.
+pub const VIRTIO_PCI_DEVICE_ID_NET: u16 = 0x1000;
+/// The PCI device ID of a transitional virtio block device.
+pub const VIRTIO_PCI_DEVICE_ID_BLOCK: u16 = 0x1001;
+
+// Offsets of the legacy transport's common registers from the start of the I/O port BAR.
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES:  u16 = 0x04;
+const REG_QUEUE_ADDRESS:   u16 = 0x08;
+const REG_QUEUE_SIZE:      u16 = 0x0C;
+const REG_QUEUE_SELECT:    u16 = 0x0E;
+const REG_QUEUE_NOTIFY:    u16 = 0x10;
+const REG_DEVICE_STATUS:   u16 = 0x12;
+const REG_ISR_STATUS:      u16 = 0x13;
+/// The device-specific configuration starts here, as long as MSI-X is not enabled.
+const REG_DEVICE_CONFIG:   u16 = 0x14;
+
+/// To use a BAR as a Port address, you must mask out the lowest 2 bits.
+const PCI_BAR_PORT_MASK: u32 = 0xFFFC;
+
+/// The alignment of a legacy virtqueue's used ring, and the unit of its queue address.
+const QUEUE_ALIGNMENT: usize = 4096;
+
+
+bitflags! {
+    /// The bits of a virtio device's status register.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub struct DeviceStatus: u8 {
+        /// The guest has noticed the device.
+        const ACKNOWLEDGE        = 0x01;
+        /// The guest knows how to drive the device.
+        const DRIVER             = 0x02;
+        /// The driver is set up and ready to drive the device.
+        const DRIVER_OK          = 0x04;
+        /// The driver has acknowledged all the features it understands.
+        const FEATURES_OK        = 0x08;
+        /// The device has experienced an error from which it can't recover.
+        const DEVICE_NEEDS_RESET = 0x40;
+        /// The guest has given up on the device.
+        const FAILED             = 0x80;
+    }
+}
+
+
+/// A virtio device's common registers, accessed through the legacy PCI transport.
+#[derive(Debug)]
+pub struct LegacyTransport {
+    /// The base I/O port of the device's registers, from BAR0.
+    port_base: u16,
+}
+
+impl LegacyTransport {
+    /// Creates a transport for the given virtio PCI device and enables PCI bus mastering,
+    /// which the device needs in order to access its virtqueues.
+    ///
+    /// Returns an error if the device doesn't support the legacy transport.
+    pub fn new(pci_device: &PciDevice) -> Result<LegacyTransport, &'static str> {
+        if pci_device.vendor_id != VIRTIO_PCI_VENDOR_ID
+            || !VIRTIO_PCI_TRANSITIONAL_DEVICE_IDS.contains(&pci_device.device_id)
+        {
+            return Err("virtio: PCI device is not a transitional virtio device");
+        }
+        let bar0 = pci_device.bars[0];
+        if bar0 & 0x1 == 0 {
+            return Err("virtio: BAR0 was not an I/O port BAR, so the legacy transport is unavailable");
+        }
+        pci_device.pci_set_command_bus_master_bit();
+        Ok(LegacyTransport { port_base: (bar0 & PCI_BAR_PORT_MASK) as u16 })
+    }
+
+    /// Resets the device and negotiates its features.
+    ///
+    /// The given `negotiate` closure receives the features offered by the device
+    /// and returns the subset of features that the driver accepts.
+    /// Returns the accepted features.
+    ///
+    /// After this, the driver should set up its virtqueues and then invoke [`Self::driver_ok()`].
+    pub fn begin_init<F: FnOnce(u32) -> u32>(&self, negotiate: F) -> u32 {
+        self.reset();
+        self.add_status(DeviceStatus::ACKNOWLEDGE);
+        self.add_status(DeviceStatus::DRIVER);
+        let device_features = self.device_features();
+        let accepted = negotiate(device_features) & device_features;
+        debug!("virtio: device features {:#X}, accepted features {:#X}", device_features, accepted);
+        self.set_guest_features(accepted);
+        accepted
+    }
+
+    /// Tells the device that the driver has finished setting it up.
+    pub fn driver_ok(&self) {
+        self.add_status(DeviceStatus::DRIVER_OK);
+    }
+
+    /// Marks the device as failed, e.g., because its initialization could not be completed.
+    pub fn fail(&self) {
+        self.add_status(DeviceStatus::FAILED);
+    }
+
+    /// Resets the device, which also discards all of its virtqueues.
+    pub fn reset(&self) {
+        self.set_status(DeviceStatus::empty());
+    }
+
+    /// Returns the device's status.
+    pub fn status(&self) -> DeviceStatus {
+        DeviceStatus::from_bits_truncate(self.port::<u8>(REG_DEVICE_STATUS).read())
+    }
+
+    /// Sets the device's status.
+    pub fn set_status(&self, status: DeviceStatus) {
+        unsafe { self.port::<u8>(REG_DEVICE_STATUS).write(status.bits()) }
+    }
+
+    /// Adds the given bits to the device's status.
+    pub fn add_status(&self, status: DeviceStatus) {
+        self.set_status(self.status() | status);
+    }
+
+    /// Returns the features offered by the device.
+    pub fn device_features(&self) -> u32 {
+        self.port::<u32>(REG_DEVICE_FEATURES).read()
+    }
+
+    /// Tells the device which features the driver will use.
+    pub fn set_guest_features(&self, features: u32) {
+        unsafe { self.port::<u32>(REG_GUEST_FEATURES).write(features) }
+    }
+
+    /// Tells the device that new buffers are available in the virtqueue at `queue_index`.
+    pub fn notify(&self, queue_index: u16) {
+        unsafe { self.port::<u16>(REG_QUEUE_NOTIFY).write(queue_index) }
+    }
+
+    /// Reads and acknowledges the device's interrupt status.
+    ///
+    /// Bit 0 indicates a used buffer notification, and bit 1 indicates a configuration change.
+    pub fn read_isr_status(&self) -> u8 {
+        self.port::<u8>(REG_ISR_STATUS).read()
+    }
+
+    /// Reads a byte from the device-specific configuration at the given `offset`.
+    pub fn read_config_u8(&self, offset: u16) -> u8 {
+        self.port::<u8>(REG_DEVICE_CONFIG + offset).read()
+    }
+
+    /// Reads a 16-bit value from the device-specific configuration at the given `offset`.
+    pub fn read_config_u16(&self, offset: u16) -> u16 {
+        self.port::<u16>(REG_DEVICE_CONFIG + offset).read()
+    }
+
+    /// Reads a 32-bit value from the device-specific configuration at the given `offset`.
+    pub fn read_config_u32(&self, offset: u16) -> u32 {
+        self.port::<u32>(REG_DEVICE_CONFIG + offset).read()
+    }
+
+    /// Reads a 64-bit value from the device-specific configuration at the given `offset`,
+    /// which the legacy transport exposes as two 32-bit halves.
+    pub fn read_config_u64(&self, offset: u16) -> u64 {
+        let low = self.read_config_u32(offset) as u64;
+        let high = self.read_config_u32(offset + 4) as u64;
+        (high << 32) | low
+    }
+
+    /// Writes a 16-bit value to the device-specific configuration at the given `offset`.
+    pub fn write_config_u16(&self, offset: u16, value: u16) {
+        unsafe { self.port::<u16>(REG_DEVICE_CONFIG + offset).write(value) }
+    }
+
+    /// Selects the virtqueue at `queue_index` and returns its size,
+    /// or `0` if the device has no such virtqueue.
+    fn select_queue(&self, queue_index: u16) -> u16 {
+        unsafe { self.port::<u16>(REG_QUEUE_SELECT).write(queue_index) };
+        self.port::<u16>(REG_QUEUE_SIZE).read()
+    }
+
+    fn port<T: PortIn + PortOut>(&self, offset: u16) -> Port<T> {
+        Port::new(self.port_base + offset)
+    }
+}
+
+
+bitflags! {
+    /// The flags of a virtqueue descriptor.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct DescriptorFlags: u16 {
+        /// The buffer continues in the descriptor given by the `next` field.
+        const NEXT  = 0x1;
+        /// The buffer is write-only for the device (otherwise, it is read-only for the device).
+        const WRITE = 0x2;
+    }
+}
+
+/// Set in the available ring's flags to ask the device not to interrupt
+/// when it consumes a buffer.
+const AVAIL_F_NO_INTERRUPT: u16 = 0x1;
+
+/// A descriptor in a virtqueue's descriptor table, which describes one buffer.
+#[derive(FromBytes)]
+#[repr(C)]
+struct Descriptor {
+    phys_addr: Volatile<u64>,
+    length:    Volatile<u32>,
+    flags:     Volatile<u16>,
+    next:      Volatile<u16>,
+}
+
+/// An entry in a virtqueue's used ring.
+#[derive(FromBytes)]
+#[repr(C)]
+struct UsedElement {
+    /// The index of the head descriptor of the used buffer chain.
+    id:     Volatile<u32>,
+    /// The number of bytes the device wrote into the buffer chain.
+    length: Volatile<u32>,
+}
+
+/// One buffer within a chain of buffers that is made available to the device.
+#[derive(Clone, Copy, Debug)]
+pub struct VirtqueueBuffer {
+    /// The physical address of the start of the buffer.
+    pub phys_addr: PhysicalAddress,
+    /// The length of the buffer in bytes.
+    pub length: u32,
+    /// Whether the device writes into this buffer (`true`) or reads from it (`false`).
+    pub device_writable: bool,
+}
+
+/// A split virtqueue laid out in memory as required by the legacy transport:
+/// the descriptor table and available ring, followed by the used ring on the next page boundary.
+#[derive(Debug)]
+pub struct Virtqueue {
+    /// The index of this queue within its device.
+    index: u16,
+    /// The number of descriptors in this queue.
+    size: u16,
+    /// The memory backing the descriptor table and both rings.
+    mp: MappedPages,
+    /// The offset of the available ring into `mp`.
+    avail_offset: usize,
+    /// The offset of the used ring into `mp`.
+    used_offset: usize,
+    /// The first descriptor in the list of free descriptors.
+    free_head: u16,
+    /// The number of descriptors in the list of free descriptors.
+    num_free: u16,
+    /// The next index into the available ring that the driver will fill.
+    avail_idx: u16,
+    /// The next index into the used ring that the driver will consume.
+    last_used_idx: u16,
+}
+
+impl Virtqueue {
+    /// Allocates the virtqueue at `queue_index` and registers it with the device.
+    ///
+    /// This must be invoked after feature negotiation but before [`LegacyTransport::driver_ok()`].
+    pub fn new(transport: &LegacyTransport, queue_index: u16) -> Result<Virtqueue, &'static str> {
+        let size = transport.select_queue(queue_index);
+        if size == 0 {
+            return Err("virtio: the device has no virtqueue at the given index");
+        }
+        if transport.port::<u32>(REG_QUEUE_ADDRESS).read() != 0 {
+            return Err("virtio: the virtqueue at the given index is already in use");
+        }
+
+        let avail_offset = size_of::<Descriptor>() * size as usize;
+        let avail_size = size_of::<u16>() * (3 + size as usize);
+        let used_offset = align_up(avail_offset + avail_size, QUEUE_ALIGNMENT);
+        let used_size = size_of::<u16>() * 3 + size_of::<UsedElement>() * size as usize;
+        let total_size = used_offset + align_up(used_size, QUEUE_ALIGNMENT);
+
+        let (mut mp, phys_addr) = create_contiguous_mapping(total_size, DMA_FLAGS)?;
+        if phys_addr.value() % QUEUE_ALIGNMENT != 0 || (phys_addr.value() / QUEUE_ALIGNMENT) > (u32::MAX as usize) {
+            return Err("virtio: virtqueue memory could not be addressed by the legacy transport");
+        }
+        mp.as_slice_mut::<u8>(0, total_size)?.fill(0);
+
+        let mut queue = Virtqueue {
+            index: queue_index,
+            size,
+            mp,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        // Chain all descriptors together into the free list.
+        for (i, descriptor) in queue.descriptors().iter_mut().enumerate() {
+            descriptor.next.write((i + 1) as u16);
+        }
+        unsafe { transport.port::<u32>(REG_QUEUE_ADDRESS).write((phys_addr.value() / QUEUE_ALIGNMENT) as u32) };
+        Ok(queue)
+    }
+
+    /// Returns the index of this queue within its device.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns the total number of descriptors in this queue.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Returns the number of descriptors that are not currently in use.
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Sets whether the device should raise an interrupt when it consumes buffers from this queue.
+    ///
+    /// This is only a hint; the device may still raise interrupts.
+    pub fn set_interrupts_enabled(&mut self, enabled: bool) {
+        let flags = if enabled { 0 } else { AVAIL_F_NO_INTERRUPT };
+        self.avail_ring()[0].write(flags);
+    }
+
+    /// Makes the given chain of `buffers` available to the device.
+    ///
+    /// Returns the index of the chain's head descriptor, which identifies the chain
+    /// once the device has used it; see [`Self::pop_used()`].
+    /// The caller must then [`notify`](LegacyTransport::notify) the device.
+    pub fn add(&mut self, buffers: &[VirtqueueBuffer]) -> Result<u16, &'static str> {
+        if buffers.is_empty() {
+            return Err("virtio: cannot add an empty buffer chain to a virtqueue");
+        }
+        if buffers.len() > self.num_free as usize {
+            return Err("virtio: not enough free descriptors in the virtqueue");
+        }
+
+        let head = self.free_head;
+        let mut next = self.free_head;
+        let descriptors = self.descriptors();
+        for (i, buffer) in buffers.iter().enumerate() {
+            let descriptor = &mut descriptors[next as usize];
+            let mut flags = DescriptorFlags::empty();
+            if buffer.device_writable {
+                flags |= DescriptorFlags::WRITE;
+            }
+            if i + 1 < buffers.len() {
+                flags |= DescriptorFlags::NEXT;
+            }
+            descriptor.phys_addr.write(buffer.phys_addr.value() as u64);
+            descriptor.length.write(buffer.length);
+            descriptor.flags.write(flags.bits());
+            // The `next` field of a free descriptor already links to the next free descriptor.
+            next = descriptor.next.read();
+        }
+        self.free_head = next;
+        self.num_free -= buffers.len() as u16;
+
+        let size = self.size;
+        let avail_idx = self.avail_idx;
+        let avail_ring = self.avail_ring();
+        avail_ring[2 + (avail_idx % size) as usize].write(head);
+        // The device must observe the descriptors and ring entry before the new index.
+        fence(Ordering::SeqCst);
+        avail_ring[1].write(avail_idx.wrapping_add(1));
+        self.avail_idx = avail_idx.wrapping_add(1);
+        Ok(head)
+    }
+
+    /// Returns `true` if the device has used a buffer chain that the driver has not yet consumed.
+    pub fn has_used(&mut self) -> bool {
+        self.last_used_idx != self.used_header()[1].read()
+    }
+
+    /// Consumes the next buffer chain that the device has used, returning its descriptors to the free list.
+    ///
+    /// Returns the index of the chain's head descriptor and the number of bytes the device wrote into it,
+    /// or `None` if the device has not used any more buffer chains.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        // Read the used element only after observing the new used index.
+        fence(Ordering::SeqCst);
+        let slot = (self.last_used_idx % self.size) as usize;
+        let (head, length) = {
+            let element = &self.used_ring()[slot];
+            (element.id.read() as u16, element.length.read())
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        self.free_chain(head);
+        Some((head, length))
+    }
+
+    /// Returns the chain of descriptors starting at `head` to the free list
+    /// without waiting for the device to use it.
+    ///
+    /// This must only be used once the device can no longer access the chain,
+    /// e.g., after the device has been reset.
+    pub fn free_chain(&mut self, head: u16) {
+        let free_head = self.free_head;
+        let descriptors = self.descriptors();
+        let mut count = 1;
+        let mut last = head;
+        while DescriptorFlags::from_bits_truncate(descriptors[last as usize].flags.read()).contains(DescriptorFlags::NEXT) {
+            last = descriptors[last as usize].next.read();
+            count += 1;
+        }
+        descriptors[last as usize].next.write(free_head);
+        self.free_head = head;
+        self.num_free += count;
+    }
+
+    fn descriptors(&mut self) -> &mut [Descriptor] {
+        let size = self.size as usize;
+        self.mp.as_slice_mut(0, size).expect("BUG: virtqueue descriptor table was out of bounds")
+    }
+
+    /// The available ring: its flags, its index, the ring entries, and the `used_event` field.
+    fn avail_ring(&mut self) -> &mut [Volatile<u16>] {
+        let (offset, size) = (self.avail_offset, self.size as usize);
+        self.mp.as_slice_mut(offset, 3 + size).expect("BUG: virtqueue available ring was out of bounds")
+    }
+
+    /// The used ring's flags and index.
+    fn used_header(&mut self) -> &mut [Volatile<u16>] {
+        let offset = self.used_offset;
+        self.mp.as_slice_mut(offset, 2).expect("BUG: virtqueue used ring was out of bounds")
+    }
+
+    fn used_ring(&mut self) -> &mut [UsedElement] {
+        let (offset, size) = (self.used_offset + 2 * size_of::<u16>(), self.size as usize);
+        self.mp.as_slice_mut(offset, size).expect("BUG: virtqueue used ring was out of bounds")
+    }
+}
+
+fn align_up(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) / alignment * alignment
+}
@@ -0,0 +1,372 @@
+//! Storage device driver for virtio block devices, e.g., QEMU's `-drive if=virtio`.
+//!
+//! The primary struct of interest is [`VirtioBlkDrive`], which is a [`StorageDevice`].
+//! Each virtio block PCI device exposes exactly one drive,
+//! so the [`VirtioBlkController`] that owns it always has a single device.
+//!
+//! Requests are submitted one at a time through the device's only request queue,
+//! and the driver polls the queue for their completion for up to [`REQUEST_TIMEOUT`].
+//! If the device doesn't complete a request in time, that request fails and the device is reset
+//! and reinitialized, such that later requests can succeed. After [`MAX_CONSECUTIVE_RESETS`]
+//! resets without a request completing in between, the drive returns an error for all later requests.
+//!
+//! # Limitations
+//! * Only the legacy PCI transport is supported; see the [`virtio`] crate.
+//! * Data is transferred through a bounce buffer, so at most
+//!   [`MAX_SECTORS_PER_REQUEST`] sectors can be read or written by a single request.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{hint, mem::size_of, time::Duration};
+use io::{BlockIo, BlockReader, BlockWriter, IoError, KnownLength};
+use log::{debug, error, info};
+use memory::{create_contiguous_mapping, MappedPages, PhysicalAddress, DMA_FLAGS};
+use pci::PciDevice;
+use spin::Mutex;
+use storage_device::{StorageController, StorageDevice, StorageDeviceRef};
+use virtio::{LegacyTransport, Virtqueue, VirtqueueBuffer, VIRTIO_PCI_DEVICE_ID_BLOCK, VIRTIO_PCI_VENDOR_ID};
+
+/// virtio block devices always address data in units of 512-byte sectors,
+/// regardless of the device's preferred block size.
+const SECTOR_SIZE_IN_BYTES: usize = 512;
+
+/// The maximum number of sectors that can be transferred by a single request.
+pub const MAX_SECTORS_PER_REQUEST: usize = 128;
+
+/// How long to wait for the device to complete a request before giving up on it.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times in a row the device is reset after failing to complete a request
+/// before the drive gives up on it.
+pub const MAX_CONSECUTIVE_RESETS: usize = 3;
+
+/// Feature bit: the device is read-only.
+const VIRTIO_BLK_F_RO:    u32 = 1 << 5;
+/// Feature bit: the device supports the flush command.
+const VIRTIO_BLK_F_FLUSH: u32 = 1 << 9;
+
+/// The offset of the `capacity` field, in sectors, in the device-specific configuration.
+const CONFIG_CAPACITY: u16 = 0;
+
+/// The index of the device's only request queue.
+const REQUEST_QUEUE_INDEX: u16 = 0;
+
+/// The size of the header at the beginning of every request:
+/// a 32-bit request type, 32 reserved bits, and the 64-bit starting sector.
+const REQUEST_HEADER_SIZE: usize = 16;
+
+// The layout of the request buffer, which holds a request's header, status, and data.
+const REQUEST_HEADER_OFFSET: usize = 0;
+const REQUEST_STATUS_OFFSET: usize = REQUEST_HEADER_SIZE;
+const REQUEST_DATA_OFFSET:   usize = SECTOR_SIZE_IN_BYTES;
+const REQUEST_BUFFER_SIZE:   usize = REQUEST_DATA_OFFSET + MAX_SECTORS_PER_REQUEST * SECTOR_SIZE_IN_BYTES;
+
+
+/// The types of requests that can be issued to a virtio block device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+enum RequestType {
+    In    = 0,
+    Out   = 1,
+    Flush = 4,
+}
+
+/// The status values that a virtio block device writes back for each request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum RequestStatus {
+    Ok          = 0,
+    IoError     = 1,
+    Unsupported = 2,
+}
+
+
+/// Returns `true` if the given PCI device is a virtio block device supported by this driver.
+pub fn is_virtio_blk_device(pci_device: &PciDevice) -> bool {
+    pci_device.vendor_id == VIRTIO_PCI_VENDOR_ID && pci_device.device_id == VIRTIO_PCI_DEVICE_ID_BLOCK
+}
+
+
+/// A virtio block device's single drive.
+#[derive(Debug)]
+pub struct VirtioBlkDrive {
+    transport: LegacyTransport,
+    request_queue: Virtqueue,
+    /// The buffer through which every request's header, status, and data are exchanged with the device.
+    request_buffer: MappedPages,
+    request_buffer_phys_addr: PhysicalAddress,
+    /// The size of the drive in sectors.
+    capacity: usize,
+    read_only: bool,
+    supports_flush: bool,
+    /// Set while the device has been reset but not yet reinitialized.
+    needs_reinit: bool,
+    /// The number of times the device has been reset since it last completed a request.
+    consecutive_resets: usize,
+}
+
+impl VirtioBlkDrive {
+    /// Initializes the virtio block device described by the given PCI device.
+    fn new(pci_device: &PciDevice) -> Result<VirtioBlkDrive, &'static str> {
+        let transport = LegacyTransport::new(pci_device)?;
+        let (request_buffer, request_buffer_phys_addr) = create_contiguous_mapping(REQUEST_BUFFER_SIZE, DMA_FLAGS)?;
+        let (features, request_queue) = init_device(&transport)?;
+
+        let capacity = transport.read_config_u64(CONFIG_CAPACITY) as usize;
+        Ok(VirtioBlkDrive {
+            transport,
+            request_queue,
+            request_buffer,
+            request_buffer_phys_addr,
+            capacity,
+            read_only: features & VIRTIO_BLK_F_RO != 0,
+            supports_flush: features & VIRTIO_BLK_F_FLUSH != 0,
+            needs_reinit: false,
+            consecutive_resets: 0,
+        })
+    }
+
+    /// Returns `true` if this drive can only be read from.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Reads data from this drive starting at the given `offset_in_sectors` into the provided `buffer`.
+    ///
+    /// The buffer length must be a multiple of the sector size (512 bytes)
+    /// and cover no more than [`MAX_SECTORS_PER_REQUEST`] sectors.
+    ///
+    /// Returns the number of sectors (*not bytes*) that were read from the drive.
+    pub fn read_sectors(&mut self, buffer: &mut [u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        let sector_count = self.check_transfer(buffer.len(), offset_in_sectors)?;
+        if sector_count == 0 {
+            return Ok(0);
+        }
+        self.submit(RequestType::In, offset_in_sectors, buffer.len())?;
+        buffer.copy_from_slice(self.request_buffer.as_slice(REQUEST_DATA_OFFSET, buffer.len())?);
+        Ok(sector_count)
+    }
+
+    /// Writes data from the provided `buffer` to this drive, starting at the given `offset_in_sectors` into the drive.
+    ///
+    /// The buffer length must be a multiple of the sector size (512 bytes)
+    /// and cover no more than [`MAX_SECTORS_PER_REQUEST`] sectors.
+    ///
+    /// Returns the number of sectors (*not bytes*) that were written to the drive.
+    pub fn write_sectors(&mut self, buffer: &[u8], offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if self.read_only {
+            return Err("virtio_blk: cannot write to a read-only drive");
+        }
+        let sector_count = self.check_transfer(buffer.len(), offset_in_sectors)?;
+        if sector_count == 0 {
+            return Ok(0);
+        }
+        self.request_buffer.as_slice_mut(REQUEST_DATA_OFFSET, buffer.len())?.copy_from_slice(buffer);
+        self.submit(RequestType::Out, offset_in_sectors, buffer.len())?;
+        Ok(sector_count)
+    }
+
+    /// Asks the drive to persist all previously-written data, if it supports doing so.
+    pub fn flush_cache(&mut self) -> Result<(), &'static str> {
+        if self.supports_flush {
+            self.submit(RequestType::Flush, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that a transfer of `length_in_bytes` starting at `offset_in_sectors` is valid for this drive.
+    ///
+    /// Returns the number of sectors to be transferred.
+    fn check_transfer(&self, length_in_bytes: usize, offset_in_sectors: usize) -> Result<usize, &'static str> {
+        if length_in_bytes % SECTOR_SIZE_IN_BYTES != 0 {
+            return Err("virtio_blk: the buffer length must be a multiple of sector size (512) bytes");
+        }
+        let sector_count = length_in_bytes / SECTOR_SIZE_IN_BYTES;
+        if sector_count > MAX_SECTORS_PER_REQUEST {
+            return Err("virtio_blk: cannot transfer more sectors than the max per request");
+        }
+        if offset_in_sectors.checked_add(sector_count).map_or(true, |end| end > self.capacity) {
+            return Err("virtio_blk: offset_in_sectors was out of bounds");
+        }
+        Ok(sector_count)
+    }
+
+    /// Submits a request with `data_length` bytes of data from the request buffer
+    /// and waits for the device to complete it.
+    fn submit(&mut self, request_type: RequestType, sector: usize, data_length: usize) -> Result<(), &'static str> {
+        if self.consecutive_resets >= MAX_CONSECUTIVE_RESETS {
+            return Err("virtio_blk: the device was reset too many times after failing to complete requests");
+        }
+        if self.needs_reinit {
+            // Reinitializing the device after its last reset failed, so try again.
+            self.reinitialize()?;
+        }
+        {
+            let header: &mut [u32] = self.request_buffer.as_slice_mut(REQUEST_HEADER_OFFSET, REQUEST_HEADER_SIZE / size_of::<u32>())?;
+            header[0] = request_type as u32;
+            header[1] = 0;
+            header[2] = sector as u32;
+            header[3] = (sector as u64 >> 32) as u32;
+        }
+        // Set the status to an invalid value so that we can tell whether the device wrote it.
+        self.request_buffer.as_slice_mut::<u8>(REQUEST_STATUS_OFFSET, 1)?[0] = 0xFF;
+
+        let base = self.request_buffer_phys_addr;
+        let header = VirtqueueBuffer {
+            phys_addr: base + REQUEST_HEADER_OFFSET,
+            length: REQUEST_HEADER_SIZE as u32,
+            device_writable: false,
+        };
+        let data = VirtqueueBuffer {
+            phys_addr: base + REQUEST_DATA_OFFSET,
+            length: data_length as u32,
+            device_writable: request_type == RequestType::In,
+        };
+        let status = VirtqueueBuffer {
+            phys_addr: base + REQUEST_STATUS_OFFSET,
+            length: 1,
+            device_writable: true,
+        };
+        let head = if data_length == 0 {
+            self.request_queue.add(&[header, status])?
+        } else {
+            self.request_queue.add(&[header, data, status])?
+        };
+        self.transport.notify(REQUEST_QUEUE_INDEX);
+
+        let deadline = time::now::<time::Monotonic>() + REQUEST_TIMEOUT;
+        let mut _loop_counter: usize = 0;
+        let used_head = loop {
+            if let Some((used_head, _length)) = self.request_queue.pop_used() {
+                break used_head;
+            }
+            if time::now::<time::Monotonic>() >= deadline {
+                error!("VirtioBlkDrive::submit(): device did not complete a {:?} request within {:?}", request_type, REQUEST_TIMEOUT);
+                self.abandon(head);
+                return Err("virtio_blk: timed out waiting for the device to complete a request");
+            }
+            _loop_counter += 1;
+            if _loop_counter % 100_000_000 == 0 {
+                debug!("VirtioBlkDrive::submit() has been waiting for a long time for a {:?} request", request_type);
+            }
+            hint::spin_loop();
+        };
+        if used_head != head {
+            error!("VirtioBlkDrive::submit(): device completed request {} instead of {}", used_head, head);
+            self.abandon(head);
+            return Err("virtio_blk: device completed an unexpected request");
+        }
+
+        self.consecutive_resets = 0;
+        match self.request_buffer.as_slice::<u8>(REQUEST_STATUS_OFFSET, 1)?[0] {
+            s if s == RequestStatus::Ok as u8 => Ok(()),
+            s if s == RequestStatus::IoError as u8 => Err("virtio_blk: device reported an I/O error"),
+            s if s == RequestStatus::Unsupported as u8 => Err("virtio_blk: device reported an unsupported request"),
+            _ => Err("virtio_blk: device reported an unknown request status"),
+        }
+    }
+
+    /// Gives up on the outstanding request whose descriptor chain starts at `head`.
+    ///
+    /// The device is reset first, so that it can no longer access the request's descriptors
+    /// or the request buffer, and then the descriptors are returned to the request queue.
+    /// Finally, the device is reinitialized such that later requests can be submitted.
+    fn abandon(&mut self, head: u16) {
+        self.transport.reset();
+        self.request_queue.free_chain(head);
+        self.needs_reinit = true;
+        self.consecutive_resets += 1;
+        if self.consecutive_resets >= MAX_CONSECUTIVE_RESETS {
+            error!("VirtioBlkDrive: giving up on the device after {} consecutive resets", self.consecutive_resets);
+            return;
+        }
+        if let Err(e) = self.reinitialize() {
+            error!("VirtioBlkDrive::abandon(): failed to reinitialize the device: {}", e);
+        }
+    }
+
+    /// Reinitializes the device after it was reset, replacing its request queue.
+    fn reinitialize(&mut self) -> Result<(), &'static str> {
+        let (_features, request_queue) = init_device(&self.transport)?;
+        self.request_queue = request_queue;
+        self.needs_reinit = false;
+        Ok(())
+    }
+}
+
+/// Resets the device, negotiates its features, and sets up its request queue.
+///
+/// Returns the accepted features and the request queue.
+fn init_device(transport: &LegacyTransport) -> Result<(u32, Virtqueue), &'static str> {
+    let features = transport.begin_init(|_offered| VIRTIO_BLK_F_RO | VIRTIO_BLK_F_FLUSH);
+    let mut request_queue = Virtqueue::new(transport, REQUEST_QUEUE_INDEX).map_err(|e| {
+        transport.fail();
+        e
+    })?;
+    // Completion is determined by polling, so interrupts are unnecessary.
+    request_queue.set_interrupts_enabled(false);
+    transport.driver_ok();
+    Ok((features, request_queue))
+}
+
+impl StorageDevice for VirtioBlkDrive {
+    fn size_in_blocks(&self) -> usize {
+        self.capacity
+    }
+}
+impl BlockIo for VirtioBlkDrive {
+    fn block_size(&self) -> usize { SECTOR_SIZE_IN_BYTES }
+}
+impl KnownLength for VirtioBlkDrive {
+    fn len(&self) -> usize { self.block_size() * self.size_in_blocks() }
+}
+impl BlockReader for VirtioBlkDrive {
+    fn read_blocks(&mut self, buffer: &mut [u8], block_offset: usize) -> Result<usize, IoError> {
+        self.read_sectors(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
+    }
+}
+impl BlockWriter for VirtioBlkDrive {
+    fn write_blocks(&mut self, buffer: &[u8], block_offset: usize) -> Result<usize, IoError> {
+        self.write_sectors(buffer, block_offset).map_err(|_e| IoError::InvalidInput)
+    }
+
+    fn flush(&mut self) -> Result<(), IoError> {
+        self.flush_cache().map_err(|_e| IoError::Other("virtio_blk: failed to flush the drive's cache"))
+    }
+}
+
+pub type VirtioBlkDriveRef = Arc<Mutex<VirtioBlkDrive>>;
+
+
+/// The storage controller for a single virtio block PCI device, which owns that device's only drive.
+#[derive(Debug)]
+pub struct VirtioBlkController {
+    drive: VirtioBlkDriveRef,
+}
+
+impl VirtioBlkController {
+    /// Creates a new instance of a virtio block controller based on the given PCI device.
+    pub fn new(pci_device: &PciDevice) -> Result<VirtioBlkController, &'static str> {
+        let drive = VirtioBlkDrive::new(pci_device)?;
+        info!("virtio block device at {}: {} sectors{}",
+            pci_device.location,
+            drive.capacity,
+            if drive.read_only { ", read-only" } else { "" },
+        );
+        Ok(VirtioBlkController { drive: Arc::new(Mutex::new(drive)) })
+    }
+
+    /// Returns the drive attached to this controller.
+    pub fn drive(&self) -> &VirtioBlkDriveRef {
+        &self.drive
+    }
+}
+
+impl StorageController for VirtioBlkController {
+    fn devices<'c>(&'c self) -> Box<(dyn Iterator<Item = StorageDeviceRef> + 'c)> {
+        Box::new(core::iter::once(Arc::clone(&self.drive) as StorageDeviceRef))
+    }
+}
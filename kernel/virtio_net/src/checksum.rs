@@ -0,0 +1,86 @@
+//! Preparing outgoing frames for checksum offload.
+//!
+//! When the device completes a TCP or UDP checksum, it sums the bytes from the
+//! start of the transport header to the end of the packet and adds that to the
+//! value already in the checksum field. Thus, the driver must first store the
+//! checksum of the IP pseudo-header in that field.
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const IPV6_HEADER_LEN: usize = 40;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+/// The offset of the checksum field within a TCP header.
+const TCP_CHECKSUM_OFFSET: usize = 16;
+/// The offset of the checksum field within a UDP header.
+const UDP_CHECKSUM_OFFSET: usize = 6;
+
+/// Stores the pseudo-header checksum of the TCP or UDP packet in the given Ethernet `frame`,
+/// such that the device can complete its checksum.
+///
+/// Returns the offsets of the transport header and of the checksum field within it,
+/// or `None` if the frame doesn't hold a TCP or UDP packet whose checksum can be offloaded,
+/// in which case the frame is left unmodified.
+pub(crate) fn prepare_offload(frame: &mut [u8]) -> Option<(u16, u16)> {
+    let ethertype = read_u16(frame, 12)?;
+    let ip = frame.get(ETHERNET_HEADER_LEN..)?;
+    let (protocol, transport_start, pseudo_sum) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let header_len = usize::from(ip.first()? & 0xF) * 4;
+            let total_len = usize::from(read_u16(ip, 2)?);
+            let fragment = read_u16(ip, 6)?;
+            // Fragments other than the first one don't have a transport header,
+            // and the checksum of a fragmented packet covers all of its fragments.
+            if fragment & 0x3FFF != 0 || header_len < 20 || total_len < header_len || total_len > ip.len() {
+                return None;
+            }
+            let protocol = ip[9];
+            let transport_len = (total_len - header_len) as u32;
+            let sum = sum_words(ip.get(12..20)?) + u32::from(protocol) + transport_len;
+            (protocol, ETHERNET_HEADER_LEN + header_len, sum)
+        }
+        ETHERTYPE_IPV6 => {
+            // Packets with extension headers aren't offloaded.
+            let protocol = *ip.get(6)?;
+            let payload_len = usize::from(read_u16(ip, 4)?);
+            if IPV6_HEADER_LEN + payload_len > ip.len() {
+                return None;
+            }
+            let sum = sum_words(ip.get(8..40)?) + u32::from(protocol) + payload_len as u32;
+            (protocol, ETHERNET_HEADER_LEN + IPV6_HEADER_LEN, sum)
+        }
+        _ => return None,
+    };
+    let checksum_offset = match protocol {
+        PROTOCOL_TCP => TCP_CHECKSUM_OFFSET,
+        PROTOCOL_UDP => UDP_CHECKSUM_OFFSET,
+        _ => return None,
+    };
+    let checksum_field = frame.get_mut(transport_start + checksum_offset..transport_start + checksum_offset + 2)?;
+    checksum_field.copy_from_slice(&fold(pseudo_sum).to_be_bytes());
+    Some((transport_start as u16, checksum_offset as u16))
+}
+
+/// Returns the one's complement sum of the big-endian 16-bit words in `bytes`, without folding it.
+fn sum_words(bytes: &[u8]) -> u32 {
+    bytes
+        .chunks(2)
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .sum()
+}
+
+/// Folds a 32-bit sum into a 16-bit one's complement sum.
+fn fold(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum as u16
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes.get(offset..offset + 2).map(|word| u16::from_be_bytes([word[0], word[1]]))
+}
+
+#[cfg(test)]
+mod test;
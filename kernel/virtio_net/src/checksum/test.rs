@@ -0,0 +1,201 @@
+//! Unit tests for preparing frames for checksum offload.
+
+extern crate std;
+use super::*;
+use alloc::{vec, vec::Vec};
+
+const SRC_V4: [u8; 4] = [10, 0, 2, 15];
+const DST_V4: [u8; 4] = [10, 0, 2, 2];
+const SRC_V6: [u8; 16] = [0xFE, 0x80, 0, 0, 0, 0, 0, 0, 0x12, 0x34, 0x56, 0xFF, 0xFE, 0x78, 0x9A, 0xBC];
+const DST_V6: [u8; 16] = [0xFE, 0x80, 0, 0, 0, 0, 0, 0, 0xAB, 0xCD, 0xEF, 0xFF, 0xFE, 0x01, 0x23, 0x45];
+
+/// Returns a transport header of `len` bytes for `protocol`, whose checksum field holds garbage,
+/// followed by `payload`.
+fn transport(protocol: u8, len: usize, payload: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0x5A; len];
+    segment[0..4].copy_from_slice(&[0x30, 0x39, 0x00, 0x50]);
+    if protocol == PROTOCOL_UDP {
+        segment[4..6].copy_from_slice(&((len + payload.len()) as u16).to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+    segment
+}
+
+fn ethernet(ethertype: u16, ip_packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xFF; 12];
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(ip_packet);
+    frame
+}
+
+/// Returns an Ethernet frame holding an IPv4 packet with `options_len` bytes of options.
+fn ipv4_frame(protocol: u8, options_len: usize, fragment: u16, segment: &[u8]) -> Vec<u8> {
+    let header_len = 20 + options_len;
+    let mut ip = vec![0; header_len];
+    ip[0] = 0x40 | (header_len / 4) as u8;
+    ip[2..4].copy_from_slice(&((header_len + segment.len()) as u16).to_be_bytes());
+    ip[6..8].copy_from_slice(&fragment.to_be_bytes());
+    ip[8] = 64;
+    ip[9] = protocol;
+    ip[12..16].copy_from_slice(&SRC_V4);
+    ip[16..20].copy_from_slice(&DST_V4);
+    ip.extend_from_slice(segment);
+    ethernet(ETHERTYPE_IPV4, &ip)
+}
+
+/// Returns an Ethernet frame holding an IPv6 packet whose first next header is `next_header`.
+fn ipv6_frame(next_header: u8, segment: &[u8]) -> Vec<u8> {
+    let mut ip = vec![0; IPV6_HEADER_LEN];
+    ip[0] = 0x60;
+    ip[4..6].copy_from_slice(&(segment.len() as u16).to_be_bytes());
+    ip[6] = next_header;
+    ip[7] = 64;
+    ip[8..24].copy_from_slice(&SRC_V6);
+    ip[24..40].copy_from_slice(&DST_V6);
+    ip.extend_from_slice(segment);
+    ethernet(ETHERTYPE_IPV6, &ip)
+}
+
+/// Completes the checksum as the device would, summing from `start` to `end`,
+/// and checks it against the full checksum computed from the given pseudo-header fields.
+fn assert_device_checksum_valid(frame: &mut [u8], start: usize, offset: usize, end: usize, pseudo: &[u8], protocol: u8) {
+    let checksum = !fold(sum_words(&frame[start..end]));
+    frame[start + offset..start + offset + 2].copy_from_slice(&checksum.to_be_bytes());
+    let pseudo_sum = sum_words(pseudo) + u32::from(protocol) + (end - start) as u32;
+    assert_eq!(fold(pseudo_sum + sum_words(&frame[start..end])), 0xFFFF);
+}
+
+fn assert_not_offloaded(mut frame: Vec<u8>) {
+    let original = frame.clone();
+    assert_eq!(prepare_offload(&mut frame), None);
+    assert_eq!(frame, original);
+}
+
+#[test]
+fn ipv4_tcp() {
+    let mut frame = ipv4_frame(PROTOCOL_TCP, 0, 0, &transport(PROTOCOL_TCP, 20, b"hello, world!"));
+    assert_eq!(prepare_offload(&mut frame), Some((34, 16)));
+    let end = frame.len();
+    assert_device_checksum_valid(&mut frame, 34, 16, end, &[SRC_V4, DST_V4].concat(), PROTOCOL_TCP);
+}
+
+#[test]
+fn ipv4_udp_with_odd_length() {
+    let mut frame = ipv4_frame(PROTOCOL_UDP, 0, 0, &transport(PROTOCOL_UDP, 8, b"odd"));
+    assert_eq!(prepare_offload(&mut frame), Some((34, 6)));
+    let end = frame.len();
+    assert_device_checksum_valid(&mut frame, 34, 6, end, &[SRC_V4, DST_V4].concat(), PROTOCOL_UDP);
+}
+
+#[test]
+fn ipv4_with_options() {
+    let mut frame = ipv4_frame(PROTOCOL_TCP, 8, 0, &transport(PROTOCOL_TCP, 20, b"data"));
+    assert_eq!(prepare_offload(&mut frame), Some((42, 16)));
+    let end = frame.len();
+    assert_device_checksum_valid(&mut frame, 42, 16, end, &[SRC_V4, DST_V4].concat(), PROTOCOL_TCP);
+}
+
+#[test]
+fn ipv4_ignores_ethernet_padding() {
+    // A short packet is padded out to the minimum Ethernet frame length,
+    // which must not be counted in the pseudo-header's length.
+    let mut frame = ipv4_frame(PROTOCOL_UDP, 0, 0, &transport(PROTOCOL_UDP, 8, b"hi"));
+    let end = frame.len();
+    frame.resize(60, 0);
+    assert_eq!(prepare_offload(&mut frame), Some((34, 6)));
+    assert_device_checksum_valid(&mut frame, 34, 6, end, &[SRC_V4, DST_V4].concat(), PROTOCOL_UDP);
+}
+
+#[test]
+fn ipv4_dont_fragment_is_offloaded() {
+    let mut frame = ipv4_frame(PROTOCOL_TCP, 0, 0x4000, &transport(PROTOCOL_TCP, 20, b""));
+    assert_eq!(prepare_offload(&mut frame), Some((34, 16)));
+}
+
+#[test]
+fn ipv4_fragments_are_not_offloaded() {
+    let segment = transport(PROTOCOL_UDP, 8, b"fragmented");
+    // The first fragment, which has more fragments after it.
+    assert_not_offloaded(ipv4_frame(PROTOCOL_UDP, 0, 0x2000, &segment));
+    // A later fragment, at an offset of 8 bytes.
+    assert_not_offloaded(ipv4_frame(PROTOCOL_UDP, 0, 0x0001, &segment));
+    // The last fragment.
+    assert_not_offloaded(ipv4_frame(PROTOCOL_UDP, 0, 0x0100, &segment));
+}
+
+#[test]
+fn ipv4_invalid_lengths_are_not_offloaded() {
+    let segment = transport(PROTOCOL_TCP, 20, b"data");
+
+    let mut short_header = ipv4_frame(PROTOCOL_TCP, 0, 0, &segment);
+    short_header[ETHERNET_HEADER_LEN] = 0x44;
+    assert_not_offloaded(short_header);
+
+    let mut total_len_too_long = ipv4_frame(PROTOCOL_TCP, 0, 0, &segment);
+    let len = total_len_too_long.len() - ETHERNET_HEADER_LEN + 1;
+    total_len_too_long[ETHERNET_HEADER_LEN + 2..ETHERNET_HEADER_LEN + 4].copy_from_slice(&(len as u16).to_be_bytes());
+    assert_not_offloaded(total_len_too_long);
+
+    let mut total_len_too_short = ipv4_frame(PROTOCOL_TCP, 8, 0, &segment);
+    total_len_too_short[ETHERNET_HEADER_LEN + 2..ETHERNET_HEADER_LEN + 4].copy_from_slice(&20u16.to_be_bytes());
+    assert_not_offloaded(total_len_too_short);
+
+    // The checksum field would lie beyond the end of the frame.
+    let mut truncated = ipv4_frame(PROTOCOL_TCP, 0, 0, &transport(PROTOCOL_TCP, 20, b""));
+    truncated.truncate(ETHERNET_HEADER_LEN + 20 + TCP_CHECKSUM_OFFSET + 1);
+    let len = truncated.len() - ETHERNET_HEADER_LEN;
+    truncated[ETHERNET_HEADER_LEN + 2..ETHERNET_HEADER_LEN + 4].copy_from_slice(&(len as u16).to_be_bytes());
+    assert_not_offloaded(truncated);
+}
+
+#[test]
+fn ipv6_tcp() {
+    let mut frame = ipv6_frame(PROTOCOL_TCP, &transport(PROTOCOL_TCP, 20, b"hello, world!"));
+    assert_eq!(prepare_offload(&mut frame), Some((54, 16)));
+    let end = frame.len();
+    assert_device_checksum_valid(&mut frame, 54, 16, end, &[SRC_V6, DST_V6].concat(), PROTOCOL_TCP);
+}
+
+#[test]
+fn ipv6_udp() {
+    let mut frame = ipv6_frame(PROTOCOL_UDP, &transport(PROTOCOL_UDP, 8, b"datagram"));
+    assert_eq!(prepare_offload(&mut frame), Some((54, 6)));
+    let end = frame.len();
+    assert_device_checksum_valid(&mut frame, 54, 6, end, &[SRC_V6, DST_V6].concat(), PROTOCOL_UDP);
+}
+
+#[test]
+fn ipv6_ignores_ethernet_padding() {
+    let mut frame = ipv6_frame(PROTOCOL_UDP, &transport(PROTOCOL_UDP, 8, b""));
+    let end = frame.len();
+    frame.resize(80, 0);
+    assert_eq!(prepare_offload(&mut frame), Some((54, 6)));
+    assert_device_checksum_valid(&mut frame, 54, 6, end, &[SRC_V6, DST_V6].concat(), PROTOCOL_UDP);
+}
+
+#[test]
+fn ipv6_extension_headers_are_not_offloaded() {
+    // Hop-by-hop options and fragment headers.
+    assert_not_offloaded(ipv6_frame(0, &transport(PROTOCOL_TCP, 20, b"data")));
+    assert_not_offloaded(ipv6_frame(44, &transport(PROTOCOL_UDP, 8, b"data")));
+}
+
+#[test]
+fn ipv6_payload_len_too_long_is_not_offloaded() {
+    let mut frame = ipv6_frame(PROTOCOL_TCP, &transport(PROTOCOL_TCP, 20, b"data"));
+    let len = frame.len() - ETHERNET_HEADER_LEN - IPV6_HEADER_LEN + 1;
+    frame[ETHERNET_HEADER_LEN + 4..ETHERNET_HEADER_LEN + 6].copy_from_slice(&(len as u16).to_be_bytes());
+    assert_not_offloaded(frame);
+}
+
+#[test]
+fn other_packets_are_not_offloaded() {
+    // ICMP over IPv4.
+    assert_not_offloaded(ipv4_frame(1, 0, 0, &[8, 0, 0, 0, 0, 1, 0, 1]));
+    // ARP.
+    assert_not_offloaded(ethernet(0x0806, &[0; 28]));
+    // Frames too short to hold an Ethernet or IP header.
+    assert_not_offloaded(vec![0; 13]);
+    assert_not_offloaded(ethernet(ETHERTYPE_IPV4, &[0x45, 0]));
+    assert_not_offloaded(ethernet(ETHERTYPE_IPV6, &[0x60; 6]));
+}
@@ -0,0 +1,402 @@
+//! NIC driver for virtio network devices, e.g., QEMU's `-device virtio-net-pci`.
+//!
+//! The primary struct of interest is [`VirtioNetNic`], which is a [`net::NetworkDevice`].
+//!
+//! Packets are exchanged through pairs of receive and transmit virtqueues.
+//! If the device supports multiple queue pairs, one pair is used per CPU (up to [`MAX_QUEUE_PAIRS`]):
+//! each CPU transmits on its own queue, and the device steers each flow's received packets
+//! to the queue on which that flow was last transmitted.
+//! All receive queues are drained into a single list of received frames.
+//!
+//! If the device can complete the checksums of outgoing TCP and UDP packets,
+//! it does so, and the interface's [`DeviceCapabilities`] tell `smoltcp` not to compute them.
+//! Incoming packets are always checked by `smoltcp`, as legacy devices
+//! don't guarantee that they have validated every packet they mark as such.
+//!
+//! # Limitations
+//! * Only the legacy PCI transport is supported; see the [`virtio`] crate.
+//! * Received packets must fit into a single [`RX_BUFFER_SIZE`]-byte buffer,
+//!   so segmentation offloads and large MTUs are not supported.
+//! * The device's interrupt line can only be shared with other virtio network devices.
+//!   If it is in use by another driver, the device is polled instead.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+mod checksum;
+mod queue;
+
+use alloc::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+use core::task::Waker;
+use interrupts::{eoi, InterruptNumber, IRQ_BASE_OFFSET};
+use log::{debug, error, info, warn};
+use net::{phy::Checksum, DeviceCapabilities};
+use nic_buffers::{ReceiveBuffer, ReceivedFrame, TransmitBuffer};
+use nic_initialization::init_rx_buf_pool;
+use pci::PciDevice;
+use spin::Once;
+use sync_irq::IrqSafeMutex;
+use virtio::{LegacyTransport, VIRTIO_PCI_DEVICE_ID_NET, VIRTIO_PCI_VENDOR_ID};
+use x86_64::structures::idt::InterruptStackFrame;
+
+use queue::{ControlQueue, NetHeader, PacketQueue, NET_HDR_F_NEEDS_CSUM, NET_HDR_SIZE};
+
+/// The maximum number of receive/transmit queue pairs used by a single device.
+pub const MAX_QUEUE_PAIRS: u16 = 8;
+
+/// The size of each receive buffer, which holds a full Ethernet frame without its FCS.
+pub const RX_BUFFER_SIZE: u16 = 2048;
+
+/// The maximum transmission unit of a virtio network device.
+const VIRTIO_NET_MTU: usize = 1500;
+
+/// The maximum number of buffers posted to each receive queue.
+const RX_BUFFERS_PER_QUEUE: usize = 64;
+/// How many receive buffers are preallocated for all virtio network devices to share.
+const RX_BUFFER_POOL_SIZE: usize = 256;
+
+/// Feature bit: the device can complete the checksums of packets sent by the driver.
+const VIRTIO_NET_F_CSUM:    u32 = 1 << 0;
+/// Feature bit: the device has a MAC address in its configuration.
+const VIRTIO_NET_F_MAC:     u32 = 1 << 5;
+/// Feature bit: the device reports its link status in its configuration.
+const VIRTIO_NET_F_STATUS:  u32 = 1 << 16;
+/// Feature bit: the device has a control queue.
+const VIRTIO_NET_F_CTRL_VQ: u32 = 1 << 17;
+/// Feature bit: the device supports multiple receive/transmit queue pairs.
+const VIRTIO_NET_F_MQ:      u32 = 1 << 22;
+
+// Offsets of fields in the device-specific configuration.
+const CONFIG_MAC:                 u16 = 0;
+const CONFIG_STATUS:              u16 = 6;
+const CONFIG_MAX_VIRTQUEUE_PAIRS: u16 = 8;
+
+/// Set in the configuration's status field if the link is up.
+const STATUS_LINK_UP: u16 = 1;
+
+/// The control command class for configuring multiple queue pairs.
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+/// The control command that sets the number of queue pairs in use.
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+
+/// Interrupt status bit: a queue has used buffers.
+const ISR_QUEUE: u8 = 0x1;
+/// Interrupt status bit: the device's configuration has changed.
+const ISR_CONFIG: u8 = 0x2;
+
+
+/// All initialized virtio network devices, which share a single interrupt handler.
+static VIRTIO_NET_NICS: IrqSafeMutex<Vec<&'static IrqSafeMutex<VirtioNetNic>>> = IrqSafeMutex::new(Vec::new());
+
+/// The pool of pre-allocated receive buffers shared by all virtio network devices.
+static RX_BUFFER_POOL: Once<mpmc::Queue<ReceiveBuffer>> = Once::new();
+
+
+/// Returns `true` if the given PCI device is a virtio network device supported by this driver.
+pub fn is_virtio_net_device(pci_device: &PciDevice) -> bool {
+    pci_device.vendor_id == VIRTIO_PCI_VENDOR_ID && pci_device.device_id == VIRTIO_PCI_DEVICE_ID_NET
+}
+
+
+/// A virtio network device.
+pub struct VirtioNetNic {
+    transport: LegacyTransport,
+    mac_address: [u8; 6],
+    rx_queues: Vec<PacketQueue<ReceiveBuffer>>,
+    tx_queues: Vec<PacketQueue<TransmitBuffer>>,
+    /// Frames that have been taken from the receive queues but not yet received by the interface.
+    received_frames: VecDeque<ReceivedFrame>,
+    /// Whether the device completes the checksums of outgoing TCP and UDP packets.
+    tx_checksum_offload: bool,
+    /// The interrupt vector used by this device, if its interrupt handler could be registered.
+    interrupt_num: Option<InterruptNumber>,
+    /// Woken whenever frames are received, set by the network interface using this NIC.
+    receive_waker: Option<Waker>,
+    /// The control queue, if the device has one. It's kept because the device
+    /// may still access it if a command timed out.
+    _control_queue: Option<ControlQueue>,
+}
+
+impl VirtioNetNic {
+    /// Initializes the virtio network device described by the given PCI device
+    /// and registers its interrupt handler.
+    ///
+    /// The returned NIC should then be registered with the `net` subsystem.
+    pub fn init(pci_device: &PciDevice) -> Result<&'static IrqSafeMutex<VirtioNetNic>, &'static str> {
+        let mut first_nic = false;
+        let pool = RX_BUFFER_POOL.call_once(|| {
+            first_nic = true;
+            mpmc::Queue::with_capacity(RX_BUFFER_POOL_SIZE)
+        });
+        if first_nic {
+            init_rx_buf_pool(RX_BUFFER_POOL_SIZE, RX_BUFFER_SIZE, pool)?;
+        }
+
+        let transport = LegacyTransport::new(pci_device)?;
+        let features = transport.begin_init(|offered| {
+            let mut wanted = VIRTIO_NET_F_CSUM | VIRTIO_NET_F_MAC | VIRTIO_NET_F_STATUS | VIRTIO_NET_F_CTRL_VQ;
+            // Multiple queue pairs can only be enabled through the control queue.
+            if offered & VIRTIO_NET_F_CTRL_VQ != 0 {
+                wanted |= VIRTIO_NET_F_MQ;
+            }
+            wanted
+        });
+
+        let max_queue_pairs = if features & VIRTIO_NET_F_MQ != 0 {
+            transport.read_config_u16(CONFIG_MAX_VIRTQUEUE_PAIRS).max(1)
+        } else {
+            1
+        };
+        let num_queue_pairs = max_queue_pairs.min(cpu::cpu_count() as u16).min(MAX_QUEUE_PAIRS).max(1);
+
+        let setup = || -> Result<_, &'static str> {
+            let mut rx_queues = Vec::with_capacity(num_queue_pairs as usize);
+            let mut tx_queues = Vec::with_capacity(num_queue_pairs as usize);
+            for pair in 0..num_queue_pairs {
+                let mut rx_queue = PacketQueue::new(&transport, 2 * pair)?;
+                // Interrupts are enabled once the interrupt handler has been registered.
+                rx_queue.set_interrupts_enabled(false);
+                refill_rx_queue(&mut rx_queue)?;
+                rx_queues.push(rx_queue);
+
+                let mut tx_queue = PacketQueue::new(&transport, 2 * pair + 1)?;
+                // Sent buffers are reclaimed lazily, so interrupts are unnecessary.
+                tx_queue.set_interrupts_enabled(false);
+                tx_queues.push(tx_queue);
+            }
+            // The control queue comes after all of the device's queue pairs.
+            let control_queue = if features & VIRTIO_NET_F_CTRL_VQ != 0 {
+                Some(ControlQueue::new(&transport, 2 * max_queue_pairs)?)
+            } else {
+                None
+            };
+            Ok((rx_queues, tx_queues, control_queue))
+        };
+        let (rx_queues, mut tx_queues, mut control_queue) = setup().map_err(|e| {
+            transport.fail();
+            e
+        })?;
+        transport.driver_ok();
+
+        // The device only uses the first queue pair until told otherwise.
+        if num_queue_pairs > 1 {
+            let result = control_queue.as_mut().expect("BUG: virtio_net: MQ was negotiated without a control queue").send(
+                &transport,
+                VIRTIO_NET_CTRL_MQ,
+                VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET,
+                &num_queue_pairs.to_le_bytes(),
+            );
+            if let Err(e) = result {
+                warn!("virtio_net: failed to enable {} queue pairs, using only one: {}", num_queue_pairs, e);
+                tx_queues.truncate(1);
+            }
+        }
+        for rx_queue in rx_queues.iter() {
+            transport.notify(rx_queue.index());
+        }
+
+        let mac_address = if features & VIRTIO_NET_F_MAC != 0 {
+            core::array::from_fn(|i| transport.read_config_u8(CONFIG_MAC + i as u16))
+        } else {
+            // Use a random, locally-administered unicast address.
+            let mut mac = [0; 6];
+            mac.copy_from_slice(&random::next_u64().to_le_bytes()[..6]);
+            mac[0] = (mac[0] & !0x01) | 0x02;
+            mac
+        };
+        let link_up = features & VIRTIO_NET_F_STATUS == 0
+            || transport.read_config_u16(CONFIG_STATUS) & STATUS_LINK_UP != 0;
+        info!("virtio network device at {}: MAC {:02X?}, {} queue pair(s), checksum offload: {}, link {}",
+            pci_device.location,
+            mac_address,
+            tx_queues.len(),
+            features & VIRTIO_NET_F_CSUM != 0,
+            if link_up { "up" } else { "down" },
+        );
+
+        let nic = VirtioNetNic {
+            transport,
+            mac_address,
+            rx_queues,
+            tx_queues,
+            received_frames: VecDeque::new(),
+            tx_checksum_offload: features & VIRTIO_NET_F_CSUM != 0,
+            interrupt_num: None,
+            receive_waker: None,
+            _control_queue: control_queue,
+        };
+        let nic_ref: &'static IrqSafeMutex<VirtioNetNic> = Box::leak(Box::new(IrqSafeMutex::new(nic)));
+        VIRTIO_NET_NICS.lock().push(nic_ref);
+
+        match register_interrupt(pci_device) {
+            Ok(interrupt_num) => {
+                let mut nic = nic_ref.lock();
+                nic.interrupt_num = Some(interrupt_num);
+                for rx_queue in nic.rx_queues.iter_mut() {
+                    rx_queue.set_interrupts_enabled(true);
+                }
+            }
+            Err(e) => warn!("virtio_net: {}, so the device will be polled", e),
+        }
+        Ok(nic_ref)
+    }
+
+    /// Moves all packets that the device has received into the list of received frames,
+    /// and gives the device new buffers to receive into.
+    fn poll_receive_queues(&mut self) {
+        for rx_queue in self.rx_queues.iter_mut() {
+            let mut received_any = false;
+            while let Some((_header, mut buffer, length)) = rx_queue.pop_used() {
+                received_any = true;
+                let frame_length = (length as usize).saturating_sub(NET_HDR_SIZE);
+                if frame_length == 0 || buffer.set_length(frame_length as u16).is_err() {
+                    warn!("virtio_net: dropping received packet with invalid length {}", length);
+                    continue;
+                }
+                self.received_frames.push_back(ReceivedFrame(vec![buffer]));
+            }
+            if received_any {
+                if let Err(e) = refill_rx_queue(rx_queue) {
+                    error!("virtio_net: failed to refill receive queue {}: {}", rx_queue.index(), e);
+                }
+                self.transport.notify(rx_queue.index());
+            }
+        }
+    }
+
+    /// The main interrupt handling routine for a virtio network device.
+    ///
+    /// Returns `true` if this device raised the interrupt.
+    fn handle_interrupt(&mut self) -> bool {
+        let status = self.transport.read_isr_status();
+        if status & ISR_CONFIG != 0 {
+            let link_status = self.transport.read_config_u16(CONFIG_STATUS);
+            debug!("virtio_net: configuration changed, link {}",
+                if link_status & STATUS_LINK_UP != 0 { "up" } else { "down" }
+            );
+        }
+        if status & ISR_QUEUE != 0 {
+            self.poll_receive_queues();
+            if !self.received_frames.is_empty() {
+                if let Some(ref waker) = self.receive_waker {
+                    waker.wake_by_ref();
+                }
+            }
+        }
+        status != 0
+    }
+}
+
+impl net::NetworkDevice for VirtioNetNic {
+    fn send(&mut self, mut buf: TransmitBuffer) {
+        let mut header = NetHeader::default();
+        if self.tx_checksum_offload {
+            if let Some((csum_start, csum_offset)) = checksum::prepare_offload(&mut buf) {
+                header.flags = NET_HDR_F_NEEDS_CSUM;
+                header.csum_start = csum_start;
+                header.csum_offset = csum_offset;
+            }
+        }
+
+        let queue_index = cpu::current_cpu().value() as usize % self.tx_queues.len();
+        let tx_queue = &mut self.tx_queues[queue_index];
+        // Free the buffers of previously-sent packets.
+        while tx_queue.pop_used().is_some() {}
+
+        let (phys_addr, length) = (buf.phys_addr(), buf.length() as u32);
+        match tx_queue.add(header, buf, phys_addr, length, false) {
+            Ok(()) => self.transport.notify(tx_queue.index()),
+            Err((_buf, e)) => warn!("virtio_net: dropping packet: {}", e),
+        }
+    }
+
+    fn receive(&mut self) -> Option<ReceivedFrame> {
+        if self.received_frames.is_empty() {
+            self.poll_receive_queues();
+        }
+        self.received_frames.pop_front()
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = VIRTIO_NET_MTU;
+        if self.tx_checksum_offload {
+            // The device completes these checksums, but received packets must still be verified.
+            caps.checksum.tcp = Checksum::Rx;
+            caps.checksum.udp = Checksum::Rx;
+        }
+        caps
+    }
+
+    fn set_receive_waker(&mut self, waker: Waker) -> bool {
+        self.receive_waker = Some(waker);
+        self.interrupt_num.is_some()
+    }
+}
+
+/// Gives the device receive buffers from the pool until the given queue is full
+/// or has [`RX_BUFFERS_PER_QUEUE`] buffers.
+///
+/// The caller must notify the device afterwards.
+fn refill_rx_queue(rx_queue: &mut PacketQueue<ReceiveBuffer>) -> Result<(), &'static str> {
+    let pool = RX_BUFFER_POOL.get().ok_or("virtio_net: receive buffer pool wasn't initialized")?;
+    for _ in 0..RX_BUFFERS_PER_QUEUE {
+        if !rx_queue.has_room() {
+            break;
+        }
+        let mut buffer = match pool.pop() {
+            Some(buffer) => buffer,
+            None => {
+                warn!("virtio_net: receive buffer pool was empty, allocating a new buffer");
+                let (mp, phys_addr) = memory::create_contiguous_mapping(RX_BUFFER_SIZE.into(), memory::DMA_FLAGS)?;
+                ReceiveBuffer::new(mp, phys_addr, RX_BUFFER_SIZE, pool)?
+            }
+        };
+        // Buffers returned to the pool have a length of zero.
+        buffer.set_length(RX_BUFFER_SIZE)?;
+        let phys_addr = buffer.phys_addr();
+        rx_queue
+            .add(NetHeader::default(), buffer, phys_addr, RX_BUFFER_SIZE.into(), true)
+            .map_err(|(_buffer, e)| e)?;
+    }
+    Ok(())
+}
+
+/// Registers the interrupt handler for the given device's legacy interrupt line.
+fn register_interrupt(pci_device: &PciDevice) -> Result<InterruptNumber, &'static str> {
+    let interrupt_num = match pci_device.pci_get_intx_info() {
+        Ok((Some(irq), _pin)) => (irq + IRQ_BASE_OFFSET) as InterruptNumber,
+        _ => return Err("PCI device had no interrupt number (IRQ vector)"),
+    };
+    match interrupts::register_interrupt(interrupt_num, virtio_net_handler) {
+        Ok(()) => Ok(interrupt_num),
+        // Another virtio network device uses the same interrupt line.
+        Err(handler) if handler == virtio_net_handler as usize => Ok(interrupt_num),
+        Err(_) => Err("interrupt number was already in use by another driver"),
+    }
+}
+
+extern "x86-interrupt" fn virtio_net_handler(_stack_frame: InterruptStackFrame) {
+    let mut handled = None;
+    for nic_ref in VIRTIO_NET_NICS.lock().iter() {
+        let mut nic = nic_ref.lock();
+        if nic.handle_interrupt() {
+            handled = handled.or(nic.interrupt_num);
+        }
+    }
+    match handled {
+        Some(interrupt_num) => eoi(interrupt_num),
+        None => {
+            warn!("virtio_net_handler(): spurious interrupt");
+            if let Some(interrupt_num) = VIRTIO_NET_NICS.lock().first().and_then(|nic| nic.lock().interrupt_num) {
+                eoi(interrupt_num);
+            }
+        }
+    }
+}
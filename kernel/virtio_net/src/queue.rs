@@ -0,0 +1,220 @@
+//! Receive and transmit queues, in which every packet is preceded by a virtio-net header.
+
+use alloc::vec::Vec;
+use core::{mem::size_of, time::Duration};
+use memory::{create_contiguous_mapping, MappedPages, PhysicalAddress, DMA_FLAGS};
+use virtio::{LegacyTransport, Virtqueue, VirtqueueBuffer};
+use zerocopy::FromBytes;
+
+/// The header that precedes every packet exchanged with the device,
+/// as laid out when the mergeable receive buffers feature is not negotiated.
+///
+/// The legacy transport uses the guest's native endianness for all fields.
+#[derive(Clone, Copy, Debug, Default, FromBytes)]
+#[repr(C)]
+pub(crate) struct NetHeader {
+    pub flags: u8,
+    pub gso_type: u8,
+    pub hdr_len: u16,
+    pub gso_size: u16,
+    /// The offset from which the device computes the checksum, if `NEEDS_CSUM` is set.
+    pub csum_start: u16,
+    /// The offset after `csum_start` at which the device stores the checksum.
+    pub csum_offset: u16,
+}
+
+/// Header flag: the device must complete the packet's checksum, see [`NetHeader::csum_start`].
+pub(crate) const NET_HDR_F_NEEDS_CSUM: u8 = 1;
+
+/// The size of the [`NetHeader`] that precedes every packet.
+pub(crate) const NET_HDR_SIZE: usize = size_of::<NetHeader>();
+
+/// A virtqueue in which each buffer chain is a [`NetHeader`] followed by a packet buffer.
+///
+/// Legacy devices require the header to be in its own descriptor,
+/// so the headers of all chains are kept in a separate array of header slots.
+pub(crate) struct PacketQueue<B> {
+    queue: Virtqueue,
+    headers: MappedPages,
+    headers_phys_addr: PhysicalAddress,
+    num_slots: u16,
+    /// The header slots that aren't used by any buffer chain.
+    free_slots: Vec<u16>,
+    /// The header slot and packet buffer of each chain owned by the device,
+    /// indexed by the chain's head descriptor.
+    in_flight: Vec<Option<(u16, B)>>,
+}
+
+impl<B> PacketQueue<B> {
+    /// Allocates the virtqueue at `queue_index` and registers it with the device.
+    pub fn new(transport: &LegacyTransport, queue_index: u16) -> Result<PacketQueue<B>, &'static str> {
+        let queue = Virtqueue::new(transport, queue_index)?;
+        // Each chain uses two descriptors: one for the header and one for the packet.
+        let num_slots = queue.size() / 2;
+        let (headers, headers_phys_addr) = create_contiguous_mapping(num_slots as usize * NET_HDR_SIZE, DMA_FLAGS)?;
+        let mut in_flight = Vec::with_capacity(queue.size() as usize);
+        in_flight.resize_with(queue.size() as usize, || None);
+        Ok(PacketQueue {
+            queue,
+            headers,
+            headers_phys_addr,
+            num_slots,
+            free_slots: (0..num_slots).rev().collect(),
+            in_flight,
+        })
+    }
+
+    /// Returns the index of this queue within its device.
+    pub fn index(&self) -> u16 {
+        self.queue.index()
+    }
+
+    /// Returns `true` if another buffer chain can be added to this queue.
+    pub fn has_room(&self) -> bool {
+        !self.free_slots.is_empty() && self.queue.num_free() >= 2
+    }
+
+    /// Sets whether the device should raise an interrupt when it consumes buffers from this queue.
+    pub fn set_interrupts_enabled(&mut self, enabled: bool) {
+        self.queue.set_interrupts_enabled(enabled);
+    }
+
+    /// Makes the given `header` and packet `buffer` available to the device.
+    ///
+    /// The packet buffer starts at `phys_addr` and is `length` bytes long.
+    /// If `device_writable` is `true`, the device writes a received packet into it,
+    /// along with the header.
+    ///
+    /// On failure, the buffer is returned along with the error.
+    /// The caller must [`notify`](LegacyTransport::notify) the device after adding buffers.
+    pub fn add(
+        &mut self,
+        header: NetHeader,
+        buffer: B,
+        phys_addr: PhysicalAddress,
+        length: u32,
+        device_writable: bool,
+    ) -> Result<(), (B, &'static str)> {
+        if !self.has_room() {
+            return Err((buffer, "virtio_net: queue is full"));
+        }
+        let slot = self.free_slots.pop().expect("BUG: virtio_net: no free header slot");
+        match self.headers.as_slice_mut::<NetHeader>(0, self.num_slots as usize) {
+            Ok(headers) => headers[slot as usize] = header,
+            Err(e) => {
+                self.free_slots.push(slot);
+                return Err((buffer, e));
+            }
+        }
+        let chain = [
+            VirtqueueBuffer {
+                phys_addr: self.headers_phys_addr + slot as usize * NET_HDR_SIZE,
+                length: NET_HDR_SIZE as u32,
+                device_writable,
+            },
+            VirtqueueBuffer { phys_addr, length, device_writable },
+        ];
+        match self.queue.add(&chain) {
+            Ok(head) => {
+                self.in_flight[head as usize] = Some((slot, buffer));
+                Ok(())
+            }
+            Err(e) => {
+                self.free_slots.push(slot);
+                Err((buffer, e))
+            }
+        }
+    }
+
+    /// Takes back the next buffer chain that the device has used.
+    ///
+    /// Returns its header, its packet buffer, and the number of bytes the device wrote into the chain,
+    /// or `None` if the device has not used any more buffer chains.
+    pub fn pop_used(&mut self) -> Option<(NetHeader, B, u32)> {
+        loop {
+            let (head, length) = self.queue.pop_used()?;
+            let Some((slot, buffer)) = self.in_flight.get_mut(head as usize).and_then(Option::take) else {
+                log::error!("virtio_net: device used unknown buffer chain {}", head);
+                continue;
+            };
+            let header = self
+                .headers
+                .as_slice::<NetHeader>(0, self.num_slots as usize)
+                .map(|headers| headers[slot as usize])
+                .unwrap_or_default();
+            self.free_slots.push(slot);
+            return Some((header, buffer, length));
+        }
+    }
+}
+
+/// The device's control queue, through which commands such as setting the number of queue pairs are sent.
+pub(crate) struct ControlQueue {
+    queue: Virtqueue,
+    buffer: MappedPages,
+    buffer_phys_addr: PhysicalAddress,
+}
+
+// The layout of the control buffer: the command's class and code, its data, and the device's ack.
+const CONTROL_HEADER_OFFSET: usize = 0;
+const CONTROL_DATA_OFFSET: usize = 16;
+const CONTROL_ACK_OFFSET: usize = 128;
+const CONTROL_BUFFER_SIZE: usize = 129;
+/// The ack that the device writes back for a successful command.
+const CONTROL_ACK_OK: u8 = 0;
+/// How long the device has to acknowledge a control command.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl ControlQueue {
+    /// Allocates the control virtqueue at `queue_index` and registers it with the device.
+    pub fn new(transport: &LegacyTransport, queue_index: u16) -> Result<ControlQueue, &'static str> {
+        let mut queue = Virtqueue::new(transport, queue_index)?;
+        // Completion is determined by polling, so interrupts are unnecessary.
+        queue.set_interrupts_enabled(false);
+        let (buffer, buffer_phys_addr) = create_contiguous_mapping(CONTROL_BUFFER_SIZE, DMA_FLAGS)?;
+        Ok(ControlQueue { queue, buffer, buffer_phys_addr })
+    }
+
+    /// Sends the command with the given `class`, `command` code, and `data`,
+    /// and waits for the device to acknowledge it.
+    ///
+    /// If the device doesn't acknowledge the command in time, an error is returned,
+    /// but the device may still use this queue's buffer later on, so this queue must be kept alive.
+    pub fn send(
+        &mut self,
+        transport: &LegacyTransport,
+        class: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), &'static str> {
+        if data.len() > CONTROL_ACK_OFFSET - CONTROL_DATA_OFFSET {
+            return Err("virtio_net: control command data is too long");
+        }
+        self.buffer.as_slice_mut::<u8>(CONTROL_HEADER_OFFSET, 2)?.copy_from_slice(&[class, command]);
+        self.buffer.as_slice_mut::<u8>(CONTROL_DATA_OFFSET, data.len())?.copy_from_slice(data);
+        // Set the ack to an invalid value so that we can tell whether the device wrote it.
+        self.buffer.as_slice_mut::<u8>(CONTROL_ACK_OFFSET, 1)?[0] = 0xFF;
+
+        let base = self.buffer_phys_addr;
+        let chain = [
+            VirtqueueBuffer { phys_addr: base + CONTROL_HEADER_OFFSET, length: 2, device_writable: false },
+            VirtqueueBuffer { phys_addr: base + CONTROL_DATA_OFFSET, length: data.len() as u32, device_writable: false },
+            VirtqueueBuffer { phys_addr: base + CONTROL_ACK_OFFSET, length: 1, device_writable: true },
+        ];
+        self.queue.add(&chain)?;
+        transport.notify(self.queue.index());
+
+        let deadline = time::now::<time::Monotonic>() + CONTROL_TIMEOUT;
+        while self.queue.pop_used().is_none() {
+            if time::now::<time::Monotonic>() >= deadline {
+                log::error!("virtio_net: timed out waiting for control command {}:{}", class, command);
+                return Err("virtio_net: timed out waiting for the device to acknowledge a control command");
+            }
+            core::hint::spin_loop();
+        }
+        match self.buffer.as_slice::<u8>(CONTROL_ACK_OFFSET, 1)?[0] {
+            CONTROL_ACK_OK => Ok(()),
+            _ => Err("virtio_net: device rejected control command"),
+        }
+    }
+}
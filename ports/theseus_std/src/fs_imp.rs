@@ -18,8 +18,8 @@ use core2::io::{self, /*IoSlice, IoSliceMut, ReadBuf,*/ SeekFrom, Read, Write, S
 use crate::path::{Path, PathBuf};
 #[cfg(feature = "time")]
 use crate::sys::time::SystemTime;
-use theseus_fs_node::{File as FileTrait, FileRef};
-use theseus_io::{ReaderWriter, LockableIo, KnownLength};
+use theseus_fs_node::{File as FileTrait, FileRef, FsNode, Metadata};
+use theseus_io::{ReaderWriter, LockableIo};
 use spin::Mutex;
 
 /// This is a typedef for a Theseus-native `FileRef` (`Arc<Mutex<dyn File>>`)
@@ -105,9 +105,7 @@ impl fmt::Debug for FileOrDirectory {
 
 #[derive(Clone, Copy, Debug)]
 pub struct FileAttr {
-    size: u64,
-    // `true` if file, `false` if directory
-    is_file: bool,
+    metadata: Metadata,
     symlink: bool,
 }
 
@@ -126,9 +124,11 @@ pub struct OpenOptions {
     create_new: bool,
 }
 
-/// Theseus doesn't yet have any notion of file permissions,
-/// so all permissions are always granted for every file.
-pub struct FilePermissions;
+/// Theseus only distinguishes between read-only and writable files;
+/// there is no notion of users or groups.
+pub struct FilePermissions {
+    readonly: bool,
+}
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct FileType {
@@ -145,17 +145,25 @@ enum FileTypeInner {
 pub struct DirBuilder {}
 
 impl FileAttr {
+    fn from_metadata(metadata: Metadata) -> FileAttr {
+        FileAttr {
+            metadata,
+            // Theseus doesn't support symlinks yet
+            symlink: false,
+        }
+    }
+
     pub fn size(&self) -> u64 {
-        self.size
+        self.metadata.size as u64
     }
 
     pub fn perm(&self) -> FilePermissions {
-        FilePermissions { }
+        FilePermissions { readonly: self.metadata.read_only }
     }
 
     pub fn file_type(&self) -> FileType {
         FileType {
-            typ: match self.is_file {
+            typ: match self.metadata.is_file() {
                 true  => FileTypeInner::File,
                 false => FileTypeInner::Dir,
             },
@@ -165,40 +173,50 @@ impl FileAttr {
 
     #[cfg(feature = "time")]
     pub fn modified(&self) -> io::Result<SystemTime> {
-        self.0
+        to_system_time(self.metadata.modified)
     }
 
     #[cfg(feature = "time")]
     pub fn accessed(&self) -> io::Result<SystemTime> {
-        self.0
+        // Theseus filesystems don't track access times.
+        Err(io::Error::from(io::ErrorKind::Unsupported))
     }
 
     #[cfg(feature = "time")]
     pub fn created(&self) -> io::Result<SystemTime> {
-        self.0
+        to_system_time(self.metadata.created)
     }
 }
 
+/// Converts a Theseus timestamp, given as the time since the Unix epoch, into a `SystemTime`.
+#[cfg(feature = "time")]
+fn to_system_time(since_epoch: Option<core::time::Duration>) -> io::Result<SystemTime> {
+    since_epoch
+        .and_then(|d| SystemTime::UNIX_EPOCH.checked_add_duration(&d))
+        .ok_or_else(|| io::Error::from(io::ErrorKind::Unsupported))
+}
+
 impl FilePermissions {
     pub fn readonly(&self) -> bool {
-        // Theseus gives all files all permissions at the moment
-        false
+        self.readonly
     }
 
-    pub fn set_readonly(&mut self, _readonly: bool) {
-        // do nothing, Theseus doesn't have file permissions yet
+    pub fn set_readonly(&mut self, readonly: bool) {
+        // Theseus filesystems don't yet support changing permissions,
+        // so this only affects this `FilePermissions` object.
+        self.readonly = readonly;
     }
 }
 
 impl Clone for FilePermissions {
     fn clone(&self) -> FilePermissions {
-        FilePermissions { }
+        FilePermissions { readonly: self.readonly }
     }
 }
 
 impl PartialEq for FilePermissions {
-    fn eq(&self, _other: &FilePermissions) -> bool {
-        true
+    fn eq(&self, other: &FilePermissions) -> bool {
+        self.readonly == other.readonly
     }
 }
 
@@ -206,7 +224,7 @@ impl Eq for FilePermissions {}
 
 impl fmt::Debug for FilePermissions {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("FilePermissions {{ }}")
+        f.debug_struct("FilePermissions").field("readonly", &self.readonly).finish()
     }
 }
 
@@ -332,10 +350,14 @@ impl File {
                 .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
 
             let file_name = path.file_name().ok_or(io::Error::from(io::ErrorKind::NotFound))?;
-            let new_file = theseus_memfs::MemFile::create(
-                file_name.to_string_lossy().into(), 
-                &containing_dir,
-            ).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let file_name: alloc::string::String = file_name.to_string_lossy().into();
+            // Directories on a persistent filesystem can only contain their own kind of file,
+            // so they must create it themselves.
+            let native_file = containing_dir.lock().create_file(&file_name);
+            let new_file = match native_file {
+                Some(result) => result,
+                None => theseus_memfs::MemFile::create(file_name, &containing_dir),
+            }.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
             Ok(theseus_file_ref_to_file(new_file, opts.clone()))
         }
@@ -367,16 +389,11 @@ impl File {
     }
 
     pub fn file_attr(&self) -> io::Result<FileAttr> {
-        let (size, is_file) = match &self.0 {
-            FileOrDirectory::OpenFile { file, ..} => (file.lock().len(), true),
-            FileOrDirectory::Directory(_) => (0, false),
+        let metadata = match &self.0 {
+            FileOrDirectory::OpenFile { file, ..} => file.lock().lock().metadata(),
+            FileOrDirectory::Directory(dir) => dir.lock().metadata(),
         };
-        Ok(FileAttr {
-            size: size as u64,
-            is_file,
-             // Theseus doesn't support symlinks yet
-            symlink: false,
-        })
+        Ok(FileAttr::from_metadata(metadata))
     }
 
     pub fn fsync(&self) -> io::Result<()> {
@@ -498,8 +515,15 @@ pub fn unlink(_p: &Path) -> io::Result<()> {
     unimplemented!()
 }
 
-pub fn rename(_old: &Path, _new: &Path) -> io::Result<()> {
-    unimplemented!()
+pub fn rename(old: &Path, new: &Path) -> io::Result<()> {
+    let working_dir = crate::env::current_dir()?;
+    let old = old.to_string_lossy();
+    let old = theseus_path::Path::new(old.as_ref());
+    if old.get(&working_dir).is_none() {
+        return Err(io::ErrorKind::NotFound.into());
+    }
+    theseus_path::rename(old, theseus_path::Path::new(new.to_string_lossy().as_ref()), &working_dir)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
 }
 
 pub fn set_perm(_p: &Path, _perm: FilePermissions) -> io::Result<()> {
@@ -530,12 +554,16 @@ pub fn link(_src: &Path, _dst: &Path) -> io::Result<()> {
     unimplemented!()
 }
 
-pub fn stat(_p: &Path) -> io::Result<FileAttr> {
-    unimplemented!()
+pub fn stat(p: &Path) -> io::Result<FileAttr> {
+    let working_dir = crate::env::current_dir()?;
+    theseus_path::Path::new(p.to_string_lossy().as_ref()).get(&working_dir)
+        .ok_or(io::ErrorKind::NotFound.into())
+        .map(|theseus_file_or_dir| FileAttr::from_metadata(theseus_file_or_dir.metadata()))
 }
 
-pub fn lstat(_p: &Path) -> io::Result<FileAttr> {
-    unimplemented!()
+pub fn lstat(p: &Path) -> io::Result<FileAttr> {
+    // Theseus doesn't support symlinks yet, so this is the same as `stat`.
+    stat(p)
 }
 
 pub fn canonicalize(_p: &Path) -> io::Result<PathBuf> {
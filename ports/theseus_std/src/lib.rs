@@ -2,6 +2,7 @@
 //! 
 //! Current ported modules include:
 //! * `fs`: basic filesystem access.
+//! * `net`: TCP and UDP sockets on the default network interface, plus IP and socket addresses.
 //! * `os_str`: platform-native string types.
 //!    * In Theseus, `OsString` = `String`, and `OsStr` = `str`.
 //! * `path`: basic path representations: `PathBuf` and `Path`.
@@ -9,6 +10,7 @@
 
 #![no_std]
 #![feature(extend_one)]
+#![feature(ip_in_core)]
 #![feature(trait_alias)]
 
 extern crate alloc;
@@ -16,6 +18,8 @@ extern crate alloc;
 mod env;
 pub mod fs;
 mod fs_imp;
+pub mod net;
+mod net_imp;
 pub mod os_str;
 mod os_str_imp;
 pub mod path;
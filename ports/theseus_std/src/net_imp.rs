@@ -0,0 +1,550 @@
+//! This module is equivalent to the Rust standard library's
+//! platform-specific "inner" net implementation.
+//!
+//! For example, for Unix-like systems, this module is implemented
+//! in the file [library/std/src/sys_common/net.rs].
+//!
+//! In Theseus, each socket is a [`theseus_net::Socket`] added to the network interface
+//! that owns its address (e.g., the loopback interface for `127.0.0.1`) or else the default one,
+//! whose network task drives the socket in the background.
+//!
+//! [library/std/src/sys_common/net.rs](https://github.com/rust-lang/rust/blob/master/library/std/src/sys_common/net.rs)
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    convert::TryFrom,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Deref,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
+};
+use core2::io;
+use spin::Mutex;
+use theseus_net::{
+    tcp, udp, AnySocket,
+    wire::{IpAddress, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv6Address},
+    NetworkInterface, Socket,
+};
+
+use crate::net::Shutdown;
+
+/// The size of each TCP socket's receive and transmit buffers.
+const TCP_BUFFER_SIZE: usize = 16 * 1024;
+/// The size of each UDP socket's receive and transmit buffers.
+const UDP_BUFFER_SIZE: usize = 16 * 1024;
+/// The number of datagrams that each UDP socket's receive and transmit buffers can hold.
+const UDP_PACKETS: usize = 16;
+/// The TTL (hop limit) of outgoing packets, unless set otherwise.
+const DEFAULT_TTL: u8 = 64;
+/// How long [`TcpStream::connect`] waits for a connection to be established before giving up.
+///
+/// This is roughly how long other systems retry an unanswered SYN by default.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(75);
+/// The number of sockets that each [`TcpListener`] keeps listening for new connections.
+const LISTEN_BACKLOG: usize = 8;
+
+/// Returns the interface through which packets to or from `address` are sent,
+/// or the default interface if no `address` is given.
+fn interface_for(address: Option<IpAddress>) -> io::Result<Arc<NetworkInterface>> {
+    address
+        .map_or_else(theseus_net::get_default_interface, theseus_net::get_interface_for)
+        .ok_or(io::Error::new(io::ErrorKind::AddrNotAvailable, "no network interface is available"))
+}
+
+/// Resolves `host` into the socket addresses of all its IP addresses with the given `port`.
+pub fn resolve(host: &str, port: u16) -> io::Result<vec::IntoIter<SocketAddr>> {
+    let addresses = dns::resolve(&interface_for(None)?, host)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let addrs: Vec<SocketAddr> = addresses
+        .into_iter()
+        .map(|address| from_endpoint(IpEndpoint::new(address, port)))
+        .collect();
+    Ok(addrs.into_iter())
+}
+
+/// A socket that is removed from its interface when dropped,
+/// such that a socket is never left behind, even if setting it up fails.
+struct OwnedSocket<T: AnySocket<'static>>(Option<Socket<T>>);
+
+impl<T: AnySocket<'static>> OwnedSocket<T> {
+    /// Takes the socket out of this wrapper, such that it won't be removed when this is dropped.
+    fn take(&mut self) -> Option<Socket<T>> {
+        self.0.take()
+    }
+}
+
+impl<T: AnySocket<'static>> Deref for OwnedSocket<T> {
+    type Target = Socket<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().expect("BUG: OwnedSocket was used after its socket was taken")
+    }
+}
+
+impl<T: AnySocket<'static>> Drop for OwnedSocket<T> {
+    fn drop(&mut self) {
+        if let Some(socket) = self.0.take() {
+            socket.remove();
+        }
+    }
+}
+
+fn new_tcp_socket(interface: Arc<NetworkInterface>) -> OwnedSocket<tcp::Socket<'static>> {
+    OwnedSocket(Some(interface.add_socket(tcp::Socket::new(
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+    ))))
+}
+
+/// Returns the given port, or an ephemeral port if it is `0`.
+fn port_or_ephemeral(port: u16) -> u16 {
+    if port == 0 { theseus_net::get_ephemeral_port() } else { port }
+}
+
+fn to_endpoint(addr: &SocketAddr) -> IpEndpoint {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => IpAddress::Ipv4(Ipv4Address(ip.octets())),
+        IpAddr::V6(ip) => IpAddress::Ipv6(Ipv6Address(ip.octets())),
+    };
+    IpEndpoint::new(ip, addr.port())
+}
+
+fn to_listen_endpoint(addr: &SocketAddr) -> IpListenEndpoint {
+    let port = port_or_ephemeral(addr.port());
+    if addr.ip().is_unspecified() {
+        IpListenEndpoint { addr: None, port }
+    } else {
+        IpListenEndpoint { addr: Some(to_endpoint(addr).addr), port }
+    }
+}
+
+fn from_endpoint(endpoint: IpEndpoint) -> SocketAddr {
+    let ip = match endpoint.addr {
+        IpAddress::Ipv4(ip) => IpAddr::V4(Ipv4Addr::from(ip.0)),
+        IpAddress::Ipv6(ip) => IpAddr::V6(Ipv6Addr::from(ip.0)),
+    };
+    SocketAddr::new(ip, endpoint.port)
+}
+
+fn from_listen_endpoint(endpoint: IpListenEndpoint) -> SocketAddr {
+    match endpoint.addr {
+        Some(addr) => from_endpoint(IpEndpoint::new(addr, endpoint.port)),
+        None => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), endpoint.port),
+    }
+}
+
+/// Calls the given socket operation `poll` once, for sockets in nonblocking mode.
+fn poll_once<R>(poll: impl FnOnce(&mut Context<'_>) -> Poll<R>) -> io::Result<R> {
+    let waker = noop_waker();
+    match poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(result) => Ok(result),
+        Poll::Pending => Err(io::ErrorKind::WouldBlock.into()),
+    }
+}
+
+/// Returns a waker that does nothing, for polling a socket operation without waiting.
+fn noop_waker() -> Waker {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+    const RAW: RawWaker = RawWaker::new(core::ptr::null(), &VTABLE);
+    // SAFETY: the vtable functions do nothing, so they trivially uphold the `RawWaker` contract.
+    unsafe { Waker::from_raw(RAW) }
+}
+
+
+pub struct TcpStream {
+    socket: OwnedSocket<tcp::Socket<'static>>,
+    nonblocking: AtomicBool,
+}
+
+impl TcpStream {
+    pub fn connect(addr: &SocketAddr) -> io::Result<TcpStream> {
+        Self::connect_timeout(addr, DEFAULT_CONNECT_TIMEOUT)
+    }
+
+    pub fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        if timeout.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot set a 0 duration timeout"));
+        }
+        let interface = interface_for(Some(to_endpoint(addr).addr))?;
+        let socket = new_tcp_socket(interface.clone());
+        socket
+            .lock()
+            .connect(to_endpoint(addr), theseus_net::get_ephemeral_port())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid address to connect to"))?;
+        interface.wake();
+        // If this fails or times out, dropping the socket removes it from the interface.
+        match socket.accept_timeout(timeout) {
+            Some(Ok(_)) => Ok(TcpStream::from_socket(socket)),
+            Some(Err(_)) => Err(io::ErrorKind::ConnectionRefused.into()),
+            None => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+
+    fn from_socket(socket: OwnedSocket<tcp::Socket<'static>>) -> TcpStream {
+        TcpStream { socket, nonblocking: AtomicBool::new(false) }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket
+            .lock()
+            .remote_endpoint()
+            .map(from_endpoint)
+            .ok_or(io::ErrorKind::NotConnected.into())
+    }
+
+    pub fn socket_addr(&self) -> io::Result<SocketAddr> {
+        self.socket
+            .lock()
+            .local_endpoint()
+            .map(from_endpoint)
+            .ok_or(io::ErrorKind::NotConnected.into())
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = if self.nonblocking.load(Ordering::Relaxed) {
+            poll_once(|cx| self.socket.poll_recv(cx, buf))?
+        } else {
+            self.socket.recv(buf)
+        };
+        match result {
+            Ok(len) => Ok(len),
+            // The remote endpoint closed the connection.
+            Err(tcp::RecvError::Finished) => Ok(0),
+            Err(_) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.socket.lock().peek_slice(buf) {
+            Ok(len) => Ok(len),
+            Err(tcp::RecvError::Finished) => Ok(0),
+            Err(_) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let result = if self.nonblocking.load(Ordering::Relaxed) {
+            poll_once(|cx| self.socket.poll_send(cx, buf))?
+        } else {
+            self.socket.send(buf)
+        };
+        result.map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match how {
+            Shutdown::Write | Shutdown::Both => {
+                self.socket.lock().close();
+                self.socket.interface().wake();
+                Ok(())
+            }
+            Shutdown::Read => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Theseus TCP sockets don't support shutting down only the read half",
+            )),
+        }
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.socket.lock().set_nagle_enabled(!nodelay);
+        Ok(())
+    }
+
+    pub fn nodelay(&self) -> io::Result<bool> {
+        Ok(!self.socket.lock().nagle_enabled())
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        let ttl = u8::try_from(ttl)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "TTL must be less than 256"))?;
+        self.socket.lock().set_hop_limit(Some(ttl));
+        Ok(())
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        Ok(self.socket.lock().hop_limit().unwrap_or(DEFAULT_TTL) as u32)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        // Gracefully close the connection in the background,
+        // after which the interface removes the socket.
+        if let Some(socket) = self.socket.take() {
+            socket.remove_when_closed();
+        }
+    }
+}
+
+impl fmt::Debug for TcpStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut res = f.debug_struct("TcpStream");
+        if let Ok(addr) = self.socket_addr() {
+            res.field("addr", &addr);
+        }
+        if let Ok(peer) = self.peer_addr() {
+            res.field("peer", &peer);
+        }
+        res.finish()
+    }
+}
+
+
+/// A Theseus TCP socket can only accept a single connection,
+/// so a listener keeps a backlog of sockets listening on the same endpoint
+/// and replaces each one that accepts a connection with a new listening socket.
+///
+/// Connections that arrive before [`TcpListener::accept`] is called are established
+/// on the other sockets in the backlog rather than being refused.
+pub struct TcpListener {
+    endpoint: IpListenEndpoint,
+    state: Mutex<ListenerState>,
+    nonblocking: AtomicBool,
+}
+
+struct ListenerState {
+    /// The sockets that are listening for (or have already established) the next connections,
+    /// oldest first.
+    ///
+    /// This holds fewer than [`LISTEN_BACKLOG`] sockets if replacement sockets couldn't be created
+    /// after accepting a connection, in which case the next call to [`TcpListener::accept`] tries again.
+    /// While [`TcpListener::accept`] waits for a connection, it holds these sockets itself
+    /// rather than holding this state's lock.
+    backlog: Vec<OwnedSocket<tcp::Socket<'static>>>,
+    /// The TTL set by [`TcpListener::set_ttl`], which applies to each new listening socket.
+    hop_limit: Option<u8>,
+}
+
+impl TcpListener {
+    pub fn bind(addr: &SocketAddr) -> io::Result<TcpListener> {
+        let listener = TcpListener {
+            endpoint: to_listen_endpoint(addr),
+            state: Mutex::new(ListenerState { backlog: Vec::with_capacity(LISTEN_BACKLOG), hop_limit: None }),
+            nonblocking: AtomicBool::new(false),
+        };
+        listener.fill_backlog(&mut listener.state.lock())?;
+        Ok(listener)
+    }
+
+    /// Adds listening sockets to the backlog until it's full.
+    ///
+    /// This only fails if the backlog is left empty, i.e., if no socket is listening.
+    fn fill_backlog(&self, state: &mut ListenerState) -> io::Result<()> {
+        while state.backlog.len() < LISTEN_BACKLOG {
+            match Self::listen(self.endpoint, state.hop_limit) {
+                Ok(socket) => state.backlog.push(socket),
+                Err(e) if state.backlog.is_empty() => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn listen(endpoint: IpListenEndpoint, hop_limit: Option<u8>) -> io::Result<OwnedSocket<tcp::Socket<'static>>> {
+        let socket = new_tcp_socket(interface_for(endpoint.addr)?);
+        {
+            let mut locked = socket.lock();
+            locked.set_hop_limit(hop_limit);
+            locked
+                .listen(endpoint)
+                .map_err(|_| io::Error::from(io::ErrorKind::AddrInUse))?;
+        }
+        Ok(socket)
+    }
+
+    pub fn socket_addr(&self) -> io::Result<SocketAddr> {
+        Ok(from_listen_endpoint(self.endpoint))
+    }
+
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        // Take the backlog out of the state such that its lock isn't held while blocking;
+        // a concurrent call to `accept` simply fills a new backlog of its own.
+        let mut backlog = {
+            let mut state = self.state.lock();
+            self.fill_backlog(&mut state)?;
+            core::mem::take(&mut state.backlog)
+        };
+        // Accept the connection on whichever socket in the backlog established one first.
+        let poll_backlog = |cx: &mut Context<'_>| {
+            backlog
+                .iter()
+                .enumerate()
+                .find_map(|(i, socket)| match socket.poll_accept(cx) {
+                    Poll::Ready(result) => Some((i, result)),
+                    Poll::Pending => None,
+                })
+                .map_or(Poll::Pending, Poll::Ready)
+        };
+        let accepted = if self.nonblocking.load(Ordering::Relaxed) {
+            poll_once(poll_backlog)
+        } else {
+            Ok(theseus_net::block_on(poll_backlog))
+        };
+        let (index, result) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                self.return_backlog(backlog);
+                return Err(e);
+            }
+        };
+        let socket = backlog.remove(index);
+        self.return_backlog(backlog);
+        match result {
+            Ok(remote) => Ok((TcpStream::from_socket(socket), from_endpoint(remote))),
+            Err(_) => Err(io::ErrorKind::ConnectionAborted.into()),
+        }
+    }
+
+    /// Returns the given sockets taken by [`TcpListener::accept`] to the backlog,
+    /// ahead of any added in the meantime as they've been listening for longer,
+    /// and then refills the backlog.
+    fn return_backlog(&self, mut backlog: Vec<OwnedSocket<tcp::Socket<'static>>>) {
+        let mut state = self.state.lock();
+        // The TTL may have been changed while these sockets were out of the backlog.
+        for socket in &backlog {
+            socket.lock().set_hop_limit(state.hop_limit);
+        }
+        backlog.append(&mut state.backlog);
+        state.backlog = backlog;
+        // If this fails, the next call to `accept` will try again.
+        let _ = self.fill_backlog(&mut state);
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        let ttl = u8::try_from(ttl)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "TTL must be less than 256"))?;
+        let mut state = self.state.lock();
+        state.hop_limit = Some(ttl);
+        for socket in &state.backlog {
+            socket.lock().set_hop_limit(Some(ttl));
+        }
+        Ok(())
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        Ok(self.state.lock().hop_limit.unwrap_or(DEFAULT_TTL) as u32)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TcpListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpListener").field("addr", &from_listen_endpoint(self.endpoint)).finish()
+    }
+}
+
+
+pub struct UdpSocket {
+    /// The socket, which is removed from its interface when this is dropped.
+    socket: OwnedSocket<udp::Socket<'static>>,
+    endpoint: IpListenEndpoint,
+    /// The remote address set by [`UdpSocket::connect`].
+    peer: Mutex<Option<SocketAddr>>,
+    nonblocking: AtomicBool,
+}
+
+impl UdpSocket {
+    pub fn bind(addr: &SocketAddr) -> io::Result<UdpSocket> {
+        let endpoint = to_listen_endpoint(addr);
+        let interface = interface_for(endpoint.addr)?;
+        let socket = OwnedSocket(Some(interface.add_socket(udp::Socket::new(
+            udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0; UDP_BUFFER_SIZE]),
+            udp::PacketBuffer::new(vec![udp::PacketMetadata::EMPTY; UDP_PACKETS], vec![0; UDP_BUFFER_SIZE]),
+        ))));
+        socket
+            .lock()
+            .bind(endpoint)
+            .map_err(|_| io::Error::from(io::ErrorKind::AddrInUse))?;
+        Ok(UdpSocket { socket, endpoint, peer: Mutex::new(None), nonblocking: AtomicBool::new(false) })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.peer.lock().ok_or(io::ErrorKind::NotConnected.into())
+    }
+
+    pub fn socket_addr(&self) -> io::Result<SocketAddr> {
+        Ok(from_listen_endpoint(self.endpoint))
+    }
+
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let result = if self.nonblocking.load(Ordering::Relaxed) {
+            poll_once(|cx| self.socket.poll_recv(cx, buf))?
+        } else {
+            self.socket.recv(buf)
+        };
+        result
+            .map(|(len, remote)| (len, from_endpoint(remote)))
+            .map_err(|_| io::ErrorKind::NotConnected.into())
+    }
+
+    pub fn send_to(&self, buf: &[u8], dst: &SocketAddr) -> io::Result<usize> {
+        let endpoint = to_endpoint(dst);
+        let result = if self.nonblocking.load(Ordering::Relaxed) {
+            poll_once(|cx| self.socket.poll_send(cx, buf, endpoint))?
+        } else {
+            self.socket.send(buf, endpoint)
+        };
+        match result {
+            Ok(()) => Ok(buf.len()),
+            Err(udp::SendError::Unaddressable) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid address to send to",
+            )),
+            Err(_) => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    /// Receives a datagram from the connected peer, discarding datagrams from any other address.
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let peer = self.peer_addr()?;
+        loop {
+            let (len, from) = self.recv_from(buf)?;
+            if from == peer {
+                return Ok(len);
+            }
+        }
+    }
+
+    pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let peer = self.peer_addr()?;
+        self.send_to(buf, &peer)
+    }
+
+    pub fn connect(&self, addr: &SocketAddr) -> io::Result<()> {
+        *self.peer.lock() = Some(*addr);
+        Ok(())
+    }
+
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        let ttl = u8::try_from(ttl)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "TTL must be less than 256"))?;
+        self.socket.lock().set_hop_limit(Some(ttl));
+        Ok(())
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        Ok(self.socket.lock().hop_limit().unwrap_or(DEFAULT_TTL) as u32)
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for UdpSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpSocket").field("addr", &from_listen_endpoint(self.endpoint)).finish()
+    }
+}
@@ -7,7 +7,7 @@ fn main() {
 	let s = b"abcdef";
 	let mut i = 0;
 	while i < 30 {
-		let mut buf = [0; 100];
+		let mut buf = [0; 1500];
 		let (number_of_bytes, src_addr) = socket.recv_from(&mut buf).expect("Didn't receive data");
 		let filled_buf = &mut buf[..number_of_bytes];
 